@@ -0,0 +1,108 @@
+//! Measures the cost of dispatching instructions in the VM.
+//!
+//! Runs a long expression, and a script that reads and writes globals, many
+//! times and reports the time per executed instruction of each. Globals are
+//! stored by index, so accessing one should cost about as much as any other
+//! instruction. As a baseline, the globals script's accesses are also
+//! replayed against a vector and against a hash map keyed by name, the way
+//! clox stores globals, and the time per access of each is reported.
+//!
+//! Compare the default build against the unchecked dispatch
+//! loop with:
+//!
+//! ```text
+//! cargo bench -p lox-bytecode --bench dispatch
+//! cargo bench -p lox-bytecode --bench dispatch --features unchecked_dispatch
+//! ```
+//!
+//! `LOX_BENCH_RUNS` sets how many times each script is run.
+
+use lox_bytecode::vm::Vm;
+use std::{collections::HashMap, env, hint::black_box, io, time::Instant};
+
+fn bench(name: &str, source: &str, runs: usize) {
+    let mut vm = Vm::new().with_writers(io::sink(), io::stderr());
+    let chunk = Vm::compile(source, &mut io::stderr()).expect("benchmark must compile");
+
+    let start = Instant::now();
+    for _ in 0..runs {
+        vm.execute(chunk.clone()).expect("benchmark must run");
+    }
+    let elapsed = start.elapsed();
+
+    let instructions = vm.instructions_executed();
+    println!(
+        "{name}: {runs} runs, {instructions} instructions, {:.2}ns per instruction",
+        elapsed.as_nanos() as f64 / instructions as f64
+    );
+}
+
+/// Replays `accesses`, pairs of a global read and written, `runs` times
+/// with `get` and `set`, and reports the time per access.
+fn lookups<S>(
+    name: &str,
+    mut store: S,
+    accesses: &[(usize, usize)],
+    runs: usize,
+    get: impl Fn(&S, usize) -> f64,
+    set: impl Fn(&mut S, usize, f64),
+) {
+    let start = Instant::now();
+    for _ in 0..runs {
+        for &(a, b) in accesses {
+            let value = get(&store, a) + get(&store, b) * 2.0;
+            set(&mut store, a, black_box(value) % 1e9);
+        }
+    }
+    let elapsed = start.elapsed();
+
+    let count = runs * accesses.len() * 3;
+    println!(
+        "{name}: {count} accesses, {:.2}ns per access",
+        elapsed.as_nanos() as f64 / count as f64
+    );
+}
+
+fn main() {
+    let runs = env::var("LOX_BENCH_RUNS")
+        .ok()
+        .and_then(|runs| runs.parse().ok())
+        .unwrap_or(2000);
+
+    let term = "(1 + 2) * 3 - -4 == 5 == !nil";
+    bench("expression", &vec![term; 200].join(" == "), runs);
+
+    let globals = (0..26)
+        .map(|i| format!("var {} = {i};\n", (b'a' + i) as char))
+        .chain((0..200).map(|i| {
+            let (a, b) = ((b'a' + i % 26) as char, (b'a' + (i + 7) % 26) as char);
+            format!("{a} = {a} + {b} * 2;\n")
+        }))
+        .collect::<String>();
+    bench("globals", &globals, runs);
+
+    let accesses = (0..200).map(|i| (i % 26, (i + 7) % 26)).collect::<Vec<_>>();
+    let names = (0..26)
+        .map(|i| ((b'a' + i) as char).to_string())
+        .collect::<Vec<_>>();
+    lookups(
+        "globals by index",
+        (0..26).map(|i| Some(i as f64)).collect::<Vec<_>>(),
+        &accesses,
+        runs,
+        |globals, i| globals[i].expect("global must be defined"),
+        |globals, i, value| globals[i] = Some(value),
+    );
+    lookups(
+        "globals by name",
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), i as f64))
+            .collect::<HashMap<_, _>>(),
+        &accesses,
+        runs,
+        |globals, i| globals[&names[i]],
+        |globals, i, value| *globals.get_mut(&names[i]).expect("global must be defined") = value,
+    );
+}
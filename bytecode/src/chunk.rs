@@ -6,17 +6,30 @@ use std::fmt;
 pub enum Error {
     #[error(transparent)]
     InvalidOpCode(#[from] num_enum::TryFromPrimitiveError<OpCode>),
+    #[error("not a compiled lox file")]
+    BadMagic,
+    #[error("unsupported .loxc version {0} (expected {VERSION})")]
+    UnsupportedVersion(u16),
+    #[error("unknown value tag {0}")]
+    InvalidValueTag(u8),
+    #[error("compiled file is truncated or malformed")]
+    Malformed,
+    #[error("invalid operand for the instruction at offset {0}")]
+    InvalidOperand(usize),
+    #[error("too many global variables")]
+    TooManyGlobals,
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
-#[derive(TryFromPrimitive, IntoPrimitive)]
+#[derive(Clone, Copy, TryFromPrimitive, IntoPrimitive)]
 #[repr(u8)]
 pub enum OpCode {
     Constant = 0,
     Nil,
     True,
     False,
+    Pop,
     Equal,
     Greater,
     Less,
@@ -24,8 +37,17 @@ pub enum OpCode {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
     Not,
     Negate,
+    Jump,
+    JumpIfFalse,
+    Print,
+    DefineGlobal,
+    GetGlobal,
+    SetGlobal,
+    GetLocal,
+    SetLocal,
     Return,
 }
 
@@ -36,6 +58,7 @@ impl fmt::Display for OpCode {
             Self::Nil => write!(f, "OP_NIL"),
             Self::True => write!(f, "OP_TRUE"),
             Self::False => write!(f, "OP_FALSE"),
+            Self::Pop => write!(f, "OP_POP"),
             Self::Equal => write!(f, "OP_EQUAL"),
             Self::Greater => write!(f, "OP_GREATER"),
             Self::Less => write!(f, "OP_LESS"),
@@ -43,14 +66,37 @@ impl fmt::Display for OpCode {
             Self::Subtract => write!(f, "OP_SUBTRACT"),
             Self::Multiply => write!(f, "OP_MULTIPLY"),
             Self::Divide => write!(f, "OP_DIVIDE"),
+            Self::Modulo => write!(f, "OP_MODULO"),
             Self::Not => write!(f, "OP_NOT"),
             Self::Negate => write!(f, "OP_NEGATE"),
+            Self::Jump => write!(f, "OP_JUMP"),
+            Self::JumpIfFalse => write!(f, "OP_JUMP_IF_FALSE"),
+            Self::Print => write!(f, "OP_PRINT"),
+            Self::DefineGlobal => write!(f, "OP_DEFINE_GLOBAL"),
+            Self::GetGlobal => write!(f, "OP_GET_GLOBAL"),
+            Self::SetGlobal => write!(f, "OP_SET_GLOBAL"),
+            Self::GetLocal => write!(f, "OP_GET_LOCAL"),
+            Self::SetLocal => write!(f, "OP_SET_LOCAL"),
             Self::Return => write!(f, "OP_RETURN"),
         }
     }
 }
 
 impl OpCode {
+    /// The number of bytes the instruction takes up, including operands.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Constant
+            | Self::DefineGlobal
+            | Self::GetGlobal
+            | Self::SetGlobal
+            | Self::GetLocal
+            | Self::SetLocal => 2,
+            Self::Jump | Self::JumpIfFalse => 3,
+            _ => 1,
+        }
+    }
+
     pub fn disassemble(&self, chunk: &Chunk, offset: usize) -> usize {
         print!("{offset:04} ");
 
@@ -67,6 +113,29 @@ impl OpCode {
             offset + 1
         }
 
+        fn jump_instruction(op: &OpCode, sign: isize, chunk: &Chunk, offset: usize) -> usize {
+            let jump = u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+            let target = offset as isize + 3 + sign * jump as isize;
+            println!("{op:-16} {offset:4} -> {target}");
+
+            offset + 3
+        }
+
+        fn byte_instruction(op: &OpCode, chunk: &Chunk, offset: usize) -> usize {
+            let slot = chunk.code[offset + 1];
+            println!("{op:-16} {slot:4}");
+
+            offset + 2
+        }
+
+        fn global_instruction(op: &OpCode, chunk: &Chunk, offset: usize) -> usize {
+            let global = chunk.code[offset + 1];
+            let name = &chunk.globals[global as usize];
+            println!("{op:-16} {global:4} '{name}'");
+
+            offset + 2
+        }
+
         match self {
             Self::Constant => {
                 let constant = chunk.code[offset + 1];
@@ -79,6 +148,7 @@ impl OpCode {
             Self::Nil => simple_intruction(self, offset),
             Self::True => simple_intruction(self, offset),
             Self::False => simple_intruction(self, offset),
+            Self::Pop => simple_intruction(self, offset),
             Self::Equal => simple_intruction(self, offset),
             Self::Greater => simple_intruction(self, offset),
             Self::Less => simple_intruction(self, offset),
@@ -86,18 +156,88 @@ impl OpCode {
             Self::Subtract => simple_intruction(self, offset),
             Self::Multiply => simple_intruction(self, offset),
             Self::Divide => simple_intruction(self, offset),
+            Self::Modulo => simple_intruction(self, offset),
             Self::Not => simple_intruction(self, offset),
             Self::Negate => simple_intruction(self, offset),
+            Self::Jump => jump_instruction(self, 1, chunk, offset),
+            Self::JumpIfFalse => jump_instruction(self, 1, chunk, offset),
+            Self::Print => simple_intruction(self, offset),
+            Self::DefineGlobal => global_instruction(self, chunk, offset),
+            Self::GetGlobal => global_instruction(self, chunk, offset),
+            Self::SetGlobal => global_instruction(self, chunk, offset),
+            Self::GetLocal => byte_instruction(self, chunk, offset),
+            Self::SetLocal => byte_instruction(self, chunk, offset),
             Self::Return => simple_intruction(self, offset),
         }
     }
 }
 
-#[derive(Default)]
+const MAGIC: &[u8; 4] = b"LOXC";
+const VERSION: u16 = 2;
+
+const TAG_BOOLEAN: u8 = 0;
+const TAG_NIL: u8 = 1;
+const TAG_NUMBER: u8 = 2;
+
+/// A cursor over the bytes of a `.loxc` file.
+struct Reader<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> Result<&'b [u8]> {
+        if self.bytes.len() < len {
+            return Err(Error::Malformed);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("slice must have N bytes"))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+}
+
+/// Whether `op` refers to a global variable by its index.
+fn is_global(op: OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal
+    )
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct Chunk {
     code: Vec<u8>,
     constants: Vec<Value>,
     lines: Vec<usize>,
+    /// The name of each global variable, at the index instructions refer to
+    /// it by. Names are resolved when compiling so that the VM can keep
+    /// globals in a vector rather than looking them up by name.
+    globals: Vec<String>,
+    /// The offset each statement starts at and how many values should be on
+    /// the stack there, for checking that statements keep it balanced. Only
+    /// the compiler records these, so they aren't serialized or kept by the
+    /// optimizer.
+    #[cfg(feature = "check_stack")]
+    statements: Vec<(usize, usize)>,
+}
+
+// Statement boundaries are only kept for checking, so they don't make
+// chunks that run the same code differ.
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.code == other.code
+            && self.constants == other.constants
+            && self.lines == other.lines
+            && self.globals == other.globals
+    }
 }
 
 impl Chunk {
@@ -105,6 +245,15 @@ impl Chunk {
         Self::default()
     }
 
+    /// A chunk whose globals start out as `globals`, so that code compiled
+    /// into it refers to them by the same indices as earlier chunks did.
+    pub fn with_globals(globals: Vec<String>) -> Self {
+        Self {
+            globals,
+            ..Self::default()
+        }
+    }
+
     pub fn code(&self) -> &[u8] {
         &self.code
     }
@@ -113,10 +262,76 @@ impl Chunk {
         &self.constants
     }
 
+    pub fn code_mut(&mut self) -> &mut [u8] {
+        &mut self.code
+    }
+
     pub fn lines(&self) -> &[usize] {
         &self.lines
     }
 
+    pub fn globals(&self) -> &[String] {
+        &self.globals
+    }
+
+    /// Records that a statement starts at the end of the code so far, with
+    /// `depth` values on the stack.
+    #[cfg(feature = "check_stack")]
+    pub(crate) fn mark_statement(&mut self, depth: usize) {
+        self.statements.push((self.code.len(), depth));
+    }
+
+    #[cfg(feature = "check_stack")]
+    pub(crate) fn statements(&self) -> &[(usize, usize)] {
+        &self.statements
+    }
+
+    /// The index of the global called `name`, adding it if it's new, or
+    /// `None` if there's no room for another.
+    pub fn global(&mut self, name: &str) -> Option<u8> {
+        let index = match self.globals.iter().position(|global| global == name) {
+            Some(index) => index,
+            None => {
+                self.globals.push(name.to_string());
+                self.globals.len() - 1
+            }
+        };
+
+        u8::try_from(index).ok()
+    }
+
+    /// Makes the chunk's code refer to each global by its index in `globals`
+    /// instead, adding the ones that aren't there yet. This lets a VM run
+    /// chunks that were compiled separately against the same globals.
+    pub fn link(&mut self, globals: &mut Vec<String>) -> Result<()> {
+        let mut indices = Vec::with_capacity(self.globals.len());
+        for name in &self.globals {
+            let index = match globals.iter().position(|global| global == name) {
+                Some(index) => index,
+                None => {
+                    globals.push(name.clone());
+                    globals.len() - 1
+                }
+            };
+            indices.push(u8::try_from(index).map_err(|_| Error::TooManyGlobals)?);
+        }
+
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let op = OpCode::try_from(self.code[offset])?;
+            if is_global(op) {
+                let global = self.code.get_mut(offset + 1).ok_or(Error::Malformed)?;
+                *global = *indices
+                    .get(*global as usize)
+                    .ok_or(Error::InvalidOperand(offset))?;
+            }
+            offset += op.size();
+        }
+        self.globals = globals.clone();
+
+        Ok(())
+    }
+
     pub fn write<B: Into<u8>>(&mut self, byte: B, line: usize) {
         self.code.push(byte.into());
         self.lines.push(line);
@@ -128,9 +343,197 @@ impl Chunk {
         (self.constants.len() - 1) as u8
     }
 
+    /// Encodes the chunk in the `.loxc` format.
+    ///
+    /// The layout is the magic bytes `LOXC` and a little-endian `u16`
+    /// version, followed by four sections, each prefixed with its length as
+    /// a `u32`: the constant pool as tagged values, the names of the globals
+    /// as UTF-8 prefixed with their length as a `u32`, the code, and the
+    /// line of each byte of code as a `u32`.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+
+        bytes.extend((self.constants.len() as u32).to_le_bytes());
+        for constant in &self.constants {
+            if let Some(b) = constant.as_boolean() {
+                bytes.extend([TAG_BOOLEAN, b as u8]);
+            } else if let Some(n) = constant.as_number() {
+                bytes.push(TAG_NUMBER);
+                bytes.extend(n.to_le_bytes());
+            } else {
+                bytes.push(TAG_NIL);
+            }
+        }
+
+        bytes.extend((self.globals.len() as u32).to_le_bytes());
+        for name in &self.globals {
+            bytes.extend((name.len() as u32).to_le_bytes());
+            bytes.extend(name.as_bytes());
+        }
+
+        bytes.extend((self.code.len() as u32).to_le_bytes());
+        bytes.extend(&self.code);
+
+        bytes.extend((self.lines.len() as u32).to_le_bytes());
+        for line in &self.lines {
+            bytes.extend((*line as u32).to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// Decodes a chunk written by [`Chunk::serialize`].
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let mut chunk = Self::new();
+
+        for _ in 0..reader.u32()? {
+            let constant = match reader.array::<1>()?[0] {
+                TAG_BOOLEAN => Value::boolean(reader.array::<1>()?[0] != 0),
+                TAG_NIL => Value::nil(),
+                TAG_NUMBER => Value::number(f64::from_le_bytes(reader.array()?)),
+                tag => return Err(Error::InvalidValueTag(tag)),
+            };
+            chunk.constants.push(constant);
+        }
+
+        for _ in 0..reader.u32()? {
+            let len = reader.u32()? as usize;
+            let name = std::str::from_utf8(reader.take(len)?).map_err(|_| Error::Malformed)?;
+            chunk.globals.push(name.to_string());
+        }
+
+        let len = reader.u32()? as usize;
+        chunk.code = reader.take(len)?.to_vec();
+
+        for _ in 0..reader.u32()? {
+            chunk.lines.push(reader.u32()? as usize);
+        }
+
+        if chunk.lines.len() != chunk.code.len() || !reader.bytes.is_empty() {
+            return Err(Error::Malformed);
+        }
+        chunk.validate()?;
+
+        Ok(chunk)
+    }
+
     pub fn disassemble(&self, name: &str) -> Result<()> {
         println!("== {name} ==");
 
+        self.disassemble_code()
+    }
+
+    /// Like [`Chunk::disassemble`], but also lists the constant pool and the
+    /// range of code offsets compiled from each source line.
+    pub fn disassemble_all(&self, name: &str) -> Result<()> {
+        println!("== {name} ==");
+
+        let uses = self.constant_uses()?;
+        let slots = u8::MAX as usize + 1;
+        println!(
+            "-- constants ({} of {slots} slots) --",
+            self.constants.len()
+        );
+        for (index, constant) in self.constants.iter().enumerate() {
+            let uses = uses[index];
+            let plural = if uses == 1 { "" } else { "s" };
+            println!(
+                "{index:4} {:<16} ({uses} use{plural})",
+                constant.to_string()
+            );
+        }
+
+        println!("-- globals --");
+        for (index, name) in self.globals.iter().enumerate() {
+            println!("{index:4} {name}");
+        }
+
+        println!("-- lines --");
+        let mut start = 0;
+        for (offset, line) in self.lines.iter().enumerate() {
+            if self.lines.get(offset + 1) != Some(line) {
+                println!("{line:4} {start:04}-{offset:04}");
+                start = offset + 1;
+            }
+        }
+
+        println!("-- code --");
+        self.disassemble_code()
+    }
+
+    /// Checks that the code is a sequence of whole, valid instructions, whose
+    /// constants and globals exist and whose jumps land on the start of an
+    /// instruction.
+    ///
+    /// The VM relies on this to decode opcodes without checking them.
+    pub fn validate(&self) -> Result<()> {
+        let mut starts = vec![false; self.code.len()];
+        let mut jumps = vec![];
+
+        let mut offset = 0;
+        while offset < self.code.len() {
+            starts[offset] = true;
+
+            let op = OpCode::try_from(self.code[offset])?;
+            if offset + op.size() > self.code.len() {
+                return Err(Error::InvalidOperand(offset));
+            }
+
+            match op {
+                OpCode::Constant if self.code[offset + 1] as usize >= self.constants.len() => {
+                    return Err(Error::InvalidOperand(offset));
+                }
+                op if is_global(op) && self.code[offset + 1] as usize >= self.globals.len() => {
+                    return Err(Error::InvalidOperand(offset));
+                }
+                OpCode::Jump | OpCode::JumpIfFalse => {
+                    let jump = u16::from_be_bytes([self.code[offset + 1], self.code[offset + 2]]);
+                    jumps.push((offset, offset + op.size() + jump as usize));
+                }
+                _ => {}
+            }
+
+            offset += op.size();
+        }
+
+        for (offset, target) in jumps {
+            if !starts.get(target).copied().unwrap_or(false) {
+                return Err(Error::InvalidOperand(offset));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts how many instructions load each constant.
+    fn constant_uses(&self) -> Result<Vec<usize>> {
+        let mut uses = vec![0; self.constants.len()];
+
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let op = OpCode::try_from(self.code[offset])?;
+            if let OpCode::Constant = op {
+                let constant = *self.code.get(offset + 1).ok_or(Error::Malformed)?;
+                *uses.get_mut(constant as usize).ok_or(Error::Malformed)? += 1;
+            }
+            offset += op.size();
+        }
+
+        Ok(uses)
+    }
+
+    fn disassemble_code(&self) -> Result<()> {
         let mut offset = 0;
         while offset < self.code.len() {
             let instruction = self.code[offset];
@@ -1,16 +1,18 @@
 use crate::{
     chunk::{Chunk, OpCode},
-    scanner::{Scanner, Token, TokenType},
+    scanner::{self, Scanner, Token, TokenType},
     value::Value,
 };
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use std::rc::Rc;
+use std::{collections::HashMap, io::Write, rc::Rc};
 
 #[derive(TryFromPrimitive, IntoPrimitive, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
 enum Precedence {
     None,
+    Comma,
     Assignment,
+    Conditional,
     Or,
     And,
     Equality,
@@ -33,22 +35,55 @@ impl std::ops::Add<u8> for Precedence {
     }
 }
 
+/// A variable declared in a block, which lives in a slot on the stack.
+struct Local {
+    name: String,
+    /// How many blocks it's declared inside, or `None` while its initializer
+    /// is being compiled.
+    depth: Option<usize>,
+}
+
 struct Parser<'p> {
     scanner: Scanner<'p>,
+    /// Where compile errors are reported.
+    err: &'p mut dyn Write,
     previous: Option<Rc<Token>>,
     current: Option<Rc<Token>>,
     had_error: bool,
     panic_mode: bool,
+    /// Indices of the constants already in the chunk, so that repeated
+    /// literals share a slot. Numbers are keyed by their bits, which keeps
+    /// `0` and `-0` apart.
+    constants: HashMap<u64, u8>,
+    /// How many expressions are being compiled inside each other.
+    depth: usize,
+    /// Whether the expression being compiled may be the target of an
+    /// assignment, which only binds as loosely as assignment does.
+    can_assign: bool,
+    /// The locals in scope, in the order of the stack slots they live in.
+    locals: Vec<Local>,
+    /// How many blocks the code being compiled is inside.
+    scope_depth: usize,
 }
 
+/// How deeply expressions may nest before compiling them would risk
+/// overflowing the stack.
+const MAX_NESTING: usize = 200;
+
 impl<'p> Parser<'p> {
-    fn new(scanner: Scanner<'p>) -> Self {
+    fn new(scanner: Scanner<'p>, err: &'p mut dyn Write) -> Self {
         Parser {
             scanner,
+            err,
             previous: None,
             current: None,
             had_error: false,
             panic_mode: false,
+            constants: HashMap::new(),
+            depth: 0,
+            can_assign: false,
+            locals: vec![],
+            scope_depth: 0,
         }
     }
 
@@ -67,18 +102,17 @@ impl<'p> Parser<'p> {
         self.panic_mode = true;
 
         let line = token.line;
-        eprint!("[line {line}] Error");
-
-        if matches!(token.typ, TokenType::Eof) {
-            eprint!(" at end");
+        let location = if matches!(token.typ, TokenType::Eof) {
+            " at end".to_string()
         } else if matches!(token.typ, TokenType::Error) {
-            // Nothing.
+            String::new()
         } else {
             let lexeme = &token.lexeme;
-            eprint!(" at '{lexeme}'");
-        }
+            format!(" at '{lexeme}'")
+        };
 
-        eprintln!(": {message}");
+        writeln!(self.err, "[line {line}] Error{location}: {message}")
+            .expect("must be able to write errors");
         self.had_error = true;
     }
 
@@ -113,6 +147,19 @@ impl<'p> Parser<'p> {
         }
     }
 
+    fn check(&self, typ: TokenType) -> bool {
+        self.current().typ == typ
+    }
+
+    fn matches(&mut self, typ: TokenType) -> bool {
+        if !self.check(typ) {
+            return false;
+        }
+        self.advance();
+
+        true
+    }
+
     fn emit_byte<B: Into<u8>>(&self, chunk: &mut Chunk, byte: B) {
         chunk.write(byte, self.previous().line);
     }
@@ -122,6 +169,26 @@ impl<'p> Parser<'p> {
         self.emit_byte(chunk, byte2);
     }
 
+    fn emit_jump(&self, chunk: &mut Chunk, instruction: OpCode) -> usize {
+        self.emit_byte(chunk, instruction);
+        self.emit_bytes(chunk, 0xff, 0xff);
+
+        chunk.code().len() - 2
+    }
+
+    fn patch_jump(&mut self, chunk: &mut Chunk, offset: usize) {
+        // -2 to adjust for the bytecode for the jump offset itself.
+        let jump = chunk.code().len() - offset - 2;
+
+        if jump > u16::MAX as usize {
+            self.error("Too much code to jump over.");
+        }
+
+        let [hi, lo] = (jump as u16).to_be_bytes();
+        chunk.code_mut()[offset] = hi;
+        chunk.code_mut()[offset + 1] = lo;
+    }
+
     fn emit_return(&self, chunk: &mut Chunk) {
         self.emit_byte(chunk, OpCode::Return)
     }
@@ -152,10 +219,13 @@ impl<'p> Parser<'p> {
             TokenType::RightParen => (None, None, Precedence::None),
             TokenType::LeftBrace => (None, None, Precedence::None),
             TokenType::RightBrace => (None, None, Precedence::None),
-            TokenType::Comma => (None, None, Precedence::None),
+            TokenType::Colon => (None, None, Precedence::None),
+            TokenType::Comma => (None, Some(Self::comma), Precedence::Comma),
             TokenType::Dot => (None, None, Precedence::None),
             TokenType::Minus => (Some(Self::unary), Some(Self::binary), Precedence::Term),
+            TokenType::Percent => (None, Some(Self::binary), Precedence::Factor),
             TokenType::Plus => (None, Some(Self::binary), Precedence::Term),
+            TokenType::Question => (None, Some(Self::ternary), Precedence::Conditional),
             TokenType::Semicolon => (None, None, Precedence::None),
             TokenType::Slash => (None, Some(Self::binary), Precedence::Factor),
             TokenType::Star => (None, Some(Self::binary), Precedence::Factor),
@@ -167,11 +237,19 @@ impl<'p> Parser<'p> {
             TokenType::GreaterEqual => (None, Some(Self::binary), Precedence::Comparison),
             TokenType::Less => (None, Some(Self::binary), Precedence::Comparison),
             TokenType::LessEqual => (None, Some(Self::binary), Precedence::Comparison),
-            TokenType::Identifier => (None, None, Precedence::None),
+            TokenType::MinusEqual => (None, None, Precedence::None),
+            TokenType::MinusMinus => (Some(Self::unary), None, Precedence::None),
+            TokenType::PlusEqual => (None, None, Precedence::None),
+            TokenType::PlusPlus => (Some(Self::unary), None, Precedence::None),
+            TokenType::SlashEqual => (None, None, Precedence::None),
+            TokenType::StarEqual => (None, None, Precedence::None),
+            TokenType::Identifier => (Some(Self::variable), None, Precedence::None),
             TokenType::String => (None, None, Precedence::None),
             TokenType::Number => (Some(Self::number), None, Precedence::None),
             TokenType::And => (None, None, Precedence::None),
+            TokenType::Break => (None, None, Precedence::None),
             TokenType::Class => (None, None, Precedence::None),
+            TokenType::Continue => (None, None, Precedence::None),
             TokenType::Else => (None, None, Precedence::None),
             TokenType::False => (Some(Self::literal), None, Precedence::None),
             TokenType::For => (None, None, Precedence::None),
@@ -208,10 +286,36 @@ impl<'p> Parser<'p> {
             TokenType::Minus => self.emit_byte(chunk, OpCode::Subtract),
             TokenType::Star => self.emit_byte(chunk, OpCode::Multiply),
             TokenType::Slash => self.emit_byte(chunk, OpCode::Divide),
+            TokenType::Percent => self.emit_byte(chunk, OpCode::Modulo),
             _ => {}
         };
     }
 
+    fn comma(&mut self, chunk: &mut Chunk) {
+        // Only the rightmost operand's value is kept.
+        self.emit_byte(chunk, OpCode::Pop);
+        self.parse_precedence(chunk, Precedence::Assignment);
+    }
+
+    fn ternary(&mut self, chunk: &mut Chunk) {
+        let then_jump = self.emit_jump(chunk, OpCode::JumpIfFalse);
+        self.emit_byte(chunk, OpCode::Pop);
+        self.expression(chunk);
+        self.consume(
+            TokenType::Colon,
+            "Expect ':' after then branch of conditional expression.",
+        );
+
+        let else_jump = self.emit_jump(chunk, OpCode::Jump);
+        self.patch_jump(chunk, then_jump);
+        self.emit_byte(chunk, OpCode::Pop);
+
+        // The else branch is parsed at the same precedence to make the
+        // operator right-associative.
+        self.parse_precedence(chunk, Precedence::Conditional);
+        self.patch_jump(chunk, else_jump);
+    }
+
     fn literal(&mut self, chunk: &mut Chunk) {
         match self.previous().typ {
             TokenType::False => self.emit_byte(chunk, OpCode::False),
@@ -221,28 +325,158 @@ impl<'p> Parser<'p> {
         }
     }
 
+    fn make_constant(&mut self, chunk: &mut Chunk, value: f64) -> u8 {
+        if let Some(constant) = self.constants.get(&value.to_bits()) {
+            return *constant;
+        }
+
+        if chunk.constants().len() > u8::MAX as usize {
+            self.error("Too many constants in one chunk.");
+            return 0;
+        }
+
+        let constant = chunk.add_constant(Value::number(value));
+        self.constants.insert(value.to_bits(), constant);
+
+        constant
+    }
+
     fn number(&mut self, chunk: &mut Chunk) {
-        let value: f64 = self.previous().lexeme.parse().expect("must be a number");
-        let constant = chunk.add_constant(Value::Number(value));
+        let value = scanner::number_value(&self.previous().lexeme);
+        let constant = self.make_constant(chunk, value);
         self.emit_bytes(chunk, OpCode::Constant, constant);
     }
 
+    /// The index of the global called `name`.
+    fn global(&mut self, chunk: &mut Chunk, name: &str) -> u8 {
+        chunk.global(name).unwrap_or_else(|| {
+            self.error("Too many global variables.");
+            0
+        })
+    }
+
+    /// The slot of the innermost local called `name`, if there is one.
+    fn resolve_local(&mut self, name: &Token) -> Option<u8> {
+        let slot = self
+            .locals
+            .iter()
+            .rposition(|local| local.name == name.lexeme)?;
+        if self.locals[slot].depth.is_none() {
+            self.error_at(name, "Can't read local variable in its own initializer.");
+        }
+
+        Some(slot as u8)
+    }
+
+    /// The instructions that get and set the variable called `name`, and
+    /// their operand.
+    fn resolve(&mut self, chunk: &mut Chunk, name: &Token) -> (OpCode, OpCode, u8) {
+        match self.resolve_local(name) {
+            Some(slot) => (OpCode::GetLocal, OpCode::SetLocal, slot),
+            None => {
+                let global = self.global(chunk, &name.lexeme);
+                (OpCode::GetGlobal, OpCode::SetGlobal, global)
+            }
+        }
+    }
+
+    fn variable(&mut self, chunk: &mut Chunk) {
+        let (get, set, operand) = self.resolve(chunk, &self.previous());
+
+        let postfix = match self.current().typ {
+            TokenType::PlusPlus => Some(OpCode::Add),
+            TokenType::MinusMinus => Some(OpCode::Subtract),
+            _ => None,
+        };
+        if let Some(operator) = postfix {
+            // Leave the old value beneath the new one, which is popped once
+            // it's stored.
+            self.advance();
+            let one = self.make_constant(chunk, 1.0);
+            self.emit_bytes(chunk, get, operand);
+            self.emit_bytes(chunk, get, operand);
+            self.emit_bytes(chunk, OpCode::Constant, one);
+            self.emit_byte(chunk, operator);
+            self.emit_bytes(chunk, set, operand);
+            self.emit_byte(chunk, OpCode::Pop);
+            return;
+        }
+
+        if !self.can_assign {
+            self.emit_bytes(chunk, get, operand);
+            return;
+        }
+        let operator = match self.current().typ {
+            TokenType::Equal => None,
+            TokenType::PlusEqual => Some(OpCode::Add),
+            TokenType::MinusEqual => Some(OpCode::Subtract),
+            TokenType::StarEqual => Some(OpCode::Multiply),
+            TokenType::SlashEqual => Some(OpCode::Divide),
+            _ => {
+                self.emit_bytes(chunk, get, operand);
+                return;
+            }
+        };
+        self.advance();
+
+        if let Some(operator) = operator {
+            self.emit_bytes(chunk, get, operand);
+            self.parse_precedence(chunk, Precedence::Assignment);
+            self.emit_byte(chunk, operator);
+        } else {
+            self.parse_precedence(chunk, Precedence::Assignment);
+        }
+        self.emit_bytes(chunk, set, operand);
+    }
+
     fn unary(&mut self, chunk: &mut Chunk) {
-        let operator_type = &self.previous().typ;
+        let operator = self.previous();
+
+        if matches!(operator.typ, TokenType::PlusPlus | TokenType::MinusMinus)
+            && self.matches(TokenType::Identifier)
+        {
+            let (get, set, operand) = self.resolve(chunk, &self.previous());
+            let one = self.make_constant(chunk, 1.0);
+            self.emit_bytes(chunk, get, operand);
+            self.emit_bytes(chunk, OpCode::Constant, one);
+            match operator.typ {
+                TokenType::PlusPlus => self.emit_byte(chunk, OpCode::Add),
+                _ => self.emit_byte(chunk, OpCode::Subtract),
+            }
+            self.emit_bytes(chunk, set, operand);
+            return;
+        }
 
         // Compile the operand.
         self.parse_precedence(chunk, Precedence::Unary);
 
-        match operator_type {
+        match operator.typ {
             TokenType::Bang => self.emit_byte(chunk, OpCode::Not),
             TokenType::Minus => self.emit_byte(chunk, OpCode::Negate),
+            // Only variables can be incremented or decremented.
+            TokenType::MinusMinus | TokenType::PlusPlus => {
+                self.error_at(&operator, "Invalid increment or decrement target.")
+            }
             _ => {}
         }
     }
 
     fn parse_precedence(&mut self, chunk: &mut Chunk, precedence: Precedence) {
+        if self.depth == MAX_NESTING {
+            self.error_at_current("Too much nesting.");
+            return;
+        }
+
+        self.depth += 1;
+        self.parse_precedence_nested(chunk, precedence);
+        self.depth -= 1;
+    }
+
+    fn parse_precedence_nested(&mut self, chunk: &mut Chunk, precedence: Precedence) {
         self.advance();
+        let can_assign = precedence <= Precedence::Assignment;
         if let Some(prefix_rule) = self.get_rule(&self.previous().typ).0 {
+            self.can_assign = can_assign;
             prefix_rule(self, chunk);
         } else {
             self.error("Expect expression.");
@@ -255,6 +489,10 @@ impl<'p> Parser<'p> {
                 infix_rule(self, chunk);
             }
         }
+
+        if can_assign && self.matches(TokenType::Equal) {
+            self.error("Invalid assignment target.");
+        }
     }
 
     fn grouping(&mut self, chunk: &mut Chunk) {
@@ -263,17 +501,171 @@ impl<'p> Parser<'p> {
     }
 
     fn expression(&mut self, chunk: &mut Chunk) {
-        self.parse_precedence(chunk, Precedence::Assignment);
+        self.parse_precedence(chunk, Precedence::Comma);
+    }
+
+    /// Adds a local for the variable just named, which can't be read until
+    /// it's initialized.
+    fn declare_local(&mut self) {
+        let name = self.previous();
+        let redeclared = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth == self.scope_depth))
+            .any(|local| local.name == name.lexeme);
+        if redeclared {
+            self.error("Already a variable with this name in this scope.");
+        }
+
+        if self.locals.len() > u8::MAX as usize {
+            self.error("Too many local variables.");
+            return;
+        }
+        self.locals.push(Local {
+            name: name.lexeme.clone(),
+            depth: None,
+        });
+    }
+
+    fn var_declaration(&mut self, chunk: &mut Chunk) {
+        self.consume(TokenType::Identifier, "Expect variable name.");
+        let global = if self.scope_depth > 0 {
+            self.declare_local();
+            None
+        } else {
+            Some(self.global(chunk, &self.previous().lexeme))
+        };
+
+        if self.matches(TokenType::Equal) {
+            self.expression(chunk);
+        } else {
+            self.emit_byte(chunk, OpCode::Nil);
+        }
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        );
+
+        match global {
+            Some(global) => self.emit_bytes(chunk, OpCode::DefineGlobal, global),
+            // The initializer's value stays on the stack, in the local's
+            // slot.
+            None => {
+                if let Some(local) = self.locals.last_mut() {
+                    local.depth = Some(self.scope_depth);
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, chunk: &mut Chunk) {
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            self.declaration(chunk);
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block.");
+    }
+
+    fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    /// Leaves a block, popping the locals declared in it off the stack.
+    fn end_scope(&mut self, chunk: &mut Chunk) {
+        self.scope_depth -= 1;
+
+        while self
+            .locals
+            .last()
+            .is_some_and(|local| local.depth.is_none_or(|depth| depth > self.scope_depth))
+        {
+            self.emit_byte(chunk, OpCode::Pop);
+            self.locals.pop();
+        }
+    }
+
+    fn print_statement(&mut self, chunk: &mut Chunk) {
+        self.expression(chunk);
+        self.consume(TokenType::Semicolon, "Expect ';' after value.");
+        self.emit_byte(chunk, OpCode::Print);
+    }
+
+    fn expression_statement(&mut self, chunk: &mut Chunk) {
+        self.expression(chunk);
+        // An expression that ends the script is its result, which is left
+        // for `Return` to print.
+        if self.scope_depth == 0 && self.check(TokenType::Eof) {
+            return;
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.");
+        self.emit_byte(chunk, OpCode::Pop);
+    }
+
+    fn statement(&mut self, chunk: &mut Chunk) {
+        if self.matches(TokenType::Print) {
+            self.print_statement(chunk);
+        } else if self.matches(TokenType::LeftBrace) {
+            self.begin_scope();
+            self.block(chunk);
+            self.end_scope(chunk);
+        } else {
+            self.expression_statement(chunk);
+        }
+    }
+
+    fn declaration(&mut self, chunk: &mut Chunk) {
+        // Only locals should be on the stack between statements.
+        #[cfg(feature = "check_stack")]
+        chunk.mark_statement(self.locals.len());
+
+        if self.matches(TokenType::Var) {
+            self.var_declaration(chunk);
+        } else {
+            self.statement(chunk);
+        }
+
+        if self.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    /// Skips to what's likely the start of the next statement, so that one
+    /// mistake isn't reported as many.
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+
+        while !self.check(TokenType::Eof) {
+            if matches!(&self.previous, Some(token) if token.typ == TokenType::Semicolon) {
+                return;
+            }
+            match self.current().typ {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => {}
+            }
+
+            self.advance();
+        }
     }
 }
 
-pub fn compile(source: &str, chunk: &mut Chunk) -> bool {
+/// Compiles `source` into `chunk`, reporting any errors to `err`. Globals
+/// that `chunk` already has keep their indices.
+pub fn compile(source: &str, chunk: &mut Chunk, err: &mut dyn Write) -> bool {
     let scanner = Scanner::new(source);
-    let mut parser = Parser::new(scanner);
+    let mut parser = Parser::new(scanner, err);
 
     parser.advance();
-    parser.expression(chunk);
-    parser.consume(TokenType::Eof, "Expect end of expression.");
+    while !parser.matches(TokenType::Eof) {
+        parser.declaration(chunk);
+    }
     parser.end_compilation(chunk);
 
     !parser.had_error
@@ -1,5 +1,9 @@
-mod chunk;
+pub mod buffer;
+pub mod chunk;
 mod compiler;
+pub mod optimizer;
+pub mod repl;
 mod scanner;
+pub mod stats;
 mod value;
 pub mod vm;
@@ -1,41 +1,183 @@
-use lox_bytecode::vm::Vm;
-use std::{env, io::Write, process};
-
-fn repl(vm: &mut Vm) -> anyhow::Result<()> {
-    loop {
-        print!("> ");
-        std::io::stdout().flush()?;
-
-        let mut line = String::new();
-        std::io::stdin().read_line(&mut line)?;
-        if line.is_empty() {
-            break;
-        }
+use lox_bytecode::{
+    chunk::Chunk,
+    optimizer::{self, MAX_OPT_LEVEL},
+    repl::repl,
+    vm::{Error, Vm},
+};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, Write},
+    path::Path,
+    process,
+};
 
-        let _ = vm.interpret(&line);
+/// Exits with the code from sysexits.h that the reference implementations
+/// use for `error`.
+fn exit_with(error: Error) -> ! {
+    match error {
+        Error::Compile => process::exit(65),
+        Error::Runtime | Error::Budget | Error::Interrupted => process::exit(70),
     }
+}
+
+/// Prints what the VM did in the last chunk it ran, if it was recording.
+fn print_stats(vm: &Vm) {
+    if let Some(stats) = vm.stats() {
+        eprint!("{stats}");
+    }
+}
+
+/// Writes an lcov tracefile for the script at `path`.
+fn write_lcov(path: &str, lines: &BTreeMap<usize, u64>, output: &mut impl Write) -> io::Result<()> {
+    writeln!(output, "TN:")?;
+    writeln!(output, "SF:{path}")?;
+    for (line, count) in lines {
+        writeln!(output, "DA:{line},{count}")?;
+    }
+    writeln!(output, "LF:{}", lines.len())?;
+    let hit = lines.values().filter(|count| **count > 0).count();
+    writeln!(output, "LH:{hit}")?;
+    writeln!(output, "end_of_record")
+}
+
+/// Runs a script, writing a report of the lines it ran to `coverage` if
+/// it's given.
+fn run_file(path: &str, vm: &mut Vm, coverage: Option<&str>) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(path)?;
+    if coverage.is_some() {
+        vm.enable_coverage();
+    }
+
+    let result = vm.interpret(&source);
+    print_stats(vm);
+    if let (Some(report), Some(lines)) = (coverage, vm.coverage()) {
+        write_lcov(path, lines, &mut fs::File::create(report)?)?;
+    }
+    result.unwrap_or_else(|error| exit_with(error));
 
     Ok(())
 }
 
-fn run_file(path: &str, vm: &mut Vm) -> anyhow::Result<()> {
+/// Compiles a script, optimizing it at `opt_level`.
+fn compile_optimized(source: &str, opt_level: u8) -> anyhow::Result<Chunk> {
+    let chunk = Vm::compile(source, &mut io::stderr()).unwrap_or_else(|error| exit_with(error));
+    if opt_level == 0 {
+        return Ok(chunk);
+    }
+
+    Ok(optimizer::optimize(&chunk)?)
+}
+
+fn compile_file(path: &str, output: &str, opt_level: u8) -> anyhow::Result<()> {
     let source = std::fs::read_to_string(path)?;
+    let chunk = compile_optimized(&source, opt_level)?;
 
-    vm.interpret(&source)?;
+    std::fs::write(output, chunk.serialize())?;
 
     Ok(())
 }
 
+fn load_compiled(path: &str) -> anyhow::Result<Chunk> {
+    let bytes = std::fs::read(path)?;
+
+    Ok(Chunk::deserialize(&bytes).unwrap_or_else(|error| {
+        eprintln!("{path}: {error}");
+        process::exit(65);
+    }))
+}
+
+fn run_compiled(path: &str, vm: &mut Vm) -> anyhow::Result<()> {
+    let chunk = load_compiled(path)?;
+
+    let result = vm.execute(chunk);
+    print_stats(vm);
+    result.unwrap_or_else(|error| exit_with(error));
+
+    Ok(())
+}
+
+/// Prints the disassembly of a script, or of an already compiled `.loxc`
+/// file, without running it. With `--dump-optimized`, it's the code that
+/// would run after optimizing.
+fn disassemble_file(path: &str, optimized: bool) -> anyhow::Result<()> {
+    let opt_level = if optimized { MAX_OPT_LEVEL } else { 0 };
+    let chunk = if path.ends_with(".loxc") {
+        let chunk = load_compiled(path)?;
+        if optimized {
+            optimizer::optimize(&chunk)?
+        } else {
+            chunk
+        }
+    } else {
+        let source = std::fs::read_to_string(path)?;
+        compile_optimized(&source, opt_level)?
+    };
+
+    chunk.disassemble_all("script")?;
+
+    Ok(())
+}
+
+fn usage() -> ! {
+    println!("Usage: lox [--coverage[=file]] [--opt-level=0|1] [--vm-stats] [script]");
+    println!("       lox [--opt-level=0|1] compile <script> [-o <output>]");
+    println!("       lox [--opt-level=0|1] run <script.loxc>");
+    println!("       lox [--dump-optimized] disassemble <script>");
+    process::exit(1);
+}
+
 fn main() -> anyhow::Result<()> {
-    let args = env::args().skip(1).collect::<Vec<_>>();
+    let mut coverage = None;
+    let mut vm_stats = false;
+    let mut opt_level = 0;
+    let mut dump_optimized = false;
+    let args = env::args()
+        .skip(1)
+        .filter(|arg| match arg.as_str() {
+            "--coverage" => {
+                coverage = Some("lcov.info".to_string());
+                false
+            }
+            "--vm-stats" => {
+                vm_stats = true;
+                false
+            }
+            "--dump-optimized" => {
+                dump_optimized = true;
+                false
+            }
+            flag if flag.starts_with("--opt-level=") => {
+                match flag["--opt-level=".len()..].parse() {
+                    Ok(level) if level <= MAX_OPT_LEVEL => opt_level = level,
+                    _ => usage(),
+                }
+                false
+            }
+            flag if flag.starts_with("--coverage=") => {
+                coverage = Some(flag["--coverage=".len()..].to_string());
+                false
+            }
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
     let mut vm = Vm::new();
+    if vm_stats {
+        vm.enable_stats();
+    }
+    vm.set_opt_level(opt_level);
 
-    match args.len() {
-        0 => repl(&mut vm),
-        1 => run_file(&args[0], &mut vm),
-        _ => {
-            println!("Usage: lox [script]");
-            process::exit(1);
+    match args[..] {
+        [] => repl(&mut vm, None),
+        ["compile", path] => {
+            let output = Path::new(path).with_extension("loxc");
+            compile_file(path, &output.to_string_lossy(), opt_level)
         }
+        ["compile", path, "-o", output] => compile_file(path, output, opt_level),
+        ["run", path] => run_compiled(path, &mut vm),
+        ["disassemble", path] => disassemble_file(path, dump_optimized),
+        [path] => run_file(path, &mut vm, coverage.as_deref()),
+        _ => usage(),
     }
 }
@@ -0,0 +1,279 @@
+//! Simplifies compiled chunks without changing what they do.
+//!
+//! The pass works on a chunk's instructions rather than its bytes, so that
+//! removing one doesn't throw off the jumps over it. It repeats until nothing
+//! changes:
+//!
+//! - jumps to jumps go straight to where the last one would,
+//! - conditional jumps on a value pushed just before are made unconditional
+//!   or dropped,
+//! - values that are pushed only to be popped again aren't pushed,
+//! - jumps to the next instruction are dropped, and
+//! - code that can't be reached is dropped.
+//!
+//! Every instruction that's kept keeps its line, so errors are reported
+//! where they were before.
+
+use crate::chunk::{Chunk, Error, OpCode};
+use std::collections::HashSet;
+
+/// The highest level `--opt-level` accepts. Level 0 leaves chunks alone.
+pub const MAX_OPT_LEVEL: u8 = 1;
+
+struct Instruction {
+    op: OpCode,
+    /// The one-byte operand of an instruction that has one: the constant a
+    /// `Constant` loads, or the global or stack slot of a variable.
+    operand: u8,
+    /// The index of the instruction a jump goes to.
+    target: usize,
+    line: usize,
+}
+
+/// Optimizes a copy of `chunk`, which must be valid.
+pub fn optimize(chunk: &Chunk) -> Result<Chunk, Error> {
+    chunk.validate()?;
+
+    let mut instructions = decode(chunk)?;
+    while simplify(&mut instructions, chunk) {}
+
+    let optimized = encode(&instructions, chunk);
+    optimized.validate()?;
+
+    Ok(optimized)
+}
+
+fn is_jump(op: OpCode) -> bool {
+    matches!(op, OpCode::Jump | OpCode::JumpIfFalse)
+}
+
+fn decode(chunk: &Chunk) -> Result<Vec<Instruction>, Error> {
+    let code = chunk.code();
+    let mut offsets = vec![];
+    let mut instructions = vec![];
+
+    let mut offset = 0;
+    while offset < code.len() {
+        let op = OpCode::try_from(code[offset])?;
+        let mut instruction = Instruction {
+            op,
+            operand: 0,
+            target: 0,
+            line: chunk.lines()[offset],
+        };
+        match op {
+            op if op.size() == 2 => instruction.operand = code[offset + 1],
+            OpCode::Jump | OpCode::JumpIfFalse => {
+                let jump = u16::from_be_bytes([code[offset + 1], code[offset + 2]]);
+                instruction.target = offset + op.size() + jump as usize;
+            }
+            _ => {}
+        }
+
+        offsets.push(offset);
+        instructions.push(instruction);
+        offset += op.size();
+    }
+
+    for (index, instruction) in instructions.iter_mut().enumerate() {
+        if is_jump(instruction.op) {
+            instruction.target = offsets
+                .binary_search(&instruction.target)
+                .map_err(|_| Error::InvalidOperand(offsets[index]))?;
+        }
+    }
+
+    Ok(instructions)
+}
+
+/// Whether the value `instruction` pushes is falsey, if it only pushes a
+/// value.
+fn pushes_falsey(instruction: &Instruction, chunk: &Chunk) -> Option<bool> {
+    match instruction.op {
+        OpCode::Constant => Some(chunk.constants()[instruction.operand as usize].is_falsey()),
+        OpCode::False | OpCode::Nil => Some(true),
+        OpCode::True => Some(false),
+        _ => None,
+    }
+}
+
+/// Makes one round of simplifications, returning whether any applied.
+fn simplify(instructions: &mut Vec<Instruction>, chunk: &Chunk) -> bool {
+    let len = instructions.len();
+    let mut changed = false;
+
+    // Jumps only go forwards, so following them always ends.
+    for index in 0..len {
+        let op = instructions[index].op;
+        if !is_jump(op) {
+            continue;
+        }
+
+        let mut target = instructions[index].target;
+        while target < len {
+            match (op, instructions[target].op) {
+                // A conditional jump's condition is still on the stack when
+                // it lands, so another conditional jump on it goes the same
+                // way.
+                (_, OpCode::Jump) | (OpCode::JumpIfFalse, OpCode::JumpIfFalse) => {
+                    target = instructions[target].target;
+                }
+                _ => break,
+            }
+        }
+        if target != instructions[index].target {
+            instructions[index].target = target;
+            changed = true;
+        }
+    }
+
+    let targets = instructions
+        .iter()
+        .filter(|instruction| is_jump(instruction.op))
+        .map(|instruction| instruction.target)
+        .collect::<HashSet<_>>();
+    let mut removed = vec![false; len];
+
+    let mut index = 0;
+    while index < len {
+        let next = index + 1;
+        if is_jump(instructions[index].op) && instructions[index].target == next {
+            removed[index] = true;
+            index += 1;
+            continue;
+        }
+
+        let Some(falsey) = pushes_falsey(&instructions[index], chunk) else {
+            index += 1;
+            continue;
+        };
+        if next == len || targets.contains(&next) {
+            index += 1;
+            continue;
+        }
+
+        match instructions[next].op {
+            OpCode::Pop if !targets.contains(&index) => {
+                removed[index] = true;
+                removed[next] = true;
+            }
+            OpCode::JumpIfFalse if falsey => instructions[next].op = OpCode::Jump,
+            OpCode::JumpIfFalse => removed[next] = true,
+            OpCode::Jump
+                if !targets.contains(&index)
+                    && lands_on_pop(instructions, instructions[next].target) =>
+            {
+                // The value would be popped as soon as the jump lands, so
+                // neither needs to happen.
+                removed[index] = true;
+                instructions[next].target += 1;
+            }
+            _ => {
+                index += 1;
+                continue;
+            }
+        }
+
+        changed = true;
+        index += 2;
+    }
+
+    for (index, reachable) in reachable(instructions, &removed).into_iter().enumerate() {
+        removed[index] |= !reachable;
+    }
+
+    if removed.iter().any(|removed| *removed) {
+        changed = true;
+        compact(instructions, &removed);
+    }
+
+    changed
+}
+
+/// Whether the instruction at `target` is a `Pop` with more code after it.
+fn lands_on_pop(instructions: &[Instruction], target: usize) -> bool {
+    target + 1 < instructions.len() && matches!(instructions[target].op, OpCode::Pop)
+}
+
+/// Which instructions can run, treating removed ones as if they did
+/// nothing.
+fn reachable(instructions: &[Instruction], removed: &[bool]) -> Vec<bool> {
+    let len = instructions.len();
+    let mut reachable = vec![false; len];
+    let mut pending = vec![0];
+
+    while let Some(index) = pending.pop() {
+        if index >= len || reachable[index] {
+            continue;
+        }
+        reachable[index] = true;
+
+        let instruction = &instructions[index];
+        if removed[index] {
+            pending.push(index + 1);
+            continue;
+        }
+        match instruction.op {
+            OpCode::Return => {}
+            OpCode::Jump => pending.push(instruction.target),
+            OpCode::JumpIfFalse => pending.extend([instruction.target, index + 1]),
+            _ => pending.push(index + 1),
+        }
+    }
+
+    reachable
+}
+
+/// Drops removed instructions. Jumps to them go to the next instruction
+/// that's kept instead.
+fn compact(instructions: &mut Vec<Instruction>, removed: &[bool]) {
+    let mut kept_before = Vec::with_capacity(removed.len() + 1);
+    let mut kept = 0;
+    for removed in removed {
+        kept_before.push(kept);
+        if !removed {
+            kept += 1;
+        }
+    }
+    kept_before.push(kept);
+
+    let mut index = 0;
+    instructions.retain(|_| {
+        index += 1;
+        !removed[index - 1]
+    });
+    for instruction in instructions.iter_mut() {
+        if is_jump(instruction.op) {
+            instruction.target = kept_before[instruction.target];
+        }
+    }
+}
+
+fn encode(instructions: &[Instruction], chunk: &Chunk) -> Chunk {
+    let mut offsets = vec![0];
+    for instruction in instructions {
+        offsets.push(offsets[offsets.len() - 1] + instruction.op.size());
+    }
+
+    let mut optimized = Chunk::with_globals(chunk.globals().to_vec());
+    for constant in chunk.constants() {
+        optimized.add_constant(*constant);
+    }
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        let line = instruction.line;
+        optimized.write(instruction.op, line);
+        match instruction.op {
+            op if op.size() == 2 => optimized.write(instruction.operand, line),
+            OpCode::Jump | OpCode::JumpIfFalse => {
+                let jump = (offsets[instruction.target] - offsets[index + 1]) as u16;
+                let [high, low] = jump.to_be_bytes();
+                optimized.write(high, line);
+                optimized.write(low, line);
+            }
+            _ => {}
+        }
+    }
+
+    optimized
+}
@@ -0,0 +1,179 @@
+//! The REPL both interpreters share, with its `:` commands.
+
+use crate::vm::Vm;
+use std::{
+    fs,
+    io::{self, Write},
+};
+
+/// What the REPL says to `:help`. The `:ast` line is left out when the
+/// session can't show syntax trees.
+const HELP: &str = "\
+:help         Show this help.
+:env          List the globals and their values.
+:ast          Show the syntax tree of the last input.
+:dis          Show the bytecode of the last input.
+:load <file>  Run a script in this session.
+:reset        Forget every global.
+:trace        Turn tracing on or off.";
+
+/// A line of REPL input starting with `:`, which is a command rather than
+/// Lox.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command<'l> {
+    Help,
+    Env,
+    Ast,
+    Dis,
+    Load(&'l str),
+    Reset,
+    Trace,
+}
+
+impl<'l> Command<'l> {
+    /// Parses `line` if it's a command, failing with what to report if it
+    /// isn't one the REPL knows.
+    pub fn parse(line: &'l str) -> Option<Result<Self, String>> {
+        let line = line.trim();
+        let command = line.strip_prefix(':')?;
+        let (name, argument) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(name, argument)| (name, argument.trim()));
+
+        let command = match (name, argument) {
+            ("help", "") => Self::Help,
+            ("env", "") => Self::Env,
+            ("ast", "") => Self::Ast,
+            ("dis", "") => Self::Dis,
+            ("load", path) if !path.is_empty() => Self::Load(path),
+            ("reset", "") => Self::Reset,
+            ("trace", "") => Self::Trace,
+            _ => return Some(Err(format!("Unknown command '{line}'. Try ':help'."))),
+        };
+
+        Some(Ok(command))
+    }
+}
+
+/// Whichever interpreter the REPL is running input on.
+pub trait Session {
+    /// Runs `source`, having reported any errors.
+    fn run(&mut self, source: &str);
+
+    /// Each global's name, with its value if it has one.
+    fn globals(&self) -> Vec<(String, Option<String>)>;
+
+    /// Forgets every global.
+    fn reset(&mut self);
+
+    fn set_trace(&mut self, trace: bool);
+}
+
+impl Session for Vm {
+    fn run(&mut self, source: &str) {
+        let _ = self.interpret(source);
+        if let Some(stats) = self.stats() {
+            eprint!("{stats}");
+        }
+    }
+
+    fn globals(&self) -> Vec<(String, Option<String>)> {
+        let mut globals = Vm::globals(self)
+            .filter_map(|(name, value)| Some((name.to_string(), Some(value?.to_string()))))
+            .collect::<Vec<_>>();
+        globals.sort();
+
+        globals
+    }
+
+    fn reset(&mut self) {
+        self.reset_globals();
+    }
+
+    fn set_trace(&mut self, trace: bool) {
+        Vm::set_trace(self, trace);
+    }
+}
+
+/// Reads lines of Lox, or commands, and runs them in `session` until stdin
+/// ends. Errors have already been reported by the time a line has run.
+///
+/// `:ast` prints the last input with `print_ast`, if there is one; the VM
+/// compiles straight to bytecode, so it has no syntax tree of its own.
+pub fn repl(session: &mut dyn Session, print_ast: Option<fn(&str)>) -> anyhow::Result<()> {
+    // What `:ast` and `:dis` show.
+    let mut last = String::new();
+    let mut trace = false;
+
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        if line.is_empty() {
+            break;
+        }
+
+        let command = match Command::parse(&line) {
+            None => {
+                session.run(&line);
+                last = line;
+                continue;
+            }
+            Some(Err(message)) => {
+                eprintln!("{message}");
+                continue;
+            }
+            Some(Ok(command)) => command,
+        };
+        match command {
+            Command::Help => {
+                for help in HELP.lines() {
+                    if print_ast.is_some() || !help.starts_with(":ast") {
+                        println!("{help}");
+                    }
+                }
+            }
+            Command::Env => {
+                for (name, value) in session.globals() {
+                    match value {
+                        Some(value) => println!("{name} = {value}"),
+                        None => println!("{name}"),
+                    }
+                }
+            }
+            Command::Ast => match print_ast {
+                Some(print_ast) => print_ast(&last),
+                None => eprintln!(
+                    "The VM compiles straight to bytecode, without a syntax tree. Try ':dis'."
+                ),
+            },
+            Command::Dis => {
+                // Compile errors have already been reported.
+                if let Ok(chunk) = Vm::compile(&last, &mut io::stderr()) {
+                    chunk.disassemble_all("input")?;
+                }
+            }
+            Command::Load(path) => match fs::read_to_string(path) {
+                Ok(source) => {
+                    session.run(&source);
+                    last = source;
+                }
+                Err(error) => eprintln!("Can't read '{path}': {error}."),
+            },
+            Command::Reset => {
+                session.reset();
+                session.set_trace(trace);
+                last.clear();
+            }
+            Command::Trace => {
+                trace = !trace;
+                session.set_trace(trace);
+                println!("Tracing is {}.", if trace { "on" } else { "off" });
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -8,10 +8,13 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    Colon,
     Comma,
     Dot,
     Minus,
+    Percent,
     Plus,
+    Question,
     Semicolon,
     Slash,
     Star,
@@ -25,6 +28,12 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    MinusEqual,
+    MinusMinus,
+    PlusEqual,
+    PlusPlus,
+    SlashEqual,
+    StarEqual,
 
     // Literals.
     Identifier,
@@ -33,7 +42,9 @@ pub enum TokenType {
 
     // Keywords.
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -62,7 +73,9 @@ impl fmt::Display for TokenType {
 fn identifier_type(lexeme: &str) -> TokenType {
     match lexeme {
         "and" => TokenType::And,
+        "break" => TokenType::Break,
         "class" => TokenType::Class,
+        "continue" => TokenType::Continue,
         "false" => TokenType::False,
         "for" => TokenType::For,
         "fun" => TokenType::Fun,
@@ -102,6 +115,18 @@ impl Token {
     }
 }
 
+/// The value of a number literal the scanner has accepted.
+pub fn number_value(lexeme: &str) -> f64 {
+    let digits = lexeme.replace('_', "");
+    match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        Some(hex) => hex
+            .chars()
+            .filter_map(|c| c.to_digit(16))
+            .fold(0.0, |n, digit| n * 16.0 + f64::from(digit)),
+        None => digits.parse().expect("must be a number"),
+    }
+}
+
 pub struct Scanner<'s> {
     source: PeekNth<Chars<'s>>,
     line: usize,
@@ -123,34 +148,58 @@ impl<'s> Scanner<'s> {
         self.source.next().expect("we shouldn't be at the end")
     }
 
-    fn skip_whitespace(&mut self) {
-        loop {
-            if let Some(c) = self.source.peek() {
-                match c {
-                    ' ' | '\r' | '\t' => {
-                        self.advance();
-                        continue;
-                    }
-                    '\n' => {
-                        self.line += 1;
-                        self.advance();
-                        continue;
-                    }
-                    '/' => {
-                        if matches!(self.source.peek_nth(2), Some('/')) {
-                            while !matches!(self.source.peek(), Some('\n')) && !self.is_at_end() {
-                                self.advance();
-                            }
-                        } else {
-                            break;
+    /// Skips whitespace and comments, or returns an error token if a block
+    /// comment is never closed.
+    fn skip_whitespace(&mut self) -> Result<(), Token> {
+        while let Some(&c) = self.source.peek() {
+            match c {
+                ' ' | '\r' | '\t' => {
+                    self.advance();
+                }
+                '\n' => {
+                    self.line += 1;
+                    self.advance();
+                }
+                '/' => match self.source.peek_nth(1) {
+                    Some('/') => {
+                        while !matches!(self.source.peek(), Some('\n')) && !self.is_at_end() {
+                            self.advance();
                         }
                     }
+                    Some('*') => self.skip_block_comment()?,
                     _ => break,
-                }
+                },
+                _ => break,
             }
+        }
+
+        Ok(())
+    }
 
-            break;
+    /// Skips a `/* ... */` comment, which may contain others.
+    fn skip_block_comment(&mut self) -> Result<(), Token> {
+        // The opening /*.
+        self.advance();
+        self.advance();
+
+        let mut depth = 1;
+        while depth > 0 {
+            match self.source.next() {
+                None => return Err(Token::error("Unterminated block comment.", self.line)),
+                Some('\n') => self.line += 1,
+                Some('/') if self.next_is_match('*') => {
+                    self.advance();
+                    depth += 1;
+                }
+                Some('*') if self.next_is_match('/') => {
+                    self.advance();
+                    depth -= 1;
+                }
+                _ => {}
+            }
         }
+
+        Ok(())
     }
 
     fn next_is_match(&mut self, expected: char) -> bool {
@@ -179,14 +228,23 @@ impl<'s> Scanner<'s> {
 
     fn scan_string(&mut self) -> Token {
         let mut lexeme = String::new();
+        let mut error = None;
         while !self.next_is_match('"') && !self.is_at_end() {
             let c = self.advance();
 
-            if c == '\n' {
-                self.line += 1;
+            match c {
+                '\\' => match self.scan_escape() {
+                    Ok(c) => lexeme.push(c),
+                    Err(message) => {
+                        error.get_or_insert(message);
+                    }
+                },
+                '\n' => {
+                    self.line += 1;
+                    lexeme.push(c);
+                }
+                c => lexeme.push(c),
             }
-
-            lexeme.push(c);
         }
 
         if self.is_at_end() {
@@ -195,27 +253,109 @@ impl<'s> Scanner<'s> {
             // The closing quote.
             self.advance();
 
-            Token::new(TokenType::String, lexeme, self.line)
+            match error {
+                Some(message) => Token::error(message, self.line),
+                None => Token::new(TokenType::String, lexeme, self.line),
+            }
+        }
+    }
+
+    /// Decodes the escape sequence after a backslash.
+    fn scan_escape(&mut self) -> Result<char, &'static str> {
+        match self.source.next() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('u') => self.scan_unicode_escape(),
+            Some('\n') => {
+                self.line += 1;
+                Err("Invalid escape sequence.")
+            }
+            _ => Err("Invalid escape sequence."),
+        }
+    }
+
+    /// Decodes the `{XXXX}` after `\u`: one to six hex digits naming a
+    /// Unicode scalar value.
+    fn scan_unicode_escape(&mut self) -> Result<char, &'static str> {
+        let mut digits = String::new();
+        if self.next_is_match('{') {
+            self.advance();
+            while matches!(self.source.peek(), Some(c) if c.is_ascii_hexdigit()) {
+                digits.push(self.advance());
+            }
+        }
+
+        if !(1..=6).contains(&digits.len()) || !self.next_is_match('}') {
+            return Err("Invalid unicode escape sequence.");
         }
+        // The closing brace.
+        self.advance();
+
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or("Invalid unicode escape sequence.")
+    }
+
+    /// Consumes a run of digits in `radix`, which may be separated by single
+    /// underscores, onto the end of `lexeme`. Returns whether the run was
+    /// well formed.
+    fn scan_digits(&mut self, lexeme: &mut String, radix: u32) -> bool {
+        let mut previous = lexeme.chars().last().filter(|c| c.is_digit(radix));
+        let mut valid = true;
+        while let Some(&c) = self.source.peek() {
+            if c == '_' {
+                valid &= previous.is_some_and(|previous| previous != '_');
+            } else if !c.is_digit(radix) {
+                break;
+            }
+            previous = Some(c);
+            lexeme.push(self.advance());
+        }
+
+        valid && previous.is_some_and(|previous| previous != '_')
     }
 
     fn scan_number(&mut self, mut lexeme: String) -> Token {
-        while matches!(self.source.peek(), Some(c) if c.is_digit(10)) {
+        if lexeme == "0" && matches!(self.source.peek(), Some('x' | 'X')) {
+            lexeme.push(self.advance());
+            return if self.scan_digits(&mut lexeme, 16) {
+                Token::new(TokenType::Number, lexeme, self.line)
+            } else {
+                Token::error("Expect hex digits after '0x'.", self.line)
+            };
+        }
+
+        let mut valid = self.scan_digits(&mut lexeme, 10);
+
+        if self.next_is_match('.')
+            && matches!(self.source.peek_nth(1), Some(c) if c.is_ascii_digit())
+        {
             lexeme.push(self.advance());
+            valid &= self.scan_digits(&mut lexeme, 10);
         }
 
-        if self.next_is_match('.') && matches!(self.source.peek_nth(2), Some(c) if c.is_digit(10)) {
+        if matches!(self.source.peek(), Some('e' | 'E')) {
             lexeme.push(self.advance());
-            while matches!(self.source.peek(), Some(c) if c.is_digit(10)) {
+            if matches!(self.source.peek(), Some('+' | '-')) {
                 lexeme.push(self.advance());
             }
+            if !self.scan_digits(&mut lexeme, 10) {
+                return Token::error("Expect digits in exponent.", self.line);
+            }
         }
 
-        Token::new(TokenType::Number, lexeme, self.line)
+        if valid {
+            Token::new(TokenType::Number, lexeme, self.line)
+        } else {
+            Token::error("Underscores in numbers must separate digits.", self.line)
+        }
     }
 
     fn scan_identifier(&mut self, mut lexeme: String) -> Token {
-        while matches!(self.source.peek(), Some(c) if c.is_alphabetic() || c.is_digit(10) || *c == '_')
+        while matches!(self.source.peek(), Some(c) if c.is_alphabetic() || c.is_ascii_digit() || *c == '_')
         {
             lexeme.push(self.advance());
         }
@@ -224,7 +364,9 @@ impl<'s> Scanner<'s> {
     }
 
     pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace();
+        if let Err(error) = self.skip_whitespace() {
+            return error;
+        }
 
         if self.is_at_end() {
             return Token::new(TokenType::Eof, String::default(), self.line);
@@ -235,7 +377,7 @@ impl<'s> Scanner<'s> {
         if c.is_alphabetic() || c == '_' {
             return self.scan_identifier(c.to_string());
         }
-        if c.is_digit(10) {
+        if c.is_ascii_digit() {
             return self.scan_number(c.to_string());
         }
 
@@ -245,12 +387,35 @@ impl<'s> Scanner<'s> {
             '{' => Token::new(TokenType::LeftBrace, c.to_string(), self.line),
             '}' => Token::new(TokenType::RightBrace, c.to_string(), self.line),
             ';' => Token::new(TokenType::Semicolon, c.to_string(), self.line),
+            ':' => Token::new(TokenType::Colon, c.to_string(), self.line),
             ',' => Token::new(TokenType::Comma, c.to_string(), self.line),
             '.' => Token::new(TokenType::Dot, c.to_string(), self.line),
-            '-' => Token::new(TokenType::Minus, c.to_string(), self.line),
-            '+' => Token::new(TokenType::Plus, c.to_string(), self.line),
-            '/' => Token::new(TokenType::Slash, c.to_string(), self.line),
-            '*' => Token::new(TokenType::Star, c.to_string(), self.line),
+            '-' => {
+                let lexeme = c.to_string();
+                if self.next_is_match('=') {
+                    self.scan_two_char_token(lexeme, '=', TokenType::MinusEqual, TokenType::Minus)
+                } else {
+                    self.scan_two_char_token(lexeme, '-', TokenType::MinusMinus, TokenType::Minus)
+                }
+            }
+            '+' => {
+                let lexeme = c.to_string();
+                if self.next_is_match('=') {
+                    self.scan_two_char_token(lexeme, '=', TokenType::PlusEqual, TokenType::Plus)
+                } else {
+                    self.scan_two_char_token(lexeme, '+', TokenType::PlusPlus, TokenType::Plus)
+                }
+            }
+            '%' => Token::new(TokenType::Percent, c.to_string(), self.line),
+            '?' => Token::new(TokenType::Question, c.to_string(), self.line),
+            '/' => {
+                let lexeme = c.to_string();
+                self.scan_two_char_token(lexeme, '=', TokenType::SlashEqual, TokenType::Slash)
+            }
+            '*' => {
+                let lexeme = c.to_string();
+                self.scan_two_char_token(lexeme, '=', TokenType::StarEqual, TokenType::Star)
+            }
             '!' => {
                 let lexeme = c.to_string();
                 self.scan_two_char_token(lexeme, '=', TokenType::BangEqual, TokenType::Bang)
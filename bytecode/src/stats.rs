@@ -0,0 +1,95 @@
+//! Counts of what the VM did while running a chunk, to guide optimizations
+//! to the compiler and instruction set.
+
+use crate::chunk::{Chunk, OpCode};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    /// How many times each opcode ran, indexed by its byte.
+    opcodes: Vec<u64>,
+    /// The most values the stack held at once.
+    max_stack: usize,
+    /// Each constant in the chunk's pool, with how many times it was loaded.
+    constants: Vec<(String, u64)>,
+}
+
+impl Stats {
+    pub(crate) fn new(chunk: &Chunk) -> Self {
+        Self {
+            opcodes: vec![0; u8::MAX as usize + 1],
+            max_stack: 0,
+            constants: chunk
+                .constants()
+                .iter()
+                .map(|constant| (constant.to_string(), 0))
+                .collect(),
+        }
+    }
+
+    /// Records the instruction at `offset` about to run with `stack` values
+    /// on the stack.
+    pub(crate) fn record(&mut self, chunk: &Chunk, offset: usize, stack: usize) {
+        let Some(&instruction) = chunk.code().get(offset) else {
+            return;
+        };
+        self.opcodes[instruction as usize] += 1;
+        self.max_stack = self.max_stack.max(stack);
+        if instruction == u8::from(OpCode::Constant) {
+            let index = chunk.code().get(offset + 1).map(|index| *index as usize);
+            if let Some((_, loads)) = index.and_then(|index| self.constants.get_mut(index)) {
+                *loads += 1;
+            }
+        }
+    }
+
+    /// How many times `op` ran.
+    pub fn executed(&self, op: OpCode) -> u64 {
+        self.opcodes[u8::from(op) as usize]
+    }
+
+    pub fn max_stack(&self) -> usize {
+        self.max_stack
+    }
+
+    /// How many times the constant at `index` in the pool was loaded.
+    pub fn constant_loads(&self, index: usize) -> u64 {
+        self.constants.get(index).map_or(0, |(_, loads)| *loads)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "-- opcodes --")?;
+        let mut opcodes = self
+            .opcodes
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .filter_map(|(byte, count)| Some((OpCode::try_from(byte as u8).ok()?, *count)))
+            .collect::<Vec<_>>();
+        opcodes.sort_by(|(_, a), (_, b)| b.cmp(a));
+        for (op, count) in opcodes {
+            writeln!(f, "{:<16} {count:>10}", op.to_string())?;
+        }
+
+        writeln!(f, "-- stack --")?;
+        writeln!(f, "{:<16} {:>10}", "high-water mark", self.max_stack)?;
+
+        let loaded = self
+            .constants
+            .iter()
+            .filter(|(_, loads)| *loads > 0)
+            .count();
+        writeln!(
+            f,
+            "-- constants ({loaded} of {} loaded) --",
+            self.constants.len()
+        )?;
+        for (index, (constant, loads)) in self.constants.iter().enumerate() {
+            writeln!(f, "{index:4} {constant:<11} {loads:>10}")?;
+        }
+
+        Ok(())
+    }
+}
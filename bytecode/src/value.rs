@@ -1,83 +1,139 @@
-use std::{fmt, ops};
+//! Lox values, in one of two representations.
+//!
+//! By default a value is a tagged enum. With the `nan_boxing` feature it is
+//! packed into a single `u64` instead: numbers are stored as their own bits,
+//! and every other value hides in the unused payload of a quiet NaN. The VM
+//! only uses the constructors and accessors below, so it works with either.
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
-pub enum Value {
+use std::fmt;
+
+#[cfg(not(feature = "nan_boxing"))]
+#[derive(Clone, Copy)]
+enum Repr {
     Boolean(bool),
-    Number(f64),
     Nil,
+    Number(f64),
 }
 
+/// All the bits of a quiet NaN, plus the one used by Intel's "QNaN Floating
+/// Point Indefinite" value, so that no NaN produced by arithmetic is
+/// mistaken for a boxed value.
+#[cfg(feature = "nan_boxing")]
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+
+#[cfg(feature = "nan_boxing")]
+const TAG_NIL: u64 = 1;
+#[cfg(feature = "nan_boxing")]
+const TAG_FALSE: u64 = 2;
+#[cfg(feature = "nan_boxing")]
+const TAG_TRUE: u64 = 3;
+
+#[cfg(feature = "nan_boxing")]
+type Repr = u64;
+
+#[derive(Clone, Copy)]
+pub struct Value(Repr);
+
+#[cfg(not(feature = "nan_boxing"))]
 impl Value {
-    pub fn is_falsey(&self) -> bool {
-        match self {
-            Self::Nil => true,
-            Self::Boolean(b) => !b,
-            _ => false,
-        }
+    pub fn boolean(b: bool) -> Self {
+        Self(Repr::Boolean(b))
     }
-}
 
-impl fmt::Display for Value {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Boolean(b) => write!(f, "{b}"),
-            Self::Number(n) => write!(f, "{n}"),
-            Self::Nil => write!(f, "nil"),
-        }
+    pub fn nil() -> Self {
+        Self(Repr::Nil)
     }
-}
 
-impl ops::Add for Value {
-    type Output = Self;
+    pub fn number(n: f64) -> Self {
+        Self(Repr::Number(n))
+    }
 
-    fn add(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a + b),
-            (a, b) => panic!("can't add values of type {a:?} and {b:?}"),
+    pub fn as_boolean(&self) -> Option<bool> {
+        match self.0 {
+            Repr::Boolean(b) => Some(b),
+            _ => None,
         }
     }
-}
 
-impl ops::Sub for Value {
-    type Output = Self;
+    pub fn is_nil(&self) -> bool {
+        matches!(self.0, Repr::Nil)
+    }
 
-    fn sub(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a - b),
-            (a, b) => panic!("can't sub values of type {a:?} and {b:?}"),
+    pub fn as_number(&self) -> Option<f64> {
+        match self.0 {
+            Repr::Number(n) => Some(n),
+            _ => None,
         }
     }
 }
 
-impl ops::Mul for Value {
-    type Output = Self;
+#[cfg(feature = "nan_boxing")]
+impl Value {
+    pub fn boolean(b: bool) -> Self {
+        Self(QNAN | if b { TAG_TRUE } else { TAG_FALSE })
+    }
+
+    pub fn nil() -> Self {
+        Self(QNAN | TAG_NIL)
+    }
 
-    fn mul(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a * b),
-            (a, b) => panic!("can't mul values of type {a:?} and {b:?}"),
+    pub fn number(n: f64) -> Self {
+        Self(n.to_bits())
+    }
+
+    pub fn as_boolean(&self) -> Option<bool> {
+        match self.0 {
+            bits if bits == QNAN | TAG_TRUE => Some(true),
+            bits if bits == QNAN | TAG_FALSE => Some(false),
+            _ => None,
         }
     }
+
+    pub fn is_nil(&self) -> bool {
+        self.0 == QNAN | TAG_NIL
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        (self.0 & QNAN != QNAN).then(|| f64::from_bits(self.0))
+    }
 }
 
-impl ops::Div for Value {
-    type Output = Self;
+impl Value {
+    pub fn is_number(&self) -> bool {
+        self.as_number().is_some()
+    }
+
+    pub fn is_falsey(&self) -> bool {
+        self.is_nil() || self.as_boolean() == Some(false)
+    }
+}
 
-    fn div(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a / b),
-            (a, b) => panic!("can't div values of type {a:?} and {b:?}"),
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.as_number(), other.as_number()) {
+            (Some(a), Some(b)) => a == b,
+            (None, None) => {
+                self.as_boolean() == other.as_boolean() && self.is_nil() == other.is_nil()
+            }
+            _ => false,
         }
     }
 }
 
-impl ops::Neg for Value {
-    type Output = Self;
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Value({self})")
+    }
+}
 
-    fn neg(self) -> Self::Output {
-        match self {
-            Self::Number(n) => Self::Number(-n),
-            a => panic!("can't neg values of type {a:?}"),
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(b) = self.as_boolean() {
+            write!(f, "{b}")
+        } else if let Some(n) = self.as_number() {
+            write!(f, "{n}")
+        } else {
+            write!(f, "nil")
         }
     }
 }
@@ -1,8 +1,19 @@
 use crate::{
     chunk::{Chunk, OpCode},
     compiler::compile,
+    optimizer,
+    stats::Stats,
     value::Value,
 };
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -10,14 +21,63 @@ pub enum Error {
     Compile,
     #[error("Runtime error.")]
     Runtime,
+    /// The chunk ran past its instruction budget or timeout.
+    #[error("Execution budget exceeded.")]
+    Budget,
+    /// The host set the VM's cancellation flag.
+    #[error("Interrupted.")]
+    Interrupted,
 }
 
+/// How many instructions run between checks of the clock.
+const CLOCK_INTERVAL: u64 = 1024;
+
 type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Default)]
 pub struct Vm {
     ip: usize,
     stack: Vec<Value>,
+    /// The value of each global, at the index chunks refer to it by once
+    /// they're linked, or `None` until it's defined.
+    globals: Vec<Option<Value>>,
+    /// The name of each global, which chunks are linked against.
+    global_names: Vec<String>,
+    instructions: u64,
+    instruction_budget: Option<u64>,
+    timeout: Option<Duration>,
+    cancel: Option<Arc<AtomicBool>>,
+    /// How many times execution entered each line, if it's being recorded.
+    coverage: Option<BTreeMap<usize, u64>>,
+    /// What the last chunk run did, if it's being recorded.
+    stats: Option<Stats>,
+    /// How much [`optimizer::optimize`] should simplify chunks before
+    /// they run.
+    opt_level: u8,
+    /// Whether to print the stack and each instruction as it runs.
+    trace: bool,
+    out: Box<dyn Write + Send>,
+    err: Box<dyn Write + Send>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self {
+            ip: 0,
+            stack: vec![],
+            globals: vec![],
+            global_names: vec![],
+            instructions: 0,
+            instruction_budget: None,
+            timeout: None,
+            cancel: None,
+            coverage: None,
+            stats: None,
+            opt_level: 0,
+            trace: cfg!(feature = "trace_execution"),
+            out: Box::new(io::stdout()),
+            err: Box::new(io::stderr()),
+        }
+    }
 }
 
 impl Vm {
@@ -25,6 +85,105 @@ impl Vm {
         Self::default()
     }
 
+    /// Sends printed values to `out` and errors to `err`, instead of stdout
+    /// and stderr. They must be `Send`, like the rest of the VM, so that it
+    /// can be moved to another thread.
+    pub fn with_writers(
+        mut self,
+        out: impl Write + Send + 'static,
+        err: impl Write + Send + 'static,
+    ) -> Self {
+        self.out = Box::new(out);
+        self.err = Box::new(err);
+
+        self
+    }
+
+    /// Sends printed values to `out` instead of stdout, leaving errors where
+    /// they were going.
+    pub fn with_output(mut self, out: impl Write + Send + 'static) -> Self {
+        self.out = Box::new(out);
+
+        self
+    }
+
+    /// The number of instructions run since the VM was created, for
+    /// comparing the cost of programs.
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Makes execution fail with [`Error::Interrupted`] as soon as `cancel` is
+    /// set, e.g. from another thread or a Ctrl-C handler. The flag is never
+    /// cleared by the VM.
+    pub fn with_cancel_flag(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+
+        self
+    }
+
+    /// Limits how many instructions each call to [`Self::execute`] may run
+    /// before failing with [`Error::Budget`].
+    pub fn set_instruction_budget(&mut self, instructions: u64) {
+        self.instruction_budget = Some(instructions);
+    }
+
+    /// Limits how long each call to [`Self::execute`] may run before failing
+    /// with [`Error::Budget`].
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// Makes [`Self::execute`] optimize chunks before running them, if
+    /// `level` is above 0.
+    pub fn set_opt_level(&mut self, level: u8) {
+        self.opt_level = level;
+    }
+
+    /// Starts recording which lines run, for [`Self::coverage`].
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(BTreeMap::new);
+    }
+
+    /// How many times execution entered each line of the chunks run since
+    /// coverage was enabled, or `None` if it isn't. Lines that never ran
+    /// have a count of zero.
+    pub fn coverage(&self) -> Option<&BTreeMap<usize, u64>> {
+        self.coverage.as_ref()
+    }
+
+    /// Starts recording what each chunk run does, for [`Self::stats`].
+    pub fn enable_stats(&mut self) {
+        self.stats.get_or_insert_with(|| Stats::new(&Chunk::new()));
+    }
+
+    /// What the last chunk run did, or `None` if stats aren't enabled.
+    pub fn stats(&self) -> Option<&Stats> {
+        self.stats.as_ref()
+    }
+
+    /// Makes the VM print the stack and disassemble each instruction to
+    /// stdout before running it, as it always does with the
+    /// `trace_execution` feature.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Each global chunks have referred to so far, in the order they were
+    /// first referred to, with its value if it has been defined.
+    pub fn globals(&self) -> impl Iterator<Item = (&str, Option<&Value>)> {
+        self.global_names.iter().enumerate().map(|(index, name)| {
+            let value = self.globals.get(index).and_then(Option::as_ref);
+            (name.as_str(), value)
+        })
+    }
+
+    /// Forgets every global, keeping the VM's settings.
+    pub fn reset_globals(&mut self) {
+        self.globals.clear();
+        self.global_names.clear();
+    }
+
     fn reset_stack(&mut self) {
         self.stack = vec![];
     }
@@ -36,38 +195,182 @@ impl Vm {
         before
     }
 
-    fn read_byte(&mut self, chunk: &Chunk) -> u8 {
+    fn read_byte(&mut self, chunk: &Chunk) -> Result<u8> {
         let ip = self.incr_ip();
 
-        chunk.code()[ip]
+        match chunk.code().get(ip) {
+            Some(byte) => Ok(*byte),
+            None => Err(self.runtime_error("Unexpected end of bytecode.", chunk)),
+        }
+    }
+
+    fn read_short(&mut self, chunk: &Chunk) -> Result<u16> {
+        let hi = self.read_byte(chunk)?;
+        let lo = self.read_byte(chunk)?;
+
+        Ok(u16::from_be_bytes([hi, lo]))
+    }
+
+    fn read_constant(&mut self, chunk: &Chunk) -> Result<Value> {
+        let idx = self.read_byte(chunk)? as usize;
+
+        match chunk.constants().get(idx) {
+            Some(constant) => Ok(*constant),
+            None => Err(self.runtime_error(&format!("Invalid constant index {idx}."), chunk)),
+        }
+    }
+
+    fn read_global(&mut self, chunk: &Chunk) -> Result<usize> {
+        let global = self.read_byte(chunk)? as usize;
+
+        match self.globals.get(global) {
+            Some(_) => Ok(global),
+            None => Err(self.runtime_error(&format!("Invalid global index {global}."), chunk)),
+        }
+    }
+
+    fn read_local(&mut self, chunk: &Chunk) -> Result<usize> {
+        let slot = self.read_byte(chunk)? as usize;
+
+        match self.stack.get(slot) {
+            Some(_) => Ok(slot),
+            None => Err(self.runtime_error(&format!("Invalid local slot {slot}."), chunk)),
+        }
     }
 
-    fn read_constant<'c>(&mut self, chunk: &'c Chunk) -> &'c Value {
-        let idx = self.read_byte(chunk) as usize;
+    fn undefined_variable(&mut self, global: usize, chunk: &Chunk) -> Error {
+        let message = format!("Undefined variable '{}'.", chunk.globals()[global]);
 
-        &chunk.constants()[idx]
+        self.runtime_error(&message, chunk)
     }
 
     fn peek(&self, distance: usize) -> Option<&Value> {
-        self.stack.get(self.stack.len() - 1 - distance)
+        let index = self.stack.len().checked_sub(distance + 1)?;
+
+        self.stack.get(index)
     }
 
-    fn runtime_error(&mut self, message: &str, chunk: &Chunk) {
-        let line = chunk.lines()[self.ip - 1];
-        eprintln!("{message}\n[line {line}] in script");
+    fn pop(&mut self, chunk: &Chunk) -> Result<Value> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.stack_underflow(chunk)),
+        }
+    }
+
+    fn stack_underflow(&mut self, chunk: &Chunk) -> Error {
+        self.runtime_error("Stack underflow.", chunk)
+    }
+
+    /// Reports `message` with the line of the instruction being run, if the
+    /// chunk records one, and returns the error to fail with.
+    fn runtime_error(&mut self, message: &str, chunk: &Chunk) -> Error {
+        let line = self.ip.checked_sub(1).and_then(|ip| chunk.lines().get(ip));
+        match line {
+            Some(line) => writeln!(self.err, "{message}\n[line {line}] in script"),
+            None => writeln!(self.err, "{message}"),
+        }
+        .expect("must be able to write errors");
         self.reset_stack();
+
+        Error::Runtime
+    }
+
+    /// Panics if the stack doesn't hold `expected` values at `self.ip`,
+    /// printing the code of the statement that ran before it.
+    #[cfg(feature = "check_stack")]
+    fn check_stack(&self, chunk: &Chunk, expected: usize) {
+        if self.stack.len() == expected {
+            return;
+        }
+
+        let start = chunk
+            .statements()
+            .iter()
+            .map(|(offset, _)| *offset)
+            .filter(|offset| *offset < self.ip)
+            .max()
+            .unwrap_or(0);
+        println!("== unbalanced statement ==");
+        let mut offset = start;
+        while offset < self.ip {
+            let op = OpCode::try_from(chunk.code()[offset]).expect("chunk must be valid");
+            offset = op.disassemble(chunk, offset);
+        }
+
+        panic!(
+            "stack holds {} values at offset {}, but should hold {expected}",
+            self.stack.len(),
+            self.ip,
+        );
     }
 
-    fn run(&mut self, chunk: Chunk) -> Result<()> {
+    /// Runs `chunk` from `ip`, tracing each instruction if `TRACE` is set.
+    /// Tracing is a parameter, rather than read from `trace` as it runs, so
+    /// that the untraced loop doesn't check it for every instruction.
+    fn run<const TRACE: bool>(&mut self, chunk: Chunk) -> Result<()> {
+        let start = self.instructions;
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut line = None;
+
         loop {
-            #[cfg(feature = "trace_execution")]
-            let offset = self.ip;
+            let executed = self.instructions - start;
+            let over_budget = self
+                .instruction_budget
+                .is_some_and(|budget| executed >= budget);
+            let over_time = executed.is_multiple_of(CLOCK_INTERVAL)
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            let error = if self
+                .cancel
+                .as_ref()
+                .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+            {
+                Some(Error::Interrupted)
+            } else if over_budget || over_time {
+                Some(Error::Budget)
+            } else {
+                None
+            };
+            if let Some(error) = error {
+                writeln!(self.err, "{error}").expect("must be able to write errors");
+                self.reset_stack();
+                return Err(error);
+            }
 
-            let instruction = self.read_byte(&chunk);
-            let op = OpCode::try_from(instruction).map_err(|_| Error::Runtime)?;
+            if let (Some(coverage), Some(&current)) =
+                (&mut self.coverage, chunk.lines().get(self.ip))
+            {
+                if line != Some(current) {
+                    *coverage.entry(current).or_default() += 1;
+                    line = Some(current);
+                }
+            }
+            if let Some(stats) = &mut self.stats {
+                stats.record(&chunk, self.ip, self.stack.len());
+            }
 
-            #[cfg(feature = "trace_execution")]
+            #[cfg(feature = "check_stack")]
+            if let Ok(index) = chunk
+                .statements()
+                .binary_search_by_key(&self.ip, |(offset, _)| *offset)
             {
+                self.check_stack(&chunk, chunk.statements()[index].1);
+            }
+
+            let offset = self.ip;
+
+            let instruction = self.read_byte(&chunk)?;
+            #[cfg(not(feature = "unchecked_dispatch"))]
+            let Ok(op) = OpCode::try_from(instruction) else {
+                let message = format!("Invalid opcode {instruction}.");
+                return Err(self.runtime_error(&message, &chunk));
+            };
+            // SAFETY: `execute` validated the chunk, so `ip` always points at
+            // the start of an instruction, which holds a valid opcode.
+            #[cfg(feature = "unchecked_dispatch")]
+            let op = unsafe { std::mem::transmute::<u8, OpCode>(instruction) };
+            self.instructions += 1;
+
+            if TRACE {
                 print!("          ");
                 for value in &self.stack {
                     print!("[{value}]");
@@ -77,85 +380,131 @@ impl Vm {
             }
 
             macro_rules! binary_op {
-                ($op:tt) => {
-                    if let (Some(Value::Number(_)), Some(Value::Number(_))) = (self.peek(0), self.peek(1)) {
-                        let b = self.stack.pop().expect("stack mut have values");
-                        let a = self.stack.pop().expect("stack mut have values");
-                        self.stack.push(a $op b);
-                    } else {
-                        self.runtime_error("Operands must be numbers.", &chunk);
-                        return Err(Error::Runtime);
+                ($constructor:expr, $op:tt) => {
+                    if self.stack.len() < 2 {
+                        return Err(self.stack_underflow(&chunk));
                     }
-                }
-            }
-
-            macro_rules! cmp_op {
-                ($op:tt) => {
-                    if let (Some(Value::Number(_)), Some(Value::Number(_))) = (self.peek(0), self.peek(1)) {
-                        let b = self.stack.pop().expect("stack mut have values");
-                        let a = self.stack.pop().expect("stack mut have values");
-                        self.stack.push(Value::Boolean(a $op b));
+                    let b = self.peek(0).and_then(Value::as_number);
+                    let a = self.peek(1).and_then(Value::as_number);
+                    if let (Some(a), Some(b)) = (a, b) {
+                        self.stack.truncate(self.stack.len() - 2);
+                        self.stack.push($constructor(a $op b));
                     } else {
-                        self.runtime_error("Operands must be numbers.", &chunk);
-                        return Err(Error::Runtime);
+                        return Err(self.runtime_error("Operands must be numbers.", &chunk));
                     }
                 }
             }
 
             match op {
                 OpCode::Constant => {
-                    let constant = self.read_constant(&chunk);
-                    self.stack.push(constant.clone());
+                    let constant = self.read_constant(&chunk)?;
+                    self.stack.push(constant);
                 }
                 OpCode::Nil => {
-                    self.stack.push(Value::Nil);
+                    self.stack.push(Value::nil());
                 }
                 OpCode::True => {
-                    self.stack.push(Value::Boolean(true));
+                    self.stack.push(Value::boolean(true));
                 }
                 OpCode::False => {
-                    self.stack.push(Value::Boolean(false));
+                    self.stack.push(Value::boolean(false));
+                }
+                OpCode::Pop => {
+                    self.pop(&chunk)?;
                 }
                 OpCode::Equal => {
-                    let b = self.stack.pop().expect("stack mut have values");
-                    let a = self.stack.pop().expect("stack mut have values");
-                    self.stack.push(Value::Boolean(a == b));
+                    let b = self.pop(&chunk)?;
+                    let a = self.pop(&chunk)?;
+                    self.stack.push(Value::boolean(a == b));
                 }
                 OpCode::Greater => {
-                    cmp_op!(>);
+                    binary_op!(Value::boolean, >);
                 }
                 OpCode::Less => {
-                    cmp_op!(<);
+                    binary_op!(Value::boolean, <);
                 }
                 OpCode::Add => {
-                    binary_op!(+);
+                    binary_op!(Value::number, +);
                 }
                 OpCode::Subtract => {
-                    binary_op!(-);
+                    binary_op!(Value::number, -);
                 }
                 OpCode::Multiply => {
-                    binary_op!(*);
+                    binary_op!(Value::number, *);
                 }
                 OpCode::Divide => {
-                    binary_op!(/);
+                    binary_op!(Value::number, /);
+                }
+                OpCode::Modulo => {
+                    binary_op!(Value::number, %);
                 }
                 OpCode::Not => {
-                    let value = self.stack.pop().expect("stack must have values");
-                    self.stack.push(Value::Boolean(value.is_falsey()));
+                    let value = self.pop(&chunk)?;
+                    self.stack.push(Value::boolean(value.is_falsey()));
                 }
                 OpCode::Negate => {
-                    if let Some(Value::Number(_)) = self.peek(0) {
-                        let value = self.stack.pop().expect("stack must have values");
-                        self.stack.push(-value);
+                    let value = self.pop(&chunk)?;
+                    if let Some(n) = value.as_number() {
+                        self.stack.push(Value::number(-n));
                     } else {
-                        self.runtime_error("Operand must be a number.", &chunk);
-                        return Err(Error::Runtime);
+                        return Err(self.runtime_error("Operand must be a number.", &chunk));
+                    }
+                }
+                OpCode::Jump => {
+                    let offset = self.read_short(&chunk)?;
+                    self.ip += offset as usize;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short(&chunk)?;
+                    let Some(condition) = self.peek(0) else {
+                        return Err(self.stack_underflow(&chunk));
+                    };
+                    if condition.is_falsey() {
+                        self.ip += offset as usize;
+                    }
+                }
+                OpCode::Print => {
+                    let value = self.pop(&chunk)?;
+                    writeln!(self.out, "{value}").expect("must be able to write output");
+                }
+                OpCode::DefineGlobal => {
+                    let global = self.read_global(&chunk)?;
+                    self.globals[global] = Some(self.pop(&chunk)?);
+                }
+                OpCode::GetGlobal => {
+                    let global = self.read_global(&chunk)?;
+                    let Some(value) = self.globals[global] else {
+                        return Err(self.undefined_variable(global, &chunk));
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetGlobal => {
+                    let global = self.read_global(&chunk)?;
+                    if self.globals[global].is_none() {
+                        return Err(self.undefined_variable(global, &chunk));
                     }
+                    let Some(&value) = self.peek(0) else {
+                        return Err(self.stack_underflow(&chunk));
+                    };
+                    self.globals[global] = Some(value);
+                }
+                OpCode::GetLocal => {
+                    let slot = self.read_local(&chunk)?;
+                    self.stack.push(self.stack[slot]);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.read_local(&chunk)?;
+                    let Some(&value) = self.peek(0) else {
+                        return Err(self.stack_underflow(&chunk));
+                    };
+                    self.stack[slot] = value;
                 }
                 OpCode::Return => {
                     if let Some(value) = self.stack.pop() {
-                        println!("{value}");
+                        writeln!(self.out, "{value}").expect("must be able to write output");
                     }
+                    #[cfg(feature = "check_stack")]
+                    self.check_stack(&chunk, 0);
 
                     return Ok(());
                 }
@@ -163,15 +512,68 @@ impl Vm {
         }
     }
 
+    /// Compiles and runs `source`, with the globals earlier sources defined.
+    /// Compile errors are reported to the error writer, along with runtime
+    /// errors.
     pub fn interpret(&mut self, source: &str) -> Result<()> {
+        let mut chunk = Chunk::with_globals(self.global_names.clone());
+        if !compile(source, &mut chunk, &mut self.err) {
+            return Err(Error::Compile);
+        }
+
+        self.execute(chunk)
+    }
+
+    /// Compiles `source` without running it, e.g. to save it as a `.loxc`
+    /// file. Compile errors are reported to `err`.
+    pub fn compile(source: &str, err: &mut dyn Write) -> Result<Chunk> {
         let mut chunk = Chunk::new();
 
-        if !compile(source, &mut chunk) {
+        if !compile(source, &mut chunk, err) {
             return Err(Error::Compile);
         }
 
+        Ok(chunk)
+    }
+
+    /// Runs an already compiled chunk, after checking that its code is valid
+    /// and optimizing it as much as [`Self::set_opt_level`] asks. Its globals
+    /// are the ones of the same names that earlier chunks defined.
+    pub fn execute(&mut self, chunk: Chunk) -> Result<()> {
+        let checked = match self.opt_level {
+            0 => chunk.validate().map(|()| chunk),
+            _ => optimizer::optimize(&chunk),
+        }
+        .and_then(|mut chunk| {
+            chunk.link(&mut self.global_names)?;
+            Ok(chunk)
+        });
+        let chunk = match checked {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                writeln!(self.err, "Invalid bytecode: {error}.")
+                    .expect("must be able to write errors");
+                return Err(Error::Runtime);
+            }
+        };
+        self.globals.resize(self.global_names.len(), None);
+
+        // Locals live in the stack's slots from the bottom up.
+        self.reset_stack();
         self.ip = 0;
+        if self.stats.is_some() {
+            self.stats = Some(Stats::new(&chunk));
+        }
+        if let Some(coverage) = &mut self.coverage {
+            for line in chunk.lines() {
+                coverage.entry(*line).or_default();
+            }
+        }
 
-        self.run(chunk)
+        if self.trace {
+            self.run::<true>(chunk)
+        } else {
+            self.run::<false>(chunk)
+        }
     }
 }
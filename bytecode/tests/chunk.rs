@@ -0,0 +1,92 @@
+use lox_bytecode::{
+    chunk::{Chunk, Error, OpCode},
+    optimizer::optimize,
+    vm::Vm,
+};
+use std::io;
+
+#[test]
+fn chunks_round_trip_through_loxc() {
+    let chunk =
+        Vm::compile("1 < 2 ? nil : -(15 + 2) % 3", &mut io::sink()).expect("source must compile");
+    let bytes = chunk.serialize();
+
+    assert_eq!(&bytes[..4], b"LOXC");
+    assert_eq!(
+        Chunk::deserialize(&bytes).expect("bytes must decode"),
+        chunk
+    );
+}
+
+#[test]
+fn globals_round_trip_through_loxc() {
+    let chunk =
+        Vm::compile("var a = 1;\nvar b = a + 1;\nb", &mut io::sink()).expect("source must compile");
+
+    assert_eq!(chunk.globals(), ["a", "b"]);
+    assert_eq!(
+        Chunk::deserialize(&chunk.serialize()).expect("bytes must decode"),
+        chunk
+    );
+}
+
+#[test]
+fn malformed_loxc_files_are_rejected() {
+    let bytes = Vm::compile("1 + 2", &mut io::sink())
+        .expect("source must compile")
+        .serialize();
+
+    assert!(matches!(
+        Chunk::deserialize(b"LOXD\x01\x00"),
+        Err(Error::BadMagic)
+    ));
+    assert!(matches!(
+        Chunk::deserialize(b"LOXC\x63\x00"),
+        Err(Error::UnsupportedVersion(99))
+    ));
+    assert!(matches!(
+        Chunk::deserialize(&bytes[..bytes.len() - 1]),
+        Err(Error::Malformed)
+    ));
+}
+
+#[test]
+fn repeated_literals_share_a_constant() {
+    let chunk = Vm::compile("1 + 2 * 1 - 2 / 1", &mut io::sink()).expect("source must compile");
+
+    assert_eq!(chunk.constants().len(), 2);
+}
+
+#[test]
+fn invalid_code_is_rejected() {
+    let mut chunk = Vm::compile("1 + 2", &mut io::sink()).expect("source must compile");
+    chunk.validate().expect("compiled code must be valid");
+
+    // Point the first constant load past the end of the constant table.
+    chunk.code_mut()[1] = 200;
+    assert!(chunk.validate().is_err());
+    assert!(Chunk::deserialize(&chunk.serialize()).is_err());
+}
+
+#[test]
+fn optimizing_drops_branches_that_cannot_run() {
+    let code = |source: &str| {
+        let chunk = Vm::compile(source, &mut io::sink()).expect("source must compile");
+        optimize(&chunk)
+            .expect("chunk must optimize")
+            .code()
+            .to_vec()
+    };
+    let [constant, ret] = [OpCode::Constant, OpCode::Return].map(u8::from);
+
+    assert_eq!(code("false ? 1 : 2"), [constant, 1, ret]);
+    assert_eq!(code("true ? 1 : 2"), [constant, 0, ret]);
+    assert_eq!(code("nil ? 1 : false ? 2 : 3"), [constant, 2, ret]);
+    // Only jumps on a value known when compiling can be dropped.
+    assert_eq!(
+        code("1 < 2 ? 3 : 4"),
+        Vm::compile("1 < 2 ? 3 : 4", &mut io::sink())
+            .expect("source must compile")
+            .code()
+    );
+}
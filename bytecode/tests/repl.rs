@@ -0,0 +1,19 @@
+use lox_bytecode::repl::Command;
+
+#[test]
+fn commands_are_parsed_the_same_way_in_both_repls() {
+    assert_eq!(Command::parse("print 1;\n"), None);
+    assert_eq!(Command::parse(" :env \n"), Some(Ok(Command::Env)));
+    assert_eq!(
+        Command::parse(":load   script.lox \n"),
+        Some(Ok(Command::Load("script.lox")))
+    );
+    assert_eq!(
+        Command::parse(":load\n"),
+        Some(Err("Unknown command ':load'. Try ':help'.".to_string()))
+    );
+    assert_eq!(
+        Command::parse(":env now\n"),
+        Some(Err("Unknown command ':env now'. Try ':help'.".to_string()))
+    );
+}
@@ -0,0 +1,331 @@
+use lox_bytecode::{
+    buffer::SharedBuffer,
+    chunk::{Chunk, OpCode},
+    optimizer::MAX_OPT_LEVEL,
+    vm::{Error, Vm},
+};
+use std::{
+    io,
+    sync::{atomic::AtomicBool, Arc},
+    thread,
+};
+
+#[test]
+fn output_and_errors_go_to_their_writers() {
+    let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+    let mut vm = Vm::new().with_writers(out.clone(), err.clone());
+
+    vm.interpret("1 + 2").expect("source must run");
+    assert!(matches!(vm.interpret("1 +"), Err(Error::Compile)));
+    assert!(matches!(vm.interpret("-nil"), Err(Error::Runtime)));
+
+    assert_eq!(out.contents(), "3\n");
+    assert_eq!(
+        err.contents(),
+        "[line 1] Error at end: Expect expression.\nOperand must be a number.\n[line 1] in script\n"
+    );
+}
+
+#[test]
+fn compile_errors_go_to_the_given_writer() {
+    let mut err = vec![];
+
+    assert!(Vm::compile("1 +", &mut err).is_err());
+    assert_eq!(
+        String::from_utf8(err).expect("errors must be UTF-8"),
+        "[line 1] Error at end: Expect expression.\n"
+    );
+}
+
+#[test]
+fn vms_can_move_between_threads() {
+    let out = SharedBuffer::default();
+    let mut vm = Vm::new().with_writers(out.clone(), io::sink());
+    vm.interpret("1 + 2").expect("source must run");
+
+    let mut vm = thread::spawn(move || {
+        vm.interpret("3 * 4").expect("source must run");
+        vm
+    })
+    .join()
+    .expect("vm must not panic");
+    vm.interpret("5 - 6").expect("source must run");
+
+    assert_eq!(out.contents(), "3\n12\n-1\n");
+}
+
+#[test]
+fn instruction_budgets_stop_execution() {
+    let mut vm = Vm::new().with_writers(io::sink(), io::sink());
+    vm.set_instruction_budget(4);
+
+    assert!(matches!(vm.interpret("1 + 2 * 3"), Err(Error::Budget)));
+    // The budget applies to each chunk separately.
+    assert!(vm.interpret("1 + 2").is_ok());
+}
+
+#[test]
+fn cancelled_vms_stop_execution() {
+    let cancel = Arc::new(AtomicBool::new(true));
+    let mut vm = Vm::new()
+        .with_writers(io::sink(), io::sink())
+        .with_cancel_flag(cancel);
+
+    assert!(matches!(vm.interpret("1 + 2"), Err(Error::Interrupted)));
+}
+
+#[test]
+fn block_comments_nest() {
+    let chunk = Vm::compile("1 /* a /* nested */ comment */ + 2", &mut io::sink())
+        .expect("source must compile");
+
+    assert_eq!(
+        chunk,
+        Vm::compile("1 + 2", &mut io::sink()).expect("source must compile")
+    );
+    assert!(Vm::compile("1 /* a /* nested */ + 2", &mut io::sink()).is_err());
+}
+
+#[test]
+fn number_literals_can_be_written_several_ways() {
+    let chunk = Vm::compile("0xFF + 1_000 * 2.5e-1", &mut io::sink()).expect("source must compile");
+
+    assert_eq!(
+        chunk,
+        Vm::compile("255 + 1000 * 0.25", &mut io::sink()).expect("source must compile")
+    );
+    for malformed in ["1__0", "10_", "0x", "1e", "1e+"] {
+        assert!(
+            Vm::compile(malformed, &mut io::sink()).is_err(),
+            "{malformed} must not compile"
+        );
+    }
+}
+
+#[test]
+fn coverage_counts_the_lines_that_run() {
+    let mut vm = Vm::new().with_writers(io::sink(), io::sink());
+    assert!(vm.coverage().is_none());
+
+    vm.enable_coverage();
+    vm.interpret("true ? 1 :\n2\n").expect("source must run");
+
+    let coverage = vm.coverage().expect("coverage must be enabled");
+    assert_eq!(coverage[&1], 1);
+    assert_eq!(coverage[&2], 0);
+    assert_eq!(coverage[&3], 1);
+}
+
+#[test]
+fn stats_count_opcodes_stack_depth_and_constant_loads() {
+    let mut vm = Vm::new().with_writers(io::sink(), io::sink());
+    assert!(vm.stats().is_none());
+
+    vm.enable_stats();
+    vm.interpret("1 + 2 * 3 - 1").expect("source must run");
+
+    let stats = vm.stats().expect("stats must be enabled");
+    assert_eq!(stats.executed(OpCode::Constant), 4);
+    assert_eq!(stats.executed(OpCode::Multiply), 1);
+    assert_eq!(stats.executed(OpCode::Return), 1);
+    assert_eq!(stats.max_stack(), 3);
+    // Both `1`s share a constant.
+    assert_eq!(stats.constant_loads(0), 2);
+}
+
+#[test]
+fn malformed_chunks_fail_without_panicking() {
+    let run = |ops: Vec<OpCode>| {
+        let mut chunk = Chunk::new();
+        for op in ops {
+            chunk.write(op, 1);
+        }
+        let err = SharedBuffer::default();
+        let mut vm = Vm::new().with_writers(io::sink(), err.clone());
+
+        assert!(matches!(vm.execute(chunk), Err(Error::Runtime)));
+        err.contents()
+    };
+
+    assert_eq!(run(vec![]), "Unexpected end of bytecode.\n");
+    assert_eq!(
+        run(vec![OpCode::Add, OpCode::Return]),
+        "Stack underflow.\n[line 1] in script\n"
+    );
+    assert_eq!(
+        run(vec![OpCode::True, OpCode::Equal, OpCode::Return]),
+        "Stack underflow.\n[line 1] in script\n"
+    );
+    assert_eq!(
+        run(vec![OpCode::Negate]),
+        "Stack underflow.\n[line 1] in script\n"
+    );
+    assert_eq!(run(vec![OpCode::Nil]), "Unexpected end of bytecode.\n");
+}
+
+#[test]
+fn optimizing_preserves_results_and_error_lines() {
+    let run = |source: &str, opt_level: u8| {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut vm = Vm::new().with_writers(out.clone(), err.clone());
+        vm.set_opt_level(opt_level);
+        let _ = vm.interpret(source);
+
+        (out.contents(), err.contents())
+    };
+
+    for source in [
+        "true ? 1 : 2",
+        "nil ? 1 : 2",
+        "false ? 1 : false ? 2 : 3",
+        "1 < 2 ? nil : -(15 + 2) % 3",
+        "(1, 2) ? 3 : 4",
+        "0 ? (true ? 5 : 6) : 7",
+        "false ? 1 :\n-nil",
+        "true ?\n-false : 2",
+    ] {
+        assert_eq!(run(source, 0), run(source, MAX_OPT_LEVEL), "{source:?}");
+    }
+}
+
+#[test]
+fn globals_persist_between_sources() {
+    let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+    let mut vm = Vm::new().with_writers(out.clone(), err.clone());
+
+    vm.interpret("var a = 1;\nvar b;\nprint b;")
+        .expect("source must run");
+    vm.interpret("a += 2;\nb = a * 2;\n++a")
+        .expect("source must run");
+    vm.interpret("print a;\nprint b;").expect("source must run");
+    assert!(matches!(vm.interpret("print c;"), Err(Error::Runtime)));
+    assert!(matches!(vm.interpret("c = 1;"), Err(Error::Runtime)));
+
+    assert_eq!(out.contents(), "nil\n4\n4\n6\n");
+    assert_eq!(
+        err.contents(),
+        "Undefined variable 'c'.\n[line 1] in script\nUndefined variable 'c'.\n[line 1] in script\n"
+    );
+}
+
+#[test]
+fn globals_can_be_used_before_they_are_declared() {
+    let chunk = Vm::compile("print a;\nvar a = 1;\nprint a;", &mut io::sink())
+        .expect("source must compile");
+    // Both `a`s refer to the same global, which isn't defined yet when the
+    // first one runs.
+    assert_eq!(chunk.globals(), ["a"]);
+
+    let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+    let mut vm = Vm::new().with_writers(out.clone(), err.clone());
+    assert!(matches!(vm.execute(chunk), Err(Error::Runtime)));
+    assert_eq!(out.contents(), "");
+    assert_eq!(
+        err.contents(),
+        "Undefined variable 'a'.\n[line 1] in script\n"
+    );
+}
+
+#[test]
+fn separately_compiled_chunks_share_globals_by_name() {
+    let out = SharedBuffer::default();
+    let mut vm = Vm::new().with_writers(out.clone(), io::sink());
+
+    let define =
+        Vm::compile("var x = 1;\nvar y = 2;", &mut io::sink()).expect("source must compile");
+    let read = Vm::compile("print y;\nprint x;", &mut io::sink()).expect("source must compile");
+    vm.execute(define).expect("chunk must run");
+    vm.execute(read).expect("chunk must run");
+
+    assert_eq!(out.contents(), "2\n1\n");
+}
+
+#[test]
+fn invalid_assignment_targets_are_rejected() {
+    let err = SharedBuffer::default();
+    let mut vm = Vm::new().with_writers(io::sink(), err.clone());
+
+    assert!(matches!(
+        vm.interpret("var a;\n1 + a = 2;"),
+        Err(Error::Compile)
+    ));
+    assert_eq!(
+        err.contents(),
+        "[line 2] Error at '=': Invalid assignment target.\n"
+    );
+}
+
+#[test]
+fn locals_live_in_blocks() {
+    let out = SharedBuffer::default();
+    let mut vm = Vm::new().with_writers(out.clone(), io::sink());
+
+    let chunk = Vm::compile(
+        "var a = 0;\n{\n  var a = 1;\n  {\n    var b = a + 1;\n    a = b * 10;\n    print ++b;\n  }\n  print a;\n}\nprint a;", &mut io::sink(),
+    )
+    .expect("source must compile");
+    // Only the outer `a` is a global; the rest live on the stack.
+    assert_eq!(chunk.globals(), ["a"]);
+    vm.execute(chunk).expect("chunk must run");
+
+    assert_eq!(out.contents(), "3\n20\n0\n");
+}
+
+#[test]
+fn postfix_increments_leave_the_old_value() {
+    let out = SharedBuffer::default();
+    let mut vm = Vm::new().with_writers(out.clone(), io::sink());
+
+    vm.interpret("var a = 1;\nprint a++;\nprint a;\n{\n  var b = a;\n  print -b--;\n  print b;\n}")
+        .expect("source must run");
+
+    assert_eq!(out.contents(), "1\n2\n-2\n1\n");
+}
+
+#[test]
+fn only_variables_can_be_decremented() {
+    let err = SharedBuffer::default();
+    let mut vm = Vm::new().with_writers(io::sink(), err.clone());
+
+    assert!(matches!(
+        vm.interpret("var a = 3;\nprint --(a);"),
+        Err(Error::Compile)
+    ));
+    assert_eq!(
+        err.contents(),
+        "[line 2] Error at '--': Invalid increment or decrement target.\n"
+    );
+}
+
+#[test]
+fn locals_are_checked_like_the_resolver_does() {
+    let err = SharedBuffer::default();
+    let mut vm = Vm::new().with_writers(io::sink(), err.clone());
+
+    assert!(matches!(
+        vm.interpret("{\n  var a = 1;\n  { var a = a; }\n  var a = 2;\n}"),
+        Err(Error::Compile)
+    ));
+    assert_eq!(
+        err.contents(),
+        "[line 3] Error at 'a': Can't read local variable in its own initializer.\n\
+         [line 4] Error at 'a': Already a variable with this name in this scope.\n"
+    );
+}
+
+#[cfg(feature = "check_stack")]
+#[test]
+fn statements_keep_the_stack_balanced() {
+    let mut vm = Vm::new().with_writers(io::sink(), io::sink());
+
+    for source in [
+        "1 + 2",
+        "var a = 1;\na = a + 1, a;\nprint a;",
+        "{\n  var a = 1;\n  {\n    var b = a;\n    b += 1;\n  }\n  print a ? 1 : 2;\n}\n3",
+        "var a;\n{ var b = ++a; }",
+        "var a = 1;\n{ var b = a++; b--; }",
+    ] {
+        // Errors are fine; panics from an unbalanced stack aren't.
+        let _ = vm.interpret(source);
+    }
+}
@@ -0,0 +1,95 @@
+/* A C interface for embedding the treewalk Lox interpreter.
+ *
+ * Interpreters are opaque handles made with lox_new() and freed with
+ * lox_free(). Nothing is printed unless the host asks for it: print output
+ * and errors go to callbacks, failures are reported with a LoxStatus, and
+ * the message for the last one can be read back with lox_last_error().
+ * Strings handed to the host belong to the interpreter, and stay valid until
+ * the next call that's given the same handle.
+ *
+ * Calls aren't re-entrant: a write callback or native must not use the
+ * handle that's running it. Such calls fail with LOX_BUSY, except that
+ * lox_last_error() returns null, lox_exit_code() returns 0 and lox_free()
+ * does nothing. A panic in the interpreter is reported as LOX_PANIC rather
+ * than unwinding into the host.
+ */
+
+#ifndef LOX_H
+#define LOX_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum LoxStatus {
+    LOX_OK = 0,
+    /* The source didn't scan, parse or resolve. */
+    LOX_COMPILE_ERROR = 1,
+    LOX_RUNTIME_ERROR = 2,
+    /* The script called exit(); see lox_exit_code(). */
+    LOX_EXIT = 3,
+    /* There's no global with the given name. */
+    LOX_UNDEFINED = 4,
+    /* A pointer was null or a string wasn't UTF-8. */
+    LOX_INVALID_ARGUMENT = 5,
+    /* The interpreter panicked, which is a bug in it. The handle can still be
+     * freed, but may be left in any state. */
+    LOX_PANIC = 6,
+    /* The handle was used by a callback or native running on its behalf. */
+    LOX_BUSY = 7,
+} LoxStatus;
+
+typedef enum LoxTag {
+    LOX_NIL = 0,
+    LOX_BOOL = 1,
+    LOX_NUMBER = 2,
+    LOX_STRING = 3,
+    /* A list, map, instance, callable or other value C can't hold, which
+     * the host only gets as a string. */
+    LOX_OBJECT = 4,
+} LoxTag;
+
+/* Only the field named by tag is meaningful, and string is NUL-terminated
+ * UTF-8. */
+typedef struct LoxValue {
+    LoxTag tag;
+    bool boolean;
+    double number;
+    const char *string;
+} LoxValue;
+
+typedef struct LoxInterpreter LoxInterpreter;
+
+/* Receives length bytes of UTF-8 text, which aren't NUL-terminated. */
+typedef void (*LoxWriteFn)(void *user_data, const char *text, size_t length);
+
+/* A native written in C. It's given count arguments, sets result, and
+ * returns false to fail with result's string as the error message. */
+typedef bool (*LoxNativeFn)(void *user_data, const LoxValue *arguments, size_t count,
+                            LoxValue *result);
+
+/* Returns null if the interpreter couldn't be made. */
+LoxInterpreter *lox_new(void);
+void lox_free(LoxInterpreter *lox);
+
+LoxStatus lox_set_output(LoxInterpreter *lox, LoxWriteFn callback, void *user_data);
+LoxStatus lox_set_error_output(LoxInterpreter *lox, LoxWriteFn callback, void *user_data);
+
+LoxStatus lox_run(LoxInterpreter *lox, const char *source);
+const char *lox_last_error(const LoxInterpreter *lox);
+int lox_exit_code(const LoxInterpreter *lox);
+
+LoxStatus lox_get_global(LoxInterpreter *lox, const char *name, LoxValue *value);
+LoxStatus lox_set_global(LoxInterpreter *lox, const char *name, LoxValue value);
+
+LoxStatus lox_define_native(LoxInterpreter *lox, const char *name, size_t arity,
+                            LoxNativeFn function, void *user_data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,478 @@
+//! A C interface for embedding the treewalk interpreter, declared in
+//! `include/lox.h`.
+//!
+//! Interpreters are opaque handles that the host makes with [`lox_new`] and
+//! frees with [`lox_free`]. Nothing is printed unless the host asks for it:
+//! `print` output and errors go to callbacks, failures are reported with a
+//! [`LoxStatus`], and the message for the last one can be read back with
+//! [`lox_last_error`]. Strings handed to the host belong to the interpreter,
+//! and stay valid until the next call that's given the same handle.
+//!
+//! A handle can't be used by a callback or native while a call to it is
+//! running: such calls fail with [`LoxStatus::Busy`] rather than aliasing
+//! the interpreter. Panics are caught at the boundary and reported as
+//! [`LoxStatus::Panic`], since unwinding into C would abort the host.
+
+use lox_treewalk::{
+    interpreter::{Error, Interpreter},
+    sync::{Lock, Shared},
+    value::Value,
+};
+use std::{
+    cell::{Cell, UnsafeCell},
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    io::{self, Write},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+/// How a call went.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoxStatus {
+    Ok = 0,
+    /// The source didn't scan, parse or resolve.
+    CompileError = 1,
+    RuntimeError = 2,
+    /// The script called `exit()`; see [`lox_exit_code`].
+    Exit = 3,
+    /// There's no global with the given name.
+    Undefined = 4,
+    /// A pointer was null or a string wasn't UTF-8.
+    InvalidArgument = 5,
+    /// The interpreter panicked, which is a bug in it. The handle can still
+    /// be freed, but may be left in any state.
+    Panic = 6,
+    /// The handle was given to a callback or native that's running on its
+    /// behalf, which can't use it until the call that ran it returns.
+    Busy = 7,
+}
+
+/// Which field of a [`LoxValue`] holds its value.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoxTag {
+    Nil = 0,
+    Bool = 1,
+    Number = 2,
+    String = 3,
+    /// A list, map, instance, callable or other value C can't hold, which
+    /// the host only gets as a string.
+    Object = 4,
+}
+
+/// A Lox value that C can hold. Only the field named by `tag` is meaningful,
+/// and `string` is NUL-terminated UTF-8.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LoxValue {
+    pub tag: LoxTag,
+    pub boolean: bool,
+    pub number: f64,
+    pub string: *const c_char,
+}
+
+impl LoxValue {
+    fn new(tag: LoxTag) -> Self {
+        Self {
+            tag,
+            boolean: false,
+            number: 0.0,
+            string: ptr::null(),
+        }
+    }
+}
+
+/// Receives `length` bytes of UTF-8 text, which aren't NUL-terminated.
+pub type LoxWriteFn =
+    unsafe extern "C" fn(user_data: *mut c_void, text: *const c_char, length: usize);
+
+/// A native written in C. It's given `count` arguments, sets `result`, and
+/// returns false to fail with `result`'s string as the error message.
+pub type LoxNativeFn = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    arguments: *const LoxValue,
+    count: usize,
+    result: *mut LoxValue,
+) -> bool;
+
+/// Data the host passes back to its own callbacks.
+#[derive(Clone, Copy)]
+struct UserData(*mut c_void);
+
+// The interpreter only ever calls back on the thread it's run on, so it's up
+// to the host to make its callbacks safe to call from there.
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+/// A writer that passes what it's given to the callback the host set, if it
+/// set one.
+#[derive(Clone, Default)]
+struct Callback(Shared<Lock<Option<(LoxWriteFn, UserData)>>>);
+
+impl Write for Callback {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Copied out so that it isn't borrowed while the host runs.
+        let callback = *self.0.borrow();
+        if let Some((callback, user_data)) = callback {
+            // SAFETY: the host promised that the callback can be called with
+            // the user data it was given alongside.
+            unsafe { callback(user_data.0, buf.as_ptr().cast(), buf.len()) };
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct LoxInterpreter {
+    /// Set while a call is using `state`, so that callbacks and natives
+    /// calling back in are turned away.
+    busy: Cell<bool>,
+    state: UnsafeCell<State>,
+}
+
+/// Everything a handle holds, which only one call may use at a time.
+struct State {
+    interpreter: Interpreter,
+    out: Callback,
+    err: Callback,
+    last_error: Option<CString>,
+    exit_code: c_int,
+    /// The string behind the last value handed out by [`lox_get_global`].
+    string: Option<CString>,
+}
+
+/// Runs `f` with the state behind `lox`, unless `lox` is null or another
+/// call is already using it, catching any panic.
+///
+/// # Safety
+///
+/// `lox` must be null or a live handle.
+unsafe fn enter<T>(
+    lox: *const LoxInterpreter,
+    f: impl FnOnce(&mut State) -> T,
+) -> Result<T, LoxStatus> {
+    let Some(lox) = lox.as_ref() else {
+        return Err(LoxStatus::InvalidArgument);
+    };
+    if lox.busy.replace(true) {
+        return Err(LoxStatus::Busy);
+    }
+    // SAFETY: the busy flag makes this the only reference to the state.
+    let state = &mut *lox.state.get();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(state)));
+    lox.busy.set(false);
+
+    result.map_err(|_| LoxStatus::Panic)
+}
+
+/// Makes an interpreter with the standard library loaded, or returns null
+/// if that panics.
+#[no_mangle]
+pub extern "C" fn lox_new() -> *mut LoxInterpreter {
+    let made = panic::catch_unwind(|| {
+        let (out, err) = (Callback::default(), Callback::default());
+        let interpreter = Interpreter::new().with_writers(out.clone(), err.clone());
+
+        LoxInterpreter {
+            busy: Cell::new(false),
+            state: UnsafeCell::new(State {
+                interpreter,
+                out,
+                err,
+                last_error: None,
+                exit_code: 0,
+                string: None,
+            }),
+        }
+    });
+
+    made.map_or(ptr::null_mut(), |lox| Box::into_raw(Box::new(lox)))
+}
+
+/// Frees an interpreter made by [`lox_new`]. Null is ignored, and so is a
+/// handle that a call is still using.
+///
+/// # Safety
+///
+/// `lox` must be null or a handle from [`lox_new`] that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn lox_free(lox: *mut LoxInterpreter) {
+    if lox.is_null() || (*lox).busy.get() {
+        return;
+    }
+    // Dropping the interpreter runs Lox values' destructors, which mustn't
+    // unwind into C. If one panics, the rest of the handle leaks.
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(lox))));
+}
+
+/// Sends `print` output to `callback`, or discards it if `callback` is null.
+///
+/// # Safety
+///
+/// `lox` must be a live handle, and `callback` must be safe to call with
+/// `user_data` for as long as the interpreter lives.
+#[no_mangle]
+pub unsafe extern "C" fn lox_set_output(
+    lox: *mut LoxInterpreter,
+    callback: Option<LoxWriteFn>,
+    user_data: *mut c_void,
+) -> LoxStatus {
+    enter(lox, |lox| {
+        *lox.out.0.borrow_mut() = callback.map(|callback| (callback, UserData(user_data)));
+        LoxStatus::Ok
+    })
+    .unwrap_or_else(|status| status)
+}
+
+/// Sends errors and warnings, as they're reported, to `callback`, or
+/// discards them if `callback` is null.
+///
+/// # Safety
+///
+/// As for [`lox_set_output`].
+#[no_mangle]
+pub unsafe extern "C" fn lox_set_error_output(
+    lox: *mut LoxInterpreter,
+    callback: Option<LoxWriteFn>,
+    user_data: *mut c_void,
+) -> LoxStatus {
+    enter(lox, |lox| {
+        *lox.err.0.borrow_mut() = callback.map(|callback| (callback, UserData(user_data)));
+        LoxStatus::Ok
+    })
+    .unwrap_or_else(|status| status)
+}
+
+/// Runs `source`, keeping the globals it defines for later runs.
+///
+/// # Safety
+///
+/// `lox` must be a live handle and `source` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn lox_run(lox: *mut LoxInterpreter, source: *const c_char) -> LoxStatus {
+    let Some(source) = string(source) else {
+        return LoxStatus::InvalidArgument;
+    };
+
+    enter(lox, |lox| {
+        lox.last_error = None;
+        match lox.interpreter.run(source) {
+            Ok(()) => LoxStatus::Ok,
+            Err(Error::Exit { code }) => {
+                lox.exit_code = code;
+                LoxStatus::Exit
+            }
+            Err(error) => {
+                let status = match error {
+                    Error::Syntax { .. } => LoxStatus::CompileError,
+                    _ => LoxStatus::RuntimeError,
+                };
+                lox.last_error = CString::new(error.to_string()).ok();
+                status
+            }
+        }
+    })
+    .unwrap_or_else(|status| status)
+}
+
+/// The message for the last failed run, or null if it didn't fail or the
+/// handle is busy.
+///
+/// # Safety
+///
+/// `lox` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn lox_last_error(lox: *const LoxInterpreter) -> *const c_char {
+    enter(lox, |lox| {
+        lox.last_error
+            .as_ref()
+            .map_or(ptr::null(), |error| error.as_ptr())
+    })
+    .unwrap_or(ptr::null())
+}
+
+/// The code the last script to call `exit()` asked to exit with, or 0 if the
+/// handle is busy.
+///
+/// # Safety
+///
+/// `lox` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn lox_exit_code(lox: *const LoxInterpreter) -> c_int {
+    enter(lox, |lox| lox.exit_code).unwrap_or(0)
+}
+
+/// Reads the global `name` into `value`.
+///
+/// # Safety
+///
+/// `lox` must be a live handle, `name` a NUL-terminated string and `value`
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn lox_get_global(
+    lox: *mut LoxInterpreter,
+    name: *const c_char,
+    value: *mut LoxValue,
+) -> LoxStatus {
+    let (Some(name), false) = (string(name), value.is_null()) else {
+        return LoxStatus::InvalidArgument;
+    };
+
+    enter(lox, |lox| {
+        let Some((_, global)) = lox
+            .interpreter
+            .global_variables()
+            .into_iter()
+            .find(|(global, _)| &**global == name)
+        else {
+            return LoxStatus::Undefined;
+        };
+
+        let (converted, string) = to_c(&global.unwrap_or(Value::Nil));
+        lox.string = string;
+        *value = converted;
+
+        LoxStatus::Ok
+    })
+    .unwrap_or_else(|status| status)
+}
+
+/// Defines the global `name` as `value`, replacing it if it already exists.
+///
+/// # Safety
+///
+/// `lox` must be a live handle, `name` a NUL-terminated string and, for a
+/// string value, `value.string` a NUL-terminated string too.
+#[no_mangle]
+pub unsafe extern "C" fn lox_set_global(
+    lox: *mut LoxInterpreter,
+    name: *const c_char,
+    value: LoxValue,
+) -> LoxStatus {
+    let (Some(name), Some(value)) = (string(name), from_c(&value)) else {
+        return LoxStatus::InvalidArgument;
+    };
+
+    enter(lox, |lox| {
+        lox.interpreter.globals().borrow_mut().define(name, &value);
+        LoxStatus::Ok
+    })
+    .unwrap_or_else(|status| status)
+}
+
+/// Defines a native taking `arity` arguments that calls `function`.
+///
+/// # Safety
+///
+/// `lox` must be a live handle and `name` a NUL-terminated string.
+/// `function` must be safe to call with `user_data` for as long as the
+/// interpreter lives, and must only read the arguments while it's running.
+#[no_mangle]
+pub unsafe extern "C" fn lox_define_native(
+    lox: *mut LoxInterpreter,
+    name: *const c_char,
+    arity: usize,
+    function: Option<LoxNativeFn>,
+    user_data: *mut c_void,
+) -> LoxStatus {
+    let (Some(name), Some(function)) = (string(name), function) else {
+        return LoxStatus::InvalidArgument;
+    };
+    let user_data = UserData(user_data);
+
+    enter(lox, |lox| {
+        lox.interpreter
+            .define_native(name, arity, move |interpreter, arguments| {
+                // Moves the whole of `user_data` in, not just its pointer.
+                let user_data = user_data;
+                // The strings have to outlive the call.
+                let (arguments, _strings): (Vec<_>, Vec<_>) = arguments.iter().map(to_c).unzip();
+                let mut result = LoxValue::new(LoxTag::Nil);
+                // SAFETY: the host promised that the function can be called
+                // with the user data it was given alongside.
+                let succeeded = unsafe {
+                    function(
+                        user_data.0,
+                        arguments.as_ptr(),
+                        arguments.len(),
+                        &mut result,
+                    )
+                };
+
+                // SAFETY: the function only hands back strings it owns.
+                let result = unsafe { from_c(&result) }.ok_or_else(|| Error::Native {
+                    message: "Native returned an invalid value.".to_string(),
+                })?;
+                if succeeded {
+                    Ok(result)
+                } else {
+                    Err(Error::Native {
+                        message: interpreter.stringify(&result)?,
+                    })
+                }
+            });
+
+        LoxStatus::Ok
+    })
+    .unwrap_or_else(|status| status)
+}
+
+/// Borrows a C string, if it's there and valid UTF-8.
+unsafe fn string<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        return None;
+    }
+
+    CStr::from_ptr(string).to_str().ok()
+}
+
+/// Converts a value for C, along with the string its pointer borrows from.
+fn to_c(value: &Value) -> (LoxValue, Option<CString>) {
+    let (mut converted, string) = match value {
+        Value::Nil => (LoxValue::new(LoxTag::Nil), None),
+        Value::Boolean(b) => (
+            LoxValue {
+                boolean: *b,
+                ..LoxValue::new(LoxTag::Bool)
+            },
+            None,
+        ),
+        Value::Number(n) => (
+            LoxValue {
+                number: *n,
+                ..LoxValue::new(LoxTag::Number)
+            },
+            None,
+        ),
+        Value::String(s) => (LoxValue::new(LoxTag::String), Some(s.to_string())),
+        value => (LoxValue::new(LoxTag::Object), Some(value.to_string())),
+    };
+    // Lox strings can hold NULs, which C strings end at.
+    let string = string.map(|string| {
+        CString::new(string).unwrap_or_else(|error| {
+            let end = error.nul_position();
+            CString::new(&error.into_vec()[..end]).expect("must end before the first NUL")
+        })
+    });
+    if let Some(string) = &string {
+        converted.string = string.as_ptr();
+    }
+
+    (converted, string)
+}
+
+/// Converts a value from C, which can't make objects.
+unsafe fn from_c(value: &LoxValue) -> Option<Value> {
+    match value.tag {
+        LoxTag::Nil => Some(Value::Nil),
+        LoxTag::Bool => Some(Value::Boolean(value.boolean)),
+        LoxTag::Number => Some(Value::Number(value.number)),
+        LoxTag::String => Some(Value::String(string(value.string)?.into())),
+        LoxTag::Object => None,
+    }
+}
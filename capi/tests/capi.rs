@@ -0,0 +1,191 @@
+use lox_capi::*;
+use std::{
+    ffi::{c_char, c_void, CStr, CString},
+    ptr, slice,
+};
+
+unsafe extern "C" fn append(user_data: *mut c_void, text: *const c_char, length: usize) {
+    let output = &mut *user_data.cast::<Vec<u8>>();
+    output.extend_from_slice(slice::from_raw_parts(text.cast(), length));
+}
+
+/// Adds its two arguments, failing if they aren't numbers.
+unsafe extern "C" fn add(
+    _: *mut c_void,
+    arguments: *const LoxValue,
+    count: usize,
+    result: *mut LoxValue,
+) -> bool {
+    let arguments = slice::from_raw_parts(arguments, count);
+    if arguments
+        .iter()
+        .any(|argument| argument.tag != LoxTag::Number)
+    {
+        (*result).tag = LoxTag::String;
+        (*result).string = c"add needs numbers.".as_ptr();
+        return false;
+    }
+
+    (*result).tag = LoxTag::Number;
+    (*result).number = arguments[0].number + arguments[1].number;
+    true
+}
+
+fn run(lox: *mut LoxInterpreter, source: &str) -> LoxStatus {
+    let source = CString::new(source).expect("source must not contain NULs");
+    unsafe { lox_run(lox, source.as_ptr()) }
+}
+
+#[test]
+fn output_goes_to_the_callback() {
+    let mut output = Vec::<u8>::new();
+    unsafe {
+        let lox = lox_new();
+        lox_set_output(lox, Some(append), ptr::from_mut(&mut output).cast());
+        assert_eq!(run(lox, "print 1 + 2;"), LoxStatus::Ok);
+        assert!(lox_last_error(lox).is_null());
+        lox_free(lox);
+    }
+
+    assert_eq!(output, b"3\n");
+}
+
+#[test]
+fn failures_have_statuses_and_messages() {
+    let mut errors = Vec::<u8>::new();
+    unsafe {
+        let lox = lox_new();
+        lox_set_error_output(lox, Some(append), ptr::from_mut(&mut errors).cast());
+
+        assert_eq!(run(lox, "print (;"), LoxStatus::CompileError);
+        assert_eq!(run(lox, "print nil + 1;"), LoxStatus::RuntimeError);
+        assert_eq!(
+            CStr::from_ptr(lox_last_error(lox)).to_str(),
+            Ok("Operands must be two numbers or two strings.\n[line 1]")
+        );
+        assert_eq!(run(lox, "exit(3);"), LoxStatus::Exit);
+        assert_eq!(lox_exit_code(lox), 3);
+
+        assert_eq!(lox_run(lox, ptr::null()), LoxStatus::InvalidArgument);
+        lox_free(lox);
+    }
+
+    assert!(String::from_utf8_lossy(&errors).contains("Operands must be two numbers"));
+}
+
+#[test]
+fn globals_are_read_and_written_as_tagged_values() {
+    unsafe {
+        let lox = lox_new();
+        let mut value = LoxValue {
+            tag: LoxTag::Nil,
+            boolean: false,
+            number: 0.0,
+            string: ptr::null(),
+        };
+
+        lox_set_global(
+            lox,
+            c"name".as_ptr(),
+            LoxValue {
+                tag: LoxTag::String,
+                string: c"Lox".as_ptr(),
+                ..value
+            },
+        );
+        assert_eq!(
+            run(lox, "var greeting = \"Hello, \" + name;\nvar list = [1];"),
+            LoxStatus::Ok
+        );
+
+        assert_eq!(
+            lox_get_global(lox, c"greeting".as_ptr(), &mut value),
+            LoxStatus::Ok
+        );
+        assert_eq!(value.tag, LoxTag::String);
+        assert_eq!(CStr::from_ptr(value.string).to_str(), Ok("Hello, Lox"));
+
+        assert_eq!(
+            lox_get_global(lox, c"list".as_ptr(), &mut value),
+            LoxStatus::Ok
+        );
+        assert_eq!(value.tag, LoxTag::Object);
+        assert_eq!(CStr::from_ptr(value.string).to_str(), Ok("[1]"));
+
+        assert_eq!(
+            lox_get_global(lox, c"missing".as_ptr(), &mut value),
+            LoxStatus::Undefined
+        );
+        lox_free(lox);
+    }
+}
+
+#[test]
+fn natives_call_back_into_c() {
+    let mut output = Vec::<u8>::new();
+    unsafe {
+        let lox = lox_new();
+        lox_set_output(lox, Some(append), ptr::from_mut(&mut output).cast());
+        lox_define_native(lox, c"add".as_ptr(), 2, Some(add), ptr::null_mut());
+
+        assert_eq!(run(lox, "print add(1, 2);"), LoxStatus::Ok);
+        assert_eq!(run(lox, "add(1, \"2\");"), LoxStatus::RuntimeError);
+        assert_eq!(
+            CStr::from_ptr(lox_last_error(lox)).to_str(),
+            Ok("add needs numbers.\n[line 1]")
+        );
+        lox_free(lox);
+    }
+
+    assert_eq!(output, b"3\n");
+}
+
+/// Tries to run more code on the handle that's running it, recording the
+/// status in the result.
+unsafe extern "C" fn reenter(
+    user_data: *mut c_void,
+    _: *const LoxValue,
+    _: usize,
+    result: *mut LoxValue,
+) -> bool {
+    let status = run(user_data.cast(), "print 1;");
+    (*result).tag = LoxTag::Number;
+    (*result).number = status as i32 as f64;
+    true
+}
+
+/// Tries to replace itself as the output callback while it's writing.
+unsafe extern "C" fn replace_output(user_data: *mut c_void, _: *const c_char, _: usize) {
+    let (lox, status) = &mut *user_data.cast::<(*mut LoxInterpreter, Option<LoxStatus>)>();
+    *status = Some(lox_set_output(*lox, None, ptr::null_mut()));
+}
+
+#[test]
+fn calls_from_callbacks_and_natives_are_turned_away() {
+    let mut value = LoxValue {
+        tag: LoxTag::Nil,
+        boolean: false,
+        number: 0.0,
+        string: ptr::null(),
+    };
+    unsafe {
+        let lox = lox_new();
+        lox_define_native(lox, c"reenter".as_ptr(), 0, Some(reenter), lox.cast());
+        assert_eq!(run(lox, "var status = reenter();"), LoxStatus::Ok);
+        assert_eq!(
+            lox_get_global(lox, c"status".as_ptr(), &mut value),
+            LoxStatus::Ok
+        );
+        assert_eq!(value.number, LoxStatus::Busy as i32 as f64);
+
+        let mut replaced = (lox, None);
+        lox_set_output(
+            lox,
+            Some(replace_output),
+            ptr::from_mut(&mut replaced).cast(),
+        );
+        assert_eq!(run(lox, "print 1;"), LoxStatus::Ok);
+        assert_eq!(replaced.1, Some(LoxStatus::Busy));
+        lox_free(lox);
+    }
+}
@@ -0,0 +1,116 @@
+//! Runs the benchmarks in `resources/test/benchmark` on both interpreters.
+//!
+//! `cargo bench --bench backends -- fib zoo` runs only the benchmarks whose
+//! names contain one of the given filters. `LOX_BENCH_RUNS` sets how many
+//! times each benchmark is run; the fastest run is reported. Comparing a run
+//! with `--features send` to one without shows what it costs to make
+//! interpreters `Send`.
+//!
+//! The bytecode compiler only supports expressions, `print`, blocks and
+//! variables so far, so it rejects most benchmarks. Those rows are reported
+//! as unsupported.
+
+use lox_bytecode::vm::Vm;
+use lox_treewalk::{
+    interpreter::Interpreter, parser::Parser, resolver::Resolver, scanner::Scanner,
+};
+use std::{
+    env, fs, io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+fn treewalk(source: &str) -> Option<Duration> {
+    let start = Instant::now();
+
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan();
+    let (statements, errors) = Parser::new(tokens).parse();
+    if !errors.is_empty() {
+        return None;
+    }
+
+    let mut interpreter = Interpreter::new().with_writers(io::sink(), io::stderr());
+    let mut resolver = Resolver::new(&mut interpreter);
+    resolver.resolve_statements(statements.clone());
+    if scanner.had_error() || resolver.had_error() {
+        return None;
+    }
+    interpreter.interpret(statements).ok()?;
+
+    Some(start.elapsed())
+}
+
+fn bytecode(source: &str) -> Option<(Duration, u64)> {
+    let start = Instant::now();
+
+    let mut vm = Vm::new().with_writers(io::sink(), io::stderr());
+    vm.interpret(source).ok()?;
+
+    Some((start.elapsed(), vm.instructions_executed()))
+}
+
+/// Runs `bench` `runs` times and keeps the fastest result.
+fn fastest<T>(
+    runs: usize,
+    bench: impl Fn() -> Option<T>,
+    time: impl Fn(&T) -> Duration,
+) -> Option<T> {
+    (0..runs)
+        .map(|_| bench())
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .min_by_key(|result| time(result))
+}
+
+fn main() -> io::Result<()> {
+    let filters = env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .collect::<Vec<_>>();
+    let runs = env::var("LOX_BENCH_RUNS")
+        .ok()
+        .and_then(|runs| runs.parse().ok())
+        .unwrap_or(1);
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../resources/test/benchmark");
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.sort();
+
+    println!(
+        "{:<20} {:>12} {:>12} {:>16}",
+        "benchmark", "treewalk", "bytecode", "instructions"
+    );
+
+    for path in paths {
+        let name = path
+            .file_stem()
+            .expect("benchmarks must have a name")
+            .to_string_lossy()
+            .into_owned();
+        if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter)) {
+            continue;
+        }
+
+        let source = fs::read_to_string(&path)?;
+
+        let treewalk = match fastest(runs, || treewalk(&source), |elapsed| *elapsed) {
+            Some(elapsed) => format!("{:.3}s", elapsed.as_secs_f64()),
+            None => "error".to_string(),
+        };
+        let (bytecode, instructions) =
+            match fastest(runs, || bytecode(&source), |(elapsed, _)| *elapsed) {
+                Some((elapsed, instructions)) => (
+                    format!("{:.3}s", elapsed.as_secs_f64()),
+                    instructions.to_string(),
+                ),
+                None => ("unsupported".to_string(), "-".to_string()),
+            };
+
+        println!("{name:<20} {treewalk:>12} {bytecode:>12} {instructions:>16}");
+    }
+
+    Ok(())
+}
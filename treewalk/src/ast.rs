@@ -1,4 +1,22 @@
-use crate::{token::Token, value::Value};
+//! The syntax tree the parser produces and the interpreter runs.
+//!
+//! Hosts can build trees themselves, too. Ones the parser would never
+//! produce, such as a unary `+`, fail with
+//! [`Error::Internal`](crate::interpreter::Error::Internal) when they're run
+//! rather than panicking. The builders on [`Expr`] and [`Stmt`] make the
+//! tokens a tree needs, and [`Interpreter::run_statements`] resolves a tree
+//! before running it.
+//!
+//! Every expression has an identity that the resolver keys its scopes on,
+//! so a tree should build a fresh expression each time it uses one rather
+//! than cloning it.
+//!
+//! [`Interpreter::run_statements`]: crate::interpreter::Interpreter::run_statements
+
+use crate::{
+    token::{Span, Token, TokenType},
+    value::Value,
+};
 use std::hash::{Hash, Hasher};
 use uuid::Uuid;
 
@@ -8,6 +26,12 @@ pub enum ExprKind {
         name: Token,
         value: Box<Expr>,
     },
+    /// `[a, b] = value`, where every target is a `Variable`.
+    AssignList {
+        targets: Vec<Expr>,
+        equals: Token,
+        value: Box<Expr>,
+    },
     Binary {
         left: Box<Expr>,
         operator: Token,
@@ -23,21 +47,59 @@ pub enum ExprKind {
         name: Token,
     },
     Grouping(Box<Expr>),
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    /// `object[index] = value`, or with an `operator`, a compound
+    /// assignment or increment that combines the element with `value`.
+    IndexSet {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+        operator: Option<Token>,
+    },
+    Lambda {
+        params: Vec<Param>,
+        body: Vec<Stmt>,
+    },
+    List(Vec<Expr>),
     Literal(Value),
     Logical {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
+    Map {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+    },
+    /// `target++` or `target--`, which is `value` from before the
+    /// `assignment`. Properties and elements are read as they're assigned,
+    /// so `value` is only evaluated for variables.
+    Postfix {
+        value: Box<Expr>,
+        assignment: Box<Expr>,
+    },
+    /// `object.name = value`, or with an `operator`, a compound assignment
+    /// or increment that combines the property with `value`.
     Set {
         object: Box<Expr>,
         name: Token,
         value: Box<Expr>,
+        operator: Option<Token>,
     },
     Super {
         keyword: Token,
         method: Token,
     },
+    Ternary {
+        condition: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
     This(Token),
     Unary {
         operator: Token,
@@ -46,9 +108,39 @@ pub enum ExprKind {
     Variable(Token),
 }
 
+impl ExprKind {
+    /// The line of the expression's first token, or 0 if it has none.
+    fn line(&self) -> usize {
+        match self {
+            Self::Assign { name, .. } | Self::Variable(name) => name.line(),
+            Self::AssignList { targets, .. } => targets.first().map_or(0, Expr::line),
+            Self::Binary { left, .. } | Self::Logical { left, .. } => left.line(),
+            Self::Call { callee, .. } => callee.line(),
+            Self::Get { object, .. }
+            | Self::Index { object, .. }
+            | Self::IndexSet { object, .. }
+            | Self::Set { object, .. } => object.line(),
+            Self::Grouping(inner) => inner.line(),
+            Self::Lambda { .. } | Self::Literal(_) => 0,
+            Self::List(elements) => elements.first().map_or(0, Expr::line),
+            Self::Map { brace, .. } => brace.line(),
+            Self::Postfix { value, .. } => value.line(),
+            Self::Super { keyword, .. } | Self::This(keyword) => keyword.line(),
+            Self::Ternary { condition, .. } => condition.line(),
+            Self::Unary { operator, .. } => operator.line(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Expr {
     id: Uuid,
+    /// The line the expression starts on, or 0 for expressions the parser
+    /// made up.
+    line: usize,
+    /// Where the expression is in its source, or empty for expressions the
+    /// parser made up and ones built by hand.
+    span: Span,
     pub kind: ExprKind,
 }
 
@@ -67,25 +159,231 @@ impl Hash for Expr {
 }
 
 impl Expr {
+    /// Creates an expression on the line of its first token.
     pub fn new(kind: ExprKind) -> Self {
+        let line = kind.line();
+
+        Self::at(kind, line)
+    }
+
+    /// Creates an expression that starts on `line`, for expressions such as
+    /// literals that don't keep the token they start with.
+    pub fn at(kind: ExprKind, line: usize) -> Self {
         let id = Uuid::new_v4();
 
-        Self { id, kind }
+        Self {
+            id,
+            line,
+            span: Span::default(),
+            kind,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Places the expression in its source, so that runtime errors can point
+    /// at it.
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+
+        self
+    }
+
+    pub fn literal(value: Value, line: usize) -> Self {
+        Self::at(ExprKind::Literal(value), line)
+    }
+
+    pub fn variable(name: &str, line: usize) -> Self {
+        Self::new(ExprKind::Variable(Token::identifier(name, line)))
+    }
+
+    pub fn assign(name: &str, value: Expr, line: usize) -> Self {
+        Self::new(ExprKind::Assign {
+            name: Token::identifier(name, line),
+            value: Box::new(value),
+        })
+    }
+
+    /// `left operator right`, where `operator` is an arithmetic or
+    /// comparison operator such as [`TokenType::Plus`].
+    pub fn binary(left: Expr, operator: TokenType, right: Expr) -> Self {
+        let operator = Token::symbol(operator, left.line());
+
+        Self::new(ExprKind::Binary {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        })
+    }
+
+    /// `left and right` or `left or right`.
+    pub fn logical(left: Expr, operator: TokenType, right: Expr) -> Self {
+        let operator = Token::symbol(operator, left.line());
+
+        Self::new(ExprKind::Logical {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        })
+    }
+
+    /// `-right` or `!right`.
+    pub fn unary(operator: TokenType, right: Expr) -> Self {
+        let operator = Token::symbol(operator, right.line());
+
+        Self::new(ExprKind::Unary {
+            operator,
+            right: Box::new(right),
+        })
+    }
+
+    pub fn grouping(inner: Expr) -> Self {
+        Self::new(ExprKind::Grouping(Box::new(inner)))
+    }
+
+    pub fn call(callee: Expr, arguments: Vec<Expr>) -> Self {
+        let paren = Token::symbol(TokenType::RightParen, callee.line());
+
+        Self::new(ExprKind::Call {
+            callee: Box::new(callee),
+            paren,
+            arguments,
+        })
+    }
+
+    pub fn get(object: Expr, name: &str) -> Self {
+        let name = Token::identifier(name, object.line());
+
+        Self::new(ExprKind::Get {
+            object: Box::new(object),
+            name,
+        })
+    }
+
+    pub fn set(object: Expr, name: &str, value: Expr) -> Self {
+        let name = Token::identifier(name, object.line());
+
+        Self::new(ExprKind::Set {
+            object: Box::new(object),
+            name,
+            value: Box::new(value),
+            operator: None,
+        })
+    }
+
+    pub fn list(elements: Vec<Expr>, line: usize) -> Self {
+        Self::at(ExprKind::List(elements), line)
+    }
+
+    /// The expressions directly inside this one. A lambda's body is made of
+    /// statements, so it has none.
+    pub fn subexpressions(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Assign { value, .. } | ExprKind::AssignList { value, .. } => vec![value],
+            ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+                vec![left, right]
+            }
+            ExprKind::Call {
+                callee, arguments, ..
+            } => std::iter::once(&**callee).chain(arguments).collect(),
+            ExprKind::Get { object, .. } => vec![object],
+            ExprKind::Grouping(expr) | ExprKind::Unary { right: expr, .. } => vec![expr],
+            ExprKind::Index { object, index, .. } => vec![object, index],
+            ExprKind::IndexSet {
+                object,
+                index,
+                value,
+                ..
+            } => vec![object, index, value],
+            ExprKind::List(elements) => elements.iter().collect(),
+            ExprKind::Map { entries, .. } => entries
+                .iter()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+            ExprKind::Postfix { value, assignment } => vec![value, assignment],
+            ExprKind::Set { object, value, .. } => vec![object, value],
+            ExprKind::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => vec![condition, then_branch, else_branch],
+            ExprKind::Lambda { .. }
+            | ExprKind::Literal(_)
+            | ExprKind::Super { .. }
+            | ExprKind::This(_)
+            | ExprKind::Variable(_) => vec![],
+        }
+    }
+
+    /// The leftmost token the expression keeps, if it keeps one. Literals,
+    /// and the brackets of groupings and lists, aren't kept.
+    pub fn first_token(&self) -> Option<&Token> {
+        match &self.kind {
+            ExprKind::Assign { name, .. } => Some(name),
+            ExprKind::AssignList { targets, .. } => targets.first()?.first_token(),
+            ExprKind::Binary { left, .. } | ExprKind::Logical { left, .. } => left.first_token(),
+            ExprKind::Call { callee: expr, .. }
+            | ExprKind::Get { object: expr, .. }
+            | ExprKind::Grouping(expr)
+            | ExprKind::Index { object: expr, .. }
+            | ExprKind::IndexSet { object: expr, .. }
+            | ExprKind::Postfix { value: expr, .. }
+            | ExprKind::Set { object: expr, .. }
+            | ExprKind::Ternary {
+                condition: expr, ..
+            } => expr.first_token(),
+            ExprKind::List(elements) => elements.first()?.first_token(),
+            ExprKind::Map { brace, .. } => Some(brace),
+            ExprKind::Super { keyword, .. } => Some(keyword),
+            ExprKind::This(keyword) => Some(keyword),
+            ExprKind::Unary { operator, .. } => Some(operator),
+            ExprKind::Variable(name) => Some(name),
+            ExprKind::Lambda { .. } | ExprKind::Literal(_) => None,
+        }
     }
 }
 
 #[derive(Clone, Debug)]
 pub enum Stmt {
+    /// `assert(condition, message);`, which fails with a runtime error if
+    /// `condition` is falsey. `text` is the condition as it was written, for
+    /// the error message.
+    Assert {
+        keyword: Token,
+        condition: Expr,
+        text: String,
+        message: Option<Expr>,
+    },
     Block(Vec<Stmt>),
+    Break(Token),
     Class {
         name: Token,
         superclass: Option<Expr>,
         methods: Vec<Stmt>,
+        class_methods: Vec<Stmt>,
+        getters: Vec<Stmt>,
     },
+    /// A variable that can't be assigned to after it's initialized.
+    Const {
+        name: Token,
+        initializer: Expr,
+    },
+    Continue(Token),
     Expression(Expr),
+    ForIn {
+        name: Token,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
     Function {
         name: Token,
-        params: Vec<Token>,
+        params: Vec<Param>,
         body: Vec<Stmt>,
     },
     If {
@@ -93,17 +391,184 @@ pub enum Stmt {
         then_branch: Box<Stmt>,
         else_branch: Option<Box<Stmt>>,
     },
-    Print(Expr),
+    /// `path` is the string or name after `import`, and `name` the variable
+    /// the module is bound to.
+    Import {
+        keyword: Token,
+        path: Token,
+        name: Token,
+    },
+    Print {
+        keyword: Token,
+        value: Expr,
+    },
     Return {
         keyword: Token,
         value: Option<Expr>,
     },
+    /// Runs the first case whose value equals `subject`, or else the
+    /// `default` case. Cases don't fall through to the ones after them, and
+    /// `break` leaves the switch early.
+    Switch {
+        keyword: Token,
+        subject: Expr,
+        cases: Vec<SwitchCase>,
+    },
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+    Try {
+        body: Vec<Stmt>,
+        catch: Option<(Token, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    },
     Var {
         name: Token,
         initializer: Option<Expr>,
     },
+    /// `var [a, b] = initializer;`
+    VarList {
+        names: Vec<Token>,
+        equals: Token,
+        initializer: Expr,
+    },
     While {
         condition: Expr,
         body: Box<Stmt>,
+        increment: Option<Expr>,
     },
 }
+
+/// A function parameter. `default` is evaluated in the callee's environment
+/// whenever a call leaves the parameter out.
+#[derive(Clone, Debug)]
+pub struct Param {
+    pub name: Token,
+    pub default: Option<Expr>,
+}
+
+/// A `case` of a `switch` statement, or its `default` case if `value` is
+/// `None`. `keyword` is the `case` or `default` token.
+#[derive(Clone, Debug)]
+pub struct SwitchCase {
+    pub keyword: Token,
+    pub value: Option<Expr>,
+    pub body: Vec<Stmt>,
+}
+
+impl Param {
+    pub fn new(name: &str, line: usize) -> Self {
+        Self {
+            name: Token::identifier(name, line),
+            default: None,
+        }
+    }
+}
+
+impl Stmt {
+    pub fn print(value: Expr) -> Self {
+        Self::Print {
+            keyword: Token::symbol(TokenType::Print, value.line()),
+            value,
+        }
+    }
+
+    pub fn expression(expr: Expr) -> Self {
+        Self::Expression(expr)
+    }
+
+    pub fn var(name: &str, initializer: Option<Expr>, line: usize) -> Self {
+        Self::Var {
+            name: Token::identifier(name, line),
+            initializer,
+        }
+    }
+
+    pub fn function(name: &str, params: &[&str], body: Vec<Stmt>, line: usize) -> Self {
+        Self::Function {
+            name: Token::identifier(name, line),
+            params: params.iter().map(|param| Param::new(param, line)).collect(),
+            body,
+        }
+    }
+
+    pub fn return_value(value: Option<Expr>, line: usize) -> Self {
+        Self::Return {
+            keyword: Token::symbol(TokenType::Return, line),
+            value,
+        }
+    }
+
+    pub fn if_else(condition: Expr, then_branch: Stmt, else_branch: Option<Stmt>) -> Self {
+        Self::If {
+            condition,
+            then_branch: Box::new(then_branch),
+            else_branch: else_branch.map(Box::new),
+        }
+    }
+
+    pub fn while_loop(condition: Expr, body: Stmt) -> Self {
+        Self::While {
+            condition,
+            body: Box::new(body),
+            increment: None,
+        }
+    }
+
+    pub fn block(statements: Vec<Stmt>) -> Self {
+        Self::Block(statements)
+    }
+
+    /// The line the statement starts on, if it's known. Blocks don't have
+    /// one of their own.
+    pub fn line(&self) -> Option<usize> {
+        let line = match self {
+            Self::Block(_) | Self::Try { .. } => 0,
+            Self::Break(keyword) | Self::Continue(keyword) => keyword.line(),
+            Self::Class { name, .. }
+            | Self::Const { name, .. }
+            | Self::ForIn { name, .. }
+            | Self::Function { name, .. }
+            | Self::Var { name, .. } => name.line(),
+            Self::Expression(expr) => expr.line(),
+            Self::If { condition, .. } | Self::While { condition, .. } => condition.line(),
+            Self::Assert { keyword, .. }
+            | Self::Import { keyword, .. }
+            | Self::Print { keyword, .. }
+            | Self::Return { keyword, .. }
+            | Self::Switch { keyword, .. }
+            | Self::Throw { keyword, .. } => keyword.line(),
+            Self::VarList { equals, .. } => equals.line(),
+        };
+
+        (line > 0).then_some(line)
+    }
+
+    /// The leftmost token the statement keeps, if it keeps one. Declarations
+    /// keep their name but not the keyword before it.
+    pub fn first_token(&self) -> Option<&Token> {
+        match self {
+            Self::Assert { keyword, .. }
+            | Self::Break(keyword)
+            | Self::Continue(keyword)
+            | Self::Import { keyword, .. }
+            | Self::Print { keyword, .. }
+            | Self::Return { keyword, .. }
+            | Self::Switch { keyword, .. }
+            | Self::Throw { keyword, .. } => Some(keyword),
+            Self::Class { name, .. }
+            | Self::Const { name, .. }
+            | Self::ForIn { name, .. }
+            | Self::Function { name, .. }
+            | Self::Var { name, .. } => Some(name),
+            Self::VarList { names, .. } => names.first(),
+            Self::Expression(expr) => expr.first_token(),
+            Self::If { condition, .. } | Self::While { condition, .. } => condition.first_token(),
+            Self::Block(statements)
+            | Self::Try {
+                body: statements, ..
+            } => statements.iter().find_map(Self::first_token),
+        }
+    }
+}
@@ -0,0 +1,373 @@
+//! Renders syntax trees for debugging the parser.
+//!
+//! Two forms are supported: the parenthesized prefix notation from the book,
+//! with one top-level statement per line, and an indented tree with one node
+//! per line.
+
+use crate::{
+    ast::{Expr, ExprKind, Param, Stmt},
+    token::Token,
+    value::Value,
+};
+use itertools::Itertools;
+
+/// A form-agnostic view of a syntax tree node.
+enum Node {
+    Atom(String),
+    List(String, Vec<Node>),
+}
+
+impl Node {
+    fn atom(label: impl Into<String>) -> Self {
+        Self::Atom(label.into())
+    }
+
+    fn list(label: impl Into<String>, children: Vec<Node>) -> Self {
+        Self::List(label.into(), children)
+    }
+
+    fn parenthesize(&self) -> String {
+        match self {
+            Self::Atom(label) => label.clone(),
+            Self::List(label, children) => {
+                let parts = std::iter::once(label.clone())
+                    .chain(children.iter().map(Node::parenthesize))
+                    .join(" ");
+
+                format!("({parts})")
+            }
+        }
+    }
+
+    fn indent(&self, depth: usize, out: &mut String) {
+        let label = match self {
+            Self::Atom(label) | Self::List(label, _) => label,
+        };
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(label);
+        out.push('\n');
+
+        if let Self::List(_, children) = self {
+            for child in children {
+                child.indent(depth + 1, out);
+            }
+        }
+    }
+}
+
+/// Prints an expression in parenthesized prefix notation, e.g.
+/// `(* (- 123) (group 45.67))`.
+pub fn expr_to_string(expr: &Expr) -> String {
+    expr_node(expr).parenthesize()
+}
+
+/// Prints each statement in parenthesized prefix notation on its own line.
+pub fn to_string(statements: &[Stmt]) -> String {
+    statements
+        .iter()
+        .map(|stmt| format!("{}\n", stmt_node(stmt).parenthesize()))
+        .collect()
+}
+
+/// Prints the statements as an indented tree, two spaces per level.
+pub fn to_tree(statements: &[Stmt]) -> String {
+    let mut out = String::new();
+    for stmt in statements {
+        stmt_node(stmt).indent(0, &mut out);
+    }
+
+    out
+}
+
+fn name(token: &Token) -> Node {
+    Node::atom(token.lexeme())
+}
+
+/// Compound assignments to properties and elements are printed with their
+/// operator, as in `(+= (. a x) 1)`.
+fn assignment(operator: &Option<Token>) -> String {
+    match operator {
+        Some(operator) => format!("{}=", operator.lexeme()),
+        None => "=".to_string(),
+    }
+}
+
+fn literal(value: &Value) -> Node {
+    match value {
+        Value::String(s) => Node::atom(format!("{s:?}")),
+        value => Node::atom(value.to_string()),
+    }
+}
+
+/// Parameters with a default are printed as `(= name default)`.
+fn params(params: &[Param]) -> Node {
+    let param = |param: &Param| match &param.default {
+        Some(default) => Node::list("=", vec![name(&param.name), expr_node(default)]),
+        None => name(&param.name),
+    };
+
+    Node::list("params", params.iter().map(param).collect())
+}
+
+fn block(label: &str, statements: &[Stmt]) -> Node {
+    Node::list(label, statements.iter().map(stmt_node).collect())
+}
+
+fn function(label: &str, stmt: &Stmt) -> Node {
+    match stmt {
+        Stmt::Function {
+            name: fun_name,
+            params: fun_params,
+            body,
+        } => {
+            let mut children = vec![name(fun_name), params(fun_params)];
+            children.extend(body.iter().map(stmt_node));
+
+            Node::list(label, children)
+        }
+        stmt => stmt_node(stmt),
+    }
+}
+
+fn expr_node(expr: &Expr) -> Node {
+    match &expr.kind {
+        ExprKind::Assign {
+            name: target,
+            value,
+        } => Node::list("=", vec![name(target), expr_node(value)]),
+        ExprKind::AssignList { targets, value, .. } => Node::list(
+            "=",
+            vec![
+                Node::list("list", targets.iter().map(expr_node).collect()),
+                expr_node(value),
+            ],
+        ),
+        ExprKind::Binary {
+            left,
+            operator,
+            right,
+        }
+        | ExprKind::Logical {
+            left,
+            operator,
+            right,
+        } => Node::list(operator.lexeme(), vec![expr_node(left), expr_node(right)]),
+        ExprKind::Call {
+            callee, arguments, ..
+        } => {
+            let mut children = vec![expr_node(callee)];
+            children.extend(arguments.iter().map(expr_node));
+
+            Node::list("call", children)
+        }
+        ExprKind::Get {
+            object,
+            name: field,
+        } => Node::list(".", vec![expr_node(object), name(field)]),
+        ExprKind::Grouping(inner) => Node::list("group", vec![expr_node(inner)]),
+        ExprKind::Index { object, index, .. } => {
+            Node::list("[]", vec![expr_node(object), expr_node(index)])
+        }
+        ExprKind::IndexSet {
+            object,
+            index,
+            value,
+            operator,
+            ..
+        } => Node::list(
+            assignment(operator),
+            vec![
+                Node::list("[]", vec![expr_node(object), expr_node(index)]),
+                expr_node(value),
+            ],
+        ),
+        ExprKind::Lambda {
+            params: fun_params,
+            body,
+        } => {
+            let mut children = vec![params(fun_params)];
+            children.extend(body.iter().map(stmt_node));
+
+            Node::list("fun", children)
+        }
+        ExprKind::List(elements) => Node::list("list", elements.iter().map(expr_node).collect()),
+        ExprKind::Literal(value) => literal(value),
+        ExprKind::Map { entries, .. } => Node::list(
+            "map",
+            entries
+                .iter()
+                .map(|(key, value)| Node::list(":", vec![expr_node(key), expr_node(value)]))
+                .collect(),
+        ),
+        ExprKind::Postfix { assignment, .. } => Node::list("postfix", vec![expr_node(assignment)]),
+        ExprKind::Set {
+            object,
+            name: field,
+            value,
+            operator,
+        } => Node::list(
+            assignment(operator),
+            vec![
+                Node::list(".", vec![expr_node(object), name(field)]),
+                expr_node(value),
+            ],
+        ),
+        ExprKind::Super { method, .. } => Node::list("super", vec![name(method)]),
+        ExprKind::Ternary {
+            condition,
+            then_branch,
+            else_branch,
+        } => Node::list(
+            "?",
+            vec![
+                expr_node(condition),
+                expr_node(then_branch),
+                expr_node(else_branch),
+            ],
+        ),
+        ExprKind::This(_) => Node::atom("this"),
+        ExprKind::Unary { operator, right } => {
+            Node::list(operator.lexeme(), vec![expr_node(right)])
+        }
+        ExprKind::Variable(variable) => name(variable),
+    }
+}
+
+fn stmt_node(stmt: &Stmt) -> Node {
+    match stmt {
+        Stmt::Block(statements) => block("block", statements),
+        Stmt::Break(_) => Node::list("break", vec![]),
+        Stmt::Class {
+            name: class_name,
+            superclass,
+            methods,
+            class_methods,
+            getters,
+        } => {
+            let mut children = vec![name(class_name)];
+            if let Some(superclass) = superclass {
+                children.push(Node::list("<", vec![expr_node(superclass)]));
+            }
+            children.extend(methods.iter().map(|method| function("fun", method)));
+            children.extend(
+                class_methods
+                    .iter()
+                    .map(|method| function("class-fun", method)),
+            );
+            children.extend(getters.iter().map(|getter| match getter {
+                Stmt::Function {
+                    name: getter_name,
+                    body,
+                    ..
+                } => {
+                    let mut children = vec![name(getter_name)];
+                    children.extend(body.iter().map(stmt_node));
+
+                    Node::list("getter", children)
+                }
+                stmt => stmt_node(stmt),
+            }));
+
+            Node::list("class", children)
+        }
+        Stmt::Const {
+            name: constant,
+            initializer,
+        } => Node::list("const", vec![name(constant), expr_node(initializer)]),
+        Stmt::Continue(_) => Node::list("continue", vec![]),
+        Stmt::Expression(expr) => Node::list(";", vec![expr_node(expr)]),
+        Stmt::ForIn {
+            name: variable,
+            iterable,
+            body,
+        } => Node::list(
+            "for-in",
+            vec![name(variable), expr_node(iterable), stmt_node(body)],
+        ),
+        Stmt::Function { .. } => function("fun", stmt),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let mut children = vec![expr_node(condition), stmt_node(then_branch)];
+            if let Some(else_branch) = else_branch {
+                children.push(stmt_node(else_branch));
+            }
+
+            Node::list("if", children)
+        }
+        Stmt::Assert {
+            condition, message, ..
+        } => {
+            let mut children = vec![expr_node(condition)];
+            children.extend(message.iter().map(expr_node));
+
+            Node::list("assert", children)
+        }
+        Stmt::Print { value: expr, .. } => Node::list("print", vec![expr_node(expr)]),
+        Stmt::Return { value, .. } => Node::list("return", value.iter().map(expr_node).collect()),
+        Stmt::Switch { subject, cases, .. } => {
+            let mut children = vec![expr_node(subject)];
+            for case in cases {
+                children.push(match &case.value {
+                    Some(value) => {
+                        let mut case_children = vec![expr_node(value)];
+                        case_children.extend(case.body.iter().map(stmt_node));
+                        Node::list("case", case_children)
+                    }
+                    None => block("default", &case.body),
+                });
+            }
+
+            Node::list("switch", children)
+        }
+        Stmt::Throw { value, .. } => Node::list("throw", vec![expr_node(value)]),
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            let mut children = vec![block("block", body)];
+            if let Some((variable, body)) = catch {
+                let mut catch = vec![name(variable)];
+                catch.extend(body.iter().map(stmt_node));
+                children.push(Node::list("catch", catch));
+            }
+            if let Some(body) = finally {
+                children.push(block("finally", body));
+            }
+
+            Node::list("try", children)
+        }
+        Stmt::Import { path, .. } => Node::list("import", vec![Node::atom(path.lexeme())]),
+        Stmt::Var {
+            name: variable,
+            initializer,
+        } => {
+            let mut children = vec![name(variable)];
+            children.extend(initializer.iter().map(expr_node));
+
+            Node::list("var", children)
+        }
+        Stmt::VarList {
+            names, initializer, ..
+        } => Node::list(
+            "var",
+            vec![
+                Node::list("list", names.iter().map(name).collect()),
+                expr_node(initializer),
+            ],
+        ),
+        Stmt::While {
+            condition,
+            body,
+            increment,
+        } => {
+            let mut children = vec![expr_node(condition), stmt_node(body)];
+            children.extend(increment.iter().map(expr_node));
+
+            Node::list("while", children)
+        }
+    }
+}
@@ -1,20 +1,29 @@
 use crate::{
     interpreter::{Error, Interpreter},
+    sync::Threadsafe,
     value::Value,
 };
 use std::{
     any::Any,
     fmt::{Debug, Display},
+    ops::RangeInclusive,
 };
 
-pub trait Callable: Debug + Display {
-    fn arity(&self) -> usize;
+pub trait Callable: Debug + Display + Threadsafe {
+    /// The numbers of arguments the callable accepts.
+    fn arity(&self) -> RangeInclusive<usize>;
 
     fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, Error>;
 
     fn box_clone(&self) -> Box<dyn Callable>;
 
     fn as_any(&self) -> &dyn Any;
+
+    /// Whether `other` is this same callable, for `==`. Callables that
+    /// keep no state of their own are the same as any other of their type.
+    fn same_as(&self, other: &dyn Callable) -> bool {
+        self.as_any().type_id() == other.as_any().type_id()
+    }
 }
 
 impl Clone for Box<dyn Callable> {
@@ -1,29 +1,44 @@
 use crate::{
     callable::Callable,
     function::LoxFunction,
+    gc::{Trace, Tracer},
     interpreter::{Error, Interpreter},
+    sync::{Lock, Shared},
     token::Token,
     value::Value,
 };
-use std::{any::Any, cell::RefCell, collections::HashMap, fmt, rc::Rc};
+use std::{any::Any, collections::HashMap, fmt, ops::RangeInclusive};
+use uuid::Uuid;
 
 #[derive(Clone, Debug)]
 pub struct LoxClass {
-    name: String,
+    /// Tells apart the classes made by each run of a declaration, since
+    /// instances keep copies of their class.
+    id: Uuid,
+    /// The name in the declaration, which private properties declared by
+    /// the class belong to.
+    name: Token,
     superclass: Option<Box<LoxClass>>,
     methods: HashMap<String, LoxFunction>,
+    class_methods: HashMap<String, LoxFunction>,
+    getters: HashMap<String, LoxFunction>,
 }
 
 impl LoxClass {
     pub fn new(
-        name: &str,
+        name: &Token,
         superclass: Option<Box<LoxClass>>,
         methods: HashMap<String, LoxFunction>,
+        class_methods: HashMap<String, LoxFunction>,
+        getters: HashMap<String, LoxFunction>,
     ) -> Self {
         Self {
-            name: name.to_string(),
+            id: Uuid::new_v4(),
+            name: name.clone(),
             superclass,
             methods,
+            class_methods,
+            getters,
         }
     }
 
@@ -31,6 +46,14 @@ impl LoxClass {
         Value::Callable(Box::new(self))
     }
 
+    pub fn name(&self) -> &str {
+        self.name.lexeme()
+    }
+
+    pub fn superclass(&self) -> Option<&LoxClass> {
+        self.superclass.as_deref()
+    }
+
     pub fn find_method(&self, name: &str) -> Option<LoxFunction> {
         let method = self.methods.get(name).cloned();
 
@@ -42,25 +65,89 @@ impl LoxClass {
             None
         }
     }
+
+    pub fn find_class_method(&self, name: &str) -> Option<LoxFunction> {
+        let method = self.class_methods.get(name).cloned();
+
+        if method.is_some() {
+            method
+        } else if let Some(superclass) = &self.superclass {
+            superclass.find_class_method(name)
+        } else {
+            None
+        }
+    }
+
+    pub fn find_getter(&self, name: &str) -> Option<LoxFunction> {
+        let getter = self.getters.get(name).cloned();
+
+        if getter.is_some() {
+            getter
+        } else if let Some(superclass) = &self.superclass {
+            superclass.find_getter(name)
+        } else {
+            None
+        }
+    }
+
+    /// The declaration of the class in this one's chain that declares the
+    /// method, class method or getter `name`.
+    pub(crate) fn declarer(&self, name: &str) -> Option<&Token> {
+        if self.methods.contains_key(name)
+            || self.class_methods.contains_key(name)
+            || self.getters.contains_key(name)
+        {
+            Some(&self.name)
+        } else {
+            self.superclass.as_ref()?.declarer(name)
+        }
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value, Error> {
+        if let Some(method) = self.find_class_method(name.lexeme()) {
+            Ok(method.bind_class(self.clone()).value())
+        } else {
+            Err(Error::Runtime {
+                message: format!("Undefined property '{}'.", name.lexeme()),
+                line: name.line(),
+                trace: vec![],
+                span: None,
+            })
+        }
+    }
 }
 
 impl fmt::Display for LoxClass {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<class {}>", self.name)
+        write!(f, "<class {}>", self.name())
+    }
+}
+
+impl LoxClass {
+    pub(crate) fn trace(&self, tracer: &mut Tracer) {
+        self.methods
+            .values()
+            .chain(self.class_methods.values())
+            .chain(self.getters.values())
+            .for_each(|method| method.trace(tracer));
+        if let Some(superclass) = &self.superclass {
+            superclass.trace(tracer);
+        }
     }
 }
 
 impl Callable for LoxClass {
-    fn arity(&self) -> usize {
+    fn arity(&self) -> RangeInclusive<usize> {
         if let Some(initializer) = self.find_method("init") {
             initializer.arity()
         } else {
-            0
+            0..=0
         }
     }
 
     fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, Error> {
-        let instance = Rc::new(RefCell::new(LoxInstance::new(self)));
+        let instance = Shared::new(Lock::new(LoxInstance::new(self)));
+        interpreter.track(&instance);
         if let Some(initializer) = self.find_method("init") {
             initializer
                 .bind(instance.clone())
@@ -77,12 +164,22 @@ impl Callable for LoxClass {
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn same_as(&self, other: &dyn Callable) -> bool {
+        other
+            .as_any()
+            .downcast_ref::<Self>()
+            .is_some_and(|other| self.id == other.id)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct LoxInstance {
     class: LoxClass,
-    fields: HashMap<String, Value>,
+    fields: HashMap<Shared<str>, Value>,
+    /// The classes whose methods set each private field, which only they
+    /// can use.
+    owners: HashMap<Shared<str>, Token>,
 }
 
 impl LoxInstance {
@@ -90,17 +187,37 @@ impl LoxInstance {
         Self {
             class: class.clone(),
             fields: HashMap::new(),
+            owners: HashMap::new(),
         }
     }
 
     pub fn value(self) -> Value {
-        Value::Instance(Rc::new(RefCell::new(self)))
+        Value::Instance(Shared::new(Lock::new(self)))
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.fields.iter().map(|(name, value)| (&**name, value))
     }
 
-    pub fn get(instance: Rc<RefCell<Self>>, name: &Token) -> Result<Value, Error> {
+    pub fn class(&self) -> &LoxClass {
+        &self.class
+    }
+
+    pub fn get(
+        instance: Shared<Lock<Self>>,
+        name: &Token,
+        interpreter: &mut Interpreter,
+    ) -> Result<Value, Error> {
         let instance_clone = instance.clone();
-        if let Some(value) = instance.borrow().fields.get(name.lexeme()) {
-            Ok(value.clone())
+        let field = instance.borrow().fields.get(name.lexeme()).cloned();
+        // Look the getter up before calling it, since its body may need to
+        // borrow the instance again.
+        let getter = instance.borrow().class.find_getter(name.lexeme());
+
+        if let Some(value) = field {
+            Ok(value)
+        } else if let Some(getter) = getter {
+            interpreter.call(&getter.bind(instance_clone), vec![], name.line())
         } else if let Some(method) = instance.borrow().class.find_method(name.lexeme()) {
             let method = method.bind(instance_clone);
             Ok(method.value())
@@ -108,17 +225,60 @@ impl LoxInstance {
             Err(Error::Runtime {
                 message: format!("Undefined property '{}'.", name.lexeme()),
                 line: name.line(),
+                trace: vec![],
+                span: None,
             })
         }
     }
 
     pub fn set(&mut self, name: &Token, value: Value) {
-        self.fields.insert(name.lexeme().to_string(), value);
+        self.fields.insert(name.name(), value);
+    }
+
+    /// The class the private property `name` belongs to: the one that set
+    /// it if it's a field, or the one that declares it if it's a method or
+    /// getter.
+    pub(crate) fn owner(&self, name: &str) -> Option<&Token> {
+        self.owners.get(name).or_else(|| self.class.declarer(name))
+    }
+
+    /// Sets the private field `name`, which belongs to `class` from then on
+    /// if it didn't belong to one already.
+    pub(crate) fn set_private(&mut self, name: &Token, value: Value, class: &Token) {
+        if self.owner(name.lexeme()).is_none() {
+            self.owners.insert(name.name(), class.clone());
+        }
+        self.set(name, value);
+    }
+
+    pub fn field(&self, name: &str) -> Option<Value> {
+        self.fields.get(name).cloned()
+    }
+
+    /// Sets the field `name`. Names made at runtime aren't interned, so that
+    /// scripts making lots of them can't fill the table.
+    pub fn set_field(&mut self, name: &str, value: Value) {
+        self.fields.insert(Shared::from(name), value);
+    }
+
+    pub fn remove_field(&mut self, name: &str) -> Option<Value> {
+        self.fields.remove(name)
+    }
+}
+
+impl Trace for LoxInstance {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.class.trace(tracer);
+        self.fields.values().for_each(|value| tracer.value(value));
+    }
+
+    fn clear(&mut self) {
+        self.fields.clear();
     }
 }
 
 impl fmt::Display for LoxInstance {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<inst {}>", self.class.name)
+        write!(f, "<inst {}>", self.class.name())
     }
 }
@@ -1,14 +1,64 @@
 use crate::{
     callable::Callable,
     interpreter::{Error, Interpreter},
+    sync::{Lock, Shared},
     value::Value,
 };
 use std::{
     any::Any,
     fmt,
+    ops::RangeInclusive,
     time::{SystemTime, UNIX_EPOCH},
 };
 
+/// A clock for `clock()` to read instead of the system's, so that scripts
+/// that time themselves print the same thing on every run.
+///
+/// Clones share the same time, so the host can keep one to move the clock
+/// while the interpreter reads another.
+#[derive(Clone, Debug, Default)]
+pub struct VirtualClock {
+    now: Shared<Lock<f64>>,
+    /// How far the clock moves on by itself each time it's read.
+    tick: f64,
+}
+
+impl VirtualClock {
+    /// A clock that stands still at zero until it's moved.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A clock that starts at zero and moves on by `tick` seconds each time
+    /// it's read.
+    pub fn ticking(tick: f64) -> Self {
+        Self {
+            now: Shared::default(),
+            tick,
+        }
+    }
+
+    /// The time `clock()` will return next, in seconds.
+    pub fn now(&self) -> f64 {
+        *self.now.borrow()
+    }
+
+    pub fn set(&self, secs: f64) {
+        *self.now.borrow_mut() = secs;
+    }
+
+    pub fn advance(&self, secs: f64) {
+        *self.now.borrow_mut() += secs;
+    }
+
+    fn read(&self) -> f64 {
+        let now = self.now();
+        self.advance(self.tick);
+
+        now
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Clock;
 
@@ -25,15 +75,18 @@ impl fmt::Display for Clock {
 }
 
 impl Callable for Clock {
-    fn arity(&self) -> usize {
-        0
+    fn arity(&self) -> RangeInclusive<usize> {
+        0..=0
     }
 
-    fn call(&self, _: &mut Interpreter, _: Vec<Value>) -> Result<Value, Error> {
-        let secs = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("we mustn't travel back in time")
-            .as_secs_f64();
+    fn call(&self, interpreter: &mut Interpreter, _: Vec<Value>) -> Result<Value, Error> {
+        let secs = match interpreter.virtual_clock() {
+            Some(clock) => clock.read(),
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("we mustn't travel back in time")
+                .as_secs_f64(),
+        };
 
         Ok(Value::Number(secs))
     }
@@ -0,0 +1,86 @@
+use crate::{
+    callable::Callable,
+    interpreter::{Error, Interpreter},
+    value::Value,
+};
+use std::{any::Any, fmt, ops::RangeInclusive};
+
+/// Converts a string to a number, or returns `nil` if it can't be parsed.
+///
+/// Lox has no literals for infinity or NaN, so strings like `"inf"` and
+/// `"NaN"`, and numbers too large to represent, give `nil` too.
+#[derive(Clone, Debug)]
+pub struct Num;
+
+impl Num {
+    pub fn value() -> Value {
+        Value::Callable(Box::new(Self))
+    }
+}
+
+impl fmt::Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+impl Callable for Num {
+    fn arity(&self) -> RangeInclusive<usize> {
+        1..=1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, Error> {
+        let value = match &arguments[0] {
+            Value::Number(n) => Value::Number(*n),
+            Value::String(s) => match s.trim().parse::<f64>() {
+                Ok(n) if n.is_finite() => Value::Number(n),
+                _ => Value::Nil,
+            },
+            _ => Value::Nil,
+        };
+
+        Ok(value)
+    }
+
+    fn box_clone(&self) -> Box<dyn Callable> {
+        Box::new((*self).clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Converts any value to the string `print` would show for it.
+#[derive(Clone, Debug)]
+pub struct Str;
+
+impl Str {
+    pub fn value() -> Value {
+        Value::Callable(Box::new(Self))
+    }
+}
+
+impl fmt::Display for Str {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+impl Callable for Str {
+    fn arity(&self) -> RangeInclusive<usize> {
+        1..=1
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, Error> {
+        Ok(Value::String(interpreter.stringify(&arguments[0])?.into()))
+    }
+
+    fn box_clone(&self) -> Box<dyn Callable> {
+        Box::new((*self).clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,226 @@
+//! Records which lines of a script and its modules run, for writing an lcov
+//! report.
+//!
+//! [`Coverage`] is a [`Hook`] that counts the statements started on each
+//! line. When the report is written, each file that ran is parsed again to
+//! find the lines with statements that never did, so that they're reported
+//! with a count of zero.
+
+use crate::{
+    ast::{Expr, ExprKind, Param, Stmt},
+    interpreter::{Error, Hook, Interpreter},
+    parser::Parser,
+    scanner::Scanner,
+    sync::{Lock, Shared},
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Statement counts by line, shared between the copy of a `Coverage` given
+/// to the interpreter and the one kept to write the report.
+#[derive(Clone, Default)]
+pub struct Coverage {
+    hits: Shared<Lock<BTreeMap<PathBuf, BTreeMap<usize, u64>>>>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes an lcov tracefile with a record for each file that ran.
+    pub fn write_lcov(&self, output: &mut impl Write) -> io::Result<()> {
+        for (file, hits) in self.hits.borrow().iter() {
+            let mut lines = statement_lines(file);
+            for (line, count) in hits {
+                *lines.entry(*line).or_default() += count;
+            }
+
+            writeln!(output, "TN:")?;
+            writeln!(output, "SF:{}", file.display())?;
+            for (line, count) in &lines {
+                writeln!(output, "DA:{line},{count}")?;
+            }
+            writeln!(output, "LF:{}", lines.len())?;
+            let hit = lines.values().filter(|count| **count > 0).count();
+            writeln!(output, "LH:{hit}")?;
+            writeln!(output, "end_of_record")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Hook for Coverage {
+    fn before_statement(
+        &mut self,
+        interpreter: &mut Interpreter,
+        line: usize,
+    ) -> Result<(), Error> {
+        let Some(file) = interpreter.current_file() else {
+            return Ok(());
+        };
+
+        let mut hits = self.hits.borrow_mut();
+        if !hits.contains_key(file) {
+            hits.insert(file.to_path_buf(), BTreeMap::new());
+        }
+        if let Some(lines) = hits.get_mut(file) {
+            *lines.entry(line).or_default() += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// The lines of `file` that statements start on, each with a count of zero.
+fn statement_lines(file: &Path) -> BTreeMap<usize, u64> {
+    let mut lines = BTreeMap::new();
+    let Ok(source) = fs::read_to_string(file) else {
+        return lines;
+    };
+
+    let mut scanner = Scanner::new(&source);
+    let tokens = scanner.scan();
+    let (statements, _) = Parser::new(tokens).parse();
+    add_statements(&statements, &mut lines);
+
+    lines
+}
+
+fn add_statements(statements: &[Stmt], lines: &mut BTreeMap<usize, u64>) {
+    for statement in statements {
+        add_statement(statement, lines);
+    }
+}
+
+fn add_statement(statement: &Stmt, lines: &mut BTreeMap<usize, u64>) {
+    if let Some(line) = statement.line() {
+        lines.insert(line, 0);
+    }
+
+    match statement {
+        Stmt::Block(statements) => add_statements(statements, lines),
+        Stmt::Class {
+            superclass,
+            methods,
+            class_methods,
+            getters,
+            ..
+        } => {
+            if let Some(superclass) = superclass {
+                add_expr(superclass, lines);
+            }
+            // Methods are never run as statements themselves, only their
+            // bodies are.
+            for method in methods.iter().chain(class_methods).chain(getters) {
+                if let Stmt::Function { params, body, .. } = method {
+                    add_function(params, body, lines);
+                }
+            }
+        }
+        Stmt::Assert {
+            condition, message, ..
+        } => {
+            add_expr(condition, lines);
+            if let Some(message) = message {
+                add_expr(message, lines);
+            }
+        }
+        Stmt::Const {
+            initializer: expr, ..
+        }
+        | Stmt::Expression(expr)
+        | Stmt::Print { value: expr, .. }
+        | Stmt::Throw { value: expr, .. }
+        | Stmt::VarList {
+            initializer: expr, ..
+        } => add_expr(expr, lines),
+        Stmt::ForIn { iterable, body, .. } => {
+            add_expr(iterable, lines);
+            add_statement(body, lines);
+        }
+        Stmt::Function { params, body, .. } => add_function(params, body, lines),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            add_expr(condition, lines);
+            add_statement(then_branch, lines);
+            if let Some(else_branch) = else_branch {
+                add_statement(else_branch, lines);
+            }
+        }
+        Stmt::Return {
+            value: Some(expr), ..
+        }
+        | Stmt::Var {
+            initializer: Some(expr),
+            ..
+        } => add_expr(expr, lines),
+        Stmt::Switch { subject, cases, .. } => {
+            add_expr(subject, lines);
+            for case in cases {
+                if let Some(value) = &case.value {
+                    add_expr(value, lines);
+                }
+                add_statements(&case.body, lines);
+            }
+        }
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            add_statements(body, lines);
+            if let Some((_, body)) = catch {
+                add_statements(body, lines);
+            }
+            if let Some(finally) = finally {
+                add_statements(finally, lines);
+            }
+        }
+        Stmt::While {
+            condition,
+            body,
+            increment,
+        } => {
+            add_expr(condition, lines);
+            add_statement(body, lines);
+            if let Some(increment) = increment {
+                add_expr(increment, lines);
+            }
+        }
+        Stmt::Break(_)
+        | Stmt::Continue(_)
+        | Stmt::Import { .. }
+        | Stmt::Return { value: None, .. }
+        | Stmt::Var {
+            initializer: None, ..
+        } => {}
+    }
+}
+
+fn add_function(params: &[Param], body: &[Stmt], lines: &mut BTreeMap<usize, u64>) {
+    for default in params.iter().filter_map(|param| param.default.as_ref()) {
+        add_expr(default, lines);
+    }
+    add_statements(body, lines);
+}
+
+/// Adds the lines of the statements in any lambdas in `expr`.
+fn add_expr(expr: &Expr, lines: &mut BTreeMap<usize, u64>) {
+    match &expr.kind {
+        ExprKind::Lambda { params, body } => add_function(params, body, lines),
+        _ => {
+            for subexpression in expr.subexpressions() {
+                add_expr(subexpression, lines);
+            }
+        }
+    }
+}
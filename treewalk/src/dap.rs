@@ -0,0 +1,600 @@
+//! A Debug Adapter Protocol server, so that editors can debug Lox scripts.
+//!
+//! Messages are JSON with a `Content-Length` header, read from one stream
+//! and written to another, normally stdin and stdout. A thread reads
+//! requests as they arrive so that `pause` and new breakpoints take effect
+//! while the script runs; anything else sent while it runs is answered when
+//! it next stops. The script's own output is sent as `output` events, since
+//! the output stream belongs to the protocol.
+
+use crate::{
+    debugger::{describe, Breakpoint, Mode},
+    framing,
+    interpreter::{Error, Hook, Interpreter},
+    json::Json,
+    sync::{Lock, Shared, Writer},
+};
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, BufReader, Read, Write},
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Mutex, PoisonError,
+    },
+    thread,
+};
+
+/// Scripts run on a single thread, which is the only one reported.
+const THREAD_ID: usize = 1;
+
+const LOCALS: usize = 1;
+const GLOBALS: usize = 2;
+
+/// Sends each message in `input` to `requests`, until either ends.
+fn read_messages(input: impl Read, requests: mpsc::Sender<Json>) {
+    let mut input = BufReader::new(input);
+    while let Some(message) = framing::read(&mut input) {
+        if requests.send(message).is_err() {
+            return;
+        }
+    }
+}
+
+/// The writing half of the protocol.
+struct Connection {
+    output: Box<dyn Writer>,
+    seq: usize,
+}
+
+impl Connection {
+    fn send(&mut self, fields: Vec<(&str, Json)>) {
+        self.seq += 1;
+        let mut message = vec![("seq", self.seq.into())];
+        message.extend(fields);
+        framing::write(&mut self.output, &Json::fields(message));
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) {
+        self.send(vec![
+            ("type", "response".into()),
+            ("request_seq", field(request, "seq")),
+            ("success", true.into()),
+            ("command", field(request, "command")),
+            ("body", body),
+        ]);
+    }
+
+    fn fail(&mut self, request: &Json, message: &str) {
+        self.send(vec![
+            ("type", "response".into()),
+            ("request_seq", field(request, "seq")),
+            ("success", false.into()),
+            ("command", field(request, "command")),
+            ("message", message.into()),
+        ]);
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(vec![
+            ("type", "event".into()),
+            ("event", event.into()),
+            ("body", body),
+        ]);
+    }
+}
+
+fn field(message: &Json, key: &str) -> Json {
+    message.get(key).cloned().unwrap_or(Json::Null)
+}
+
+fn command(request: &Json) -> &str {
+    request.get("command").and_then(Json::as_str).unwrap_or("")
+}
+
+fn arguments<'a>(request: &'a Json, key: &str) -> Option<&'a Json> {
+    request.get("arguments")?.get(key)
+}
+
+/// Sends what the script writes as `output` events, a line at a time.
+struct Output {
+    connection: Shared<Lock<Connection>>,
+    category: &'static str,
+    buffer: Vec<u8>,
+}
+
+impl Output {
+    fn send(&mut self, text: &[u8]) {
+        let body = Json::fields(vec![
+            ("category", self.category.into()),
+            ("output", String::from_utf8_lossy(text).into_owned().into()),
+        ]);
+        self.connection.borrow_mut().event("output", body);
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line = self.buffer.drain(..=end).collect::<Vec<_>>();
+            self.send(&line);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let text = std::mem::take(&mut self.buffer);
+            self.send(&text);
+        }
+
+        Ok(())
+    }
+}
+
+/// What a `launch` request asked to run.
+struct Launch {
+    program: PathBuf,
+    args: Vec<String>,
+    stop_on_entry: bool,
+}
+
+impl Launch {
+    fn from_request(request: &Json) -> Option<Self> {
+        let program = arguments(request, "program")?.as_str()?;
+        let args = arguments(request, "args")
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|arg| arg.as_str().map(str::to_string))
+            .collect();
+        let stop_on_entry = arguments(request, "stopOnEntry")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+
+        Some(Self {
+            program: PathBuf::from(program),
+            args,
+            stop_on_entry,
+        })
+    }
+}
+
+/// The debugging state shared by the request loop and the running script.
+struct Adapter {
+    connection: Shared<Lock<Connection>>,
+    /// Only ever used through `&mut self`, but locked so that the adapter
+    /// can be shared when interpreters are `Send`.
+    requests: Mutex<Receiver<Json>>,
+    /// Requests that arrived while the script ran, to answer at the next
+    /// stop.
+    pending: VecDeque<Json>,
+    breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+    /// Why the script is stopping next, when it's not a step or breakpoint.
+    reason: Option<&'static str>,
+    /// The file and line running at each call depth, outermost first.
+    frames: Vec<(Option<PathBuf>, usize)>,
+    disconnected: bool,
+}
+
+impl Adapter {
+    /// The next request, waiting for one if needed, or `None` once the
+    /// input has ended.
+    fn next_request(&mut self) -> Option<Json> {
+        self.pending
+            .pop_front()
+            .or_else(|| self.requests().recv().ok())
+    }
+
+    fn requests(&mut self) -> &Receiver<Json> {
+        self.requests
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_breakpoints(&mut self, request: &Json) {
+        let Some(path) = arguments(request, "source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+        else {
+            return self
+                .connection
+                .borrow_mut()
+                .fail(request, "Expect a source path.");
+        };
+        let lines = arguments(request, "breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|breakpoint| breakpoint.get("line")?.as_f64())
+            .map(|line| line as usize)
+            .collect::<Vec<_>>();
+
+        let path = PathBuf::from(path);
+        let path = path.canonicalize().unwrap_or(path);
+        self.breakpoints
+            .retain(|breakpoint| *breakpoint != Breakpoint::new(&path, breakpoint.line()));
+        let mut verified = vec![];
+        for line in lines {
+            self.breakpoints.push(Breakpoint::new(&path, line));
+            verified.push(Json::fields(vec![
+                ("verified", true.into()),
+                ("line", line.into()),
+            ]));
+        }
+
+        let body = Json::fields(vec![("breakpoints", verified.into())]);
+        self.connection.borrow_mut().respond(request, body);
+    }
+
+    /// Handles the requests that can't wait for the script to stop.
+    fn poll(&mut self) -> Result<(), Error> {
+        loop {
+            let request = match self.requests().try_recv() {
+                Ok(request) => request,
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return Ok(()),
+            };
+            match command(&request) {
+                "pause" => {
+                    self.connection.borrow_mut().respond(&request, Json::Null);
+                    self.mode = Mode::Step;
+                    self.reason = Some("pause");
+                }
+                "setBreakpoints" => self.set_breakpoints(&request),
+                "disconnect" => {
+                    self.connection.borrow_mut().respond(&request, Json::Null);
+                    self.disconnected = true;
+                    return Err(Error::Interrupted);
+                }
+                _ => self.pending.push_back(request),
+            }
+        }
+    }
+
+    fn stack_trace(&self, interpreter: &Interpreter) -> Json {
+        let calls = interpreter.call_stack();
+        let frames = self
+            .frames
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(depth, (file, line))| {
+                let name = match depth {
+                    0 => "script".to_string(),
+                    depth => calls.get(depth - 1).cloned().unwrap_or_default(),
+                };
+                let mut frame = vec![
+                    ("id", (depth + 1).into()),
+                    ("name", name.into()),
+                    ("line", (*line).into()),
+                    ("column", 1.0.into()),
+                ];
+                if let Some(file) = file {
+                    let path = file.display().to_string();
+                    frame.push(("source", Json::fields(vec![("path", path.into())])));
+                }
+
+                Json::fields(frame)
+            })
+            .collect::<Vec<_>>();
+
+        Json::fields(vec![
+            ("totalFrames", frames.len().into()),
+            ("stackFrames", frames.into()),
+        ])
+    }
+
+    fn variables(&self, interpreter: &mut Interpreter, reference: usize) -> Json {
+        let variables = match reference {
+            LOCALS => interpreter.local_variables(),
+            GLOBALS => interpreter.global_variables(),
+            _ => vec![],
+        };
+        let variables = variables
+            .into_iter()
+            .map(|(name, value)| {
+                let value = describe(interpreter, value).unwrap_or_else(|e| e.to_string());
+                Json::fields(vec![
+                    ("name", name.as_ref().into()),
+                    ("value", value.into()),
+                    ("variablesReference", 0.0.into()),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        Json::fields(vec![("variables", variables.into())])
+    }
+
+    /// Evaluates a variable by name, in any scope, or else an expression in
+    /// the global scope.
+    fn evaluate(&self, interpreter: &mut Interpreter, expression: &str) -> Result<String, Error> {
+        let variable = interpreter
+            .local_variables()
+            .into_iter()
+            .chain(interpreter.global_variables())
+            .find(|(name, _)| name.as_ref() == expression);
+        match variable {
+            Some((_, value)) => describe(interpreter, value),
+            None => {
+                let value = interpreter.eval_expr(expression)?;
+                describe(interpreter, Some(value))
+            }
+        }
+    }
+
+    /// Reports the stop and answers requests until one resumes the script.
+    fn stop(&mut self, interpreter: &mut Interpreter, reason: &'static str) -> Result<(), Error> {
+        let body = Json::fields(vec![
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ]);
+        self.connection.borrow_mut().event("stopped", body);
+
+        let depth = interpreter.call_stack().len();
+        loop {
+            let Some(request) = self.next_request() else {
+                self.mode = Mode::Detached;
+                return Ok(());
+            };
+            let resume = match command(&request) {
+                "continue" => Some(Mode::Continue),
+                "next" => Some(Mode::StepOver(depth)),
+                "stepIn" => Some(Mode::Step),
+                "stepOut" => Some(Mode::StepOut(depth)),
+                _ => None,
+            };
+            if let Some(mode) = resume {
+                let body = Json::fields(vec![("allThreadsContinued", true.into())]);
+                self.connection.borrow_mut().respond(&request, body);
+                self.mode = mode;
+                return Ok(());
+            }
+
+            match command(&request) {
+                "stackTrace" => {
+                    let body = self.stack_trace(interpreter);
+                    self.connection.borrow_mut().respond(&request, body);
+                }
+                "scopes" => {
+                    // Only the innermost frame's variables can be inspected.
+                    let frame = arguments(&request, "frameId").and_then(Json::as_f64);
+                    let scopes = if frame == Some((depth + 1) as f64) {
+                        vec![
+                            Json::fields(vec![
+                                ("name", "Locals".into()),
+                                ("variablesReference", LOCALS.into()),
+                                ("expensive", false.into()),
+                            ]),
+                            Json::fields(vec![
+                                ("name", "Globals".into()),
+                                ("variablesReference", GLOBALS.into()),
+                                ("expensive", false.into()),
+                            ]),
+                        ]
+                    } else {
+                        vec![]
+                    };
+                    let body = Json::fields(vec![("scopes", scopes.into())]);
+                    self.connection.borrow_mut().respond(&request, body);
+                }
+                "variables" => {
+                    let reference = arguments(&request, "variablesReference")
+                        .and_then(Json::as_f64)
+                        .unwrap_or(0.0);
+                    let body = self.variables(interpreter, reference as usize);
+                    self.connection.borrow_mut().respond(&request, body);
+                }
+                "evaluate" => {
+                    let expression = arguments(&request, "expression")
+                        .and_then(Json::as_str)
+                        .unwrap_or("");
+                    match self.evaluate(interpreter, expression) {
+                        Ok(result) => {
+                            let body = Json::fields(vec![
+                                ("result", result.into()),
+                                ("variablesReference", 0.0.into()),
+                            ]);
+                            self.connection.borrow_mut().respond(&request, body);
+                        }
+                        Err(error) => {
+                            let message = error.to_string();
+                            self.connection.borrow_mut().fail(&request, &message);
+                        }
+                    }
+                }
+                "threads" => {
+                    let body = threads();
+                    self.connection.borrow_mut().respond(&request, body);
+                }
+                "pause" => self.connection.borrow_mut().respond(&request, Json::Null),
+                "setBreakpoints" => self.set_breakpoints(&request),
+                "disconnect" => {
+                    self.connection.borrow_mut().respond(&request, Json::Null);
+                    self.disconnected = true;
+                    return Err(Error::Interrupted);
+                }
+                other => {
+                    let message = format!("Unsupported request '{other}'.");
+                    self.connection.borrow_mut().fail(&request, &message);
+                }
+            }
+        }
+    }
+}
+
+/// Drives the debugging of a running script.
+struct Session(Shared<Lock<Adapter>>);
+
+impl Hook for Session {
+    fn before_statement(
+        &mut self,
+        interpreter: &mut Interpreter,
+        line: usize,
+    ) -> Result<(), Error> {
+        let mut adapter = self.0.borrow_mut();
+
+        let depth = interpreter.call_stack().len();
+        adapter.frames.truncate(depth + 1);
+        adapter.frames.resize(depth + 1, (None, 0));
+        adapter.frames[depth] = (interpreter.current_file().map(PathBuf::from), line);
+
+        adapter.poll()?;
+
+        let at_breakpoint = adapter
+            .breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.is_hit(interpreter, line));
+        if adapter.mode.stops(depth, at_breakpoint) {
+            let reason = match adapter.reason.take() {
+                Some(reason) => reason,
+                None if at_breakpoint => "breakpoint",
+                None => "step",
+            };
+            adapter.stop(interpreter, reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn threads() -> Json {
+    let thread = Json::fields(vec![("id", THREAD_ID.into()), ("name", "main".into())]);
+
+    Json::fields(vec![("threads", vec![thread].into())])
+}
+
+/// Runs the launched script to the end, returning its exit code.
+fn run(adapter: &Shared<Lock<Adapter>>, mut interpreter: Interpreter, launch: Launch) -> i32 {
+    let connection = adapter.borrow().connection.clone();
+    let output = |category| Output {
+        connection: connection.clone(),
+        category,
+        buffer: vec![],
+    };
+    let mut err = output("stderr");
+
+    // Breakpoints are set on absolute paths, so the script must be run from
+    // one for them to match.
+    let program = launch.program.canonicalize().unwrap_or(launch.program);
+    let source = match fs::read_to_string(&program) {
+        Ok(source) => source,
+        Err(error) => {
+            let program = program.display();
+            writeln!(err, "Can't read '{program}': {error}.").expect("must be able to write");
+            return 66;
+        }
+    };
+
+    interpreter = interpreter.with_writers(output("stdout"), output("stderr"));
+    interpreter.set_script_path(&program);
+    interpreter.set_args(launch.args);
+
+    let statements = match interpreter.compile(&source) {
+        Ok(statements) => statements,
+        Err(error) => return error.exit_code(),
+    };
+
+    {
+        let mut adapter = adapter.borrow_mut();
+        if launch.stop_on_entry {
+            adapter.mode = Mode::Step;
+            adapter.reason = Some("entry");
+        } else {
+            adapter.mode = Mode::Continue;
+        }
+    }
+    interpreter.set_hook(Session(adapter.clone()));
+
+    match interpreter.interpret(statements) {
+        Ok(()) => 0,
+        Err(error) => error.exit_code(),
+    }
+}
+
+/// Serves one debugging session, reading requests from `input` and writing
+/// responses and events to `output`. Each launched script runs in an
+/// interpreter from `new_interpreter`.
+pub fn serve(
+    input: impl Read + Send + 'static,
+    output: impl Writer + 'static,
+    new_interpreter: impl Fn() -> Interpreter,
+) {
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || read_messages(input, sender));
+
+    let connection = Shared::new(Lock::new(Connection {
+        output: Box::new(output),
+        seq: 0,
+    }));
+    let adapter = Shared::new(Lock::new(Adapter {
+        connection: connection.clone(),
+        requests: Mutex::new(requests),
+        pending: VecDeque::new(),
+        breakpoints: vec![],
+        mode: Mode::Continue,
+        reason: None,
+        frames: vec![],
+        disconnected: false,
+    }));
+
+    let mut launch = None;
+    let mut configured = false;
+    loop {
+        let Some(request) = adapter.borrow_mut().next_request() else {
+            return;
+        };
+        match command(&request) {
+            "initialize" => {
+                let capabilities = Json::fields(vec![
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsEvaluateForHovers", true.into()),
+                ]);
+                let mut connection = connection.borrow_mut();
+                connection.respond(&request, capabilities);
+                connection.event("initialized", Json::Null);
+            }
+            "launch" => match Launch::from_request(&request) {
+                Some(requested) => {
+                    launch = Some(requested);
+                    connection.borrow_mut().respond(&request, Json::Null);
+                }
+                None => connection
+                    .borrow_mut()
+                    .fail(&request, "Expect a program to launch."),
+            },
+            "configurationDone" => {
+                configured = true;
+                connection.borrow_mut().respond(&request, Json::Null);
+            }
+            "setBreakpoints" => adapter.borrow_mut().set_breakpoints(&request),
+            "threads" => connection.borrow_mut().respond(&request, threads()),
+            "disconnect" => {
+                connection.borrow_mut().respond(&request, Json::Null);
+                return;
+            }
+            other => {
+                let message = format!("Unsupported request '{other}'.");
+                connection.borrow_mut().fail(&request, &message);
+            }
+        }
+
+        if configured {
+            if let Some(launch) = launch.take() {
+                let code = run(&adapter, new_interpreter(), launch);
+                if adapter.borrow().disconnected {
+                    return;
+                }
+
+                let mut connection = connection.borrow_mut();
+                let body = Json::fields(vec![("exitCode", (code as f64).into())]);
+                connection.event("exited", body);
+                connection.event("terminated", Json::Null);
+            }
+        }
+    }
+}
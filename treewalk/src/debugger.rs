@@ -0,0 +1,351 @@
+//! An interactive debugger for scripts run by the interpreter.
+//!
+//! The debugger is a [`Hook`]: before each statement it decides whether to
+//! stop, and if it does, it reads commands until one of them resumes the
+//! script. It starts out stopped at the first statement, so that breakpoints
+//! can be set before anything runs.
+
+use crate::{
+    interpreter::{Error, Hook, Interpreter},
+    sync::{Shared, Threadsafe},
+    value::Value,
+};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+const HELP: &str = "\
+Commands:
+  break [file:]line    stop when the line is about to run (b)
+  delete [file:]line   remove a breakpoint (d)
+  breakpoints          list the breakpoints
+  step                 run the next statement, stepping into calls (s)
+  next                 run the next statement, stepping over calls (n)
+  finish               run until the current function returns (f)
+  continue             run until the next breakpoint (c)
+  list                 show the source around the current line (l)
+  print name           show the value of a variable (p)
+  locals               show the local variables in scope
+  globals              show the global variables
+  backtrace            show the functions being called (bt)
+  quit                 stop the script (q)";
+
+/// A line to stop at. Without a file, the line is in the script being run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Breakpoint {
+    file: Option<PathBuf>,
+    line: usize,
+}
+
+impl Breakpoint {
+    /// Parses `line` or `file:line`.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (file, line) = match spec.rsplit_once(':') {
+            Some((file, line)) => (Some(PathBuf::from(file)), line),
+            None => (None, spec),
+        };
+        let line = line.parse().ok().filter(|line| *line > 0)?;
+
+        Some(Self { file, line })
+    }
+
+    /// A breakpoint on `line` of `file`.
+    pub fn new(file: impl Into<PathBuf>, line: usize) -> Self {
+        Self {
+            file: Some(file.into()),
+            line,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Whether the breakpoint is on the statement the interpreter is about
+    /// to run, which starts on `line`.
+    pub(crate) fn is_hit(&self, interpreter: &Interpreter, line: usize) -> bool {
+        self.matches(interpreter.current_file(), interpreter.script_path(), line)
+    }
+
+    fn matches(&self, file: Option<&Path>, script: Option<&Path>, line: usize) -> bool {
+        if self.line != line {
+            return false;
+        }
+
+        match (&self.file, file) {
+            (Some(expected), Some(file)) => file.ends_with(expected),
+            (Some(_), None) => false,
+            (None, file) => file == script,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file.display(), self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+/// When to stop next, besides at breakpoints.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Mode {
+    /// Only at breakpoints.
+    Continue,
+    /// At the next statement.
+    Step,
+    /// At the next statement at most this many calls deep.
+    StepOver(usize),
+    /// At the next statement fewer than this many calls deep.
+    StepOut(usize),
+    /// Never, because there are no more commands to read.
+    Detached,
+}
+
+impl Mode {
+    /// Whether to stop at a statement `depth` calls deep, or at a breakpoint
+    /// if `at_breakpoint`.
+    pub(crate) fn stops(self, depth: usize, at_breakpoint: bool) -> bool {
+        match self {
+            Self::Continue => at_breakpoint,
+            Self::Step => true,
+            Self::StepOver(max) => at_breakpoint || depth <= max,
+            Self::StepOut(max) => at_breakpoint || depth < max,
+            Self::Detached => false,
+        }
+    }
+}
+
+pub struct Debugger<R, W> {
+    input: R,
+    output: W,
+    breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+    /// The lines of each file stopped in, read the first time it's needed.
+    sources: HashMap<PathBuf, Vec<String>>,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    /// Creates a debugger that reads commands from `input` and writes what
+    /// it shows to `output`.
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            breakpoints: vec![],
+            mode: Mode::Step,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Adds a breakpoint, and makes the debugger run until it reaches one
+    /// instead of stopping at the first statement.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+        self.mode = Mode::Continue;
+    }
+
+    fn should_stop(&self, interpreter: &Interpreter, line: usize) -> bool {
+        let at_breakpoint = self
+            .breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.is_hit(interpreter, line));
+
+        self.mode
+            .stops(interpreter.call_stack().len(), at_breakpoint)
+    }
+
+    fn source_line(&mut self, file: Option<&Path>, line: usize) -> Option<String> {
+        let file = file?;
+        if !self.sources.contains_key(file) {
+            let source = fs::read_to_string(file).ok()?;
+            let lines = source.lines().map(str::to_string).collect();
+            self.sources.insert(file.to_path_buf(), lines);
+        }
+
+        self.sources[file].get(line.checked_sub(1)?).cloned()
+    }
+
+    fn show_line(&mut self, interpreter: &Interpreter, line: usize) {
+        let file = interpreter.current_file().map(Path::to_path_buf);
+        let location = match &file {
+            Some(file) => format!("{}:{line}", file.display()),
+            None => format!("line {line}"),
+        };
+        match self.source_line(file.as_deref(), line) {
+            Some(text) => writeln!(self.output, "{location}: {}", text.trim()),
+            None => writeln!(self.output, "{location}"),
+        }
+        .expect("must be able to write");
+    }
+
+    fn list(&mut self, interpreter: &Interpreter, line: usize) {
+        let file = interpreter.current_file().map(Path::to_path_buf);
+        for number in line.saturating_sub(2).max(1)..=line + 2 {
+            let Some(text) = self.source_line(file.as_deref(), number) else {
+                continue;
+            };
+            let marker = if number == line { "->" } else { "  " };
+            writeln!(self.output, "{marker} {number:>4} {text}").expect("must be able to write");
+        }
+    }
+
+    fn show_variables(
+        &mut self,
+        interpreter: &mut Interpreter,
+        variables: Vec<(Shared<str>, Option<Value>)>,
+    ) -> Result<(), Error> {
+        if variables.is_empty() {
+            writeln!(self.output, "(none)").expect("must be able to write");
+        }
+        for (name, value) in variables {
+            let value = describe(interpreter, value)?;
+            writeln!(self.output, "{name} = {value}").expect("must be able to write");
+        }
+
+        Ok(())
+    }
+
+    fn print(&mut self, interpreter: &mut Interpreter, name: &str) -> Result<(), Error> {
+        let value = interpreter
+            .local_variables()
+            .into_iter()
+            .chain(interpreter.global_variables())
+            .find(|(variable, _)| variable.as_ref() == name);
+        match value {
+            Some((_, value)) => {
+                let value = describe(interpreter, value)?;
+                writeln!(self.output, "{name} = {value}").expect("must be able to write");
+            }
+            None => {
+                writeln!(self.output, "No variable named '{name}'.").expect("must be able to write")
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads and runs commands until one resumes the script.
+    fn prompt(&mut self, interpreter: &mut Interpreter, line: usize) -> Result<(), Error> {
+        self.show_line(interpreter, line);
+
+        loop {
+            write!(self.output, "(debug) ").expect("must be able to write");
+            self.output.flush().expect("must be able to write");
+
+            let mut command = String::new();
+            let read = self.input.read_line(&mut command).unwrap_or(0);
+            if read == 0 {
+                writeln!(self.output).expect("must be able to write");
+                self.mode = Mode::Detached;
+                return Ok(());
+            }
+
+            let mut words = command.split_whitespace();
+            let depth = interpreter.call_stack().len();
+            match (words.next(), words.next()) {
+                (None, _) => {}
+                (Some("step" | "s"), None) => {
+                    self.mode = Mode::Step;
+                    return Ok(());
+                }
+                (Some("next" | "n"), None) => {
+                    self.mode = Mode::StepOver(depth);
+                    return Ok(());
+                }
+                (Some("finish" | "f"), None) => {
+                    self.mode = Mode::StepOut(depth);
+                    return Ok(());
+                }
+                (Some("continue" | "c"), None) => {
+                    self.mode = Mode::Continue;
+                    return Ok(());
+                }
+                (Some("quit" | "q"), None) => return Err(Error::Interrupted),
+                (Some("break" | "b"), Some(spec)) => match Breakpoint::parse(spec) {
+                    Some(breakpoint) => {
+                        writeln!(self.output, "Breakpoint at {breakpoint}.")
+                            .expect("must be able to write");
+                        self.breakpoints.push(breakpoint);
+                    }
+                    None => writeln!(self.output, "Expect a line or file:line.")
+                        .expect("must be able to write"),
+                },
+                (Some("delete" | "d"), Some(spec)) => {
+                    let before = self.breakpoints.len();
+                    if let Some(breakpoint) = Breakpoint::parse(spec) {
+                        self.breakpoints.retain(|existing| *existing != breakpoint);
+                    }
+                    if self.breakpoints.len() == before {
+                        writeln!(self.output, "No breakpoint at {spec}.")
+                            .expect("must be able to write");
+                    }
+                }
+                (Some("breakpoints"), None) => {
+                    if self.breakpoints.is_empty() {
+                        writeln!(self.output, "(none)").expect("must be able to write");
+                    }
+                    for breakpoint in &self.breakpoints {
+                        writeln!(self.output, "{breakpoint}").expect("must be able to write");
+                    }
+                }
+                (Some("list" | "l"), None) => self.list(interpreter, line),
+                (Some("print" | "p"), Some(name)) => self.print(interpreter, name)?,
+                (Some("locals"), None) => {
+                    let variables = interpreter.local_variables();
+                    self.show_variables(interpreter, variables)?;
+                }
+                (Some("globals"), None) => {
+                    let variables = interpreter.global_variables();
+                    self.show_variables(interpreter, variables)?;
+                }
+                (Some("backtrace" | "bt"), None) => {
+                    for function in interpreter.call_stack().iter().rev() {
+                        writeln!(self.output, "in {function}").expect("must be able to write");
+                    }
+                    writeln!(self.output, "in script").expect("must be able to write");
+                }
+                (Some("help" | "h"), None) => {
+                    writeln!(self.output, "{HELP}").expect("must be able to write")
+                }
+                _ => writeln!(
+                    self.output,
+                    "Unknown command '{}'. Type 'help' for a list.",
+                    command.trim()
+                )
+                .expect("must be able to write"),
+            }
+        }
+    }
+}
+
+impl<R: BufRead + Threadsafe, W: Write + Threadsafe> Hook for Debugger<R, W> {
+    fn before_statement(
+        &mut self,
+        interpreter: &mut Interpreter,
+        line: usize,
+    ) -> Result<(), Error> {
+        if self.should_stop(interpreter, line) {
+            self.prompt(interpreter, line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Shows a variable's value the way it would be written in Lox.
+pub(crate) fn describe(
+    interpreter: &mut Interpreter,
+    value: Option<Value>,
+) -> Result<String, Error> {
+    match value {
+        Some(Value::String(s)) => Ok(format!("{s:?}")),
+        Some(value) => interpreter.stringify(&value),
+        None => Ok("<unassigned>".to_string()),
+    }
+}
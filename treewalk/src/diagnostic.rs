@@ -0,0 +1,374 @@
+//! Errors and warnings as `lox` shows them: the message, then the line of
+//! source it's about with the offending code underlined, then any notes and
+//! help, optionally in color.
+//!
+//! ```text
+//! [line 2] Warning at 'unused': Local variable is never read.
+//!   |
+//! 2 |   var unused = 1;
+//!   |       ^^^^^^
+//!   = note: Warnings are denied, so the script won't run.
+//! ```
+//!
+//! Editors and CI can have them as JSON instead, one object per line, with
+//! [`ErrorFormat::Json`].
+
+use crate::{
+    interpreter::Error,
+    json::Json,
+    parser,
+    resolver::{ResolveError, Warning},
+    scanner::ScanError,
+    token::Span,
+};
+use std::{
+    fmt::{self, Write},
+    path::Path,
+};
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// How the interpreter reports errors and warnings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// As text, for people.
+    #[default]
+    Human,
+    /// As JSON objects, one per line, for tools.
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// What kind of problem it is, such as `parse-error` or `runtime-error`,
+    /// for tools to tell them apart.
+    pub code: &'static str,
+    /// What went wrong, such as "Expect expression.".
+    pub message: String,
+    /// The line it's on, if that's known.
+    pub line: Option<usize>,
+    /// How it's reported as text, which is what the reference
+    /// implementations print. Runtime errors run over several lines, for the
+    /// stack trace.
+    pub report: String,
+    /// The code the diagnostic is about, if it's known.
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        let message = message.into();
+
+        Self::new(Severity::Error, "error", message.clone(), None, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        let message = message.into();
+
+        Self::new(Severity::Warning, "warning", message.clone(), None, message)
+    }
+
+    fn new(
+        severity: Severity,
+        code: &'static str,
+        message: String,
+        line: Option<usize>,
+        report: String,
+    ) -> Self {
+        Self {
+            severity,
+            code,
+            message,
+            line,
+            report,
+            span: None,
+            notes: vec![],
+            help: vec![],
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = code;
+
+        self
+    }
+
+    pub fn with_line(mut self, line: usize) -> Self {
+        self.line = Some(line);
+
+        self
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+
+        self
+    }
+
+    /// Renders just the report, with its first line in color if `color` is
+    /// set.
+    pub fn render_message(&self, color: bool) -> String {
+        let mut lines = self.report.lines();
+        let first = lines.next().unwrap_or_default();
+        let mut rendered = if color {
+            format!("{}{first}{RESET}", self.accent())
+        } else {
+            first.to_string()
+        };
+        for line in lines {
+            rendered.push('\n');
+            rendered.push_str(line);
+        }
+
+        rendered
+    }
+
+    fn accent(&self) -> &'static str {
+        match self.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+
+    /// Renders the diagnostic without a trailing newline, showing the code
+    /// it's about if that's in `source`, and using ANSI colors if `color` is
+    /// set.
+    pub fn render(&self, source: Option<&str>, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{style}{text}{RESET}")
+            } else {
+                text.to_string()
+            }
+        };
+        let accent = self.accent();
+
+        let mut rendered = self.render_message(color);
+        let snippet = source
+            .zip(self.span)
+            .and_then(|(source, span)| Snippet::new(source, span));
+        let gutter = " ".repeat(snippet.as_ref().map_or(1, |snippet| snippet.number.len()));
+        let bar = paint(BLUE, "|");
+        if let Some(snippet) = &snippet {
+            let _ = write!(
+                rendered,
+                "\n{gutter} {bar}\n{} {bar} {}\n{gutter} {bar} {}{}",
+                paint(BLUE, &snippet.number),
+                snippet.text,
+                snippet.indent,
+                paint(accent, &snippet.carets),
+            );
+        }
+        for (label, lines) in [("note", &self.notes), ("help", &self.help)] {
+            for line in lines {
+                let _ = write!(
+                    rendered,
+                    "\n{gutter} {} {}: {line}",
+                    paint(BLUE, "="),
+                    paint(BOLD, label)
+                );
+            }
+        }
+
+        rendered
+    }
+
+    /// The diagnostic as a JSON object, for reporting as a line of JSON. The
+    /// file is the one it's about, if that's known.
+    pub fn to_json(&self, file: Option<&Path>) -> Json {
+        let strings =
+            |strings: &[String]| Json::Array(strings.iter().map(|s| s.as_str().into()).collect());
+        let span = self
+            .span
+            .filter(|span| !span.is_empty())
+            .map_or(Json::Null, |span| {
+                Json::fields(vec![
+                    ("offset", span.offset.into()),
+                    ("len", span.len.into()),
+                ])
+            });
+
+        Json::fields(vec![
+            (
+                "file",
+                file.map_or(Json::Null, |file| file.display().to_string().into()),
+            ),
+            ("line", self.line.map_or(Json::Null, Json::from)),
+            ("span", span),
+            ("severity", self.severity.name().into()),
+            ("code", self.code.into()),
+            ("message", self.message.as_str().into()),
+            ("notes", strings(&self.notes)),
+            ("help", strings(&self.help)),
+        ])
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render(None, false))
+    }
+}
+
+impl From<&ScanError> for Diagnostic {
+    fn from(error: &ScanError) -> Self {
+        let span = Span {
+            offset: error.offset,
+            len: 1,
+        };
+        let message = error.message.clone();
+
+        Self::new(
+            Severity::Error,
+            "scan-error",
+            message,
+            Some(error.line),
+            error.to_string(),
+        )
+        .with_span(span)
+    }
+}
+
+impl From<&parser::Error> for Diagnostic {
+    fn from(error: &parser::Error) -> Self {
+        let parser::Error::ParseError { token, message } = error;
+
+        Self::new(
+            Severity::Error,
+            "parse-error",
+            message.clone(),
+            Some(token.line()),
+            error.to_string(),
+        )
+        .with_span(Span::of(token))
+    }
+}
+
+impl From<&ResolveError> for Diagnostic {
+    fn from(error: &ResolveError) -> Self {
+        Self::new(
+            Severity::Error,
+            "resolve-error",
+            error.message.clone(),
+            Some(error.token.line()),
+            error.to_string(),
+        )
+        .with_span(Span::of(&error.token))
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        Self::new(
+            Severity::Warning,
+            "warning",
+            warning.message.clone(),
+            Some(warning.token.line()),
+            warning.to_string(),
+        )
+        .with_span(Span::of(&warning.token))
+    }
+}
+
+/// An error that stopped the script, reported with its stack trace.
+impl From<&Error> for Diagnostic {
+    fn from(error: &Error) -> Self {
+        let mut report = error.to_string();
+        if let Error::Runtime { trace, .. } | Error::Panic { trace, .. } = error {
+            for frame in trace {
+                let _ = write!(report, "\n{frame}");
+            }
+        }
+        let message = report.lines().next().unwrap_or_default().to_string();
+        let (code, line) = match error {
+            Error::Runtime { line, .. } => ("runtime-error", Some(*line)),
+            Error::Panic { line, .. } => ("panic", Some(*line)),
+            Error::Budget => ("budget-exceeded", None),
+            Error::Interrupted => ("interrupted", None),
+            Error::Internal { .. } => ("internal-error", None),
+            _ => ("error", None),
+        };
+        let span = match error {
+            Error::Runtime { span, .. } => *span,
+            _ => None,
+        };
+
+        Self {
+            span,
+            ..Self::new(Severity::Error, code, message, line, report)
+        }
+    }
+}
+
+/// The line of source a span starts on, cut up for rendering.
+struct Snippet<'s> {
+    number: String,
+    text: &'s str,
+    /// Whitespace as wide as the text before the span. Tabs are kept so that
+    /// the carets line up however wide they're shown.
+    indent: String,
+    carets: String,
+}
+
+impl<'s> Snippet<'s> {
+    /// Spans running onto later lines are underlined to the end of the
+    /// first. Empty spans, and ones that aren't in `source`, have no snippet.
+    fn new(source: &'s str, span: Span) -> Option<Self> {
+        if span.is_empty() || !source.is_char_boundary(span.offset) {
+            return None;
+        }
+        let before = source.get(..span.offset)?;
+        let start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        let line = source[start..].split('\n').next().unwrap_or_default();
+        let text = line.strip_suffix('\r').unwrap_or(line);
+        let covered = text.get(span.offset - start..).unwrap_or_default();
+        let width = covered
+            .char_indices()
+            .take_while(|(i, _)| *i < span.len)
+            .count();
+
+        Some(Self {
+            number: (before.matches('\n').count() + 1).to_string(),
+            text,
+            indent: before[start..]
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect(),
+            carets: "^".repeat(width.max(1)),
+        })
+    }
+}
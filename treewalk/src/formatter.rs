@@ -0,0 +1,863 @@
+//! Re-emits Lox source in a canonical layout.
+//!
+//! The formatter walks the syntax tree, but also keeps a cursor into the
+//! tokens the tree was parsed from. That lets it put their comment trivia
+//! back where it was, keep single blank lines between statements, and undo the parser's
+//! desugaring of `for` loops, compound assignments and increments.
+
+use crate::{
+    ast::{Expr, ExprKind, Param, Stmt},
+    parser::Parser,
+    scanner::Scanner,
+    token::{Comment, Token, TokenType},
+    value::Value,
+};
+
+/// Formats `source`, or returns the messages for the syntax errors that stop
+/// it from being parsed.
+pub fn format(source: &str) -> Result<String, Vec<String>> {
+    let mut scanner = Scanner::new(source).with_trivia();
+    let tokens = scanner.scan().to_vec();
+    let (statements, errors) = Parser::new(&tokens).parse();
+    if !errors.is_empty() || scanner.had_error() {
+        return Err(scanner
+            .errors()
+            .iter()
+            .map(ToString::to_string)
+            .chain(errors.iter().map(ToString::to_string))
+            .collect());
+    }
+
+    let mut formatter = Formatter::new(&tokens);
+    for stmt in &statements {
+        formatter.stmt(stmt);
+    }
+    formatter.comments();
+
+    Ok(formatter.out)
+}
+
+/// The place an assignment stores its value.
+enum Target<'a> {
+    Field(&'a Expr, &'a Token),
+    Index(&'a Expr, &'a Expr),
+    Variable(&'a Token),
+}
+
+/// The kinds of member a class body can hold.
+enum Member {
+    ClassMethod,
+    Getter,
+    Method,
+}
+
+struct Formatter<'t> {
+    tokens: &'t [Token],
+    cursor: usize,
+    /// Whether the comments before the token at the cursor, and after the
+    /// one before it, have been written.
+    leading_written: bool,
+    trailing_written: bool,
+    /// The comment that ended the line before a separator, which is written
+    /// after the separator instead.
+    moved: Option<&'t Comment>,
+    out: String,
+    indent: usize,
+    /// The line of the last source token written, for telling trailing
+    /// comments from ones on a line of their own.
+    line: usize,
+    at_line_start: bool,
+    /// Set when a comment splits a statement across lines, so that the rest
+    /// of it is indented further.
+    continued: bool,
+}
+
+impl<'t> Formatter<'t> {
+    fn new(tokens: &'t [Token]) -> Self {
+        Self {
+            tokens,
+            cursor: 0,
+            leading_written: false,
+            trailing_written: false,
+            moved: None,
+            out: String::new(),
+            indent: 0,
+            line: 1,
+            at_line_start: true,
+            continued: false,
+        }
+    }
+
+    fn peek(&self) -> &'t Token {
+        &self.tokens[self.cursor]
+    }
+
+    fn peek_is(&self, lexeme: &str) -> bool {
+        self.peek().lexeme() == lexeme
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.at_line_start {
+            let indent = self.indent + usize::from(self.continued);
+            self.out.push_str(&"  ".repeat(indent));
+            self.at_line_start = false;
+        }
+        self.out.push_str(text);
+    }
+
+    fn newline(&mut self) {
+        while self.out.ends_with(' ') {
+            self.out.pop();
+        }
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    /// Keeps one blank line before something on `next_line` if the source
+    /// had any.
+    fn blank_line(&mut self, next_line: usize) {
+        let after_open = self.out.is_empty() || self.out.ends_with("{\n");
+        let after_blank = self.out.ends_with("\n\n");
+        if self.at_line_start && !after_open && !after_blank && next_line > self.line + 1 {
+            self.newline();
+        }
+    }
+
+    fn trailing(&self) -> Option<&'t Comment> {
+        if self.moved.is_some() {
+            return self.moved;
+        }
+        if self.trailing_written || self.cursor == 0 {
+            return None;
+        }
+        self.tokens[self.cursor - 1].trivia()?.trailing.as_ref()
+    }
+
+    fn leading(&self) -> &'t [Comment] {
+        match self.tokens[self.cursor].trivia() {
+            Some(trivia) if !self.leading_written => &trivia.leading,
+            _ => &[],
+        }
+    }
+
+    fn has_comments(&self) -> bool {
+        self.trailing().is_some() || !self.leading().is_empty()
+    }
+
+    /// Writes the comment that followed the last token on its line.
+    fn trailing_comment(&mut self) {
+        if let Some(comment) = self.trailing() {
+            if !self.at_line_start && !self.out.ends_with(' ') {
+                self.write(" ");
+            }
+            self.write(&comment.text);
+            self.line = self.line.max(comment.last_line());
+            if self.moved.take().is_none() {
+                self.trailing_written = true;
+            }
+        }
+    }
+
+    /// Ends the line after a comment, unless it's a block comment with more
+    /// code after it on the same line. Separators and closing brackets
+    /// follow such a comment without a space, as they would the code before
+    /// it.
+    fn after_comment(&mut self, comment: &Comment) {
+        if comment.is_block() && comment.last_line() == self.peek().line() {
+            if ![",", ";", ")", "]", "}"].contains(&self.peek().lexeme()) {
+                self.write(" ");
+            }
+        } else {
+            self.newline();
+        }
+    }
+
+    /// Writes the comments between the last token and the next one.
+    fn comments(&mut self) {
+        if !self.at_line_start && self.has_comments() {
+            self.continued = true;
+        }
+
+        if let Some(comment) = self.trailing() {
+            self.trailing_comment();
+            self.after_comment(comment);
+        }
+        for comment in self.leading() {
+            if !self.at_line_start && comment.line > self.line {
+                self.newline();
+            }
+            self.blank_line(comment.line);
+            self.write(&comment.text);
+            self.line = comment.last_line();
+            self.after_comment(comment);
+        }
+        self.leading_written = true;
+    }
+
+    fn advance(&mut self) -> &'t Token {
+        let token = &self.tokens[self.cursor];
+        self.line = token.line();
+        self.cursor += 1;
+        self.leading_written = false;
+        self.trailing_written = false;
+
+        token
+    }
+
+    /// Whether `lexeme` is a separator that comes after a comment ending the
+    /// line before it. The separator is written first, so that it stays on
+    /// the same line as the value it follows, and the comment after it.
+    fn separator_after_comment(&self, lexeme: &str) -> bool {
+        let Some(comment) = self.trailing() else {
+            return false;
+        };
+
+        [",", ";"].contains(&lexeme)
+            && self.peek_is(lexeme)
+            && self.leading().is_empty()
+            && comment.last_line() < self.peek().line()
+            && self
+                .peek()
+                .trivia()
+                .is_none_or(|trivia| trivia.trailing.is_none())
+    }
+
+    /// Writes a token, consuming it from the source if it's the next one
+    /// there. Tokens the parser made up while desugaring aren't.
+    fn token(&mut self, lexeme: &str) {
+        if self.separator_after_comment(lexeme) {
+            let moved = self.trailing();
+            self.advance();
+            self.write(lexeme);
+            self.moved = moved;
+            return;
+        }
+
+        self.comments();
+        self.blank_line(self.peek().line());
+
+        if self.peek_is(lexeme) {
+            self.advance();
+        }
+        self.write(lexeme);
+    }
+
+    fn literal(&mut self, value: &Value) {
+        self.comments();
+        self.blank_line(self.peek().line());
+
+        if matches!(
+            self.peek().typ(),
+            TokenType::False
+                | TokenType::Nil
+                | TokenType::Number
+                | TokenType::String
+                | TokenType::Interpolation
+                | TokenType::InterpolationEnd
+                | TokenType::True
+        ) {
+            let literal = self.advance();
+            self.write(literal.lexeme());
+        } else {
+            self.write(&value.to_string());
+        }
+    }
+
+    /// Whether the formatter is between the parts of an interpolated string,
+    /// where the parser made up the concatenations joining them.
+    fn in_interpolation(&self) -> bool {
+        self.cursor > 0 && self.tokens[self.cursor - 1].typ() == &TokenType::Interpolation
+            || matches!(
+                self.peek().typ(),
+                TokenType::Interpolation | TokenType::InterpolationEnd
+            )
+    }
+
+    fn end_line(&mut self) {
+        self.trailing_comment();
+        self.newline();
+        self.continued = false;
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        self.inline_stmt(stmt);
+        self.end_line();
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        self.token("{");
+        if !statements.is_empty() || self.has_comments() {
+            self.end_line();
+            self.indent += 1;
+            for stmt in statements {
+                self.stmt(stmt);
+            }
+            self.comments();
+            self.indent -= 1;
+        }
+        self.token("}");
+    }
+
+    /// Writes the body of a control flow statement, keeping statements
+    /// without braces on the same line.
+    fn body(&mut self, stmt: &Stmt) {
+        self.write(" ");
+        match stmt {
+            Stmt::Block(statements) if self.peek_is("{") => self.block(statements),
+            stmt => self.inline_stmt(stmt),
+        }
+    }
+
+    fn list<T>(&mut self, items: &[T], mut item: impl FnMut(&mut Self, &T)) {
+        for (i, element) in items.iter().enumerate() {
+            if i > 0 {
+                self.token(",");
+                self.write(" ");
+            }
+            item(self, element);
+        }
+    }
+
+    fn params(&mut self, params: &[Param]) {
+        self.token("(");
+        self.list(params, |f, param| {
+            f.token(param.name.lexeme());
+            if let Some(default) = &param.default {
+                f.write(" ");
+                f.token("=");
+                f.write(" ");
+                f.expr(default);
+            }
+        });
+        self.token(")");
+    }
+
+    fn function(&mut self, name: &Token, params: &[Param], body: &[Stmt]) {
+        self.token(name.lexeme());
+        self.params(params);
+        self.write(" ");
+        self.block(body);
+    }
+
+    fn for_loop(&mut self, initializer: Option<&Stmt>, loop_stmt: &Stmt) {
+        let Stmt::While {
+            condition,
+            body,
+            increment,
+        } = loop_stmt
+        else {
+            return self.inline_stmt(loop_stmt);
+        };
+
+        self.token("for");
+        self.write(" ");
+        self.token("(");
+        match initializer {
+            Some(initializer) => self.inline_stmt(initializer),
+            None => self.token(";"),
+        }
+        if !self.peek_is(";") {
+            self.write(" ");
+            self.expr(condition);
+        }
+        self.token(";");
+        if let Some(increment) = increment {
+            self.write(" ");
+            self.expr(increment);
+        }
+        self.token(")");
+        self.body(body);
+    }
+
+    fn inline_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block(statements) => match statements.as_slice() {
+                [initializer, loop_stmt] if self.peek_is("for") => {
+                    self.for_loop(Some(initializer), loop_stmt)
+                }
+                statements => self.block(statements),
+            },
+            Stmt::Break(_) => {
+                self.token("break");
+                self.token(";");
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                class_methods,
+                getters,
+            } => {
+                self.token("class");
+                self.write(" ");
+                self.token(name.lexeme());
+                if let Some(superclass) = superclass {
+                    self.write(" ");
+                    self.token("<");
+                    self.write(" ");
+                    self.expr(superclass);
+                }
+                self.write(" ");
+
+                // Members are stored by kind, so put them back in source
+                // order.
+                let mut members = methods
+                    .iter()
+                    .map(|method| (Member::Method, method))
+                    .chain(
+                        class_methods
+                            .iter()
+                            .map(|method| (Member::ClassMethod, method)),
+                    )
+                    .chain(getters.iter().map(|getter| (Member::Getter, getter)))
+                    .filter_map(|(kind, member)| match member {
+                        Stmt::Function { name, params, body } => Some((kind, name, params, body)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                members.sort_by_key(|(_, name, _, _)| name.line());
+
+                self.token("{");
+                if members.is_empty() && !self.has_comments() {
+                    self.token("}");
+                    return;
+                }
+                self.end_line();
+                self.indent += 1;
+                for (kind, name, params, body) in members {
+                    match kind {
+                        Member::ClassMethod => {
+                            self.token("class");
+                            self.write(" ");
+                            self.function(name, params, body);
+                        }
+                        Member::Getter => {
+                            self.token(name.lexeme());
+                            self.write(" ");
+                            self.block(body);
+                        }
+                        Member::Method => self.function(name, params, body),
+                    }
+                    self.end_line();
+                }
+                self.comments();
+                self.indent -= 1;
+                self.token("}");
+            }
+            Stmt::Const { name, initializer } => {
+                self.token("const");
+                self.write(" ");
+                self.token(name.lexeme());
+                self.write(" ");
+                self.token("=");
+                self.write(" ");
+                self.expr(initializer);
+                self.token(";");
+            }
+            Stmt::Continue(_) => {
+                self.token("continue");
+                self.token(";");
+            }
+            Stmt::Expression(expr) => {
+                self.expr(expr);
+                self.token(";");
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+            } => {
+                self.token("for");
+                self.write(" ");
+                self.token("(");
+                self.token("var");
+                self.write(" ");
+                self.token(name.lexeme());
+                self.write(" ");
+                self.token("in");
+                self.write(" ");
+                self.expr(iterable);
+                self.token(")");
+                self.body(body);
+            }
+            Stmt::Function { name, params, body } => {
+                self.token("fun");
+                self.write(" ");
+                self.function(name, params, body);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.token("if");
+                self.write(" ");
+                self.token("(");
+                self.expr(condition);
+                self.token(")");
+                let braced = self.peek_is("{");
+                self.body(then_branch);
+
+                if let Some(else_branch) = else_branch {
+                    if braced {
+                        self.write(" ");
+                    } else {
+                        self.end_line();
+                    }
+                    self.token("else");
+                    match else_branch.as_ref() {
+                        Stmt::If { .. } if self.peek_is("if") => {
+                            self.write(" ");
+                            self.inline_stmt(else_branch);
+                        }
+                        else_branch => self.body(else_branch),
+                    }
+                }
+            }
+            Stmt::Assert {
+                condition, message, ..
+            } => {
+                self.token("assert");
+                self.token("(");
+                self.expr(condition);
+                if let Some(message) = message {
+                    self.token(",");
+                    self.write(" ");
+                    self.expr(message);
+                }
+                self.token(")");
+                self.token(";");
+            }
+            Stmt::Print { value: expr, .. } => {
+                self.token("print");
+                self.write(" ");
+                self.expr(expr);
+                self.token(";");
+            }
+            Stmt::Return { value, .. } => {
+                self.token("return");
+                if let Some(value) = value {
+                    self.write(" ");
+                    self.expr(value);
+                }
+                self.token(";");
+            }
+            Stmt::Switch { subject, cases, .. } => {
+                self.token("switch");
+                self.write(" ");
+                self.token("(");
+                self.expr(subject);
+                self.token(")");
+                self.write(" ");
+                self.token("{");
+                if !cases.is_empty() || self.has_comments() {
+                    self.end_line();
+                    self.indent += 1;
+                    for case in cases {
+                        match &case.value {
+                            Some(value) => {
+                                self.token("case");
+                                self.write(" ");
+                                self.expr(value);
+                            }
+                            None => self.token("default"),
+                        }
+                        self.token(":");
+                        self.end_line();
+                        self.indent += 1;
+                        for stmt in &case.body {
+                            self.stmt(stmt);
+                        }
+                        self.indent -= 1;
+                    }
+                    self.comments();
+                    self.indent -= 1;
+                }
+                self.token("}");
+            }
+            Stmt::Throw { value, .. } => {
+                self.token("throw");
+                self.write(" ");
+                self.expr(value);
+                self.token(";");
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.token("try");
+                self.write(" ");
+                self.block(body);
+                if let Some((name, handler)) = catch {
+                    self.write(" ");
+                    self.token("catch");
+                    self.write(" ");
+                    self.token("(");
+                    self.token(name.lexeme());
+                    self.token(")");
+                    self.write(" ");
+                    self.block(handler);
+                }
+                if let Some(finally) = finally {
+                    self.write(" ");
+                    self.token("finally");
+                    self.write(" ");
+                    self.block(finally);
+                }
+            }
+            Stmt::Import { path, .. } => {
+                self.token("import");
+                self.write(" ");
+                self.token(path.lexeme());
+                self.token(";");
+            }
+            Stmt::Var { name, initializer } => {
+                self.token("var");
+                self.write(" ");
+                self.token(name.lexeme());
+                if let Some(initializer) = initializer {
+                    self.write(" ");
+                    self.token("=");
+                    self.write(" ");
+                    self.expr(initializer);
+                }
+                self.token(";");
+            }
+            Stmt::VarList {
+                names, initializer, ..
+            } => {
+                self.token("var");
+                self.write(" ");
+                self.token("[");
+                self.list(names, |f, name| f.token(name.lexeme()));
+                self.token("]");
+                self.write(" ");
+                self.token("=");
+                self.write(" ");
+                self.expr(initializer);
+                self.token(";");
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                if self.peek_is("for") {
+                    return self.for_loop(None, stmt);
+                }
+
+                self.token("while");
+                self.write(" ");
+                self.token("(");
+                self.expr(condition);
+                self.token(")");
+                self.body(body);
+            }
+        }
+    }
+
+    fn target(&mut self, target: &Target) {
+        match target {
+            Target::Field(object, name) => {
+                self.expr(object);
+                self.token(".");
+                self.token(name.lexeme());
+            }
+            Target::Index(object, index) => {
+                self.expr(object);
+                self.token("[");
+                self.expr(index);
+                self.token("]");
+            }
+            Target::Variable(name) => self.token(name.lexeme()),
+        }
+    }
+
+    /// Writes an assignment as the source spelled it: plainly, as a
+    /// compound assignment, or as a prefix increment or decrement. A
+    /// `combined` value is already the operand of a compound assignment.
+    fn assignment(&mut self, target: Target, value: &Expr, combined: bool) {
+        if self.peek_is("++") || self.peek_is("--") {
+            let operator = self.peek().lexeme();
+            self.token(operator);
+            return self.target(&target);
+        }
+
+        self.target(&target);
+        self.write(" ");
+        let operator = self.peek().lexeme();
+        let operand = match &value.kind {
+            _ if combined => Some(value),
+            ExprKind::Binary { right, .. } => Some(&**right),
+            _ => None,
+        };
+        match operand {
+            Some(operand) if ["+=", "-=", "*=", "/="].contains(&operator) => {
+                self.token(operator);
+                self.write(" ");
+                self.expr(operand);
+            }
+            _ => {
+                self.token("=");
+                self.write(" ");
+                self.expr(value);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Assign { name, value } => {
+                self.assignment(Target::Variable(name), value, false)
+            }
+            ExprKind::AssignList { targets, value, .. } => {
+                self.token("[");
+                self.list(targets, Self::expr);
+                self.token("]");
+                self.write(" ");
+                self.token("=");
+                self.write(" ");
+                self.expr(value);
+            }
+            ExprKind::Binary {
+                left,
+                operator,
+                right,
+            }
+            | ExprKind::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.expr(left);
+                if self.in_interpolation() {
+                    self.expr(right);
+                    return;
+                }
+                if operator.typ() != &TokenType::Comma {
+                    self.write(" ");
+                }
+                self.token(operator.lexeme());
+                self.write(" ");
+                self.expr(right);
+            }
+            // The `str` call wrapping an interpolated expression.
+            ExprKind::Call {
+                paren, arguments, ..
+            } if paren.typ() == &TokenType::Interpolation => self.expr(&arguments[0]),
+            ExprKind::Call {
+                callee, arguments, ..
+            } => {
+                self.expr(callee);
+                self.token("(");
+                self.list(arguments, Self::expr);
+                self.token(")");
+            }
+            ExprKind::Get { object, name } => {
+                self.expr(object);
+                self.token(".");
+                self.token(name.lexeme());
+            }
+            ExprKind::Grouping(inner) => {
+                self.token("(");
+                self.expr(inner);
+                self.token(")");
+            }
+            ExprKind::Index { object, index, .. } => {
+                self.expr(object);
+                self.token("[");
+                self.expr(index);
+                self.token("]");
+            }
+            ExprKind::IndexSet {
+                object,
+                index,
+                value,
+                operator,
+                ..
+            } => self.assignment(Target::Index(object, index), value, operator.is_some()),
+            ExprKind::Lambda { params, body } => {
+                self.token("fun");
+                self.write(" ");
+                self.params(params);
+                self.write(" ");
+                self.block(body);
+            }
+            ExprKind::List(elements) => {
+                self.token("[");
+                self.list(elements, Self::expr);
+                self.token("]");
+            }
+            ExprKind::Literal(value) => self.literal(value),
+            ExprKind::Map { entries, .. } => {
+                self.token("{");
+                self.list(entries, |f, (key, value)| {
+                    f.expr(key);
+                    f.token(":");
+                    f.write(" ");
+                    f.expr(value);
+                });
+                self.token("}");
+            }
+            ExprKind::Postfix { value, assignment } => {
+                self.expr(value);
+                let increment = match &assignment.kind {
+                    ExprKind::Assign { value, .. } => {
+                        matches!(&value.kind, ExprKind::Binary { operator, .. } if operator.lexeme() == "+")
+                    }
+                    ExprKind::Set { operator, .. } | ExprKind::IndexSet { operator, .. } => {
+                        operator
+                            .as_ref()
+                            .is_some_and(|operator| operator.lexeme() == "+")
+                    }
+                    _ => true,
+                };
+                self.token(if increment { "++" } else { "--" });
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
+                operator,
+            } => self.assignment(Target::Field(object, name), value, operator.is_some()),
+            ExprKind::Super { method, .. } => {
+                self.token("super");
+                self.token(".");
+                self.token(method.lexeme());
+            }
+            ExprKind::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.write(" ");
+                self.token("?");
+                self.write(" ");
+                self.expr(then_branch);
+                self.write(" ");
+                self.token(":");
+                self.write(" ");
+                self.expr(else_branch);
+            }
+            ExprKind::This(_) => self.token("this"),
+            ExprKind::Unary { operator, right } => {
+                // `--x` on something that can't be decremented negates it
+                // twice.
+                if let ExprKind::Unary { right: inner, .. } = &right.kind {
+                    if self.peek_is("--") {
+                        self.token("--");
+                        return self.expr(inner);
+                    }
+                }
+
+                self.token(operator.lexeme());
+                // Keep `- -x` from turning into a decrement.
+                if operator.lexeme() == "-" && self.peek().lexeme().starts_with('-') {
+                    self.write(" ");
+                }
+                self.expr(right);
+            }
+            ExprKind::Variable(name) => self.token(name.lexeme()),
+        }
+    }
+}
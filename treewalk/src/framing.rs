@@ -0,0 +1,41 @@
+//! Messages framed by a `Content-Length` header, the way the Debug Adapter
+//! and Language Server protocols send them.
+
+use crate::json::Json;
+use std::io::{BufRead, Write};
+
+/// The longest message body that will be read. The body is allocated before
+/// it's read, so a bogus `Content-Length` mustn't be trusted.
+const MAX_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Reads the next message, or `None` once `input` ends or holds something
+/// that isn't a message, including one longer than [`MAX_LENGTH`].
+pub(crate) fn read(input: &mut impl BufRead) -> Option<Json> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0; length.filter(|length| *length <= MAX_LENGTH)?];
+    input.read_exact(&mut body).ok()?;
+
+    Json::parse(&String::from_utf8_lossy(&body)).ok()
+}
+
+/// Writes `message` with its header.
+pub(crate) fn write(output: &mut impl Write, message: &Json) {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())
+        .expect("must be able to write messages");
+    output.flush().expect("must be able to write messages");
+}
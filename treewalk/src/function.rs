@@ -1,34 +1,45 @@
 use crate::{
-    ast::Stmt,
+    ast::{Param, Stmt},
     callable::Callable,
-    class::LoxInstance,
+    class::{LoxClass, LoxInstance},
+    gc::Tracer,
     interpreter::{Environment, Error, Interpreter},
-    token::{Token, TokenType},
+    sync::{Lock, Shared},
+    token::Token,
     value::Value,
 };
-use std::{any::Any, cell::RefCell, fmt, rc::Rc};
+use std::{any::Any, fmt, ops::RangeInclusive};
+use uuid::Uuid;
 
 #[derive(Clone, Debug)]
 pub struct LoxFunction {
-    name: Token,
-    params: Vec<Token>,
+    /// Tells apart the functions made by each run of a declaration, which
+    /// bound methods share with the method they were bound from.
+    id: Uuid,
+    /// Anonymous functions have no name.
+    name: Option<Token>,
+    params: Vec<Param>,
     body: Vec<Stmt>,
-    closure: Rc<RefCell<Environment>>,
+    closure: Shared<Lock<Environment>>,
+    /// The globals of the module the function was defined in.
+    globals: Shared<Lock<Environment>>,
     is_initializer: bool,
 }
 
 impl LoxFunction {
     pub fn new(
-        name: Token,
-        params: Vec<Token>,
+        name: Option<Token>,
+        params: Vec<Param>,
         body: Vec<Stmt>,
-        closure: Rc<RefCell<Environment>>,
+        closure: Shared<Lock<Environment>>,
         is_initializer: bool,
     ) -> Self {
         Self {
+            id: Uuid::new_v4(),
             name,
             params,
             body,
+            globals: Environment::globals(&closure),
             closure,
             is_initializer,
         }
@@ -38,58 +49,89 @@ impl LoxFunction {
         Value::Callable(Box::new(self))
     }
 
-    pub fn bind(self, instance: Rc<RefCell<LoxInstance>>) -> Self {
+    /// The function's name, unless it's anonymous.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(Token::lexeme)
+    }
+
+    pub fn bind(self, instance: Shared<Lock<LoxInstance>>) -> Self {
+        self.bind_this(Value::Instance(instance))
+    }
+
+    /// Class methods are bound to the class they were looked up on.
+    pub fn bind_class(self, class: LoxClass) -> Self {
+        self.bind_this(class.value())
+    }
+
+    fn bind_this(self, this: Value) -> Self {
         let environment = Environment::wrap(self.closure);
-        environment
-            .borrow_mut()
-            .define("this", &Value::Instance(instance));
-
-        LoxFunction::new(
-            self.name,
-            self.params,
-            self.body,
-            environment,
-            self.is_initializer,
-        )
+        environment.borrow_mut().define("this", &this);
+
+        Self {
+            closure: environment,
+            ..self
+        }
+    }
+}
+
+impl LoxFunction {
+    pub(crate) fn trace(&self, tracer: &mut Tracer) {
+        tracer.object(&self.closure);
+        tracer.object(&self.globals);
     }
 }
 
 impl fmt::Display for LoxFunction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let name = self.name.lexeme();
-
-        write!(f, "<fn {name}>")
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name.lexeme()),
+            None => write!(f, "<fn>"),
+        }
     }
 }
 
 impl Callable for LoxFunction {
-    fn arity(&self) -> usize {
-        self.params.len()
+    fn arity(&self) -> RangeInclusive<usize> {
+        let required = self
+            .params
+            .iter()
+            .take_while(|param| param.default.is_none())
+            .count();
+
+        required..=self.params.len()
     }
 
     fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, Error> {
         let environment = Environment::wrap(self.closure.clone());
-        for (idx, param) in self.params.iter().enumerate() {
-            environment
-                .borrow_mut()
-                .define(param.lexeme(), &arguments[idx]);
-        }
 
-        match interpreter.execute_block(self.body.clone(), environment) {
+        // A bound method's closure holds nothing but `this`.
+        let result = interpreter.with_globals(self.globals.clone(), |interpreter| {
+            let mut arguments = arguments.into_iter();
+            for param in &self.params {
+                // Defaults can refer to the parameters before them.
+                let value = match (arguments.next(), &param.default) {
+                    (Some(value), _) => value,
+                    (None, Some(default)) => {
+                        interpreter.evaluate_in(default.clone(), environment.clone())?
+                    }
+                    (None, None) => unreachable!("calls are checked against the arity"),
+                };
+                environment.borrow_mut().define(param.name.lexeme(), &value);
+            }
+
+            interpreter.execute_block(self.body.clone(), environment)
+        });
+        match result {
             Ok(_) => {
                 if !self.is_initializer {
                     Ok(Value::Nil)
                 } else {
-                    self.closure
-                        .borrow()
-                        .get(&Token::new(TokenType::This, "this", None, 42))
+                    Ok(self.closure.borrow().get_at(0, 0))
                 }
             }
             Err(Error::Return { value }) => {
                 if self.is_initializer {
-                    self.closure
-                        .borrow()
-                        .get(&Token::new(TokenType::This, "this", None, 42))
+                    Ok(self.closure.borrow().get_at(0, 0))
                 } else {
                     Ok(value)
                 }
@@ -102,6 +144,14 @@ impl Callable for LoxFunction {
         Box::new((*self).clone())
     }
 
+    /// Binding a method makes a new closure for `this`, so each time a
+    /// method is looked up it's a different function.
+    fn same_as(&self, other: &dyn Callable) -> bool {
+        other.as_any().downcast_ref::<Self>().is_some_and(|other| {
+            self.id == other.id && Shared::ptr_eq(&self.closure, &other.closure)
+        })
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
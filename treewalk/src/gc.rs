@@ -0,0 +1,196 @@
+//! Frees values that only keep each other alive.
+//!
+//! Values are reference counted, so a closure stored in the scope it
+//! captured, or two instances that refer to each other, would never be freed.
+//! Every scope, instance, list and map an interpreter makes is tracked on its
+//! [`Heap`], which finds the unreachable ones by trial deletion: the
+//! references each object gets from other tracked objects are taken off its
+//! count, and any with references left over are held from outside the heap,
+//! by the interpreter or the Rust stack, so they're kept along with
+//! everything they refer to. The rest are emptied, which breaks their cycles
+//! and lets them be freed.
+//!
+//! References the collector can't see, such as those a native's closure
+//! holds, only ever make it keep more than it needs to.
+
+use crate::{
+    class::LoxClass,
+    function::LoxFunction,
+    sync::{Lock, Shared, Threadsafe, Weak},
+    value::Value,
+};
+use std::{collections::HashMap, fmt};
+
+/// How many objects a heap tracks before it first collects.
+const INITIAL_THRESHOLD: usize = 1024;
+
+/// An object that can refer to others, and so be part of a cycle.
+pub(crate) trait Trace: Threadsafe {
+    /// Reports every tracked object this one refers to.
+    fn trace(&self, tracer: &mut Tracer);
+
+    /// Drops everything this object refers to.
+    fn clear(&mut self);
+}
+
+impl Trace for Vec<Value> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.iter().for_each(|value| tracer.value(value));
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self);
+    }
+}
+
+/// Collects the references an object holds.
+#[derive(Default)]
+pub(crate) struct Tracer {
+    addresses: Vec<usize>,
+}
+
+impl Tracer {
+    pub(crate) fn object<T: ?Sized>(&mut self, object: &Shared<Lock<T>>) {
+        self.addresses.push(address(object));
+    }
+
+    pub(crate) fn value(&mut self, value: &Value) {
+        match value {
+            Value::Instance(instance) => self.object(instance),
+            Value::List(list) => self.object(list),
+            Value::Map(map) => self.object(map),
+            Value::Callable(callable) => {
+                let callable = callable.as_any();
+                if let Some(function) = callable.downcast_ref::<LoxFunction>() {
+                    function.trace(self);
+                } else if let Some(class) = callable.downcast_ref::<LoxClass>() {
+                    class.trace(self);
+                }
+            }
+            // Modules are only ever held by the interpreter's cache and the
+            // scopes that import them, so their globals are left alone.
+            Value::Boolean(_)
+            | Value::Module(_)
+            | Value::Nil
+            | Value::Number(_)
+            | Value::Range(_)
+            | Value::String(_) => {}
+        }
+    }
+}
+
+fn address<T: ?Sized>(object: &Shared<Lock<T>>) -> usize {
+    Shared::as_ptr(object) as *const () as usize
+}
+
+/// The objects an interpreter has made that could be part of a cycle.
+pub struct Heap {
+    objects: Vec<Weak<Lock<dyn Trace>>>,
+    /// How many objects can be tracked before the next collection.
+    threshold: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            objects: vec![],
+            threshold: INITIAL_THRESHOLD,
+        }
+    }
+}
+
+impl fmt::Debug for Heap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Heap")
+            .field("objects", &self.objects.len())
+            .finish()
+    }
+}
+
+impl Heap {
+    /// Tracks `object`, collecting first if the heap has doubled since the
+    /// last collection.
+    pub(crate) fn track<T: Trace + 'static>(heap: &Shared<Lock<Heap>>, object: &Shared<Lock<T>>) {
+        let mut heap = heap.borrow_mut();
+        if heap.objects.len() >= heap.threshold {
+            heap.collect();
+        }
+
+        heap.objects
+            .push(Shared::downgrade(object) as Weak<Lock<dyn Trace>>);
+    }
+
+    /// How many tracked objects haven't been freed yet.
+    pub fn live(&self) -> usize {
+        self.objects
+            .iter()
+            .filter(|object| object.strong_count() > 0)
+            .count()
+    }
+
+    /// Frees the objects that can only be reached from each other, returning
+    /// how many there were.
+    pub fn collect(&mut self) -> usize {
+        let objects = self
+            .objects
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<_>>();
+        let indices = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| (address(object), index))
+            .collect::<HashMap<_, _>>();
+
+        // Leaving out the reference just taken to each object.
+        let mut outside = objects
+            .iter()
+            .map(|object| Shared::strong_count(object) - 1)
+            .collect::<Vec<_>>();
+        let mut references = vec![vec![]; objects.len()];
+        for (index, object) in objects.iter().enumerate() {
+            // An object that's borrowed is in use, and anything it refers to
+            // isn't discounted, so all of them are kept.
+            let Ok(object) = object.try_borrow() else {
+                outside[index] += 1;
+                continue;
+            };
+
+            let mut tracer = Tracer::default();
+            object.trace(&mut tracer);
+            for address in tracer.addresses {
+                if let Some(&referenced) = indices.get(&address) {
+                    outside[referenced] -= 1;
+                    references[index].push(referenced);
+                }
+            }
+        }
+
+        let mut reachable = vec![false; objects.len()];
+        let mut pending = (0..objects.len())
+            .filter(|index| outside[*index] > 0)
+            .collect::<Vec<_>>();
+        while let Some(index) = pending.pop() {
+            if !reachable[index] {
+                reachable[index] = true;
+                pending.extend(&references[index]);
+            }
+        }
+
+        let mut freed = 0;
+        self.objects.clear();
+        for (object, reachable) in objects.iter().zip(reachable) {
+            if !reachable {
+                if let Ok(mut object) = object.try_borrow_mut() {
+                    object.clear();
+                    freed += 1;
+                    continue;
+                }
+            }
+            self.objects.push(Shared::downgrade(object));
+        }
+        self.threshold = (self.objects.len() * 2).max(INITIAL_THRESHOLD);
+
+        freed
+    }
+}
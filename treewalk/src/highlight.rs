@@ -0,0 +1,275 @@
+//! Classifies every token in Lox source, so that editors can highlight code
+//! the way loxide itself reads it.
+//!
+//! Keywords, literals and operators are told apart by the scanner alone.
+//! Identifiers are classified by what they name: the resolver finds the
+//! declaration each one refers to, and the declaration says whether it's a
+//! function, class, parameter and so on.
+
+use crate::{
+    ast::{Expr, ExprKind, Param, Stmt},
+    interpreter::Interpreter,
+    parser::Parser,
+    resolver::Resolver,
+    scanner::Scanner,
+    token::{Span, TokenType},
+};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenCategory {
+    Comment,
+    Keyword,
+    Number,
+    String,
+    Operator,
+    Punctuation,
+    /// A variable that isn't one of the kinds below, including globals that
+    /// are never declared, like native functions.
+    Variable,
+    Constant,
+    Parameter,
+    Function,
+    Class,
+    Method,
+    /// A field or method looked up with `.`.
+    Property,
+    /// The variable an imported module is bound to.
+    Module,
+}
+
+/// The tokens and comments in `source`, in order, with what each is. Source
+/// that doesn't scan or parse is still classified as far as possible.
+pub fn semantic_tokens(source: &str) -> Vec<(Span, TokenCategory)> {
+    let mut scanner = Scanner::new(source).with_trivia();
+    let tokens = scanner.scan().to_vec();
+    let (statements, _) = Parser::new(&tokens).parse();
+
+    let mut declarations = HashMap::new();
+    for statement in &statements {
+        declare_stmt(statement, &mut declarations);
+    }
+
+    let mut interpreter = Interpreter::new();
+    let mut resolver = Resolver::new(&mut interpreter);
+    resolver.resolve_statements(statements);
+    let mut definitions = HashMap::new();
+    for (mention, declaration) in resolver.definitions() {
+        definitions.insert(mention.offset(), declaration.offset());
+    }
+
+    let mut classified = vec![];
+    let mut previous = None;
+    for token in &tokens {
+        let trivia = token.trivia();
+        for comment in trivia
+            .map(|trivia| trivia.leading.as_slice())
+            .unwrap_or_default()
+        {
+            let span = Span {
+                offset: comment.offset,
+                len: comment.text.len(),
+            };
+            classified.push((span, TokenCategory::Comment));
+        }
+
+        let category = match token.typ() {
+            TokenType::Eof => continue,
+            TokenType::Identifier => {
+                let offset = token.offset();
+                let declaration = definitions.get(&offset).unwrap_or(&offset);
+                match declarations.get(declaration) {
+                    Some(category) => *category,
+                    None if previous == Some(TokenType::Dot) => TokenCategory::Property,
+                    None => TokenCategory::Variable,
+                }
+            }
+            typ => category(*typ),
+        };
+        classified.push((Span::of(token), category));
+
+        if let Some(comment) = trivia.and_then(|trivia| trivia.trailing.as_ref()) {
+            let span = Span {
+                offset: comment.offset,
+                len: comment.text.len(),
+            };
+            classified.push((span, TokenCategory::Comment));
+        }
+        previous = Some(*token.typ());
+    }
+
+    classified
+}
+
+fn category(typ: TokenType) -> TokenCategory {
+    use TokenType::*;
+
+    match typ {
+        LeftParen | RightParen | LeftBrace | RightBrace | LeftBracket | RightBracket | Colon
+        | Comma | Dot | Semicolon => TokenCategory::Punctuation,
+        Minus | Percent | Plus | Question | Slash | Star | Bang | BangEqual | Equal
+        | EqualEqual | Greater | GreaterEqual | Less | LessEqual | MinusEqual | MinusMinus
+        | PlusEqual | PlusPlus | SlashEqual | StarEqual => TokenCategory::Operator,
+        String | Interpolation | InterpolationEnd => TokenCategory::String,
+        Number => TokenCategory::Number,
+        Identifier | Eof => TokenCategory::Variable,
+        And | Assert | Break | Case | Catch | Class | Const | Continue | Default | Else | False
+        | Finally | Fun | For | If | Import | In | Nil | Or | Print | Return | Super | Switch
+        | This | Throw | True | Try | Var | While => TokenCategory::Keyword,
+    }
+}
+
+/// Records what each name declared in `statement` is, by where the name
+/// starts.
+fn declare_stmt(statement: &Stmt, declarations: &mut HashMap<usize, TokenCategory>) {
+    match statement {
+        Stmt::Block(statements) => declare_stmts(statements, declarations),
+        Stmt::Class {
+            name,
+            superclass,
+            methods,
+            class_methods,
+            getters,
+        } => {
+            declarations.insert(name.offset(), TokenCategory::Class);
+            if let Some(superclass) = superclass {
+                declare_expr(superclass, declarations);
+            }
+            for method in methods.iter().chain(class_methods).chain(getters) {
+                if let Stmt::Function { name, params, body } = method {
+                    declarations.insert(name.offset(), TokenCategory::Method);
+                    declare_function(params, body, declarations);
+                }
+            }
+        }
+        Stmt::Const { name, initializer } => {
+            declarations.insert(name.offset(), TokenCategory::Constant);
+            declare_expr(initializer, declarations);
+        }
+        Stmt::ForIn {
+            name,
+            iterable,
+            body,
+        } => {
+            declarations.insert(name.offset(), TokenCategory::Variable);
+            declare_expr(iterable, declarations);
+            declare_stmt(body, declarations);
+        }
+        Stmt::Function { name, params, body } => {
+            declarations.insert(name.offset(), TokenCategory::Function);
+            declare_function(params, body, declarations);
+        }
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            declare_expr(condition, declarations);
+            declare_stmt(then_branch, declarations);
+            if let Some(else_branch) = else_branch {
+                declare_stmt(else_branch, declarations);
+            }
+        }
+        Stmt::Import { name, .. } => {
+            declarations.insert(name.offset(), TokenCategory::Module);
+        }
+        Stmt::Assert {
+            condition, message, ..
+        } => {
+            declare_expr(condition, declarations);
+            if let Some(message) = message {
+                declare_expr(message, declarations);
+            }
+        }
+        Stmt::Expression(expr)
+        | Stmt::Print { value: expr, .. }
+        | Stmt::Throw { value: expr, .. } => declare_expr(expr, declarations),
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                declare_expr(value, declarations);
+            }
+        }
+        Stmt::Switch { subject, cases, .. } => {
+            declare_expr(subject, declarations);
+            for case in cases {
+                if let Some(value) = &case.value {
+                    declare_expr(value, declarations);
+                }
+                declare_stmts(&case.body, declarations);
+            }
+        }
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            declare_stmts(body, declarations);
+            if let Some((name, body)) = catch {
+                declarations.insert(name.offset(), TokenCategory::Variable);
+                declare_stmts(body, declarations);
+            }
+            if let Some(finally) = finally {
+                declare_stmts(finally, declarations);
+            }
+        }
+        Stmt::Var { name, initializer } => {
+            declarations.insert(name.offset(), TokenCategory::Variable);
+            if let Some(initializer) = initializer {
+                declare_expr(initializer, declarations);
+            }
+        }
+        Stmt::VarList {
+            names, initializer, ..
+        } => {
+            for name in names {
+                declarations.insert(name.offset(), TokenCategory::Variable);
+            }
+            declare_expr(initializer, declarations);
+        }
+        Stmt::While {
+            condition,
+            body,
+            increment,
+        } => {
+            declare_expr(condition, declarations);
+            declare_stmt(body, declarations);
+            if let Some(increment) = increment {
+                declare_expr(increment, declarations);
+            }
+        }
+        Stmt::Break(_) | Stmt::Continue(_) => {}
+    }
+}
+
+fn declare_stmts(statements: &[Stmt], declarations: &mut HashMap<usize, TokenCategory>) {
+    for statement in statements {
+        declare_stmt(statement, declarations);
+    }
+}
+
+fn declare_function(
+    params: &[Param],
+    body: &[Stmt],
+    declarations: &mut HashMap<usize, TokenCategory>,
+) {
+    for param in params {
+        declarations.insert(param.name.offset(), TokenCategory::Parameter);
+        if let Some(default) = &param.default {
+            declare_expr(default, declarations);
+        }
+    }
+    declare_stmts(body, declarations);
+}
+
+/// Records the parameters of lambdas in `expr`, and what's declared in their
+/// bodies.
+fn declare_expr(expr: &Expr, declarations: &mut HashMap<usize, TokenCategory>) {
+    match &expr.kind {
+        ExprKind::Lambda { params, body } => declare_function(params, body, declarations),
+        _ => {
+            for subexpression in expr.subexpressions() {
+                declare_expr(subexpression, declarations);
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! Interning of identifiers and string literals.
+//!
+//! Interned strings share one allocation, so cloning them is cheap and
+//! equal strings can usually be compared by pointer. The table only keeps
+//! strings that something else still holds on to: as it grows, strings
+//! nothing else uses any more are dropped from it, so long-lived hosts that
+//! scan source over and over don't keep every lexeme they've ever seen.
+
+use crate::sync::Shared;
+use std::{cell::RefCell, collections::HashSet};
+
+/// How big the table can get before strings nothing uses are first dropped.
+const MIN_PRUNE_LEN: usize = 1024;
+
+#[derive(Default)]
+struct Strings {
+    interned: HashSet<Shared<str>>,
+    /// How many strings were left after they were last pruned. The table is
+    /// pruned again once it has doubled, so pruning takes amortized constant
+    /// time per string interned.
+    pruned_len: usize,
+}
+
+impl Strings {
+    fn prune(&mut self) {
+        self.interned
+            .retain(|interned| Shared::strong_count(interned) > 1);
+        self.pruned_len = self.interned.len();
+    }
+}
+
+thread_local! {
+    static STRINGS: RefCell<Strings> = RefCell::new(Strings::default());
+}
+
+/// Returns the shared copy of `s`, adding it to the table if needed.
+pub fn intern(s: &str) -> Shared<str> {
+    STRINGS.with(|strings| {
+        let mut strings = strings.borrow_mut();
+        if let Some(interned) = strings.interned.get(s) {
+            return interned.clone();
+        }
+
+        if strings.interned.len() >= MIN_PRUNE_LEN.max(2 * strings.pruned_len) {
+            strings.prune();
+        }
+        let interned: Shared<str> = Shared::from(s);
+        strings.interned.insert(interned.clone());
+
+        interned
+    })
+}
@@ -1,43 +1,259 @@
+#[cfg(feature = "send")]
+use crate::stepper::Stepper;
 use crate::{
     ast::{Expr, ExprKind, Stmt},
+    callable::Callable,
     class::{LoxClass, LoxInstance},
-    clock::Clock,
+    clock::{Clock, VirtualClock},
+    conversion::{Num, Str},
+    diagnostic::{Diagnostic, ErrorFormat},
     function::LoxFunction,
-    token::{Token, TokenType},
+    gc::{Heap, Trace, Tracer},
+    intern::intern,
+    io::{ReadFile, ReadLine},
+    iterator::LoxIterator,
+    map::LoxMap,
+    module::{self, Module},
+    native::NativeFunction,
+    optimizer,
+    parser::{Parser, MAX_NESTING},
+    repr::LoxValueRepr,
+    resolver::{Resolver, Shadowing},
+    scanner::Scanner,
+    stdlib,
+    sync::{Lock, Shared, Threadsafe, Writer},
+    token::{Span, Token, TokenType},
     value::Value,
 };
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    io::{self, Write},
+    iter,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// The default for [`Interpreter::set_max_call_depth`]. Nesting calls this
+/// deeply needs a thread with a [`STACK_SIZE`] stack: in a debug build, a
+/// default 8MB main thread overflows after about fifty calls.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// The stack size to spawn threads that run scripts with, which is enough
+/// for [`DEFAULT_MAX_CALL_DEPTH`] nested calls in a debug build.
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// One entry in the stack trace of a runtime error.
+///
+/// Frames print as `[line 2] in <fn inner>`, naming the function the way
+/// `print` shows it, or as `[line 9] in script` for top-level code. That's
+/// the layout of clox's traces, but clox names functions as `inner()`.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// The function that was executing, or `None` for top-level code.
+    pub function: Option<String>,
+    pub line: usize,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(function) => write!(f, "[line {}] in {function}", self.line),
+            None => write!(f, "[line {}] in script", self.line),
+        }
+    }
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    /// `trace` lists the calls that were active when the error happened,
+    /// innermost first. It's empty for errors raised by top-level code.
+    ///
+    /// `span` is where in the script the error happened, if the code that
+    /// raised it came from the parser. Errors in modules have an empty span,
+    /// since they didn't happen in the script's source.
     #[error("{message}\n[line {line}]")]
-    Runtime { message: String, line: usize },
+    Runtime {
+        message: String,
+        line: usize,
+        trace: Vec<Frame>,
+        span: Option<Span>,
+    },
+
+    /// Raised by native functions, which don't know where they were called
+    /// from. The interpreter turns it into a runtime error at the call site.
+    #[error("{message}")]
+    Native { message: String },
 
     #[error("Returning {value:?}")]
     Return { value: Value },
+
+    /// Unwinds the stack to the nearest enclosing `catch` block. One that
+    /// escapes the script becomes an [`Error::Runtime`].
+    #[error("Throwing {value:?}")]
+    Throw { value: Value, line: usize },
+
+    /// Raised for source that doesn't compile, by [`Interpreter::compile`]
+    /// and for anything but a single valid expression by
+    /// [`Interpreter::eval_expr`]. The message holds the errors found.
+    #[error("{message}")]
+    Syntax { message: String },
+
+    /// Raised by `exit()`. It unwinds the script, running `finally` blocks on
+    /// the way, and leaves the host to decide what exiting means.
+    #[error("Exiting with status {code}.")]
+    Exit { code: i32 },
+
+    /// Raised by `panic()`. It stops the script like a runtime error, but
+    /// can't be caught.
+    #[error("{message}\n[line {line}]")]
+    Panic {
+        message: String,
+        line: usize,
+        trace: Vec<Frame>,
+    },
+
+    /// Raised when a script runs past its step limit or timeout. Unlike
+    /// runtime errors, it can't be caught by the script.
+    #[error("Execution budget exceeded.")]
+    Budget,
+
+    /// Raised when the host sets the interpreter's cancellation flag.
+    #[error("Interrupted.")]
+    Interrupted,
+
+    /// Raised for syntax trees the parser and resolver would never produce,
+    /// such as a unary `+`, which hosts building their own trees might. It
+    /// can't be caught by the script.
+    #[error("Internal error: {message}")]
+    Internal { message: String },
+
+    #[error("Breaking out of loop")]
+    Break,
+
+    #[error("Continuing loop")]
+    Continue,
 }
 
-#[derive(Clone, Default, Debug)]
+impl Error {
+    /// A [`Error::Native`] with `message`.
+    pub(crate) fn native(message: impl Into<String>) -> Self {
+        Self::Native {
+            message: message.into(),
+        }
+    }
+
+    /// The code from sysexits.h that the reference implementations exit
+    /// with for this error, or the one the script asked to exit with.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Syntax { .. } => 65,
+            Self::Exit { code } => *code,
+            _ => 70,
+        }
+    }
+}
+
+/// A scope of variables.
+///
+/// Locals live in `slots`, at the index the resolver assigned them, which is
+/// the order they are declared in. Globals can be used before they are
+/// declared, so the global scope looks its variables up by name instead.
+/// It also remembers which globals are constants, since the resolver can't
+/// check assignments to them.
+///
+/// A variable declared without an initializer holds `None` until it's first
+/// assigned, so that reading it can be reported.
+#[derive(Clone, Debug)]
 pub struct Environment {
-    enclosing: Option<Rc<RefCell<Environment>>>,
-    values: HashMap<String, Value>,
+    enclosing: Option<Shared<Lock<Environment>>>,
+    /// The heap the scope is tracked on, which the scopes nested in it are
+    /// tracked on too.
+    heap: Shared<Lock<Heap>>,
+    values: HashMap<Shared<str>, Option<Value>>,
+    constants: HashSet<Shared<str>>,
+    slots: Vec<Option<Value>>,
+    /// The name of the variable in each slot, for debuggers.
+    names: Vec<Shared<str>>,
 }
 
 impl Environment {
-    pub fn wrap(enclosing: Rc<RefCell<Environment>>) -> Rc<RefCell<Self>> {
-        let environment = Self {
-            enclosing: Some(enclosing),
+    /// Creates the global scope of a script or module.
+    pub fn global(heap: &Shared<Lock<Heap>>) -> Shared<Lock<Self>> {
+        Self::tracked(None, heap.clone())
+    }
+
+    pub fn wrap(enclosing: Shared<Lock<Environment>>) -> Shared<Lock<Self>> {
+        let heap = enclosing.borrow().heap.clone();
+
+        Self::tracked(Some(enclosing), heap)
+    }
+
+    fn tracked(
+        enclosing: Option<Shared<Lock<Environment>>>,
+        heap: Shared<Lock<Heap>>,
+    ) -> Shared<Lock<Self>> {
+        let environment = Shared::new(Lock::new(Self {
+            enclosing,
+            heap: heap.clone(),
             values: HashMap::new(),
-        };
+            constants: HashSet::new(),
+            slots: vec![],
+            names: vec![],
+        }));
+        Heap::track(&heap, &environment);
 
-        Rc::new(RefCell::new(environment))
+        environment
     }
 
+    /// Defines a global by name, or the next local slot in any other scope.
     pub fn define(&mut self, name: &str, value: &Value) {
-        self.values.insert(name.to_string(), value.clone());
+        self.define_value(name, Some(value.clone()));
+    }
+
+    /// Defines a variable that has no value until it's assigned one.
+    pub fn declare(&mut self, name: &str) {
+        self.define_value(name, None);
+    }
+
+    fn define_value(&mut self, name: &str, value: Option<Value>) {
+        if self.enclosing.is_some() {
+            self.slots.push(value);
+            self.names.push(intern(name));
+        } else {
+            self.constants.remove(name);
+            self.values.insert(intern(name), value);
+        }
+    }
+
+    /// Like [`Environment::define`], but the variable can't be assigned to
+    /// afterwards.
+    pub fn define_constant(&mut self, name: &str, value: &Value) {
+        self.define(name, value);
+        if self.enclosing.is_none() {
+            self.constants.insert(intern(name));
+        }
     }
 
-    fn ancestor(&self, distance: usize) -> Rc<RefCell<Environment>> {
+    /// The global scope `environment` is nested in.
+    pub fn globals(environment: &Shared<Lock<Environment>>) -> Shared<Lock<Environment>> {
+        let mut environment = environment.clone();
+        loop {
+            let enclosing = environment.borrow().enclosing.clone();
+            match enclosing {
+                Some(enclosing) => environment = enclosing,
+                None => return environment,
+            }
+        }
+    }
+
+    fn ancestor(&self, distance: usize) -> Shared<Lock<Environment>> {
         let mut environment = self.enclosing.clone().expect("must have an ancestor");
         for _ in 1..distance {
             let new_env = environment
@@ -54,57 +270,140 @@ impl Environment {
     fn assign(&mut self, name: &Token, value: &Value) -> Result<(), Error> {
         let lexeme = name.lexeme();
 
-        if self.values.contains_key(lexeme) {
-            self.values.insert(lexeme.to_string(), value.clone());
-
-            return Ok(());
+        if self.constants.contains(lexeme) {
+            return Err(Error::Runtime {
+                message: format!("Can't assign to constant '{lexeme}'."),
+                line: name.line(),
+                trace: vec![],
+                span: None,
+            });
         }
 
-        if let Some(enclosing) = &self.enclosing {
-            enclosing.borrow_mut().assign(name, value)
+        if let Some(slot) = self.values.get_mut(lexeme) {
+            *slot = Some(value.clone());
+
+            Ok(())
         } else {
             Err(Error::Runtime {
                 message: format!("Undefined variable '{lexeme}'."),
                 line: name.line(),
+                trace: vec![],
+                span: None,
             })
         }
     }
 
-    fn assign_at(&mut self, distance: usize, name: &Token, value: &Value) -> Result<(), Error> {
+    fn assign_at(&mut self, distance: usize, slot: usize, value: &Value) {
         if distance == 0 {
-            self.assign(name, value)
+            self.slots[slot] = Some(value.clone());
         } else {
-            self.ancestor(distance).borrow_mut().assign(name, value)
+            self.ancestor(distance).borrow_mut().slots[slot] = Some(value.clone());
         }
     }
 
     pub fn get(&self, name: &Token) -> Result<Value, Error> {
         let lexeme = name.lexeme();
 
-        if let Some(value) = self.values.get(lexeme) {
-            return Ok(value.clone());
-        }
-
-        if let Some(enclosing) = &self.enclosing {
-            enclosing.borrow().get(name)
-        } else {
-            Err(Error::Runtime {
+        match self.values.get(lexeme) {
+            Some(value) => value.clone().ok_or_else(|| unassigned(name)),
+            None => Err(Error::Runtime {
                 message: format!("Undefined variable '{lexeme}'."),
                 line: name.line(),
-            })
+                trace: vec![],
+                span: None,
+            }),
         }
     }
 
-    fn get_at(&self, distance: usize, name: &Token) -> Result<Value, Error> {
+    /// Looks up the local variable `name` resolved to.
+    pub fn get_local(&self, name: &Token, distance: usize, slot: usize) -> Result<Value, Error> {
+        self.slot(distance, slot).ok_or_else(|| unassigned(name))
+    }
+
+    /// Looks up a local that's always assigned, like `this` or `super`.
+    pub fn get_at(&self, distance: usize, slot: usize) -> Value {
+        self.slot(distance, slot)
+            .expect("implicit variables are always assigned")
+    }
+
+    fn slot(&self, distance: usize, slot: usize) -> Option<Value> {
         if distance == 0 {
-            self.get(name)
+            self.slots[slot].clone()
         } else {
-            self.ancestor(distance).borrow().get(name)
+            self.ancestor(distance).borrow().slots[slot].clone()
+        }
+    }
+}
+
+impl Trace for Environment {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(enclosing) = &self.enclosing {
+            tracer.object(enclosing);
         }
+        for value in self.values.values().chain(&self.slots).flatten() {
+            tracer.value(value);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.enclosing = None;
+        self.values.clear();
+        self.slots.clear();
+    }
+}
+
+/// Fails if the private property `name` belongs to a class other than
+/// `class`, the one whose methods use it. Properties that don't exist yet
+/// belong to no class.
+fn check_owner(owner: Option<&Token>, class: &Token, name: &Token) -> Result<(), Error> {
+    match owner {
+        Some(owner) if owner != class => Err(Error::Runtime {
+            message: format!(
+                "Private property '{}' can only be accessed in class '{}'.",
+                name.lexeme(),
+                owner.lexeme()
+            ),
+            line: name.line(),
+            trace: vec![],
+            span: None,
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn unassigned(name: &Token) -> Error {
+    Error::Runtime {
+        message: format!("Variable '{}' used before assignment.", name.lexeme()),
+        line: name.line(),
+        trace: vec![],
+        span: None,
+    }
+}
+
+/// Fails if `name` is a private property, one whose name starts with `_`,
+/// being used through anything but `this`. Only the methods of an object
+/// can use its private fields and methods.
+fn check_private(object: &Expr, name: &Token) -> Result<(), Error> {
+    if !name.lexeme().starts_with('_') || matches!(object.kind, ExprKind::This(_)) {
+        return Ok(());
     }
+
+    Err(Error::Runtime {
+        message: format!(
+            "Private property '{}' can only be accessed through 'this'.",
+            name.lexeme()
+        ),
+        line: name.line(),
+        trace: vec![],
+        span: None,
+    })
+}
+
+fn internal(message: String) -> Error {
+    Error::Internal { message }
 }
 
-fn is_truthy(value: &Value) -> bool {
+pub(crate) fn is_truthy(value: &Value) -> bool {
     match value {
         Value::Nil => false,
         Value::Boolean(b) => *b,
@@ -119,6 +418,8 @@ fn check_number_operand(operator: Token, operand: Value) -> Result<f64, Error> {
         Err(Error::Runtime {
             message: "Operand must be a number.".to_string(),
             line: operator.line(),
+            trace: vec![],
+            span: None,
         })
     }
 }
@@ -130,53 +431,1258 @@ fn check_number_operands(operator: Token, left: Value, right: Value) -> Result<(
         Err(Error::Runtime {
             message: "Operands must be numbers.".to_string(),
             line: operator.line(),
+            trace: vec![],
+            span: None,
         })
     }
 }
 
+fn list_index(bracket: &Token, list: &[Value], index: Value) -> Result<usize, Error> {
+    match index {
+        Value::Number(n) if n.fract() == 0.0 => {
+            if n >= 0.0 && (n as usize) < list.len() {
+                Ok(n as usize)
+            } else {
+                Err(Error::Runtime {
+                    message: "List index out of range.".to_string(),
+                    line: bracket.line(),
+                    trace: vec![],
+                    span: None,
+                })
+            }
+        }
+        _ => Err(Error::Runtime {
+            message: "List index must be an integer.".to_string(),
+            line: bracket.line(),
+            trace: vec![],
+            span: None,
+        }),
+    }
+}
+
+/// Returns the elements of the list `value` is, which must have exactly
+/// `count` of them to be destructured.
+fn unpack(equals: &Token, value: &Value, count: usize) -> Result<Vec<Value>, Error> {
+    let error = |message: String| Error::Runtime {
+        message,
+        line: equals.line(),
+        trace: vec![],
+        span: None,
+    };
+    match value {
+        Value::List(list) if list.borrow().len() == count => Ok(list.borrow().clone()),
+        Value::List(list) => Err(error(format!(
+            "Expected {count} values to unpack but got {}.",
+            list.borrow().len()
+        ))),
+        _ => Err(error("Can only destructure lists.".to_string())),
+    }
+}
+
+fn map_key(token: &Token, key: Value) -> Result<Value, Error> {
+    if LoxMap::is_hashable(&key) {
+        return Ok(key);
+    }
+
+    let message = if matches!(key, Value::Instance(_)) {
+        "Instances with an 'equals' method can't be map keys."
+    } else {
+        "Map keys must be booleans, nil, numbers, strings or instances."
+    };
+    Err(Error::Runtime {
+        message: message.to_string(),
+        line: token.line(),
+        trace: vec![],
+        span: None,
+    })
+}
+
+/// Returns the element of a list or map `object` at `index`.
+fn element(object: &Value, bracket: &Token, index: Value) -> Result<Value, Error> {
+    match object {
+        Value::List(list) => {
+            let list = list.borrow();
+            let index = list_index(bracket, &list, index)?;
+
+            Ok(list[index].clone())
+        }
+        Value::Map(map) => {
+            let key = map_key(bracket, index)?;
+
+            map.borrow().get(&key).ok_or_else(|| Error::Runtime {
+                message: format!("Undefined key '{key}'."),
+                line: bracket.line(),
+                trace: vec![],
+                span: None,
+            })
+        }
+        _ => Err(Error::Runtime {
+            message: "Only lists and maps can be indexed.".to_string(),
+            line: bracket.line(),
+            trace: vec![],
+            span: None,
+        }),
+    }
+}
+
+/// Instances with a `toString()` method can be concatenated with strings.
+fn is_string_like(value: &Value) -> bool {
+    match value {
+        Value::String(_) => true,
+        Value::Instance(instance) => to_string_method(instance).is_some(),
+        _ => false,
+    }
+}
+
+fn to_string_method(instance: &Shared<Lock<LoxInstance>>) -> Option<LoxFunction> {
+    instance
+        .borrow()
+        .class()
+        .find_method("toString")
+        .filter(|method| method.arity().contains(&0))
+}
+
+pub(crate) fn equals_method(instance: &Shared<Lock<LoxInstance>>) -> Option<LoxFunction> {
+    instance
+        .borrow()
+        .class()
+        .find_method("equals")
+        .filter(|method| method.arity().contains(&1))
+}
+
+/// Observes a script as it runs, for tools such as debuggers.
+pub trait Hook: Threadsafe {
+    /// Called before running each statement whose line is known. An error
+    /// stops the script as if the statement had raised it.
+    fn before_statement(&mut self, interpreter: &mut Interpreter, line: usize)
+        -> Result<(), Error>;
+
+    /// Called as `function` is called, before any of its statements run.
+    fn enter_call(&mut self, _function: &str) {}
+
+    /// Called as the function most recently entered returns, whether or
+    /// not it succeeded.
+    fn exit_call(&mut self) {}
+}
+
+/// Runs several hooks, in order.
+impl Hook for Vec<Box<dyn Hook>> {
+    fn before_statement(
+        &mut self,
+        interpreter: &mut Interpreter,
+        line: usize,
+    ) -> Result<(), Error> {
+        for hook in self {
+            hook.before_statement(interpreter, line)?;
+        }
+
+        Ok(())
+    }
+
+    fn enter_call(&mut self, function: &str) {
+        for hook in self {
+            hook.enter_call(function);
+        }
+    }
+
+    fn exit_call(&mut self) {
+        for hook in self {
+            hook.exit_call();
+        }
+    }
+}
+
+/// How much work a single call to [`Interpreter::interpret`] may do.
+#[derive(Default)]
+struct Budget {
+    step_limit: Option<u64>,
+    timeout: Option<Duration>,
+    steps: u64,
+    deadline: Option<Instant>,
+    /// Set once the budget runs out, so that `finally` blocks can't keep the
+    /// script going.
+    exhausted: bool,
+    /// Checked before every step; the host sets it to stop the script.
+    cancel: Option<Arc<AtomicBool>>,
+    /// Called every so many steps to let the host take a turn, failing to
+    /// stop the script.
+    #[cfg(feature = "send")]
+    pause: Option<(u64, Pause)>,
+}
+
+#[cfg(feature = "send")]
+pub(crate) type Pause = Box<dyn FnMut() -> Result<(), Error> + Send>;
+
+impl Budget {
+    /// How many steps run between checks of the clock.
+    const CLOCK_INTERVAL: u64 = 1024;
+
+    fn reset(&mut self) {
+        self.steps = 0;
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.exhausted = false;
+    }
+
+    fn step(&mut self) -> Result<(), Error> {
+        if let Some(cancel) = &self.cancel {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Error::Interrupted);
+            }
+        }
+
+        self.steps += 1;
+
+        let over_steps = self.step_limit.is_some_and(|limit| self.steps > limit);
+        let over_time = self.steps.is_multiple_of(Self::CLOCK_INTERVAL)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+        if self.exhausted || over_steps || over_time {
+            self.exhausted = true;
+            return Err(Error::Budget);
+        }
+
+        #[cfg(feature = "send")]
+        if let Some((every, pause)) = &mut self.pause {
+            if self.steps.is_multiple_of(*every) {
+                pause()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
 pub struct Interpreter {
-    globals: Rc<RefCell<Environment>>,
-    environment: Rc<RefCell<Environment>>,
-    locals: HashMap<Expr, usize>,
+    /// Every scope, instance, list and map made by scripts, for collecting
+    /// the ones in cycles.
+    heap: Shared<Lock<Heap>>,
+    /// The globals of the module being run, which start as the script's.
+    globals: Shared<Lock<Environment>>,
+    /// The natives every module's globals start out with.
+    builtins: Vec<(Shared<str>, Value)>,
+    /// Imported modules, by canonical path.
+    modules: HashMap<PathBuf, Value>,
+    /// The files being run, outermost first: the script, if it has been
+    /// given, and then each module whose import is in progress.
+    importing: Vec<PathBuf>,
+    /// Where to look for modules not found next to the importing file.
+    module_paths: Vec<PathBuf>,
+    /// Whether the host has given scripts the file system, which also lets
+    /// a script with no path import modules from the working directory.
+    io_enabled: bool,
+    /// The globals of each module run so far, with the file it came from.
+    module_files: Vec<(Shared<Lock<Environment>>, PathBuf)>,
+    hook: Option<Box<dyn Hook>>,
+    environment: Shared<Lock<Environment>>,
+    locals: HashMap<Expr, (usize, usize)>,
+    /// The class each use of a private property through `this` is in.
+    privates: HashMap<Expr, Token>,
+    /// Addresses of the values currently being stringified, so that
+    /// self-referential values don't recurse forever.
+    stringifying: Vec<usize>,
+    /// The functions currently being called, outermost first.
+    call_stack: Vec<String>,
+    /// How deep `call_stack` may grow before calls fail with a stack
+    /// overflow.
+    max_call_depth: usize,
+    /// The line of the statement being executed, which calls Lox code
+    /// doesn't spell out, such as to `toString()` methods, are made from.
+    line: usize,
+    budget: Budget,
+    /// Makes [`Self::compile`] fail on warnings as well as errors.
+    deny_warnings: bool,
+    /// Makes global `var` declarations leave variables that already have
+    /// values as they are.
+    keep_globals: bool,
+    /// Allows what the reference implementations reject but other languages
+    /// don't, such as comparing strings.
+    extensions: bool,
+    /// Whether [`Self::compile`] reports locals that shadow other variables.
+    shadowing: Shadowing,
+    /// How much [`optimizer::optimize`] should simplify compiled scripts.
+    opt_level: u8,
+    /// Whether errors and warnings are shown with the code they're about.
+    show_source: bool,
+    /// The script last given to [`Self::compile`], if it's showing its
+    /// source.
+    source: Option<String>,
+    /// Whether errors and warnings are shown in color.
+    color: bool,
+    error_format: ErrorFormat,
+    /// The generator `random()` draws from once a seed has been given.
+    rng: Option<StdRng>,
+    /// The clock `clock()` reads instead of the system's, if any.
+    clock: Option<VirtualClock>,
+    out: Box<dyn Writer>,
+    err: Box<dyn Writer>,
 }
 
 impl Default for Interpreter {
     fn default() -> Self {
-        let globals = Rc::new(RefCell::new(Environment::default()));
-        globals.borrow_mut().define("clock", &Clock::value());
+        let mut interpreter = Self::without_stdlib();
+        stdlib::load(&mut interpreter);
+
+        interpreter
+    }
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        // Functions are kept in the scope they're declared in, so globals are
+        // nearly always in a cycle. Emptying them lets everything scripts
+        // made be collected.
+        let globals = self.module_files.iter().map(|(globals, _)| globals);
+        for globals in iter::once(&self.globals).chain(globals) {
+            if let Ok(mut globals) = globals.try_borrow_mut() {
+                globals.clear();
+            }
+        }
+        if let Ok(mut heap) = self.heap.try_borrow_mut() {
+            heap.collect();
+        }
+    }
+}
 
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an interpreter whose globals hold only the core natives,
+    /// leaving out everything in [`stdlib`].
+    pub fn without_stdlib() -> Self {
+        let heap = Shared::default();
+        let globals = Environment::global(&heap);
         let environment = globals.clone();
         let locals = HashMap::new();
 
-        Self {
-            globals,
-            environment,
-            locals,
+        let mut interpreter = Self {
+            heap,
+            globals,
+            builtins: vec![],
+            modules: HashMap::new(),
+            importing: vec![],
+            module_paths: vec![],
+            io_enabled: false,
+            module_files: vec![],
+            hook: None,
+            environment,
+            locals,
+            privates: HashMap::new(),
+            stringifying: vec![],
+            call_stack: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            line: 0,
+            budget: Budget::default(),
+            deny_warnings: false,
+            keep_globals: false,
+            extensions: false,
+            shadowing: Shadowing::Allow,
+            opt_level: 0,
+            show_source: false,
+            source: None,
+            color: false,
+            error_format: ErrorFormat::Human,
+            rng: None,
+            clock: None,
+            out: Box::new(io::stdout()),
+            err: Box::new(io::stderr()),
+        };
+        interpreter.define_builtin("clock", Clock::value());
+        interpreter.define_builtin("num", Num::value());
+        interpreter.define_builtin("str", Str::value());
+
+        interpreter
+    }
+
+    /// Sends `print` output to `out` and runtime errors to `err`, instead of
+    /// stdout and stderr.
+    pub fn with_writers(mut self, out: impl Writer + 'static, err: impl Writer + 'static) -> Self {
+        self.out = Box::new(out);
+        self.err = Box::new(err);
+
+        self
+    }
+
+    /// Sends `print` output to `out` instead of stdout, leaving errors where
+    /// they were going.
+    pub fn with_output(mut self, out: impl Writer + 'static) -> Self {
+        self.out = Box::new(out);
+
+        self
+    }
+
+    /// Writes a diagnostic, such as a compile error, to the error writer,
+    /// for hosts that want them to end up with the runtime errors.
+    pub fn report(&mut self, diagnostic: impl fmt::Display) {
+        writeln!(self.err, "{diagnostic}").expect("must be able to write errors");
+    }
+
+    /// Writes a diagnostic about the script to the error writer.
+    fn show(&mut self, diagnostic: &Diagnostic) {
+        let source = self.source.take();
+        let file = self.script_path().map(Path::to_path_buf);
+        self.show_in(diagnostic, file.as_deref(), source.as_deref());
+        self.source = source;
+    }
+
+    /// Writes a diagnostic about `file`, whose source is `source`, to the
+    /// error writer, as [`Self::set_error_format`], [`Self::set_show_source`]
+    /// and [`Self::set_color`] ask.
+    fn show_in(&mut self, diagnostic: &Diagnostic, file: Option<&Path>, source: Option<&str>) {
+        let rendered = match self.error_format {
+            ErrorFormat::Json => diagnostic.to_json(file).to_string(),
+            ErrorFormat::Human if self.show_source => diagnostic.render(source, self.color),
+            ErrorFormat::Human => diagnostic.render_message(self.color),
+        };
+        writeln!(self.err, "{rendered}").expect("must be able to write errors");
+    }
+
+    /// Calls `hook` before each statement is run.
+    pub fn set_hook(&mut self, hook: impl Hook + 'static) {
+        self.hook = Some(Box::new(hook));
+    }
+
+    /// The script given to [`Self::set_script_path`], if any.
+    pub fn script_path(&self) -> Option<&Path> {
+        self.importing.first().map(PathBuf::as_path)
+    }
+
+    /// The file of the module being run, if it's known.
+    pub fn current_file(&self) -> Option<&Path> {
+        self.module_files
+            .iter()
+            .find(|(globals, _)| Shared::ptr_eq(globals, &self.globals))
+            .map(|(_, file)| file.as_path())
+            .or_else(|| self.script_path())
+    }
+
+    /// The functions currently being called, outermost first.
+    pub fn call_stack(&self) -> &[String] {
+        &self.call_stack
+    }
+
+    /// The local variables in scope, innermost scope first and in the order
+    /// they were declared within each scope, leaving out any that are
+    /// shadowed. Unassigned variables have no value.
+    pub fn local_variables(&self) -> Vec<(Shared<str>, Option<Value>)> {
+        let mut variables: Vec<(Shared<str>, Option<Value>)> = vec![];
+        let mut environment = Some(self.environment.clone());
+        while let Some(scope) = environment {
+            let scope = scope.borrow();
+            for (name, value) in scope.names.iter().zip(&scope.slots) {
+                if variables.iter().all(|(seen, _)| seen != name) {
+                    variables.push((name.clone(), value.clone()));
+                }
+            }
+            environment = scope.enclosing.clone();
+        }
+
+        variables
+    }
+
+    /// The globals of the module being run, sorted by name, leaving out the
+    /// natives every module starts with.
+    pub fn global_variables(&self) -> Vec<(Shared<str>, Option<Value>)> {
+        let mut variables = self
+            .globals
+            .borrow()
+            .values
+            .iter()
+            .filter(|(name, _)| self.builtins.iter().all(|(builtin, _)| builtin != *name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        variables.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        variables
+    }
+
+    /// Limits how deeply calls may nest before raising a "Stack overflow."
+    /// runtime error. Each call uses a good deal of the host's stack, so a
+    /// thread running deep recursion needs a larger stack than the default:
+    /// see [`STACK_SIZE`]. Embedders running scripts on a smaller stack
+    /// should lower the limit to match.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Makes the script fail with [`Error::Interrupted`] as soon as `cancel`
+    /// is set, e.g. from another thread or a Ctrl-C handler. The flag is
+    /// never cleared by the interpreter.
+    pub fn with_cancel_flag(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.budget.cancel = Some(cancel);
+
+        self
+    }
+
+    /// Limits how many statements each call to [`Self::interpret`] may run
+    /// before failing with [`Error::Budget`].
+    pub fn set_step_limit(&mut self, steps: u64) {
+        self.budget.step_limit = Some(steps);
+    }
+
+    /// Limits how long each call to [`Self::interpret`] may run before
+    /// failing with [`Error::Budget`].
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.budget.timeout = Some(timeout);
+    }
+
+    /// Runs `source` on a thread of its own, `steps` statements at a time,
+    /// so that async hosts can await it without blocking for long. See
+    /// [`Stepper`].
+    #[cfg(feature = "send")]
+    pub fn run_async(self, source: &str, steps: u64) -> Stepper {
+        Stepper::new(self, source, steps)
+    }
+
+    /// Calls the pause function after every so many statements, stopping the
+    /// script if it fails, or stops pausing if `pause` is `None`.
+    #[cfg(feature = "send")]
+    pub(crate) fn set_pause(&mut self, pause: Option<(u64, Pause)>) {
+        self.budget.pause = pause;
+    }
+
+    /// Makes [`Self::compile`], and so [`Self::run`], fail on warnings as
+    /// well as errors.
+    pub fn set_deny_warnings(&mut self, deny: bool) {
+        self.deny_warnings = deny;
+    }
+
+    /// Lets scripts compare strings with `<`, `<=`, `>` and `>=`, ordering
+    /// them lexicographically. Without it they're runtime errors, as in the
+    /// reference implementations.
+    pub fn set_extensions(&mut self, extensions: bool) {
+        self.extensions = extensions;
+    }
+
+    /// Orders the operands of a comparison, which must be numbers, or
+    /// strings with [`Self::set_extensions`]. `NaN` is unordered.
+    fn compare(
+        &self,
+        operator: Token,
+        left: Value,
+        right: Value,
+    ) -> Result<Option<cmp::Ordering>, Error> {
+        if let (true, Value::String(left), Value::String(right)) = (self.extensions, &left, &right)
+        {
+            return Ok(Some(left.cmp(right)));
+        }
+        let (left, right) = check_number_operands(operator, left, right)?;
+
+        Ok(left.partial_cmp(&right))
+    }
+
+    /// Makes global `var` declarations skip variables that already have
+    /// values, so that running a script again keeps the data the last run
+    /// left while redefining its functions and classes.
+    pub fn set_keep_globals(&mut self, keep: bool) {
+        self.keep_globals = keep;
+    }
+
+    /// Whether a `var` declaration of `name` should leave it as it is, for
+    /// [`Self::set_keep_globals`].
+    fn keeps_global(&self, name: &Token) -> bool {
+        let environment = self.environment.borrow();
+
+        self.keep_globals
+            && environment.enclosing.is_none()
+            && matches!(environment.values.get(name.lexeme()), Some(Some(_)))
+    }
+
+    /// Makes [`Self::compile`] warn about, or reject, local variables that
+    /// shadow a variable from an enclosing scope.
+    pub fn set_shadowing(&mut self, shadowing: Shadowing) {
+        self.shadowing = shadowing;
+    }
+
+    pub(crate) fn shadowing(&self) -> Shadowing {
+        self.shadowing
+    }
+
+    /// Makes [`Self::compile`] simplify the scripts it compiles, and the
+    /// modules they import, as [`optimizer::optimize`] does at `level`.
+    pub fn set_opt_level(&mut self, level: u8) {
+        self.opt_level = level;
+    }
+
+    /// Makes [`Self::compile`], [`Self::run`] and [`Self::interpret`] follow
+    /// each error and warning they report about the script with the line it's
+    /// about, with the offending code underlined, and any notes and help.
+    pub fn set_show_source(&mut self, show: bool) {
+        self.show_source = show;
+    }
+
+    /// Makes errors and warnings about the script use ANSI colors.
+    pub fn set_color(&mut self, color: bool) {
+        self.color = color;
+    }
+
+    /// Makes the errors and warnings the interpreter reports, and those
+    /// about the modules scripts import, be written as text or JSON lines.
+    pub fn set_error_format(&mut self, format: ErrorFormat) {
+        self.error_format = format;
+    }
+
+    /// Makes `random()` return the same sequence of numbers on every run that
+    /// uses the same `seed`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Some(StdRng::seed_from_u64(seed));
+    }
+
+    /// Makes `clock()` read `clock` instead of the system's clock.
+    pub fn set_clock(&mut self, clock: VirtualClock) {
+        self.clock = Some(clock);
+    }
+
+    pub(crate) fn virtual_clock(&self) -> Option<&VirtualClock> {
+        self.clock.as_ref()
+    }
+
+    /// A number in `[0, 1)`, from the seeded generator if there is one.
+    pub(crate) fn random(&mut self) -> f64 {
+        match &mut self.rng {
+            Some(rng) => rng.gen(),
+            None => rand::random(),
+        }
+    }
+
+    /// Whether `left == right`, calling the `equals(other)` method of `left`
+    /// if it's an instance that defines one. Otherwise instances are only
+    /// equal to themselves.
+    pub fn equals(&mut self, left: &Value, right: &Value) -> Result<bool, Error> {
+        if let Value::Instance(instance) = left {
+            if let Some(method) = equals_method(instance) {
+                let method = method.bind(instance.clone());
+                let result = self.call(&method, vec![right.clone()], self.line)?;
+                return Ok(is_truthy(&result));
+            }
+        }
+
+        Ok(left == right)
+    }
+
+    /// Converts `value` to the text `print` shows for it, calling the
+    /// `toString()` method of any instance that defines one.
+    pub fn stringify(&mut self, value: &Value) -> Result<String, Error> {
+        let Some(address) = value.address() else {
+            return Ok(value.to_string());
+        };
+
+        // Past a point, deeply nested lists and maps are cut short like ones
+        // that contain themselves, rather than overflowing the stack. Calls
+        // to `toString()` are already limited by the call depth.
+        let too_deep = self.stringifying.len() >= MAX_NESTING
+            && matches!(value, Value::List(_) | Value::Map(_));
+        if too_deep || self.stringifying.contains(&address) {
+            return Ok(match value {
+                Value::List(_) => "[...]".to_string(),
+                Value::Map(_) => "{...}".to_string(),
+                _ => value.to_string(),
+            });
+        }
+
+        self.stringifying.push(address);
+        let result = self.stringify_inner(value);
+        self.stringifying.pop();
+
+        result
+    }
+
+    fn stringify_inner(&mut self, value: &Value) -> Result<String, Error> {
+        match value {
+            Value::Instance(instance) => match to_string_method(instance) {
+                Some(method) => {
+                    let method = method.bind(instance.clone());
+                    let result = self.call(&method, vec![], self.line)?;
+                    self.stringify(&result)
+                }
+                None => Ok(value.to_string()),
+            },
+            Value::List(list) => {
+                let elements = list.borrow().clone();
+                let mut strings = vec![];
+                for element in &elements {
+                    strings.push(self.stringify(element)?);
+                }
+
+                Ok(format!("[{}]", strings.join(", ")))
+            }
+            Value::Map(map) => {
+                let entries: Vec<_> = {
+                    let map = map.borrow();
+                    map.keys().cloned().zip(map.values().cloned()).collect()
+                };
+                let mut strings = vec![];
+                for (key, value) in &entries {
+                    strings.push(format!(
+                        "{}: {}",
+                        self.stringify(key)?,
+                        self.stringify(value)?
+                    ));
+                }
+
+                Ok(format!("{{{}}}", strings.join(", ")))
+            }
+            _ => Ok(value.to_string()),
+        }
+    }
+
+    /// Calls `function` from `line`, failing with a stack overflow if calls
+    /// already nest as deeply as [`Self::set_max_call_depth`] allows. Every
+    /// call Lox code makes goes through here, including the implicit ones
+    /// to getters and `toString()` and `equals()` methods.
+    pub(crate) fn call(
+        &mut self,
+        function: &dyn Callable,
+        arguments: Vec<Value>,
+        line: usize,
+    ) -> Result<Value, Error> {
+        if self.call_stack.len() >= self.max_call_depth {
+            return Err(Error::Runtime {
+                message: "Stack overflow.".to_string(),
+                line,
+                trace: vec![],
+                span: None,
+            });
+        }
+
+        let name = function.to_string();
+        if let Some(hook) = &mut self.hook {
+            hook.enter_call(&name);
+        }
+        self.call_stack.push(name);
+        let result = function.call(self, arguments);
+        self.call_stack.pop();
+        self.line = line;
+        if let Some(hook) = &mut self.hook {
+            hook.exit_call();
+        }
+
+        let call_line = line;
+        match result {
+            Err(Error::Native { message }) => Err(Error::Runtime {
+                message,
+                line: call_line,
+                trace: vec![self.frame(call_line)],
+                span: None,
+            }),
+            Err(Error::Runtime {
+                message,
+                line,
+                mut trace,
+                span,
+            }) => {
+                if trace.is_empty() {
+                    trace.push(Frame {
+                        function: Some(function.to_string()),
+                        line,
+                    });
+                }
+                trace.push(self.frame(call_line));
+
+                Err(Error::Runtime {
+                    message,
+                    line,
+                    trace,
+                    span,
+                })
+            }
+            Err(Error::Panic {
+                message,
+                line,
+                mut trace,
+            }) => {
+                let line = if trace.is_empty() { call_line } else { line };
+                trace.push(self.frame(call_line));
+
+                Err(Error::Panic {
+                    message,
+                    line,
+                    trace,
+                })
+            }
+            result => result,
+        }
+    }
+
+    fn frame(&self, line: usize) -> Frame {
+        Frame {
+            function: self.call_stack.last().cloned(),
+            line,
+        }
+    }
+
+    pub fn globals(&self) -> Shared<Lock<Environment>> {
+        self.globals.clone()
+    }
+
+    /// Copies the globals of the module being run, leaving out the natives
+    /// every module starts with. Globals declared without a value are nil.
+    pub fn globals_snapshot(&self) -> HashMap<String, LoxValueRepr> {
+        self.global_variables()
+            .into_iter()
+            .map(|(name, value)| {
+                let value =
+                    value.map_or(LoxValueRepr::Nil, |value| LoxValueRepr::from_value(&value));
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+
+    /// Defines the global `name` as `value`, replacing it if it's already
+    /// defined. Fails if `value` isn't data, which is all a host can make.
+    pub fn set_global(&mut self, name: &str, value: &LoxValueRepr) -> Result<(), Error> {
+        let value = value.to_value(self)?;
+        self.globals.borrow_mut().define(name, &value);
+
+        Ok(())
+    }
+
+    /// Defines the `readLine` and `readFile` natives, giving scripts access
+    /// to stdin and the file system.
+    pub fn enable_io(&mut self) {
+        self.io_enabled = true;
+        self.define_builtin("readLine", ReadLine::value());
+        self.define_builtin("readFile", ReadFile::value());
+    }
+
+    /// Exposes the script's command-line arguments as the global `args`, a
+    /// list of strings.
+    pub fn set_args(&mut self, args: impl IntoIterator<Item = String>) {
+        let args = args
+            .into_iter()
+            .map(|arg| Value::String(arg.into()))
+            .collect();
+        let args = self.list_value(args);
+        self.define_builtin("args", args);
+    }
+
+    /// Makes a list, tracked so that it's freed even if it ends up in a
+    /// cycle.
+    pub fn list_value(&self, values: Vec<Value>) -> Value {
+        let list = Shared::new(Lock::new(values));
+        self.track(&list);
+
+        Value::List(list)
+    }
+
+    /// Makes a map, tracked like [`Self::list_value`].
+    pub fn map_value(&self, map: LoxMap) -> Value {
+        let map = Shared::new(Lock::new(map));
+        self.track(&map);
+
+        Value::Map(map)
+    }
+
+    pub(crate) fn track<T: Trace + 'static>(&self, object: &Shared<Lock<T>>) {
+        Heap::track(&self.heap, object);
+    }
+
+    /// Frees the scopes, instances, lists and maps that scripts can no longer
+    /// reach but that keep each other alive, returning how many there were.
+    /// This also happens on its own whenever the number of them doubles.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.heap.borrow_mut().collect()
+    }
+
+    /// How many scopes, instances, lists and maps made by scripts are still
+    /// alive.
+    pub fn live_objects(&self) -> usize {
+        self.heap.borrow().live()
+    }
+
+    /// Defines a global that modules get too.
+    fn define_builtin(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().define(name, &value);
+        self.builtins.push((intern(name), value));
+    }
+
+    /// Tells the interpreter which file the script was read from, so that
+    /// its imports are found next to it.
+    pub fn set_script_path(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        self.importing = vec![path.canonicalize().unwrap_or(path)];
+    }
+
+    /// Adds a directory to look for modules in when they aren't next to the
+    /// file importing them. Directories are searched in the order added.
+    pub fn add_module_path(&mut self, path: impl Into<PathBuf>) {
+        self.module_paths.push(path.into());
+    }
+
+    /// Runs `f` with unresolved names looked up in `globals`, as they are in
+    /// the module a function was defined in.
+    pub(crate) fn with_globals<T>(
+        &mut self,
+        globals: Shared<Lock<Environment>>,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let previous = std::mem::replace(&mut self.globals, globals);
+        let result = f(self);
+        self.globals = previous;
+
+        result
+    }
+
+    /// Looks for `path` next to the importing file and then on the module
+    /// paths. Only a host that enabled I/O lets a script with no path of its
+    /// own import from the working directory, and paths that could leave
+    /// these directories are never followed.
+    fn find_module(&self, path: &Path) -> Option<PathBuf> {
+        if !module::stays_inside(path) {
+            return None;
+        }
+        let importer = self.importing.last().and_then(|file| file.parent());
+
+        importer
+            .into_iter()
+            .chain(self.module_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .chain((importer.is_none() && self.io_enabled).then(|| path.to_path_buf()))
+            .find(|file| file.is_file())
+            .and_then(|file| file.canonicalize().ok())
+    }
+
+    /// Loads the module `path` refers to, running it if it hasn't been run
+    /// before.
+    fn import(&mut self, path: &Token) -> Result<Value, Error> {
+        let error = |message: String| Error::Runtime {
+            message,
+            line: path.line(),
+            trace: vec![],
+            span: None,
+        };
+        let relative = module::file(path);
+        let shown = relative.display();
+
+        let file = self
+            .find_module(&relative)
+            .ok_or_else(|| error(format!("Can't find module '{shown}'.")))?;
+        if let Some(module) = self.modules.get(&file) {
+            return Ok(module.clone());
+        }
+        if self.importing.contains(&file) {
+            return Err(error(format!("Circular import of module '{shown}'.")));
+        }
+
+        let source = fs::read_to_string(&file)
+            .map_err(|e| error(format!("Can't read module '{shown}': {e}.")))?;
+        let mut scanner = Scanner::new(&source);
+        let tokens = scanner.scan();
+        let (statements, errors) = Parser::new(tokens).parse();
+        let diagnostics = scanner
+            .errors()
+            .iter()
+            .map(Diagnostic::from)
+            .chain(errors.iter().map(Diagnostic::from))
+            .collect::<Vec<_>>();
+        // A file that doesn't scan and parse as Lox might not be Lox at all,
+        // so none of it is echoed back.
+        let snippets = (!scanner.had_error() && errors.is_empty()).then_some(source.as_str());
+        for diagnostic in &diagnostics {
+            self.show_in(diagnostic, Some(&file), snippets);
+        }
+        let mut resolver = Resolver::new(self);
+        resolver.resolve_statements(statements.clone());
+        let resolve_errors = resolver.errors().to_vec();
+        for error in &resolve_errors {
+            self.show_in(&Diagnostic::from(error), Some(&file), snippets);
+        }
+        if !errors.is_empty() || scanner.had_error() || !resolve_errors.is_empty() {
+            return Err(error(format!("Module '{shown}' has errors.")));
+        }
+        let statements = optimizer::optimize(statements, self.opt_level);
+
+        let globals = Environment::global(&self.heap);
+        for (name, value) in &self.builtins {
+            globals.borrow_mut().define(name, value);
+        }
+
+        self.module_files.push((globals.clone(), file.clone()));
+        self.importing.push(file.clone());
+        let result = self.with_globals(globals.clone(), |interpreter| {
+            interpreter.execute_block(statements, globals.clone())
+        });
+        self.importing.pop();
+        result?;
+
+        let name = file
+            .file_stem()
+            .map_or_else(|| intern(""), |stem| intern(&stem.to_string_lossy()));
+        let module = Module::new(name, globals).value();
+        self.modules.insert(file, module.clone());
+
+        Ok(module)
+    }
+
+    /// Exposes a Rust function to Lox scripts as the global `name`.
+    ///
+    /// Calls with the wrong number of arguments are rejected before
+    /// `function` runs, so it can index `arity` arguments freely.
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&mut Interpreter, Vec<Value>) -> Result<Value, Error> + Threadsafe + 'static,
+    {
+        let native = NativeFunction::new(name, arity, function).value();
+        self.define_builtin(name, native);
+    }
+
+    /// Like [`Self::define_native`], for a function taking at least
+    /// `min_arity` arguments.
+    pub fn define_variadic_native<F>(&mut self, name: &str, min_arity: usize, function: F)
+    where
+        F: Fn(&mut Interpreter, Vec<Value>) -> Result<Value, Error> + Threadsafe + 'static,
+    {
+        let native = NativeFunction::variadic(name, min_arity, function).value();
+        self.define_builtin(name, native);
+    }
+
+    /// Defines a global module, like the ones scripts import, whose members
+    /// are read as properties, e.g. `math.sin`.
+    pub fn define_module(&mut self, name: &str, members: Vec<(&str, Value)>) {
+        let globals = Environment::global(&self.heap);
+        for (member, value) in members {
+            globals.borrow_mut().define(member, &value);
+        }
+        self.define_builtin(name, Module::new(intern(name), globals).value());
+    }
+
+    fn lookup_variable(&self, name: &Token, expr: &Expr) -> Result<Value, Error> {
+        if let Some((distance, slot)) = self.locals.get(expr) {
+            self.environment.borrow().get_local(name, *distance, *slot)
+        } else {
+            self.globals.borrow().get(name)
+        }
+    }
+
+    fn evaluate(&mut self, expr: Expr) -> Result<Value, Error> {
+        let span = expr.span();
+
+        self.evaluate_unplaced(expr)
+            .map_err(|error| self.place(error, span))
+    }
+
+    /// Places a runtime error raised by the code at `span`, unless code
+    /// inside it was placed already.
+    fn place(&self, mut error: Error, span: Span) -> Error {
+        if let Error::Runtime {
+            span: placed @ None,
+            ..
+        } = &mut error
+        {
+            let in_module = self
+                .module_files
+                .iter()
+                .any(|(globals, _)| Shared::ptr_eq(globals, &self.globals));
+            if in_module {
+                *placed = Some(Span::default());
+            } else if !span.is_empty() {
+                *placed = Some(span);
+            }
+        }
+
+        error
+    }
+
+    /// Applies a binary `operator` to operands that are already evaluated.
+    fn binary(&mut self, operator: Token, left: Value, right: Value) -> Result<Value, Error> {
+        match operator.typ() {
+            TokenType::Greater => {
+                let ordering = self.compare(operator, left, right)?;
+
+                Ok(Value::Boolean(ordering.is_some_and(cmp::Ordering::is_gt)))
+            }
+            TokenType::GreaterEqual => {
+                let ordering = self.compare(operator, left, right)?;
+
+                Ok(Value::Boolean(ordering.is_some_and(cmp::Ordering::is_ge)))
+            }
+            TokenType::Less => {
+                let ordering = self.compare(operator, left, right)?;
+
+                Ok(Value::Boolean(ordering.is_some_and(cmp::Ordering::is_lt)))
+            }
+            TokenType::LessEqual => {
+                let ordering = self.compare(operator, left, right)?;
+
+                Ok(Value::Boolean(ordering.is_some_and(cmp::Ordering::is_le)))
+            }
+            TokenType::Comma => Ok(right),
+            TokenType::EqualEqual => Ok(Value::Boolean(self.equals(&left, &right)?)),
+            TokenType::BangEqual => Ok(Value::Boolean(!self.equals(&left, &right)?)),
+            TokenType::Minus => {
+                let (left, right) = check_number_operands(operator, left, right)?;
+
+                Ok(Value::Number(left - right))
+            }
+            TokenType::Plus => {
+                if let (Value::Number(left), Value::Number(right)) = (left.clone(), right.clone()) {
+                    Ok(Value::Number(left + right))
+                } else if let (Value::String(left), Value::String(right)) =
+                    (left.clone(), right.clone())
+                {
+                    Ok(Value::String(format!("{left}{right}").into()))
+                } else if is_string_like(&left) && is_string_like(&right) {
+                    let (left, right) = (self.stringify(&left)?, self.stringify(&right)?);
+                    Ok(Value::String(format!("{left}{right}").into()))
+                } else {
+                    Err(Error::Runtime {
+                        message: "Operands must be two numbers or two strings.".to_string(),
+                        line: operator.line(),
+                        trace: vec![],
+                        span: None,
+                    })
+                }
+            }
+            TokenType::Percent => {
+                let (left, right) = check_number_operands(operator, left, right)?;
+
+                Ok(Value::Number(left % right))
+            }
+            TokenType::Slash => {
+                let (left, right) = check_number_operands(operator, left, right)?;
+
+                Ok(Value::Number(left / right))
+            }
+            TokenType::Star => {
+                let (left, right) = check_number_operands(operator, left, right)?;
+
+                Ok(Value::Number(left * right))
+            }
+            typ => Err(internal(format!("{typ:?} is not a valid binary operator."))),
         }
     }
-}
 
-impl Interpreter {
-    pub fn new() -> Self {
-        Self::default()
-    }
+    /// Evaluates a `Set` or `IndexSet`, returning the value it replaced
+    /// when it has an operator to combine that with, and the value it
+    /// assigned. The object and index are only evaluated once.
+    fn assign_element(&mut self, assignment: Expr) -> Result<(Option<Value>, Value), Error> {
+        let class = self.private_class(&assignment);
+        match assignment.kind {
+            ExprKind::Set {
+                object,
+                name,
+                value,
+                operator,
+            } => {
+                let private = check_private(&object, &name);
+                let Value::Instance(instance) = self.evaluate(*object)? else {
+                    return Err(Error::Runtime {
+                        message: "Only instances have fields.".to_string(),
+                        line: name.line(),
+                        trace: vec![],
+                        span: None,
+                    });
+                };
+                private?;
+                if let Some(class) = &class {
+                    check_owner(instance.borrow().owner(name.lexeme()), class, &name)?;
+                }
 
-    pub fn globals(&self) -> Rc<RefCell<Environment>> {
-        self.globals.clone()
-    }
+                let old = match operator {
+                    Some(_) => Some(LoxInstance::get(instance.clone(), &name, self)?),
+                    None => None,
+                };
+                let mut value = self.evaluate(*value)?;
+                if let (Some(operator), Some(old)) = (operator, &old) {
+                    value = self.binary(operator, old.clone(), value)?;
+                }
+                match &class {
+                    Some(class) => instance
+                        .borrow_mut()
+                        .set_private(&name, value.clone(), class),
+                    None => instance.borrow_mut().set(&name, value.clone()),
+                }
 
-    fn lookup_variable(&self, name: &Token, expr: &Expr) -> Result<Value, Error> {
-        let distance = self.locals.get(expr);
-        if let Some(distance) = distance {
-            self.environment.borrow().get_at(*distance, name)
-        } else {
-            self.globals.borrow().get(name)
+                Ok((old, value))
+            }
+            ExprKind::IndexSet {
+                object,
+                bracket,
+                index,
+                value,
+                operator,
+            } => {
+                let object = self.evaluate(*object)?;
+                let index = self.evaluate(*index)?;
+
+                let old = match operator {
+                    Some(_) => Some(element(&object, &bracket, index.clone())?),
+                    None => None,
+                };
+                let mut value = self.evaluate(*value)?;
+                if let (Some(operator), Some(old)) = (operator, &old) {
+                    value = self.binary(operator, old.clone(), value)?;
+                }
+
+                match object {
+                    Value::List(list) => {
+                        let mut list = list.borrow_mut();
+                        let index = list_index(&bracket, &list, index)?;
+                        list[index] = value.clone();
+                    }
+                    Value::Map(map) => {
+                        let key = map_key(&bracket, index)?;
+                        map.borrow_mut().insert(key, value.clone());
+                    }
+                    _ => {
+                        return Err(Error::Runtime {
+                            message: "Only lists and maps can be indexed.".to_string(),
+                            line: bracket.line(),
+                            trace: vec![],
+                            span: None,
+                        })
+                    }
+                }
+
+                Ok((old, value))
+            }
+            _ => Err(internal(
+                "Only properties and elements are assigned here.".to_string(),
+            )),
         }
     }
 
-    fn evaluate(&mut self, expr: Expr) -> Result<Value, Error> {
+    fn evaluate_unplaced(&mut self, expr: Expr) -> Result<Value, Error> {
         match expr.kind {
             ExprKind::Literal(value) => Ok(value),
+            ExprKind::Lambda { params, body } => {
+                Ok(LoxFunction::new(None, params, body, self.environment.clone(), false).value())
+            }
+            ExprKind::List(elements) => {
+                let mut values = vec![];
+                for element in elements {
+                    values.push(self.evaluate(element)?);
+                }
+
+                Ok(self.list_value(values))
+            }
+            ExprKind::Map { brace, entries } => {
+                let mut map = LoxMap::new();
+                for (key, value) in entries {
+                    let key = map_key(&brace, self.evaluate(key)?)?;
+                    let value = self.evaluate(value)?;
+                    map.insert(key, value);
+                }
+
+                Ok(self.map_value(map))
+            }
+            ExprKind::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.evaluate(*object)?;
+                let index = self.evaluate(*index)?;
+
+                element(&object, &bracket, index)
+            }
+            ExprKind::IndexSet { .. } | ExprKind::Set { .. } => {
+                let (_, value) = self.assign_element(expr)?;
+
+                Ok(value)
+            }
             ExprKind::Grouping(group) => self.evaluate(*group),
             ExprKind::Unary { operator, right } => {
                 let value = self.evaluate(*right)?;
@@ -188,7 +1694,7 @@ impl Interpreter {
                         Ok(Value::Number(-n))
                     }
                     TokenType::Bang => Ok(Value::Boolean(!is_truthy(&value))),
-                    typ => panic!("{typ:?} is not a valid unary operator"),
+                    typ => Err(internal(format!("{typ:?} is not a valid unary operator."))),
                 }
             }
             ExprKind::Binary {
@@ -199,60 +1705,7 @@ impl Interpreter {
                 let left = self.evaluate(*left)?;
                 let right = self.evaluate(*right)?;
 
-                match operator.typ() {
-                    TokenType::Greater => {
-                        let (left, right) = check_number_operands(operator, left, right)?;
-
-                        Ok(Value::Boolean(left > right))
-                    }
-                    TokenType::GreaterEqual => {
-                        let (left, right) = check_number_operands(operator, left, right)?;
-
-                        Ok(Value::Boolean(left >= right))
-                    }
-                    TokenType::Less => {
-                        let (left, right) = check_number_operands(operator, left, right)?;
-
-                        Ok(Value::Boolean(left < right))
-                    }
-                    TokenType::LessEqual => {
-                        let (left, right) = check_number_operands(operator, left, right)?;
-
-                        Ok(Value::Boolean(left <= right))
-                    }
-                    TokenType::EqualEqual => Ok(Value::Boolean(left == right)),
-                    TokenType::BangEqual => Ok(Value::Boolean(left != right)),
-                    TokenType::Minus => {
-                        let (left, right) = check_number_operands(operator, left, right)?;
-
-                        Ok(Value::Number(left - right))
-                    }
-                    TokenType::Plus => {
-                        if let (Value::Number(left), Value::Number(right)) =
-                            (left.clone(), right.clone())
-                        {
-                            Ok(Value::Number(left + right))
-                        } else if let (Value::String(left), Value::String(right)) = (left, right) {
-                            Ok(Value::String(format!("{left}{right}")))
-                        } else {
-                            Err(Error::Runtime {
-                                message: "Operands must be two numbers or two strings.".to_string(),
-                                line: operator.line(),
-                            })
-                        }
-                    }
-                    TokenType::Slash => {
-                        let (left, right) = check_number_operands(operator, left, right)?;
-
-                        Ok(Value::Number(left / right))
-                    }
-                    TokenType::Star => {
-                        let (left, right) = check_number_operands(operator, left, right)?;
-
-                        Ok(Value::Number(left * right))
-                    }
-                    typ => panic!("{typ:?} is not a valid binary operator."),
-                }
+                self.binary(operator, left, right)
             }
             ExprKind::Variable(ref name) => self.lookup_variable(name, &expr),
             ExprKind::Assign {
@@ -260,13 +1713,21 @@ impl Interpreter {
                 ref value,
             } => {
                 let value = self.evaluate(*value.clone())?;
+                self.assign_variable(&expr, name, &value)?;
 
-                if let Some(distance) = self.locals.get(&expr) {
-                    self.environment
-                        .borrow_mut()
-                        .assign_at(*distance, name, &value)?;
-                } else {
-                    self.globals.borrow_mut().assign(name, &value)?;
+                Ok(value)
+            }
+            ExprKind::AssignList {
+                ref targets,
+                ref equals,
+                ref value,
+            } => {
+                let value = self.evaluate(*value.clone())?;
+                let elements = unpack(equals, &value, targets.len())?;
+                for (target, element) in targets.iter().zip(&elements) {
+                    if let ExprKind::Variable(name) = &target.kind {
+                        self.assign_variable(target, name, element)?;
+                    }
                 }
 
                 Ok(value)
@@ -303,84 +1764,132 @@ impl Interpreter {
                 if let Value::Callable(function) = callee {
                     let arity = function.arity();
                     let arg_cnt = evaluated_args.len();
-                    if arg_cnt != arity {
+                    if !arity.contains(&arg_cnt) {
+                        let expected = if arity.start() == arity.end() {
+                            arity.start().to_string()
+                        } else {
+                            format!("{} to {}", arity.start(), arity.end())
+                        };
                         Err(Error::Runtime {
-                            message: format!("Expected {arity} arguments but got {arg_cnt}."),
+                            message: format!("Expected {expected} arguments but got {arg_cnt}."),
                             line: paren.line(),
+                            trace: vec![],
+                            span: None,
                         })
                     } else {
-                        function.call(self, evaluated_args)
+                        self.call(&*function, evaluated_args, paren.line())
                     }
                 } else {
                     Err(Error::Runtime {
                         message: "Can only call functions and classes.".to_string(),
                         line: paren.line(),
+                        trace: vec![],
+                        span: None,
                     })
                 }
             }
-            ExprKind::Get { object, name } => {
-                if let Value::Instance(instance) = self.evaluate(*object)? {
-                    LoxInstance::get(instance, &name)
-                } else {
-                    Err(Error::Runtime {
+            ExprKind::Get { .. } => {
+                let class = self.private_class(&expr);
+                let ExprKind::Get { object, name } = expr.kind else {
+                    unreachable!("the expression must be a get");
+                };
+                let private = check_private(&object, &name);
+                match self.evaluate(*object)? {
+                    Value::Instance(instance) => {
+                        private?;
+                        if let Some(class) = &class {
+                            check_owner(instance.borrow().owner(name.lexeme()), class, &name)?;
+                        }
+                        LoxInstance::get(instance, &name, self)
+                    }
+                    Value::Module(module) => module.get(&name),
+                    Value::Callable(callable) if callable.as_any().is::<LoxClass>() => {
+                        private?;
+                        let object = callable
+                            .as_any()
+                            .downcast_ref::<LoxClass>()
+                            .expect("must be a class");
+                        if let Some(class) = &class {
+                            check_owner(object.declarer(name.lexeme()), class, &name)?;
+                        }
+                        object.get(&name)
+                    }
+                    _ => Err(Error::Runtime {
                         message: "Only instances have properties.".to_string(),
                         line: name.line(),
-                    })
+                        trace: vec![],
+                        span: None,
+                    }),
                 }
             }
-            ExprKind::Set {
-                object,
-                name,
-                value,
-            } => {
-                if let Value::Instance(instance) = self.evaluate(*object)? {
-                    let value = self.evaluate(*value)?;
-                    instance.borrow_mut().set(&name, value.clone());
+            ExprKind::Postfix { value, assignment } => {
+                if let ExprKind::Set { .. } | ExprKind::IndexSet { .. } = assignment.kind {
+                    let (old, _) = self.assign_element(*assignment)?;
+
+                    return old
+                        .ok_or_else(|| internal("Increments must have an operator.".to_string()));
+                }
+
+                let value = self.evaluate(*value)?;
+                self.evaluate(*assignment)?;
 
-                    Ok(value)
+                Ok(value)
+            }
+            ExprKind::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if is_truthy(&self.evaluate(*condition)?) {
+                    self.evaluate(*then_branch)
                 } else {
-                    Err(Error::Runtime {
-                        message: "Only instances have fields.".to_string(),
-                        line: name.line(),
-                    })
+                    self.evaluate(*else_branch)
                 }
             }
             ExprKind::This(ref keyword) => self.lookup_variable(keyword, &expr),
             ExprKind::Super { ref method, .. } => {
-                let distance = self.locals.get(&expr).expect("must have super in locals");
+                let (distance, slot) = *self
+                    .locals
+                    .get(&expr)
+                    .ok_or_else(|| internal("'super' wasn't resolved.".to_string()))?;
 
-                let superclass = {
-                    self.environment
-                        .borrow()
-                        .get_at(*distance, &Token::new(TokenType::Super, "super", None, 42))?
-                };
-
-                let object = {
-                    self.environment.borrow().get_at(
-                        distance - 1,
-                        &Token::new(TokenType::Super, "this", None, 42),
-                    )?
-                };
+                // `this` is always bound in the scope just inside `super`'s.
+                let superclass = self.environment.borrow().get_at(distance, slot);
+                let object = self.environment.borrow().get_at(distance - 1, 0);
 
                 if let Value::Callable(callable) = superclass {
                     if let Some(class) = callable.as_any().downcast_ref::<LoxClass>() {
                         let name = method.lexeme();
-                        let method = class.find_method(name).ok_or(Error::Runtime {
+                        let undefined = || Error::Runtime {
                             message: format!("Undefined property '{name}'."),
                             line: method.line(),
-                        })?;
-
-                        if let Value::Instance(object) = object {
-                            return Ok(method.bind(object).value());
+                            trace: vec![],
+                            span: None,
+                        };
+
+                        // Inside a class method, `this` is the class itself.
+                        match object {
+                            Value::Instance(object) => {
+                                let method = class.find_method(name).ok_or_else(undefined)?;
+                                return Ok(method.bind(object).value());
+                            }
+                            Value::Callable(object) => {
+                                if let Some(object) = object.as_any().downcast_ref::<LoxClass>() {
+                                    let method =
+                                        class.find_class_method(name).ok_or_else(undefined)?;
+                                    return Ok(method.bind_class(object.clone()).value());
+                                }
+                            }
+                            _ => {}
                         }
 
-                        panic!("object must be an instance");
+                        return Err(internal(
+                            "'this' must be an instance or a class.".to_string(),
+                        ));
                     }
-
-                    panic!("superclass must be a class");
                 }
 
-                panic!("superclass must be a callable");
+                Err(internal("The superclass must be a class.".to_string()))
             }
         }
     }
@@ -396,7 +1905,7 @@ impl Interpreter {
     pub fn execute_block(
         &mut self,
         statements: Vec<Stmt>,
-        environment: Rc<RefCell<Environment>>,
+        environment: Shared<Lock<Environment>>,
     ) -> Result<(), Error> {
         let previous = self.environment.clone();
         self.environment = environment;
@@ -408,23 +1917,131 @@ impl Interpreter {
         res
     }
 
+    fn assign_variable(&mut self, expr: &Expr, name: &Token, value: &Value) -> Result<(), Error> {
+        if let Some((distance, slot)) = self.locals.get(expr) {
+            self.environment
+                .borrow_mut()
+                .assign_at(*distance, *slot, value);
+        } else {
+            self.globals.borrow_mut().assign(name, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Calls the hook, which is taken out while it runs so that Lox code it
+    /// runs, such as `toString()` methods, doesn't call it again.
+    fn run_hook(&mut self, line: usize) -> Result<(), Error> {
+        let Some(mut hook) = self.hook.take() else {
+            return Ok(());
+        };
+        let result = hook.before_statement(self, line);
+        self.hook = Some(hook);
+
+        result
+    }
+
+    /// Evaluates `expr` as if it appeared in `environment`.
+    pub(crate) fn evaluate_in(
+        &mut self,
+        expr: Expr,
+        environment: Shared<Lock<Environment>>,
+    ) -> Result<Value, Error> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let res = self.evaluate(expr);
+        self.environment = previous;
+
+        res
+    }
+
+    fn execute_catch(
+        &mut self,
+        name: &Token,
+        exception: Value,
+        handler: Vec<Stmt>,
+    ) -> Result<(), Error> {
+        let environment = Environment::wrap(self.environment.clone());
+        environment.borrow_mut().define(name.lexeme(), &exception);
+
+        self.execute_block(handler, environment)
+    }
+
     fn execute(&mut self, stmt: Stmt) -> Result<(), Error> {
+        self.budget.step()?;
+        if let Some(line) = stmt.line() {
+            self.line = line;
+            if self.hook.is_some() {
+                self.run_hook(line)?;
+            }
+        }
+
         match stmt {
             Stmt::Expression(expression) => {
                 self.evaluate(expression)?;
             }
-            Stmt::Print(expression) => {
+            Stmt::Assert {
+                keyword,
+                condition,
+                text,
+                message,
+            } => {
+                let span = condition.span();
+                if !is_truthy(&self.evaluate(condition)?) {
+                    let mut message = match message {
+                        Some(message) => {
+                            let message = self.evaluate(message)?;
+                            format!("Assertion failed: {text} ({})", self.stringify(&message)?)
+                        }
+                        None => format!("Assertion failed: {text}"),
+                    };
+                    message.push('.');
+
+                    let error = Error::Runtime {
+                        message,
+                        line: keyword.line(),
+                        trace: vec![],
+                        span: None,
+                    };
+                    return Err(self.place(error, span));
+                }
+            }
+            Stmt::Print {
+                value: expression, ..
+            } => {
                 let value = self.evaluate(expression)?;
-                println!("{value}");
+                let value = self.stringify(&value)?;
+                writeln!(self.out, "{value}").expect("must be able to write output");
+            }
+            Stmt::Import { path, name, .. } => {
+                let module = self.import(&path)?;
+                self.environment.borrow_mut().define(name.lexeme(), &module);
             }
+            Stmt::Var { name, .. } if self.keeps_global(&name) => {}
             Stmt::Var { name, initializer } => {
-                let value = if let Some(initializer) = initializer {
-                    self.evaluate(initializer)?
+                if let Some(initializer) = initializer {
+                    let value = self.evaluate(initializer)?;
+                    self.environment.borrow_mut().define(name.lexeme(), &value);
                 } else {
-                    Value::Nil
-                };
-
-                self.environment.borrow_mut().define(name.lexeme(), &value);
+                    self.environment.borrow_mut().declare(name.lexeme());
+                }
+            }
+            Stmt::Const { name, initializer } => {
+                let value = self.evaluate(initializer)?;
+                self.environment
+                    .borrow_mut()
+                    .define_constant(name.lexeme(), &value);
+            }
+            Stmt::VarList { names, .. } if names.iter().all(|name| self.keeps_global(name)) => {}
+            Stmt::VarList {
+                names,
+                equals,
+                initializer,
+            } => {
+                let value = self.evaluate(initializer)?;
+                let elements = unpack(&equals, &value, names.len())?;
+                for (name, element) in names.iter().zip(&elements) {
+                    self.environment.borrow_mut().define(name.lexeme(), element);
+                }
             }
             Stmt::Block(statements) => {
                 self.execute_block(statements, Environment::wrap(self.environment.clone()))?;
@@ -440,15 +2057,125 @@ impl Interpreter {
                     self.execute(*else_branch)?;
                 }
             }
-            Stmt::While { condition, body } => {
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
                 while is_truthy(&self.evaluate(condition.clone())?) {
-                    self.execute(*body.clone())?;
+                    match self.execute(*body.clone()) {
+                        Ok(()) | Err(Error::Continue) => {}
+                        Err(Error::Break) => break,
+                        Err(error) => return Err(error),
+                    }
+
+                    if let Some(ref increment) = increment {
+                        self.evaluate(increment.clone())?;
+                    }
+                }
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+            } => {
+                let span = iterable.span();
+                let iterable = self.evaluate(iterable)?;
+                let iterator = LoxIterator::new(&iterable).ok_or_else(|| {
+                    let error = Error::Runtime {
+                        message: "Can only iterate over lists, maps and ranges.".to_string(),
+                        line: name.line(),
+                        trace: vec![],
+                        span: None,
+                    };
+                    self.place(error, span)
+                })?;
+
+                for value in iterator {
+                    // Each iteration gets a fresh binding, so closures
+                    // capture the value from their own pass of the loop.
+                    let environment = Environment::wrap(self.environment.clone());
+                    environment.borrow_mut().define(name.lexeme(), &value);
+
+                    match self.execute_block(vec![*body.clone()], environment) {
+                        Ok(()) | Err(Error::Continue) => {}
+                        Err(Error::Break) => break,
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            Stmt::Throw { keyword, value } => {
+                let value = self.evaluate(value)?;
+
+                return Err(Error::Throw {
+                    value,
+                    line: keyword.line(),
+                });
+            }
+            Stmt::Switch { subject, cases, .. } => {
+                let subject = self.evaluate(subject)?;
+
+                let mut chosen = None;
+                let mut default = None;
+                for case in cases {
+                    match case.value {
+                        Some(value) => {
+                            let value = self.evaluate(value)?;
+                            if self.equals(&subject, &value)? {
+                                chosen = Some(case.body);
+                                break;
+                            }
+                        }
+                        None => default = Some(case.body),
+                    }
+                }
+
+                if let Some(body) = chosen.or(default) {
+                    // 'break' leaves the switch, while 'continue' goes on to
+                    // the loop around it.
+                    match self.execute_block(body, Environment::wrap(self.environment.clone())) {
+                        Ok(()) | Err(Error::Break) => {}
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                let result = self.execute_block(body, Environment::wrap(self.environment.clone()));
+
+                // Runtime errors can be caught too, as their message.
+                let result = match (result, catch) {
+                    (Err(Error::Throw { value, .. }), Some((name, handler))) => {
+                        self.execute_catch(&name, value, handler)
+                    }
+                    (Err(Error::Runtime { message, .. }), Some((name, handler))) => {
+                        self.execute_catch(&name, Value::String(message.into()), handler)
+                    }
+                    (result, _) => result,
+                };
+
+                // If the finally block itself throws, returns or breaks,
+                // that replaces whatever the try or catch block was doing.
+                if let Some(finally) = finally {
+                    self.execute_block(finally, Environment::wrap(self.environment.clone()))?;
                 }
+
+                result?;
             }
+            Stmt::Break(_) => return Err(Error::Break),
+            Stmt::Continue(_) => return Err(Error::Continue),
             Stmt::Function { name, params, body } => {
-                let function =
-                    LoxFunction::new(name.clone(), params, body, self.environment.clone(), false)
-                        .value();
+                let function = LoxFunction::new(
+                    Some(name.clone()),
+                    params,
+                    body,
+                    self.environment.clone(),
+                    false,
+                )
+                .value();
                 self.environment
                     .borrow_mut()
                     .define(name.lexeme(), &function);
@@ -466,32 +2193,30 @@ impl Interpreter {
                 name,
                 superclass,
                 methods,
+                class_methods,
+                getters,
             } => {
                 let mut sc = None;
 
                 if let Some(superclass) = superclass {
+                    let span = superclass.span();
                     let value = self.evaluate(superclass)?;
-                    if let Value::Callable(ref callable) = value {
-                        if let Some(class) = callable.as_any().downcast_ref::<LoxClass>().cloned() {
-                            sc = Some(Box::new(class));
-                        } else {
-                            return Err(Error::Runtime {
-                                message: "Superclass must be a class.".to_string(),
-                                line: name.line(),
-                            });
+                    let class = match value {
+                        Value::Callable(ref callable) => {
+                            callable.as_any().downcast_ref::<LoxClass>().cloned()
                         }
-                    } else {
-                        return Err(Error::Runtime {
+                        _ => None,
+                    };
+                    let Some(class) = class else {
+                        let error = Error::Runtime {
                             message: "Superclass must be a class.".to_string(),
                             line: name.line(),
-                        });
-                    }
-                }
-
-                {
-                    self.environment
-                        .borrow_mut()
-                        .define(name.lexeme(), &Value::Nil);
+                            trace: vec![],
+                            span: None,
+                        };
+                        return Err(self.place(error, span));
+                    };
+                    sc = Some(Box::new(class));
                 }
 
                 if let Some(ref superclass) = &sc {
@@ -501,44 +2226,232 @@ impl Interpreter {
                         .define("super", &superclass.clone().value());
                 }
 
-                let mut functions = HashMap::new();
-                for method in methods {
-                    if let Stmt::Function { name, params, body } = method {
-                        let function = LoxFunction::new(
-                            name.clone(),
-                            params,
-                            body,
-                            self.environment.clone(),
-                            name.lexeme() == "init",
-                        );
-                        functions.insert(name.lexeme().to_string(), function);
+                let environment = self.environment.clone();
+                let functions = |methods: Vec<Stmt>, allow_initializer: bool| {
+                    let mut functions = HashMap::new();
+                    for method in methods {
+                        if let Stmt::Function { name, params, body } = method {
+                            let function = LoxFunction::new(
+                                Some(name.clone()),
+                                params,
+                                body,
+                                environment.clone(),
+                                allow_initializer && name.lexeme() == "init",
+                            );
+                            functions.insert(name.lexeme().to_string(), function);
+                        }
                     }
-                }
+
+                    functions
+                };
+                let methods = functions(methods, true);
+                let class_methods = functions(class_methods, false);
+                let getters = functions(getters, false);
 
                 if sc.is_some() {
                     let enclosing = { self.environment.borrow().ancestor(0) };
                     self.environment = enclosing;
                 }
 
-                let class = LoxClass::new(name.lexeme(), sc, functions).value();
+                // Methods only look the class up once they're called, so it
+                // can be defined after they've captured their environment.
+                let class = LoxClass::new(&name, sc, methods, class_methods, getters).value();
+
+                self.environment.borrow_mut().define(name.lexeme(), &class);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records that `expr` refers to the local in `slot` of the scope
+    /// `depth` scopes out from where it's evaluated.
+    pub fn resolve(&mut self, expr: Expr, depth: usize, slot: usize) {
+        self.locals.insert(expr, (depth, slot));
+    }
+
+    /// Records that `expr`, a use of a private property through `this`, is
+    /// in the methods of `class`, so it can only use the class's own.
+    pub fn resolve_private(&mut self, expr: Expr, class: Token) {
+        self.privates.insert(expr, class);
+    }
+
+    /// The class `expr` is in, if it uses a private property through
+    /// `this`.
+    fn private_class(&self, expr: &Expr) -> Option<Token> {
+        let (ExprKind::Get { name, .. } | ExprKind::Set { name, .. }) = &expr.kind else {
+            return None;
+        };
+        if !name.lexeme().starts_with('_') {
+            return None;
+        }
+
+        self.privates.get(expr).cloned()
+    }
 
-                self.environment.borrow_mut().assign(&name, &class)?;
+    /// Evaluates `source` as a single expression in the global scope, for
+    /// hosts using Lox as an expression language. Unlike [`Self::interpret`],
+    /// it leaves reporting errors to the caller.
+    pub fn eval_expr(&mut self, source: &str) -> Result<Value, Error> {
+        fn syntax_error<T: ToString>(errors: &[T]) -> Error {
+            Error::Syntax {
+                message: errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
             }
         }
 
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan();
+        let expr = Parser::new(tokens)
+            .parse_expression()
+            .map_err(|errors| syntax_error(&errors))?;
+        if scanner.had_error() {
+            return Err(syntax_error(scanner.errors()));
+        }
+
+        let mut resolver = Resolver::new(self);
+        resolver.resolve_statements(vec![Stmt::Expression(expr.clone())]);
+        if resolver.had_error() {
+            return Err(syntax_error(resolver.errors()));
+        }
+
+        self.budget.reset();
+        self.evaluate(expr).map_err(|error| self.uncaught(error))
+    }
+
+    /// Scans, parses and resolves `source`, reporting every error and warning
+    /// to the error writer. Fails with [`Error::Syntax`] if there were any
+    /// errors, or any denied warnings. The statements are optimized at the
+    /// level given to [`Self::set_opt_level`].
+    pub fn compile(&mut self, source: &str) -> Result<Vec<Stmt>, Error> {
+        self.source = self.show_source.then(|| source.to_string());
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan();
+        let (statements, errors) = Parser::new(tokens).parse();
+        let diagnostics = scanner
+            .errors()
+            .iter()
+            .map(Diagnostic::from)
+            .chain(errors.iter().map(Diagnostic::from))
+            .collect::<Vec<_>>();
+        if !diagnostics.is_empty() {
+            return Err(self.show_all(&diagnostics));
+        }
+
+        self.check(&statements)?;
+
+        Ok(optimizer::optimize(statements, self.opt_level))
+    }
+
+    /// Resolves `statements`, whether the parser made them or a host built
+    /// them, reporting every error and warning to the error writer. Fails
+    /// with [`Error::Syntax`] if there were any errors, or any denied
+    /// warnings. Statements must be checked before they're interpreted, or
+    /// their local variables won't be found.
+    pub fn check(&mut self, statements: &[Stmt]) -> Result<(), Error> {
+        let denied = self.deny_warnings;
+        let mut resolver = Resolver::new(self);
+        resolver.resolve_statements(statements.to_vec());
+        let mut failed = resolver.had_error();
+        let warned = !resolver.warnings().is_empty();
+        let diagnostics = resolver
+            .errors()
+            .iter()
+            .map(Diagnostic::from)
+            .chain(resolver.warnings().iter().map(|warning| {
+                let warning = Diagnostic::from(warning);
+                if denied {
+                    warning.with_note("Warnings are denied, so the script won't run.")
+                } else {
+                    warning
+                }
+            }))
+            .collect::<Vec<_>>();
+        failed |= denied && warned;
+
+        let error = self.show_all(&diagnostics);
+        if failed {
+            return Err(error);
+        }
+
         Ok(())
     }
 
-    pub fn resolve(&mut self, expr: Expr, depth: usize) {
-        self.locals.insert(expr, depth);
+    /// Shows each of `diagnostics`, returning the [`Error::Syntax`] they add
+    /// up to.
+    fn show_all(&mut self, diagnostics: &[Diagnostic]) -> Error {
+        for diagnostic in diagnostics {
+            self.show(diagnostic);
+        }
+
+        Error::Syntax {
+            message: diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.report.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Checks and runs statements built without the parser, such as those a
+    /// macro system or a transpiler generates.
+    pub fn run_statements(&mut self, statements: Vec<Stmt>) -> Result<(), Error> {
+        // Whatever was compiled last isn't where these came from.
+        self.source = None;
+        self.check(&statements)?;
+
+        self.interpret(optimizer::optimize(statements, self.opt_level))
+    }
+
+    /// Compiles and runs `source`, reporting errors as [`Self::compile`] and
+    /// [`Self::interpret`] do.
+    pub fn run(&mut self, source: &str) -> Result<(), Error> {
+        let statements = self.compile(source)?;
+        let result = self.interpret(statements);
+        self.source = None;
+
+        result
     }
 
-    pub fn interpret(&mut self, statements: Vec<Stmt>) {
+    /// Runs `statements`, stopping at the first runtime error. The error is
+    /// reported to the error writer before being returned, unless it's the
+    /// script asking to exit.
+    pub fn interpret(&mut self, statements: Vec<Stmt>) -> Result<(), Error> {
+        self.budget.reset();
+
         for statement in statements {
             if let Err(error) = self.execute(statement) {
-                println!("{error}");
-                return;
+                if let Error::Exit { .. } = error {
+                    return Err(error);
+                }
+                let error = self.uncaught(error);
+
+                self.show(&Diagnostic::from(&error));
+                return Err(error);
             }
         }
+
+        Ok(())
+    }
+
+    /// Turns an exception that no `catch` block handled into the runtime
+    /// error it's reported as, showing the value as `print` would.
+    fn uncaught(&mut self, error: Error) -> Error {
+        let Error::Throw { value, line } = error else {
+            return error;
+        };
+
+        match self.stringify(&value) {
+            Ok(value) => Error::Runtime {
+                message: format!("Uncaught exception: {value}"),
+                line,
+                trace: vec![],
+                span: None,
+            },
+            Err(error) => error,
+        }
     }
 }
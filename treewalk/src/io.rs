@@ -0,0 +1,107 @@
+//! Natives that let scripts read from the outside world.
+//!
+//! They aren't defined unless the host opts in with
+//! [`Interpreter::enable_io`], so untrusted scripts stay sandboxed.
+
+use crate::{
+    callable::Callable,
+    interpreter::{Error, Interpreter},
+    value::Value,
+};
+use std::{any::Any, fmt, ops::RangeInclusive};
+
+/// Reads a line from stdin without its line ending, or returns `nil` once
+/// stdin is exhausted.
+#[derive(Clone, Debug)]
+pub struct ReadLine;
+
+impl ReadLine {
+    pub fn value() -> Value {
+        Value::Callable(Box::new(Self))
+    }
+}
+
+impl fmt::Display for ReadLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+impl Callable for ReadLine {
+    fn arity(&self) -> RangeInclusive<usize> {
+        0..=0
+    }
+
+    fn call(&self, _: &mut Interpreter, _: Vec<Value>) -> Result<Value, Error> {
+        let mut line = String::new();
+        let read = std::io::stdin()
+            .read_line(&mut line)
+            .map_err(|error| Error::Native {
+                message: format!("Could not read line: {error}."),
+            })?;
+
+        if read == 0 {
+            return Ok(Value::Nil);
+        }
+
+        let len = line.trim_end_matches(&['\r', '\n'][..]).len();
+        line.truncate(len);
+
+        Ok(Value::String(line.into()))
+    }
+
+    fn box_clone(&self) -> Box<dyn Callable> {
+        Box::new((*self).clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Reads the whole of the file at the given path into a string.
+#[derive(Clone, Debug)]
+pub struct ReadFile;
+
+impl ReadFile {
+    pub fn value() -> Value {
+        Value::Callable(Box::new(Self))
+    }
+}
+
+impl fmt::Display for ReadFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+impl Callable for ReadFile {
+    fn arity(&self) -> RangeInclusive<usize> {
+        1..=1
+    }
+
+    fn call(&self, _: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, Error> {
+        let path = match &arguments[0] {
+            Value::String(path) => path.to_string(),
+            _ => {
+                return Err(Error::Native {
+                    message: "Path must be a string.".to_string(),
+                })
+            }
+        };
+
+        let contents = std::fs::read_to_string(&path).map_err(|error| Error::Native {
+            message: format!("Could not read file '{path}': {error}."),
+        })?;
+
+        Ok(Value::String(contents.into()))
+    }
+
+    fn box_clone(&self) -> Box<dyn Callable> {
+        Box::new((*self).clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
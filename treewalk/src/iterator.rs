@@ -0,0 +1,102 @@
+//! The iteration protocol behind `for (var x in ...)` loops.
+
+use crate::{
+    sync::{Lock, Shared},
+    value::Value,
+};
+use std::{fmt, vec};
+
+/// A lazy sequence of numbers, as produced by the `range` native.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    start: f64,
+    stop: f64,
+    step: f64,
+}
+
+impl Range {
+    /// Returns `None` if `step` is zero, since the range would never end.
+    pub fn new(start: f64, stop: f64, step: f64) -> Option<Self> {
+        (step != 0.0).then_some(Self { start, stop, step })
+    }
+
+    pub fn value(self) -> Value {
+        Value::Range(self)
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "<range {} to {} by {}>",
+            self.start, self.stop, self.step
+        )
+    }
+}
+
+pub enum LoxIterator {
+    /// Lists are walked by index, so elements pushed during the loop are
+    /// visited too.
+    List {
+        list: Shared<Lock<Vec<Value>>>,
+        index: usize,
+    },
+    /// Maps yield a snapshot of their keys, taken when the loop starts.
+    Keys(vec::IntoIter<Value>),
+    Range {
+        next: f64,
+        range: Range,
+    },
+}
+
+impl LoxIterator {
+    /// Returns `None` if `value` can't be iterated over.
+    pub fn new(value: &Value) -> Option<Self> {
+        match value {
+            Value::List(list) => Some(Self::List {
+                list: list.clone(),
+                index: 0,
+            }),
+            Value::Map(map) => Some(Self::Keys(
+                map.borrow().keys().cloned().collect::<Vec<_>>().into_iter(),
+            )),
+            Value::Range(range) => Some(Self::Range {
+                next: range.start,
+                range: *range,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Iterator for LoxIterator {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        match self {
+            Self::List { list, index } => {
+                let value = list.borrow().get(*index).cloned();
+                *index += 1;
+
+                value
+            }
+            Self::Keys(keys) => keys.next(),
+            Self::Range { next, range } => {
+                let value = *next;
+                let done = if range.step > 0.0 {
+                    value >= range.stop
+                } else {
+                    value <= range.stop
+                };
+
+                if done {
+                    None
+                } else {
+                    *next += range.step;
+                    Some(Value::Number(value))
+                }
+            }
+        }
+    }
+}
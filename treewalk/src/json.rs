@@ -0,0 +1,930 @@
+//! A minimal JSON encoder, used to export syntax trees to external tools,
+//! and a decoder for the messages editors send to the debug adapter. Scripts
+//! read and write JSON through the `jsonParse` and `jsonStringify` natives.
+//!
+//! Every node becomes an object with a `"type"` key naming its variant, and
+//! the variant's fields under their names in [`crate::ast`]. Tokens are
+//! encoded as `{"type", "lexeme", "line"}` objects, plus `"literal"` for
+//! numbers and strings.
+
+use crate::{
+    ast::{Expr, ExprKind, Param, Stmt},
+    interpreter::{Error, Interpreter},
+    map::LoxMap,
+    token::Token,
+    value::{Value, Visiting},
+};
+use std::{fmt, iter::Peekable, str::CharIndices};
+
+/// How deeply arrays and objects may nest. Decoding recurses, so without a
+/// limit a message of nothing but `[`s would overflow the stack.
+pub const MAX_NESTING: usize = 200;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Array(Vec<Json>),
+    Boolean(bool),
+    Null,
+    Number(f64),
+    Object(Vec<(String, Json)>),
+    String(String),
+}
+
+/// Why a JSON document couldn't be decoded, and the byte offset where it
+/// went wrong.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+#[error("Invalid JSON at byte {offset}: {message}")]
+pub struct ParseError {
+    pub message: String,
+    pub offset: usize,
+}
+
+impl Json {
+    /// An object with the given entries, in order.
+    pub fn fields(fields: Vec<(&str, Json)>) -> Self {
+        Self::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    fn object(typ: &str, fields: Vec<(&str, Json)>) -> Self {
+        let mut entries = vec![("type".to_string(), Self::String(typ.to_string()))];
+        entries.extend(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value)),
+        );
+
+        Self::Object(entries)
+    }
+}
+
+impl Json {
+    /// Decodes a single JSON value, surrounded by nothing but whitespace.
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut decoder = Decoder {
+            text,
+            chars: text.char_indices().peekable(),
+            depth: 0,
+        };
+        let value = decoder.value()?;
+        decoder.whitespace();
+        match decoder.chars.peek() {
+            None => Ok(value),
+            Some(_) => Err(decoder.error("Expect end of input.")),
+        }
+    }
+
+    /// The value of `key`, if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Self::Object(entries) => entries
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Self::Array(elements) => Some(elements),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Self::Boolean(b)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(n: f64) -> Self {
+        Self::Number(n)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        Self::Number(n as f64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Self::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Self::String(s)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(elements: Vec<Json>) -> Self {
+        Self::Array(elements)
+    }
+}
+
+/// Lets hosts that use serde_json pass its values to
+/// [`Value::from_json`].
+#[cfg(feature = "serde_json")]
+impl From<&serde_json::Value> for Json {
+    fn from(value: &serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Array(elements) => {
+                Self::Array(elements.iter().map(Self::from).collect())
+            }
+            serde_json::Value::Bool(b) => Self::Boolean(*b),
+            serde_json::Value::Null => Self::Null,
+            // Every number serde_json parses fits in an f64, losing
+            // precision beyond 2^53 like Lox numbers do.
+            serde_json::Value::Number(n) => Self::Number(n.as_f64().unwrap_or(f64::NAN)),
+            serde_json::Value::Object(entries) => Self::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Self::from(value)))
+                    .collect(),
+            ),
+            serde_json::Value::String(s) => Self::String(s.clone()),
+        }
+    }
+}
+
+/// Lets hosts that use serde_json take the results of [`Value::to_json`].
+/// Numbers that JSON can't represent, like infinity, become `null`.
+#[cfg(feature = "serde_json")]
+impl From<&Json> for serde_json::Value {
+    fn from(json: &Json) -> Self {
+        match json {
+            Json::Array(elements) => Self::Array(elements.iter().map(Self::from).collect()),
+            Json::Boolean(b) => Self::Bool(*b),
+            Json::Null => Self::Null,
+            Json::Number(n) => Self::from(*n),
+            Json::Object(entries) => Self::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Self::from(value)))
+                    .collect(),
+            ),
+            Json::String(s) => Self::String(s.clone()),
+        }
+    }
+}
+
+impl Value {
+    /// Makes the value for a JSON document, with arrays as lists and objects
+    /// as maps with string keys.
+    pub fn from_json(json: &Json, interpreter: &Interpreter) -> Self {
+        match json {
+            Json::Array(elements) => interpreter.list_value(
+                elements
+                    .iter()
+                    .map(|element| Self::from_json(element, interpreter))
+                    .collect(),
+            ),
+            Json::Boolean(b) => Self::Boolean(*b),
+            Json::Null => Self::Nil,
+            Json::Number(n) => Self::Number(*n),
+            Json::Object(entries) => {
+                let mut map = LoxMap::new();
+                for (key, value) in entries {
+                    map.insert(
+                        Self::String(key.as_str().into()),
+                        Self::from_json(value, interpreter),
+                    );
+                }
+                interpreter.map_value(map)
+            }
+            Json::String(s) => Self::String(s.as_str().into()),
+        }
+    }
+
+    /// Encodes this as JSON, if it's nil, a boolean, a number, a string, or
+    /// a list or map of them. Map keys must be strings.
+    pub fn to_json(&self) -> Result<Json, Error> {
+        encode(self, &mut Visiting::default())
+    }
+}
+
+/// Encodes `value`, marking each list and map as it's encoded so that one
+/// inside itself is reported rather than encoded forever.
+fn encode(value: &Value, encoding: &mut Visiting) -> Result<Json, Error> {
+    match value {
+        Value::List(_) | Value::Map(_) if encoding.depth() == MAX_NESTING => Err(Error::native(
+            "Can't encode lists and maps nested this deeply as JSON.",
+        )),
+        Value::Nil => Ok(Json::Null),
+        Value::Boolean(b) => Ok(Json::Boolean(*b)),
+        Value::Number(n) => Ok(Json::Number(*n)),
+        Value::String(s) => Ok(Json::String(s.to_string())),
+        Value::List(_) | Value::Map(_) => encoding
+            .visit(value, |encoding| encode_visited(value, encoding))
+            .unwrap_or_else(|| {
+                Err(Error::native(
+                    "Can't encode a list or map inside itself as JSON.",
+                ))
+            }),
+        _ => Err(Error::native(
+            "Only nil, booleans, numbers, strings, lists and maps can be encoded as JSON.",
+        )),
+    }
+}
+
+fn encode_visited(value: &Value, encoding: &mut Visiting) -> Result<Json, Error> {
+    match value {
+        Value::List(list) => list
+            .borrow()
+            .iter()
+            .map(|element| encode(element, encoding))
+            .collect::<Result<_, _>>()
+            .map(Json::Array),
+        Value::Map(map) => {
+            let map = map.borrow();
+            map.keys()
+                .zip(map.values())
+                .map(|(key, value)| match key {
+                    Value::String(key) => Ok((key.to_string(), encode(value, encoding)?)),
+                    _ => Err(Error::native("JSON object keys must be strings.")),
+                })
+                .collect::<Result<_, _>>()
+                .map(Json::Object)
+        }
+        _ => unreachable!("only lists and maps are encoded here"),
+    }
+}
+
+struct Decoder<'a> {
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    /// How many arrays and objects are being decoded inside each other.
+    depth: usize,
+}
+
+impl Decoder<'_> {
+    fn offset(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map_or(self.text.len(), |(offset, _)| *offset)
+    }
+
+    fn error(&mut self, message: &str) -> ParseError {
+        ParseError {
+            message: message.to_string(),
+            offset: self.offset(),
+        }
+    }
+
+    fn whitespace(&mut self) {
+        while self
+            .chars
+            .peek()
+            .is_some_and(|(_, c)| matches!(c, ' ' | '\t' | '\n' | '\r'))
+        {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.chars.peek() {
+            Some((_, c)) if *c == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            _ => Err(self.error(&format!("Expect '{expected}'."))),
+        }
+    }
+
+    /// Decodes an array or object, failing if it's nested too deeply.
+    fn nested(
+        &mut self,
+        decode: fn(&mut Self) -> Result<Json, ParseError>,
+    ) -> Result<Json, ParseError> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error("Too much nesting."));
+        }
+
+        self.depth += 1;
+        let result = decode(self);
+        self.depth -= 1;
+
+        result
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, ParseError> {
+        for expected in keyword.chars() {
+            self.expect(expected)?;
+        }
+
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, ParseError> {
+        self.whitespace();
+        match self.chars.peek().map(|(_, c)| *c) {
+            Some('{') => self.nested(Self::object),
+            Some('[') => self.nested(Self::array),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('t') => self.keyword("true", Json::Boolean(true)),
+            Some('f') => self.keyword("false", Json::Boolean(false)),
+            Some('n') => self.keyword("null", Json::Null),
+            Some('-' | '0'..='9') => self.number(),
+            _ => Err(self.error("Expect a value.")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, ParseError> {
+        self.expect('{')?;
+        let mut entries = vec![];
+        self.whitespace();
+        if self.chars.next_if(|(_, c)| *c == '}').is_some() {
+            return Ok(Json::Object(entries));
+        }
+
+        loop {
+            self.whitespace();
+            let key = self.string()?;
+            self.whitespace();
+            self.expect(':')?;
+            entries.push((key, self.value()?));
+            self.whitespace();
+            if self.chars.next_if(|(_, c)| *c == ',').is_none() {
+                break;
+            }
+        }
+        self.expect('}')?;
+
+        Ok(Json::Object(entries))
+    }
+
+    fn array(&mut self) -> Result<Json, ParseError> {
+        self.expect('[')?;
+        let mut elements = vec![];
+        self.whitespace();
+        if self.chars.next_if(|(_, c)| *c == ']').is_some() {
+            return Ok(Json::Array(elements));
+        }
+
+        loop {
+            elements.push(self.value()?);
+            self.whitespace();
+            if self.chars.next_if(|(_, c)| *c == ',').is_none() {
+                break;
+            }
+        }
+        self.expect(']')?;
+
+        Ok(Json::Array(elements))
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(s),
+                Some((_, '\\')) => s.push(self.escape()?),
+                Some((_, c)) if !c.is_control() => s.push(c),
+                _ => return Err(self.error("Unterminated string.")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, ParseError> {
+        let escaped = match self.chars.next() {
+            Some((_, '"')) => '"',
+            Some((_, '\\')) => '\\',
+            Some((_, '/')) => '/',
+            Some((_, 'b')) => '\u{8}',
+            Some((_, 'f')) => '\u{c}',
+            Some((_, 'n')) => '\n',
+            Some((_, 'r')) => '\r',
+            Some((_, 't')) => '\t',
+            Some((_, 'u')) => {
+                let high = self.hex()?;
+                if !(0xd800..0xdc00).contains(&high) {
+                    return char::from_u32(high).ok_or_else(|| self.error("Invalid escape."));
+                }
+                // Characters outside the basic plane are written as a
+                // surrogate pair.
+                self.expect('\\')?;
+                self.expect('u')?;
+                let low = self.hex()?;
+                let c = 0x10000 + ((high - 0xd800) << 10) + low.wrapping_sub(0xdc00);
+                return char::from_u32(c).ok_or_else(|| self.error("Invalid escape."));
+            }
+            _ => return Err(self.error("Invalid escape.")),
+        };
+
+        Ok(escaped)
+    }
+
+    fn hex(&mut self) -> Result<u32, ParseError> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .next()
+                .and_then(|(_, c)| c.to_digit(16))
+                .ok_or_else(|| self.error("Expect four hex digits."))?;
+            value = value * 16 + digit;
+        }
+
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Json, ParseError> {
+        let start = self.offset();
+        while self
+            .chars
+            .peek()
+            .is_some_and(|(_, c)| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+        {
+            self.chars.next();
+        }
+        let end = self.offset();
+
+        self.text[start..end]
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| ParseError {
+                message: "Invalid number.".to_string(),
+                offset: start,
+            })
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{element}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Boolean(b) => write!(f, "{b}"),
+            Self::Null => write!(f, "null"),
+            // JSON has no representation for these, so fall back to null as
+            // JavaScript's JSON.stringify does.
+            Self::Number(n) if !n.is_finite() => write!(f, "null"),
+            Self::Number(n) => write!(f, "{n}"),
+            Self::Object(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+            Self::String(s) => write_string(f, s),
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Encodes a whole program as an array of statements.
+pub fn statements(statements: &[Stmt]) -> Json {
+    Json::Array(statements.iter().map(stmt).collect())
+}
+
+pub fn token(token: &Token) -> Json {
+    let mut entries = vec![
+        (
+            "type".to_string(),
+            Json::String(format!("{:?}", token.typ())),
+        ),
+        (
+            "lexeme".to_string(),
+            Json::String(token.lexeme().to_string()),
+        ),
+        ("line".to_string(), Json::Number(token.line() as f64)),
+    ];
+    if let Some(literal) = token.value() {
+        entries.push(("literal".to_string(), value(literal)));
+    }
+
+    Json::Object(entries)
+}
+
+fn value(value: &Value) -> Json {
+    match value {
+        Value::Boolean(b) => Json::Boolean(*b),
+        Value::Nil => Json::Null,
+        Value::Number(n) => Json::Number(*n),
+        Value::String(s) => Json::String(s.to_string()),
+        // Only the literals above can appear in a syntax tree.
+        value => Json::String(value.to_string()),
+    }
+}
+
+fn params(params: &[Param]) -> Json {
+    Json::Array(
+        params
+            .iter()
+            .map(|param| {
+                Json::Object(vec![
+                    ("name".to_string(), token(&param.name)),
+                    (
+                        "default".to_string(),
+                        optional(param.default.as_ref(), expr),
+                    ),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn optional<T>(value: Option<T>, encode: impl FnOnce(T) -> Json) -> Json {
+    value.map_or(Json::Null, encode)
+}
+
+pub fn expr(e: &Expr) -> Json {
+    match &e.kind {
+        ExprKind::Assign { name, value } => Json::object(
+            "Assign",
+            vec![("name", token(name)), ("value", expr(value))],
+        ),
+        ExprKind::AssignList {
+            targets,
+            equals,
+            value,
+        } => Json::object(
+            "AssignList",
+            vec![
+                ("targets", Json::Array(targets.iter().map(expr).collect())),
+                ("equals", token(equals)),
+                ("value", expr(value)),
+            ],
+        ),
+        ExprKind::Binary {
+            left,
+            operator,
+            right,
+        } => Json::object(
+            "Binary",
+            vec![
+                ("left", expr(left)),
+                ("operator", token(operator)),
+                ("right", expr(right)),
+            ],
+        ),
+        ExprKind::Call {
+            callee,
+            paren,
+            arguments,
+        } => Json::object(
+            "Call",
+            vec![
+                ("callee", expr(callee)),
+                ("paren", token(paren)),
+                (
+                    "arguments",
+                    Json::Array(arguments.iter().map(expr).collect()),
+                ),
+            ],
+        ),
+        ExprKind::Get { object, name } => {
+            Json::object("Get", vec![("object", expr(object)), ("name", token(name))])
+        }
+        ExprKind::Grouping(inner) => Json::object("Grouping", vec![("expression", expr(inner))]),
+        ExprKind::Index {
+            object,
+            bracket,
+            index,
+        } => Json::object(
+            "Index",
+            vec![
+                ("object", expr(object)),
+                ("bracket", token(bracket)),
+                ("index", expr(index)),
+            ],
+        ),
+        ExprKind::IndexSet {
+            object,
+            bracket,
+            index,
+            value,
+            operator,
+        } => Json::object(
+            "IndexSet",
+            vec![
+                ("object", expr(object)),
+                ("bracket", token(bracket)),
+                ("index", expr(index)),
+                ("value", expr(value)),
+                ("operator", operator.as_ref().map_or(Json::Null, token)),
+            ],
+        ),
+        ExprKind::Lambda { params, body } => Json::object(
+            "Lambda",
+            vec![("params", self::params(params)), ("body", statements(body))],
+        ),
+        ExprKind::List(elements) => Json::object(
+            "List",
+            vec![("elements", Json::Array(elements.iter().map(expr).collect()))],
+        ),
+        ExprKind::Literal(literal) => Json::object("Literal", vec![("value", value(literal))]),
+        ExprKind::Logical {
+            left,
+            operator,
+            right,
+        } => Json::object(
+            "Logical",
+            vec![
+                ("left", expr(left)),
+                ("operator", token(operator)),
+                ("right", expr(right)),
+            ],
+        ),
+        ExprKind::Map { brace, entries } => Json::object(
+            "Map",
+            vec![
+                ("brace", token(brace)),
+                (
+                    "entries",
+                    Json::Array(
+                        entries
+                            .iter()
+                            .map(|(key, value)| {
+                                Json::Object(vec![
+                                    ("key".to_string(), expr(key)),
+                                    ("value".to_string(), expr(value)),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+            ],
+        ),
+        ExprKind::Postfix { value, assignment } => Json::object(
+            "Postfix",
+            vec![("value", expr(value)), ("assignment", expr(assignment))],
+        ),
+        ExprKind::Set {
+            object,
+            name,
+            value,
+            operator,
+        } => Json::object(
+            "Set",
+            vec![
+                ("object", expr(object)),
+                ("name", token(name)),
+                ("value", expr(value)),
+                ("operator", operator.as_ref().map_or(Json::Null, token)),
+            ],
+        ),
+        ExprKind::Super { keyword, method } => Json::object(
+            "Super",
+            vec![("keyword", token(keyword)), ("method", token(method))],
+        ),
+        ExprKind::Ternary {
+            condition,
+            then_branch,
+            else_branch,
+        } => Json::object(
+            "Ternary",
+            vec![
+                ("condition", expr(condition)),
+                ("then_branch", expr(then_branch)),
+                ("else_branch", expr(else_branch)),
+            ],
+        ),
+        ExprKind::This(keyword) => Json::object("This", vec![("keyword", token(keyword))]),
+        ExprKind::Unary { operator, right } => Json::object(
+            "Unary",
+            vec![("operator", token(operator)), ("right", expr(right))],
+        ),
+        ExprKind::Variable(name) => Json::object("Variable", vec![("name", token(name))]),
+    }
+}
+
+pub fn stmt(s: &Stmt) -> Json {
+    match s {
+        Stmt::Assert {
+            keyword,
+            condition,
+            text,
+            message,
+        } => Json::object(
+            "Assert",
+            vec![
+                ("keyword", token(keyword)),
+                ("condition", expr(condition)),
+                ("text", Json::String(text.clone())),
+                ("message", optional(message.as_ref(), expr)),
+            ],
+        ),
+        Stmt::Block(body) => Json::object("Block", vec![("statements", statements(body))]),
+        Stmt::Break(keyword) => Json::object("Break", vec![("keyword", token(keyword))]),
+        Stmt::Class {
+            name,
+            superclass,
+            methods,
+            class_methods,
+            getters,
+        } => Json::object(
+            "Class",
+            vec![
+                ("name", token(name)),
+                ("superclass", optional(superclass.as_ref(), expr)),
+                ("methods", statements(methods)),
+                ("class_methods", statements(class_methods)),
+                ("getters", statements(getters)),
+            ],
+        ),
+        Stmt::Const { name, initializer } => Json::object(
+            "Const",
+            vec![("name", token(name)), ("initializer", expr(initializer))],
+        ),
+        Stmt::Continue(keyword) => Json::object("Continue", vec![("keyword", token(keyword))]),
+        Stmt::Expression(e) => Json::object("Expression", vec![("expression", expr(e))]),
+        Stmt::ForIn {
+            name,
+            iterable,
+            body,
+        } => Json::object(
+            "ForIn",
+            vec![
+                ("name", token(name)),
+                ("iterable", expr(iterable)),
+                ("body", stmt(body)),
+            ],
+        ),
+        Stmt::Function { name, params, body } => Json::object(
+            "Function",
+            vec![
+                ("name", token(name)),
+                ("params", self::params(params)),
+                ("body", statements(body)),
+            ],
+        ),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => Json::object(
+            "If",
+            vec![
+                ("condition", expr(condition)),
+                ("then_branch", stmt(then_branch)),
+                ("else_branch", optional(else_branch.as_deref(), stmt)),
+            ],
+        ),
+        Stmt::Import {
+            keyword,
+            path,
+            name,
+        } => Json::object(
+            "Import",
+            vec![
+                ("keyword", token(keyword)),
+                ("path", token(path)),
+                ("name", token(name)),
+            ],
+        ),
+        Stmt::Print { keyword, value } => Json::object(
+            "Print",
+            vec![("keyword", token(keyword)), ("expression", expr(value))],
+        ),
+        Stmt::Return { keyword, value } => Json::object(
+            "Return",
+            vec![
+                ("keyword", token(keyword)),
+                ("value", optional(value.as_ref(), expr)),
+            ],
+        ),
+        Stmt::Switch {
+            keyword,
+            subject,
+            cases,
+        } => Json::object(
+            "Switch",
+            vec![
+                ("keyword", token(keyword)),
+                ("subject", expr(subject)),
+                (
+                    "cases",
+                    Json::Array(
+                        cases
+                            .iter()
+                            .map(|case| {
+                                Json::Object(vec![
+                                    ("keyword".to_string(), token(&case.keyword)),
+                                    ("value".to_string(), optional(case.value.as_ref(), expr)),
+                                    ("body".to_string(), statements(&case.body)),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+            ],
+        ),
+        Stmt::Throw { keyword, value } => Json::object(
+            "Throw",
+            vec![("keyword", token(keyword)), ("value", expr(value))],
+        ),
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+        } => Json::object(
+            "Try",
+            vec![
+                ("body", statements(body)),
+                (
+                    "catch",
+                    optional(catch.as_ref(), |(name, body)| {
+                        Json::Object(vec![
+                            ("name".to_string(), token(name)),
+                            ("body".to_string(), statements(body)),
+                        ])
+                    }),
+                ),
+                ("finally", optional(finally.as_deref(), statements)),
+            ],
+        ),
+        Stmt::Var { name, initializer } => Json::object(
+            "Var",
+            vec![
+                ("name", token(name)),
+                ("initializer", optional(initializer.as_ref(), expr)),
+            ],
+        ),
+        Stmt::VarList {
+            names,
+            equals,
+            initializer,
+        } => Json::object(
+            "VarList",
+            vec![
+                ("names", Json::Array(names.iter().map(token).collect())),
+                ("equals", token(equals)),
+                ("initializer", expr(initializer)),
+            ],
+        ),
+        Stmt::While {
+            condition,
+            body,
+            increment,
+        } => Json::object(
+            "While",
+            vec![
+                ("condition", expr(condition)),
+                ("body", stmt(body)),
+                ("increment", optional(increment.as_ref(), expr)),
+            ],
+        ),
+    }
+}
@@ -1,31 +1,37 @@
 pub mod ast;
+pub mod ast_printer;
 pub mod callable;
 pub mod class;
 pub mod clock;
+pub mod conversion;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod diagnostic;
+pub mod formatter;
+mod framing;
 pub mod function;
+pub mod gc;
+pub mod highlight;
+pub mod intern;
 pub mod interpreter;
+pub mod io;
+pub mod iterator;
+pub mod json;
+pub mod lsp;
+pub mod map;
+pub mod module;
+pub mod native;
+pub mod optimizer;
 pub mod parser;
+pub mod profile;
+pub mod repr;
 pub mod resolver;
 pub mod scanner;
+pub mod stdlib;
+#[cfg(feature = "send")]
+pub mod stepper;
+pub mod sync;
+pub mod test_runner;
 pub mod token;
 pub mod value;
-
-use token::{Token, TokenType};
-
-fn report(line: usize, where_: &str, message: &str) {
-    println!("[line {line}] Error{where_}: {message}");
-}
-
-pub fn error_line(line: usize, message: &str) {
-    report(line, "", message);
-}
-
-pub fn error_token(token: &Token, message: &str) {
-    let line = token.line();
-    if matches!(token.typ(), TokenType::Eof) {
-        report(line, " at end", message);
-    } else {
-        let lexeme = token.lexeme();
-        report(line, &format!(" at '{lexeme}'"), message);
-    };
-}
@@ -0,0 +1,526 @@
+//! A Language Server Protocol server, so that editors can check Lox code as
+//! it's written.
+//!
+//! Each open document is scanned, parsed and resolved whenever it changes,
+//! and what's found is published as diagnostics. The resolver's record of
+//! where each variable was declared answers go-to-definition and rename,
+//! and [`highlight`] classifies tokens for semantic highlighting.
+
+use crate::{
+    ast::Stmt,
+    framing,
+    highlight::{self, TokenCategory},
+    interpreter::Interpreter,
+    json::Json,
+    parser::{self, Parser},
+    resolver::Resolver,
+    scanner::Scanner,
+    token::{Token, TokenType},
+};
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+};
+
+const ERROR: usize = 1;
+const WARNING: usize = 2;
+
+const METHOD_NOT_FOUND: f64 = -32601.0;
+const INVALID_PARAMS: f64 = -32602.0;
+
+// Symbol kinds.
+const MODULE: usize = 2;
+const CLASS: usize = 5;
+const METHOD: usize = 6;
+const PROPERTY: usize = 7;
+const FUNCTION: usize = 12;
+const VARIABLE: usize = 13;
+const CONSTANT: usize = 14;
+
+/// The semantic token types, in the order their indexes refer to them.
+const TOKEN_TYPES: [&str; 12] = [
+    "comment",
+    "keyword",
+    "number",
+    "string",
+    "operator",
+    "variable",
+    "parameter",
+    "function",
+    "class",
+    "method",
+    "property",
+    "namespace",
+];
+const READONLY: usize = 1;
+
+/// A request that couldn't be answered.
+struct Failure {
+    code: f64,
+    message: String,
+}
+
+impl Failure {
+    fn invalid(message: &str) -> Self {
+        Self {
+            code: INVALID_PARAMS,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// An open document, and what checking it found.
+struct Document {
+    text: String,
+    statements: Vec<Stmt>,
+    diagnostics: Vec<Json>,
+    /// Each mention of a variable, and the name in its declaration.
+    definitions: Vec<(Token, Token)>,
+}
+
+impl Document {
+    fn new(text: String) -> Self {
+        let mut scanner = Scanner::new(&text);
+        let tokens = scanner.scan().to_vec();
+        let (statements, parse_errors) = Parser::new(&tokens).parse();
+
+        let mut diagnostics = vec![];
+        for error in scanner.errors() {
+            let start = error.offset.min(text.len());
+            let end = text[start..]
+                .chars()
+                .next()
+                .map_or(start, |c| start + c.len_utf8());
+            diagnostics.push(diagnostic(range(&text, start, end), ERROR, &error.message));
+        }
+        for parser::Error::ParseError { token, message } in &parse_errors {
+            diagnostics.push(diagnostic(token_range(&text, token), ERROR, message));
+        }
+
+        let mut interpreter = Interpreter::new();
+        let mut resolver = Resolver::new(&mut interpreter);
+        resolver.resolve_statements(statements.clone());
+        for error in resolver.errors() {
+            let range = token_range(&text, &error.token);
+            diagnostics.push(diagnostic(range, ERROR, &error.message));
+        }
+        for warning in resolver.warnings() {
+            let range = token_range(&text, &warning.token);
+            diagnostics.push(diagnostic(range, WARNING, &warning.message));
+        }
+
+        // Names the parser made up, like the `str` that interpolation calls,
+        // aren't anywhere in the text.
+        let definitions = resolver
+            .definitions()
+            .into_iter()
+            .filter(|(mention, declaration)| in_text(&text, mention) && in_text(&text, declaration))
+            .collect();
+
+        Self {
+            text,
+            statements,
+            diagnostics,
+            definitions,
+        }
+    }
+
+    /// The variable mentioned at `position`, and the name in its declaration.
+    fn definition_at(&self, position: Option<&Json>) -> Option<&(Token, Token)> {
+        let offset = offset(&self.text, position?)?;
+
+        self.definitions.iter().find(|(mention, _)| {
+            (mention.offset()..=mention.offset() + mention.lexeme().len()).contains(&offset)
+        })
+    }
+
+    fn symbols(&self) -> Vec<Json> {
+        self.statements
+            .iter()
+            .flat_map(|statement| self.statement_symbols(statement))
+            .collect()
+    }
+
+    fn statement_symbols(&self, statement: &Stmt) -> Vec<Json> {
+        let symbol = |name: &Token, kind, children| symbol(&self.text, name, kind, children);
+        match statement {
+            Stmt::Class {
+                name,
+                methods,
+                class_methods,
+                getters,
+                ..
+            } => {
+                let members = methods
+                    .iter()
+                    .chain(class_methods)
+                    .map(|method| (method, METHOD))
+                    .chain(getters.iter().map(|getter| (getter, PROPERTY)));
+                let children = members
+                    .filter_map(|(member, kind)| match member {
+                        Stmt::Function { name, .. } => Some(symbol(name, kind, vec![])),
+                        _ => None,
+                    })
+                    .collect();
+
+                vec![symbol(name, CLASS, children)]
+            }
+            Stmt::Const { name, .. } => vec![symbol(name, CONSTANT, vec![])],
+            Stmt::Function { name, .. } => vec![symbol(name, FUNCTION, vec![])],
+            Stmt::Import { name, .. } if in_text(&self.text, name) => {
+                vec![symbol(name, MODULE, vec![])]
+            }
+            Stmt::Var { name, .. } => vec![symbol(name, VARIABLE, vec![])],
+            Stmt::VarList { names, .. } => names
+                .iter()
+                .map(|name| symbol(name, VARIABLE, vec![]))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Whether `token` is where its offset says it is in `text`.
+fn in_text(text: &str, token: &Token) -> bool {
+    let start = token.offset();
+    !token.lexeme().is_empty()
+        && text.get(start..start + token.lexeme().len()) == Some(token.lexeme())
+}
+
+/// The line and UTF-16 column of `offset`, counting from zero, as the
+/// protocol expects.
+fn position(text: &str, offset: usize) -> Json {
+    let before = &text[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let character = before[line_start..].encode_utf16().count();
+
+    Json::fields(vec![("line", line.into()), ("character", character.into())])
+}
+
+/// The byte offset of a protocol position in `text`.
+fn offset(text: &str, position: &Json) -> Option<usize> {
+    let line = position.get("line")?.as_f64()? as usize;
+    let character = position.get("character")?.as_f64()? as usize;
+
+    let line_start = match line {
+        0 => 0,
+        line => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+
+    Some(text.len())
+}
+
+fn range(text: &str, start: usize, end: usize) -> Json {
+    Json::fields(vec![
+        ("start", position(text, start)),
+        ("end", position(text, end)),
+    ])
+}
+
+fn token_range(text: &str, token: &Token) -> Json {
+    let start = token.offset().min(text.len());
+    let end = (start + token.lexeme().len()).min(text.len());
+
+    range(text, start, end)
+}
+
+/// The semantic token type and modifiers for `category`, if editors have
+/// one for it.
+fn token_type(category: TokenCategory) -> Option<(usize, usize)> {
+    let (name, modifiers) = match category {
+        TokenCategory::Comment => ("comment", 0),
+        TokenCategory::Keyword => ("keyword", 0),
+        TokenCategory::Number => ("number", 0),
+        TokenCategory::String => ("string", 0),
+        TokenCategory::Operator => ("operator", 0),
+        TokenCategory::Punctuation => return None,
+        TokenCategory::Variable => ("variable", 0),
+        TokenCategory::Constant => ("variable", READONLY),
+        TokenCategory::Parameter => ("parameter", 0),
+        TokenCategory::Function => ("function", 0),
+        TokenCategory::Class => ("class", 0),
+        TokenCategory::Method => ("method", 0),
+        TokenCategory::Property => ("property", 0),
+        TokenCategory::Module => ("namespace", 0),
+    };
+    let index = TOKEN_TYPES.iter().position(|typ| *typ == name)?;
+
+    Some((index, modifiers))
+}
+
+/// The semantic tokens in `text`, encoded relative to each other as the
+/// protocol expects. Tokens spanning several lines, like block comments, are
+/// split into one per line, since not every editor handles them otherwise.
+fn semantic_tokens(text: &str) -> Json {
+    let line_starts = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(newline, _)| newline + 1))
+        .collect::<Vec<_>>();
+
+    let mut data = vec![];
+    let (mut previous_line, mut previous_start) = (0, 0);
+    for (span, category) in highlight::semantic_tokens(text) {
+        let Some((typ, modifiers)) = token_type(category) else {
+            continue;
+        };
+        let end = (span.offset + span.len).min(text.len());
+        let mut start = span.offset.min(end);
+        for piece in text[start..end].split('\n') {
+            if !piece.is_empty() {
+                let line = line_starts.partition_point(|line_start| *line_start <= start) - 1;
+                let character = text[line_starts[line]..start].encode_utf16().count();
+                let delta = match line == previous_line {
+                    true => character - previous_start,
+                    false => character,
+                };
+                data.extend([
+                    line - previous_line,
+                    delta,
+                    piece.encode_utf16().count(),
+                    typ,
+                    modifiers,
+                ]);
+                (previous_line, previous_start) = (line, character);
+            }
+            start += piece.len() + 1;
+        }
+    }
+
+    let data = data.into_iter().map(Json::from).collect::<Vec<_>>();
+    Json::fields(vec![("data", data.into())])
+}
+
+fn diagnostic(range: Json, severity: usize, message: &str) -> Json {
+    Json::fields(vec![
+        ("range", range),
+        ("severity", severity.into()),
+        ("source", "loxide".into()),
+        ("message", message.into()),
+    ])
+}
+
+fn symbol(text: &str, name: &Token, kind: usize, children: Vec<Json>) -> Json {
+    Json::fields(vec![
+        ("name", name.lexeme().into()),
+        ("kind", kind.into()),
+        ("range", token_range(text, name)),
+        ("selectionRange", token_range(text, name)),
+        ("children", children.into()),
+    ])
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut scanner = Scanner::new(name);
+    let tokens = scanner.scan();
+
+    matches!(
+        tokens,
+        [token, eof] if token.typ() == &TokenType::Identifier
+            && token.lexeme() == name
+            && eof.typ() == &TokenType::Eof
+    )
+}
+
+struct Server<W> {
+    output: W,
+    documents: HashMap<String, Document>,
+}
+
+impl<W: Write> Server<W> {
+    fn send(&mut self, fields: Vec<(&str, Json)>) {
+        let mut message = vec![("jsonrpc", "2.0".into())];
+        message.extend(fields);
+        framing::write(&mut self.output, &Json::fields(message));
+    }
+
+    fn publish(&mut self, uri: &str) {
+        let diagnostics = self
+            .documents
+            .get(uri)
+            .map(|document| document.diagnostics.clone())
+            .unwrap_or_default();
+        let params = Json::fields(vec![
+            ("uri", uri.into()),
+            ("diagnostics", diagnostics.into()),
+        ]);
+        self.send(vec![
+            ("method", "textDocument/publishDiagnostics".into()),
+            ("params", params),
+        ]);
+    }
+
+    fn document<'a>(&'a self, params: &'a Json) -> Result<(&'a str, &'a Document), Failure> {
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .ok_or_else(|| Failure::invalid("Expect a document."))?;
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| Failure::invalid("Document isn't open."))?;
+
+        Ok((uri, document))
+    }
+
+    /// Handles a notification, which gets no response.
+    fn notify(&mut self, method: &str, params: &Json) {
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let text = match method {
+            "textDocument/didOpen" => params
+                .get("textDocument")
+                .and_then(|document| document.get("text")),
+            // Documents are synchronized in full, so the last change holds
+            // all of the text.
+            "textDocument/didChange" => params
+                .get("contentChanges")
+                .and_then(Json::as_array)
+                .and_then(<[Json]>::last)
+                .and_then(|change| change.get("text")),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish(&uri);
+                return;
+            }
+            _ => return,
+        };
+
+        if let Some(text) = text.and_then(Json::as_str) {
+            self.documents
+                .insert(uri.clone(), Document::new(text.to_string()));
+            self.publish(&uri);
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, Failure> {
+        match method {
+            "initialize" => {
+                let capabilities = Json::fields(vec![
+                    ("textDocumentSync", 1.0.into()),
+                    ("definitionProvider", true.into()),
+                    ("renameProvider", true.into()),
+                    ("documentSymbolProvider", true.into()),
+                    (
+                        "semanticTokensProvider",
+                        Json::fields(vec![
+                            (
+                                "legend",
+                                Json::fields(vec![
+                                    ("tokenTypes", TOKEN_TYPES.map(Json::from).to_vec().into()),
+                                    ("tokenModifiers", vec!["readonly".into()].into()),
+                                ]),
+                            ),
+                            ("full", true.into()),
+                        ]),
+                    ),
+                ]);
+                let info = Json::fields(vec![("name", "loxide".into())]);
+
+                Ok(Json::fields(vec![
+                    ("capabilities", capabilities),
+                    ("serverInfo", info),
+                ]))
+            }
+            "shutdown" => Ok(Json::Null),
+            "textDocument/definition" => {
+                let (uri, document) = self.document(params)?;
+                let Some((_, declaration)) = document.definition_at(params.get("position")) else {
+                    return Ok(Json::Null);
+                };
+
+                Ok(Json::fields(vec![
+                    ("uri", uri.into()),
+                    ("range", token_range(&document.text, declaration)),
+                ]))
+            }
+            "textDocument/rename" => {
+                let (uri, document) = self.document(params)?;
+                let new_name = params
+                    .get("newName")
+                    .and_then(Json::as_str)
+                    .filter(|name| is_identifier(name))
+                    .ok_or_else(|| Failure::invalid("Expect an identifier."))?;
+                let Some((_, declaration)) = document.definition_at(params.get("position")) else {
+                    return Ok(Json::Null);
+                };
+
+                let edits = document
+                    .definitions
+                    .iter()
+                    .filter(|(_, other)| other.offset() == declaration.offset())
+                    .map(|(mention, _)| {
+                        Json::fields(vec![
+                            ("range", token_range(&document.text, mention)),
+                            ("newText", new_name.into()),
+                        ])
+                    })
+                    .collect::<Vec<_>>();
+
+                let changes = Json::fields(vec![(uri, edits.into())]);
+                Ok(Json::fields(vec![("changes", changes)]))
+            }
+            "textDocument/semanticTokens/full" => {
+                let (_, document) = self.document(params)?;
+
+                Ok(semantic_tokens(&document.text))
+            }
+            "textDocument/documentSymbol" => {
+                let (_, document) = self.document(params)?;
+
+                Ok(document.symbols().into())
+            }
+            _ => Err(Failure {
+                code: METHOD_NOT_FOUND,
+                message: format!("Unsupported method '{method}'."),
+            }),
+        }
+    }
+}
+
+/// Serves editors, reading requests and notifications from `input` and
+/// writing responses and diagnostics to `output`, until `input` ends or an
+/// `exit` notification arrives.
+pub fn serve(mut input: impl BufRead, output: impl Write) {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+    };
+
+    while let Some(message) = framing::read(&mut input) {
+        let method = message
+            .get("method")
+            .and_then(Json::as_str)
+            .unwrap_or_default();
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        let Some(id) = message.get("id").cloned() else {
+            if method == "exit" {
+                return;
+            }
+            server.notify(method, &params);
+            continue;
+        };
+
+        match server.request(method, &params) {
+            Ok(result) => server.send(vec![("id", id), ("result", result)]),
+            Err(failure) => {
+                let error = Json::fields(vec![
+                    ("code", failure.code.into()),
+                    ("message", failure.message.into()),
+                ]);
+                server.send(vec![("id", id), ("error", error)]);
+            }
+        }
+    }
+}
@@ -1,62 +1,744 @@
+use lox_bytecode::{
+    repl::{repl, Session},
+    vm::{self, Vm},
+};
 use lox_treewalk::{
-    interpreter::Interpreter, parser::Parser, resolver::Resolver, scanner::Scanner,
+    ast_printer,
+    clock::VirtualClock,
+    coverage::Coverage,
+    dap,
+    debugger::{Breakpoint, Debugger},
+    diagnostic::ErrorFormat,
+    formatter,
+    interpreter::{Error, Hook, Interpreter, STACK_SIZE},
+    json, lsp,
+    optimizer::{self, MAX_OPT_LEVEL},
+    parser::Parser,
+    profile::Profiler,
+    resolver::Shadowing,
+    scanner::Scanner,
+    test_runner::{self, Test, TestResult},
+};
+use std::{
+    env, fs,
+    io::{BufReader, IsTerminal},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
-use std::{env, io::Write, process};
 
-fn run(interpreter: &mut Interpreter, source: &str) {
-    let mut scanner = Scanner::new(source);
+/// How `--print-ast` and `--emit-ast` should render the syntax tree.
+#[derive(Clone, Copy)]
+enum AstForm {
+    Json,
+    Parenthesized,
+    Tree,
+}
+
+/// Which interpreter runs scripts, from `--backend`.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Backend {
+    #[default]
+    Treewalk,
+    /// The bytecode VM, which only supports part of the language so far.
+    Vm,
+}
+
+/// Whether errors and warnings are shown in color, from `--color`.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Color {
+    /// Only when stderr is a terminal and `NO_COLOR` isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    fn enabled(self) -> bool {
+        match self {
+            Self::Auto => std::io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    allow_io: bool,
+    backend: Backend,
+    /// Where `debug` should stop, from `--break`.
+    breakpoints: Vec<Breakpoint>,
+    /// Makes `fmt` report files that need formatting instead of rewriting
+    /// them.
+    check: bool,
+    color: Color,
+    error_format: ErrorFormat,
+    /// Where to write an lcov report of the lines a script ran, from
+    /// `--coverage`.
+    coverage: Option<PathBuf>,
+    deny_warnings: bool,
+    /// Allows comparing strings, from `--extensions`.
+    extensions: bool,
+    /// Makes `watch` run the script again with the globals the last run
+    /// left, from `--keep-globals`.
+    keep_globals: bool,
+    /// How many tests `test` runs at once, from `--jobs`.
+    jobs: Option<usize>,
+    /// Directories to search for modules, from `--module-path`.
+    module_paths: Vec<PathBuf>,
+    no_stdlib: bool,
+    print_ast: Option<AstForm>,
+    /// Prints how long each function took once the script ends, from
+    /// `--profile`.
+    profile: bool,
+    /// Seeds `random()`, from `--seed`.
+    seed: Option<u64>,
+    /// How much to simplify scripts before running them, from
+    /// `--opt-level`.
+    opt_level: u8,
+    /// What to do about locals that shadow other variables, from
+    /// `--shadowing`.
+    shadowing: Shadowing,
+    /// Makes `clock()` count one second per call from zero, from
+    /// `--virtual-clock`.
+    virtual_clock: bool,
+}
+
+impl Options {
+    fn interpreter(&self) -> Interpreter {
+        let mut interpreter = if self.no_stdlib {
+            Interpreter::without_stdlib()
+        } else {
+            Interpreter::new()
+        };
+
+        if self.allow_io {
+            interpreter.enable_io();
+        }
+        interpreter.set_deny_warnings(self.deny_warnings);
+        interpreter.set_extensions(self.extensions);
+        interpreter.set_color(self.color.enabled());
+        interpreter.set_error_format(self.error_format);
+        for path in &self.module_paths {
+            interpreter.add_module_path(path);
+        }
+        interpreter.set_opt_level(self.opt_level);
+        interpreter.set_shadowing(self.shadowing);
+        if let Some(seed) = self.seed {
+            interpreter.set_seed(seed);
+        }
+        if self.virtual_clock {
+            interpreter.set_clock(VirtualClock::ticking(1.0));
+        }
+
+        interpreter
+    }
+}
+
+/// The exit code from sysexits.h for scripts that don't compile, as used by
+/// the reference implementations.
+const COMPILE_ERROR: i32 = 65;
+
+/// The exit code from sysexits.h for scripts that fail as they run.
+const RUNTIME_ERROR: i32 = 70;
+
+/// Prints the line of each statement, and the function it's in, before it
+/// runs, while the flag is set.
+struct Tracer(Arc<AtomicBool>);
+
+impl Hook for Tracer {
+    fn before_statement(
+        &mut self,
+        interpreter: &mut Interpreter,
+        line: usize,
+    ) -> Result<(), Error> {
+        if self.0.load(Ordering::Relaxed) {
+            let function = interpreter
+                .call_stack()
+                .last()
+                .map_or("script", String::as_str);
+            println!("[line {line}] in {function}");
+        }
+
+        Ok(())
+    }
+}
+
+struct TreewalkSession<'o> {
+    options: &'o Options,
+    interpreter: Interpreter,
+    trace: Arc<AtomicBool>,
+}
+
+impl<'o> TreewalkSession<'o> {
+    fn new(options: &'o Options) -> Self {
+        let trace = Arc::new(AtomicBool::new(false));
+
+        Self {
+            options,
+            interpreter: Self::interpreter(options, &trace),
+            trace,
+        }
+    }
+
+    fn interpreter(options: &Options, trace: &Arc<AtomicBool>) -> Interpreter {
+        let mut interpreter = options.interpreter();
+        interpreter.set_args([]);
+        interpreter.set_hook(Tracer(trace.clone()));
+
+        interpreter
+    }
+}
 
+impl Session for TreewalkSession<'_> {
+    fn run(&mut self, source: &str) {
+        // The session carries on unless the script asked to exit.
+        if let Err(Error::Exit { code }) = self.interpreter.run(source) {
+            process::exit(code);
+        }
+    }
+
+    fn globals(&self) -> Vec<(String, Option<String>)> {
+        self.interpreter
+            .global_variables()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.map(|value| value.to_string())))
+            .collect()
+    }
+
+    fn reset(&mut self) {
+        self.interpreter = Self::interpreter(self.options, &self.trace);
+    }
+
+    fn set_trace(&mut self, trace: bool) {
+        self.trace.store(trace, Ordering::Relaxed);
+    }
+}
+
+/// Prints the syntax tree of REPL input, or its syntax errors.
+fn print_input_ast(source: &str) {
+    let mut scanner = Scanner::new(source);
     let tokens = scanner.scan();
-    let mut parser = Parser::new(tokens);
+    let (statements, errors) = Parser::new(tokens).parse();
+    for error in scanner.errors() {
+        eprintln!("{error}");
+    }
+    for error in &errors {
+        eprintln!("{error}");
+    }
+
+    print!("{}", ast_printer::to_tree(&statements));
+}
 
-    if let Ok(statements) = parser.parse() {
-        let mut resolver = Resolver::new(interpreter);
-        resolver.resolve_statements(statements.clone());
-        if resolver.had_error() {
-            return;
+fn run_prompt(options: &Options) -> anyhow::Result<()> {
+    repl(&mut TreewalkSession::new(options), Some(print_input_ast))
+}
+
+/// Exits with the code for `error` from a script the VM ran.
+fn vm_exit_with(error: vm::Error) -> ! {
+    match error {
+        vm::Error::Compile => process::exit(COMPILE_ERROR),
+        vm::Error::Runtime | vm::Error::Budget | vm::Error::Interrupted => {
+            process::exit(RUNTIME_ERROR)
         }
+    }
+}
+
+/// Like [`run_prompt`], on the VM. Globals defined on one line can be used
+/// on the next.
+fn run_vm_prompt(options: &Options) -> anyhow::Result<()> {
+    let mut vm = Vm::new();
+    vm.set_opt_level(options.opt_level);
 
-        interpreter.interpret(statements);
+    repl(&mut vm, Some(print_input_ast))
+}
+
+fn run_vm_file(path: &str, options: &Options) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(path)?;
+    let mut vm = Vm::new();
+    vm.set_opt_level(options.opt_level);
+
+    vm.interpret(&source)
+        .unwrap_or_else(|error| vm_exit_with(error));
+
+    Ok(())
+}
+
+fn run_file(path: &str, args: &[String], options: &Options) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(path)?;
+    let mut interpreter = options.interpreter();
+    interpreter.set_script_path(path);
+    interpreter.set_args(args.iter().cloned());
+    interpreter.set_show_source(true);
+
+    let coverage = Coverage::new();
+    let profiler = Profiler::new();
+    let mut hooks: Vec<Box<dyn Hook>> = vec![];
+    if options.coverage.is_some() {
+        hooks.push(Box::new(coverage.clone()));
     }
+    if options.profile {
+        hooks.push(Box::new(profiler.clone()));
+    }
+    if !hooks.is_empty() {
+        interpreter.set_hook(hooks);
+    }
+
+    let result = interpreter.run(&source);
+    if let Some(report) = &options.coverage {
+        coverage.write_lcov(&mut fs::File::create(report)?)?;
+    }
+    if options.profile {
+        profiler.write_report(&mut std::io::stderr())?;
+    }
+    if let Err(error) = result {
+        process::exit(error.exit_code());
+    }
+
+    Ok(())
 }
 
-fn run_prompt() -> anyhow::Result<()> {
-    let mut interpreter = Interpreter::new();
+/// How often `watch` checks whether the script has changed.
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs the script, then runs it again whenever it changes, until killed.
+/// Each run starts afresh, unless `--keep-globals` asks for the globals the
+/// last run left, so that functions and classes are redefined but variables
+/// keep their values.
+fn watch(path: &str, args: &[String], options: &Options) -> anyhow::Result<()> {
+    let mut session: Option<Interpreter> = None;
+    let mut last_seen = None;
+    fs::metadata(path)?;
 
     loop {
-        print!("> ");
-        std::io::stdout().flush()?;
+        // Editors that replace the file leave it missing for a moment.
+        let seen = fs::metadata(path)
+            .and_then(|metadata| Ok((metadata.modified()?, metadata.len())))
+            .ok();
+        if seen.is_none() || seen == last_seen {
+            thread::sleep(WATCH_INTERVAL);
+            continue;
+        }
+        let changed = last_seen.is_some();
+        // Only reporting a file that can't be read when it changes again
+        // keeps this from spinning on it.
+        last_seen = seen;
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("Can't read {path}: {error}");
+                continue;
+            }
+        };
+        if changed {
+            eprintln!("{path} changed, running it again.");
+        }
+
+        if !options.keep_globals {
+            session = None;
+        }
+        let interpreter = session.get_or_insert_with(|| {
+            let mut interpreter = options.interpreter();
+            interpreter.set_script_path(path);
+            interpreter.set_args(args.iter().cloned());
+            interpreter.set_show_source(true);
+            interpreter.set_keep_globals(options.keep_globals);
+            interpreter
+        });
+        // Errors have already been reported, and even the script asking to
+        // exit only ends this run.
+        let _ = interpreter.run(&source);
+    }
+}
+
+/// Runs the script under the debugger, which reads commands from stdin.
+fn debug(path: &str, args: &[String], options: &Options) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(path)?;
+    let mut interpreter = options.interpreter();
+    interpreter.set_script_path(path);
+    interpreter.set_args(args.iter().cloned());
+    interpreter.set_show_source(true);
+
+    let mut debugger = Debugger::new(BufReader::new(std::io::stdin()), std::io::stdout());
+    for breakpoint in &options.breakpoints {
+        debugger.add_breakpoint(breakpoint.clone());
+    }
+    interpreter.set_hook(debugger);
+
+    if let Err(error) = interpreter.run(&source) {
+        process::exit(error.exit_code());
+    }
+
+    Ok(())
+}
+
+/// Serves the Debug Adapter Protocol over stdin and stdout.
+fn serve_dap(options: &Options) -> anyhow::Result<()> {
+    dap::serve(std::io::stdin(), std::io::stdout(), || {
+        options.interpreter()
+    });
+
+    Ok(())
+}
+
+/// Serves the Language Server Protocol over stdin and stdout.
+fn serve_lsp() -> anyhow::Result<()> {
+    lsp::serve(std::io::stdin().lock(), std::io::stdout());
+
+    Ok(())
+}
+
+/// Collects the `.lox` files under `path`, or `path` itself if it's a file.
+fn lox_files(path: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() || entry.extension().is_some_and(|ext| ext == "lox") {
+            lox_files(&entry, files)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks each script for syntax and resolution errors without running it.
+/// Diagnostics go to stderr and a verdict for each file to stdout.
+fn check(paths: &[String], options: &Options) -> anyhow::Result<()> {
+    let mut files = vec![];
+    for path in paths {
+        lox_files(Path::new(path), &mut files)?;
+    }
+
+    let mut failed = false;
+    for file in files {
+        let source = fs::read_to_string(&file)?;
+        let mut interpreter = options.interpreter();
+        interpreter.set_script_path(&file);
+        interpreter.set_show_source(true);
+
+        if interpreter.compile(&source).is_ok() {
+            println!("{}: ok", file.display());
+        } else {
+            println!("{}: failed", file.display());
+            failed = true;
+        }
+    }
+
+    if failed {
+        process::exit(COMPILE_ERROR);
+    }
+
+    Ok(())
+}
+
+/// Rewrites each script in the canonical layout, or with `--check` lists the
+/// ones that would change.
+fn fmt(paths: &[String], options: &Options) -> anyhow::Result<()> {
+    let mut files = vec![];
+    for path in paths {
+        lox_files(Path::new(path), &mut files)?;
+    }
 
-        let mut line = String::new();
-        std::io::stdin().read_line(&mut line)?;
-        if line.is_empty() {
-            break;
+    let mut failure = None;
+    for file in files {
+        let source = fs::read_to_string(&file)?;
+        let formatted = match formatter::format(&source) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{error}");
+                }
+                eprintln!("{}: not formatted due to syntax errors", file.display());
+                failure = Some(COMPILE_ERROR);
+                continue;
+            }
+        };
+
+        if formatted == source {
+            continue;
+        }
+        if options.check {
+            println!("{}", file.display());
+            failure = failure.or(Some(1));
+        } else {
+            fs::write(&file, formatted)?;
         }
+    }
 
-        run(&mut interpreter, &line);
+    if let Some(code) = failure {
+        process::exit(code);
     }
 
     Ok(())
 }
 
-fn run_file(path: &str) -> anyhow::Result<()> {
+/// Runs the tests in each test file, or in the test files under each
+/// directory, and reports how each went.
+fn test(paths: &[String], options: &Options) -> anyhow::Result<()> {
+    let mut files = vec![];
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            let mut found = vec![];
+            lox_files(path, &mut found)?;
+            files.extend(
+                found
+                    .into_iter()
+                    .filter(|file| test_runner::is_test_file(file)),
+            );
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    let start = Instant::now();
+    let mut tests = vec![];
+    let mut broken = false;
+    for file in files {
+        let source = fs::read_to_string(&file)?;
+        let names = match test_runner::tests(&source) {
+            Ok(names) => names,
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{error}");
+                }
+                println!("{}: failed to compile", file.display());
+                broken = true;
+                continue;
+            }
+        };
+
+        let source = Arc::<str>::from(source);
+        tests.extend(names.into_iter().map(|name| Test {
+            path: file.clone(),
+            source: source.clone(),
+            name,
+        }));
+    }
+
+    let jobs = options
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get));
+    let mut failures: Vec<(String, TestResult)> = vec![];
+    let mut passed = 0;
+    test_runner::run_tests(
+        &tests,
+        jobs,
+        || options.interpreter(),
+        |test, result| {
+            let name = format!("{}::{}", test.path.display(), test.name);
+            let verdict = if result.passed() { "ok" } else { "FAILED" };
+            println!(
+                "test {name} ... {verdict} ({:.3}s)",
+                result.duration.as_secs_f64()
+            );
+
+            if result.passed() {
+                passed += 1;
+            } else {
+                failures.push((name, result));
+            }
+        },
+    );
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (test, result) in &failures {
+            println!("\n---- {test} ----");
+            print!("{}", result.output);
+            println!("{}", result.failure.as_deref().unwrap_or_default());
+        }
+    }
+
+    let verdict = if failures.is_empty() && !broken {
+        "ok"
+    } else {
+        "FAILED"
+    };
+    println!(
+        "\ntest result: {verdict}. {passed} passed; {} failed; finished in {:.3}s",
+        failures.len(),
+        start.elapsed().as_secs_f64()
+    );
+
+    if broken {
+        process::exit(COMPILE_ERROR);
+    }
+    if !failures.is_empty() {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Parses the script and prints its syntax tree, simplified as much as
+/// `--opt-level` asks, instead of running it.
+fn print_ast(path: &str, form: AstForm, opt_level: u8) -> anyhow::Result<()> {
     let source = std::fs::read_to_string(path)?;
-    let mut interpreter = Interpreter::new();
+    let mut scanner = Scanner::new(&source);
+
+    let tokens = scanner.scan();
+    let mut parser = Parser::new(tokens);
+
+    let (statements, errors) = parser.parse();
+    for error in scanner.errors() {
+        eprintln!("{error}");
+    }
+    for error in &errors {
+        eprintln!("{error}");
+    }
+    if !errors.is_empty() || scanner.had_error() {
+        process::exit(COMPILE_ERROR);
+    }
 
-    run(&mut interpreter, &source);
+    let statements = optimizer::optimize(statements, opt_level);
+    match form {
+        AstForm::Json => println!("{}", json::statements(&statements)),
+        AstForm::Parenthesized => print!("{}", ast_printer::to_string(&statements)),
+        AstForm::Tree => print!("{}", ast_printer::to_tree(&statements)),
+    }
 
     Ok(())
 }
 
+fn usage() -> ! {
+    println!("Usage: lox [--allow-io] [--color=auto|always|never] [--coverage[=file]] [--deny-warnings] [--error-format=human|json] [--extensions] [--module-path=<dir>]... [--no-stdlib] [--opt-level=0|1] [--print-ast[=tree]] [--profile] [--seed=<n>] [--shadowing=allow|warn|deny] [--virtual-clock] [--emit-ast=json|sexpr] [script [arg...]]");
+    println!("       lox --backend=vm [--opt-level=0|1] [script]");
+    println!(
+        "       lox [--color=auto|always|never] [--deny-warnings] [--error-format=human|json] check <script|directory>..."
+    );
+    println!("       lox [--break=[file:]line]... debug <script> [arg...]");
+    println!("       lox dap");
+    println!("       lox lsp");
+    println!("       lox fmt [--check] <script|directory>...");
+    println!("       lox [--keep-globals] watch <script> [arg...]");
+    println!("       lox [--jobs=<n>] [--opt-level=0|1] [--seed=<n>] test <script|directory>...");
+    process::exit(1);
+}
+
 fn main() -> anyhow::Result<()> {
-    let args = env::args().skip(1).collect::<Vec<_>>();
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(lox)?
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
 
-    match args.len() {
-        0 => run_prompt(),
-        1 => run_file(&args[0]),
-        _ => {
-            println!("Usage: lox [script]");
-            process::exit(1);
+fn lox() -> anyhow::Result<()> {
+    let mut options = Options::default();
+    let mut args = vec![];
+    let mut command_line = env::args().skip(1);
+    while let Some(arg) = command_line.next() {
+        match arg.as_str() {
+            "--allow-io" => options.allow_io = true,
+            "--backend=treewalk" => options.backend = Backend::Treewalk,
+            "--backend=vm" => options.backend = Backend::Vm,
+            "--check" => options.check = true,
+            "--color=auto" => options.color = Color::Auto,
+            "--color=always" => options.color = Color::Always,
+            "--color=never" => options.color = Color::Never,
+            "--coverage" => options.coverage = Some(PathBuf::from("lcov.info")),
+            "--deny-warnings" => options.deny_warnings = true,
+            "--error-format=human" => options.error_format = ErrorFormat::Human,
+            "--error-format=json" => options.error_format = ErrorFormat::Json,
+            "--extensions" => options.extensions = true,
+            "--keep-globals" => options.keep_globals = true,
+            "--no-stdlib" => options.no_stdlib = true,
+            "--profile" => options.profile = true,
+            "--virtual-clock" => options.virtual_clock = true,
+            "--print-ast" => options.print_ast = Some(AstForm::Parenthesized),
+            "--print-ast=tree" => options.print_ast = Some(AstForm::Tree),
+            "--emit-ast=json" => options.print_ast = Some(AstForm::Json),
+            "--emit-ast=sexpr" => options.print_ast = Some(AstForm::Parenthesized),
+            flag if flag.starts_with("--break=") => {
+                match Breakpoint::parse(&flag["--break=".len()..]) {
+                    Some(breakpoint) => options.breakpoints.push(breakpoint),
+                    None => usage(),
+                }
+            }
+            flag if flag.starts_with("--coverage=") => {
+                options.coverage = Some(PathBuf::from(&flag["--coverage=".len()..]))
+            }
+            flag if flag.starts_with("--jobs=") => match flag["--jobs=".len()..].parse() {
+                Ok(jobs) if jobs > 0 => options.jobs = Some(jobs),
+                _ => usage(),
+            },
+            flag if flag.starts_with("--module-path=") => options
+                .module_paths
+                .push(PathBuf::from(&flag["--module-path=".len()..])),
+            flag if flag.starts_with("--seed=") => match flag["--seed=".len()..].parse() {
+                Ok(seed) => options.seed = Some(seed),
+                Err(_) => usage(),
+            },
+            "--shadowing=allow" => options.shadowing = Shadowing::Allow,
+            "--shadowing=warn" => options.shadowing = Shadowing::Warn,
+            "--shadowing=deny" => options.shadowing = Shadowing::Deny,
+            flag if flag.starts_with("--opt-level=") => {
+                match flag["--opt-level=".len()..].parse() {
+                    Ok(level) if level <= MAX_OPT_LEVEL => options.opt_level = level,
+                    _ => usage(),
+                }
+            }
+            flag if flag.starts_with("--") => usage(),
+            "check" | "dap" | "debug" | "fmt" | "lsp" | "test" | "watch" if args.is_empty() => {
+                args.push(arg)
+            }
+            // Everything after the script belongs to the script.
+            _ if args.is_empty() || args == ["debug"] || args == ["watch"] => {
+                args.push(arg);
+                args.extend(command_line.by_ref());
+            }
+            _ => args.push(arg),
         }
     }
+
+    if options.backend == Backend::Vm {
+        return match (&args[..], options.print_ast) {
+            ([], None) => run_vm_prompt(&options),
+            ([path], None)
+                if !matches!(
+                    path.as_str(),
+                    "check" | "dap" | "debug" | "fmt" | "lsp" | "test" | "watch"
+                ) =>
+            {
+                run_vm_file(path, &options)
+            }
+            _ => usage(),
+        };
+    }
+
+    match args.first().map(String::as_str) {
+        Some("check") if args.len() > 1 => return check(&args[1..], &options),
+        Some("dap") if args.len() == 1 => return serve_dap(&options),
+        Some("debug") if args.len() > 1 => return debug(&args[1], &args[2..], &options),
+        Some("lsp") if args.len() == 1 => return serve_lsp(),
+        Some("fmt") if args.len() > 1 => return fmt(&args[1..], &options),
+        Some("test") if args.len() > 1 => return test(&args[1..], &options),
+        Some("watch") if args.len() > 1 => return watch(&args[1], &args[2..], &options),
+        Some("check" | "dap" | "debug" | "fmt" | "lsp" | "test" | "watch") => usage(),
+        _ => {}
+    }
+
+    match (args.len(), options.print_ast) {
+        (1, Some(form)) => print_ast(&args[0], form, options.opt_level),
+        (_, Some(_)) => usage(),
+        (0, None) => run_prompt(&options),
+        (_, None) => run_file(&args[0], &args[1..], &options),
+    }
 }
@@ -0,0 +1,133 @@
+use crate::{
+    gc::{Trace, Tracer},
+    interpreter::equals_method,
+    sync::Shared,
+    value::{Value, Visiting},
+};
+use std::{collections::HashMap, fmt};
+
+/// The hashable subset of [`Value`]s that can be used as map keys.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum MapKey {
+    Boolean(bool),
+    /// An instance, by its address. The map keeps the instance alive, so
+    /// the address can't be reused while it's a key.
+    Instance(usize),
+    Nil,
+    Number(u64),
+    String(Shared<str>),
+}
+
+impl MapKey {
+    fn new(value: &Value) -> Option<Self> {
+        match value {
+            Value::Boolean(b) => Some(Self::Boolean(*b)),
+            Value::Nil => Some(Self::Nil),
+            // Normalise -0 so that it finds the same entry as 0.
+            Value::Number(n) if *n == 0.0 => Some(Self::Number(0.0f64.to_bits())),
+            Value::Number(n) => Some(Self::Number(n.to_bits())),
+            Value::String(s) => Some(Self::String(s.clone())),
+            // Instances are the same key only if they're the same instance,
+            // as they're only equal to themselves. Ones with an `equals`
+            // method can be equal to others, which would have to be found
+            // without hashing.
+            Value::Instance(instance) if equals_method(instance).is_none() => Some(Self::Instance(
+                Shared::as_ptr(instance) as *const () as usize,
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// A map that remembers the order its keys were first inserted in, so that
+/// printing and iteration are deterministic.
+#[derive(Clone, Debug, Default)]
+pub struct LoxMap {
+    indices: HashMap<MapKey, usize>,
+    entries: Vec<(Value, Value)>,
+}
+
+impl LoxMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_hashable(key: &Value) -> bool {
+        MapKey::new(key).is_some()
+    }
+
+    pub fn get(&self, key: &Value) -> Option<Value> {
+        let index = self.indices.get(&MapKey::new(key)?)?;
+
+        Some(self.entries[*index].1.clone())
+    }
+
+    /// Panics if `key` is not hashable; check with [`LoxMap::is_hashable`].
+    pub fn insert(&mut self, key: Value, value: Value) {
+        let map_key = MapKey::new(&key).expect("map keys must be hashable");
+
+        if let Some(index) = self.indices.get(&map_key) {
+            self.entries[*index].1 = value;
+        } else {
+            self.indices.insert(map_key, self.entries.len());
+            self.entries.push((key, value));
+        }
+    }
+
+    pub fn contains_key(&self, key: &Value) -> bool {
+        MapKey::new(key).is_some_and(|key| self.indices.contains_key(&key))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+impl Trace for LoxMap {
+    fn trace(&self, tracer: &mut Tracer) {
+        for (key, value) in &self.entries {
+            tracer.value(key);
+            tracer.value(value);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.indices.clear();
+        self.entries.clear();
+    }
+}
+
+impl LoxMap {
+    /// Writes this as [`fmt::Display`] does, with `visiting` marking the
+    /// lists and maps it's inside of.
+    pub(crate) fn write(&self, f: &mut fmt::Formatter<'_>, visiting: &mut Visiting) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            key.write(f, visiting)?;
+            write!(f, ": ")?;
+            value.write(f, visiting)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl fmt::Display for LoxMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &mut Visiting::default())
+    }
+}
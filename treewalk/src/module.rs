@@ -0,0 +1,63 @@
+//! Lox source files loaded with `import`.
+//!
+//! Each module runs once, in globals of its own that start out holding only
+//! the interpreter's natives. Importing it again gives back the same module,
+//! whose top-level variables are read as properties.
+
+use crate::{
+    interpreter::{Environment, Error},
+    sync::{Lock, Shared},
+    token::{Token, TokenType},
+    value::Value,
+};
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub struct Module {
+    name: Shared<str>,
+    globals: Shared<Lock<Environment>>,
+}
+
+impl Module {
+    pub fn new(name: Shared<str>, globals: Shared<Lock<Environment>>) -> Self {
+        Self { name, globals }
+    }
+
+    pub fn value(self) -> Value {
+        Value::Module(Shared::new(self))
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value, Error> {
+        self.globals.borrow().get(name).map_err(|_| Error::Runtime {
+            message: format!("Undefined property '{}'.", name.lexeme()),
+            line: name.line(),
+            trace: vec![],
+            span: None,
+        })
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
+
+/// The file an import refers to: a string is used as is, while a bare name
+/// gets a `.lox` extension.
+pub fn file(path: &Token) -> PathBuf {
+    match (path.typ(), path.value()) {
+        (TokenType::String, Some(Value::String(path))) => PathBuf::from(path.as_ref()),
+        _ => PathBuf::from(format!("{}.lox", path.lexeme())),
+    }
+}
+
+/// Whether `path` is relative and never goes up a directory, so that joining
+/// it to a directory can't name a file outside it.
+pub fn stays_inside(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
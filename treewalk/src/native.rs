@@ -0,0 +1,98 @@
+use crate::{
+    callable::Callable,
+    interpreter::{Error, Interpreter},
+    sync::{Shared, Threadsafe},
+    value::Value,
+};
+use std::{any::Any, fmt, ops::RangeInclusive};
+
+/// The most arguments the parser allows a call to pass.
+const MAX_ARGUMENTS: usize = 255;
+
+trait NativeFn: Fn(&mut Interpreter, Vec<Value>) -> Result<Value, Error> + Threadsafe {}
+
+impl<F> NativeFn for F where F: Fn(&mut Interpreter, Vec<Value>) -> Result<Value, Error> + Threadsafe
+{}
+
+/// A function implemented in Rust by a host program, see
+/// [`Interpreter::define_native`].
+#[derive(Clone)]
+pub struct NativeFunction {
+    name: String,
+    arity: RangeInclusive<usize>,
+    function: Shared<dyn NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&mut Interpreter, Vec<Value>) -> Result<Value, Error> + Threadsafe + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            arity: arity..=arity,
+            function: Shared::new(function),
+        }
+    }
+
+    /// A native taking at least `min_arity` arguments, and as many more as
+    /// a call can pass.
+    pub fn variadic<F>(name: &str, min_arity: usize, function: F) -> Self
+    where
+        F: Fn(&mut Interpreter, Vec<Value>) -> Result<Value, Error> + Threadsafe + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            arity: min_arity..=MAX_ARGUMENTS,
+            function: Shared::new(function),
+        }
+    }
+
+    pub fn value(self) -> Value {
+        Value::Callable(Box::new(self))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+impl Callable for NativeFunction {
+    fn arity(&self) -> RangeInclusive<usize> {
+        self.arity.clone()
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, Error> {
+        (self.function)(interpreter, arguments)
+    }
+
+    fn box_clone(&self) -> Box<dyn Callable> {
+        Box::new((*self).clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn same_as(&self, other: &dyn Callable) -> bool {
+        other
+            .as_any()
+            .downcast_ref::<Self>()
+            .is_some_and(|other| Shared::ptr_eq(&self.function, &other.function))
+    }
+}
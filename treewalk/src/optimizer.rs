@@ -0,0 +1,360 @@
+//! Simplifies syntax trees before they run, without changing what they do.
+//!
+//! At level 1, arithmetic, comparisons and concatenation whose operands are
+//! literals are folded into their results, and `if` and `while` statements,
+//! ternaries and logical operators with literal conditions lose the branches
+//! that can't run. Double negations cancel out wherever only truthiness
+//! matters, so `if (!!x)` becomes `if (x)`. Anything that would fail is left
+//! for the interpreter, so errors are still reported on the same lines.
+//!
+//! Expressions keep their identity as they're simplified, so trees can be
+//! optimized after they've been resolved.
+
+use crate::{
+    ast::{Expr, ExprKind, Param, Stmt},
+    interpreter::is_truthy,
+    token::TokenType,
+    value::Value,
+};
+use std::mem;
+
+/// The highest level [`optimize`] understands. Level 0 leaves trees alone.
+pub const MAX_OPT_LEVEL: u8 = 1;
+
+pub fn optimize(mut statements: Vec<Stmt>, level: u8) -> Vec<Stmt> {
+    if level > 0 {
+        statements.iter_mut().for_each(statement);
+    }
+
+    statements
+}
+
+/// What an expression simplifies to.
+enum Simplified {
+    Value(Value),
+    Expr(Expr),
+}
+
+fn statement(stmt: &mut Stmt) {
+    let replacement = match stmt {
+        Stmt::Assert {
+            condition: test,
+            message,
+            ..
+        } => {
+            condition(test);
+            message.iter_mut().for_each(expression);
+            None
+        }
+        Stmt::Block(body) => {
+            body.iter_mut().for_each(statement);
+            None
+        }
+        Stmt::Function { params, body, .. } => {
+            params.iter_mut().for_each(param);
+            body.iter_mut().for_each(statement);
+            None
+        }
+        Stmt::Class {
+            superclass,
+            methods,
+            class_methods,
+            getters,
+            ..
+        } => {
+            superclass.iter_mut().for_each(expression);
+            methods
+                .iter_mut()
+                .chain(class_methods)
+                .chain(getters)
+                .for_each(statement);
+            None
+        }
+        Stmt::Const { initializer, .. }
+        | Stmt::Expression(initializer)
+        | Stmt::Print {
+            value: initializer, ..
+        }
+        | Stmt::Throw {
+            value: initializer, ..
+        }
+        | Stmt::VarList { initializer, .. } => {
+            expression(initializer);
+            None
+        }
+        Stmt::Return { value, .. }
+        | Stmt::Var {
+            initializer: value, ..
+        } => {
+            value.iter_mut().for_each(expression);
+            None
+        }
+        Stmt::ForIn { iterable, body, .. } => {
+            expression(iterable);
+            statement(body);
+            None
+        }
+        Stmt::If {
+            condition: test,
+            then_branch,
+            else_branch,
+        } => {
+            condition(test);
+            statement(then_branch);
+            if let Some(else_branch) = else_branch {
+                statement(else_branch);
+            }
+
+            literal(test).map(|value| {
+                if is_truthy(value) {
+                    mem::replace(&mut **then_branch, Stmt::Block(vec![]))
+                } else {
+                    else_branch
+                        .take()
+                        .map_or(Stmt::Block(vec![]), |else_branch| *else_branch)
+                }
+            })
+        }
+        Stmt::Switch { subject, cases, .. } => {
+            expression(subject);
+            for case in cases {
+                case.value.iter_mut().for_each(expression);
+                case.body.iter_mut().for_each(statement);
+            }
+            None
+        }
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            body.iter_mut()
+                .chain(catch.iter_mut().flat_map(|(_, body)| body))
+                .chain(finally.iter_mut().flatten())
+                .for_each(statement);
+            None
+        }
+        Stmt::While {
+            condition: test,
+            body,
+            increment,
+        } => {
+            condition(test);
+            statement(body);
+            increment.iter_mut().for_each(expression);
+
+            literal(test)
+                .filter(|value| !is_truthy(value))
+                .map(|_| Stmt::Block(vec![]))
+        }
+        Stmt::Break(_) | Stmt::Continue(_) | Stmt::Import { .. } => None,
+    };
+
+    if let Some(replacement) = replacement {
+        *stmt = replacement;
+    }
+}
+
+fn param(param: &mut Param) {
+    param.default.iter_mut().for_each(expression);
+}
+
+/// Simplifies an expression whose value is only ever tested for truthiness.
+fn condition(expr: &mut Expr) {
+    expression(expr);
+
+    while let ExprKind::Unary { operator, right } = &mut expr.kind {
+        let ExprKind::Unary {
+            operator: inner,
+            right: negated,
+        } = &mut right.kind
+        else {
+            break;
+        };
+        if *operator.typ() != TokenType::Bang || *inner.typ() != TokenType::Bang {
+            break;
+        }
+
+        *expr = take(negated);
+    }
+}
+
+fn expression(expr: &mut Expr) {
+    let simplified = match &mut expr.kind {
+        ExprKind::Assign { value, .. } | ExprKind::AssignList { value, .. } => {
+            expression(value);
+            None
+        }
+        ExprKind::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            expression(left);
+            expression(right);
+
+            if *operator.typ() == TokenType::Comma {
+                literal(left)
+                    .is_some()
+                    .then(|| Simplified::Expr(take(right)))
+            } else if let (Some(left), Some(right)) = (literal(left), literal(right)) {
+                binary(*operator.typ(), left, right).map(Simplified::Value)
+            } else {
+                None
+            }
+        }
+        ExprKind::Call {
+            callee, arguments, ..
+        } => {
+            expression(callee);
+            arguments.iter_mut().for_each(expression);
+            None
+        }
+        ExprKind::Get { object, .. } => {
+            expression(object);
+            None
+        }
+        ExprKind::Grouping(inner) => {
+            expression(inner);
+            literal(inner).cloned().map(Simplified::Value)
+        }
+        ExprKind::Index { object, index, .. } => {
+            expression(object);
+            expression(index);
+            None
+        }
+        ExprKind::IndexSet {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            expression(object);
+            expression(index);
+            expression(value);
+            None
+        }
+        ExprKind::Lambda { params, body } => {
+            params.iter_mut().for_each(param);
+            body.iter_mut().for_each(statement);
+            None
+        }
+        ExprKind::List(elements) => {
+            elements.iter_mut().for_each(expression);
+            None
+        }
+        ExprKind::Logical {
+            left,
+            operator,
+            right,
+        } => {
+            expression(left);
+            expression(right);
+
+            literal(left).map(is_truthy).map(|truthy| {
+                let short_circuits = match operator.typ() {
+                    TokenType::Or => truthy,
+                    _ => !truthy,
+                };
+                Simplified::Expr(take(if short_circuits { left } else { right }))
+            })
+        }
+        ExprKind::Map { entries, .. } => {
+            for (key, value) in entries {
+                expression(key);
+                expression(value);
+            }
+            None
+        }
+        ExprKind::Postfix { value, assignment } => {
+            expression(value);
+            expression(assignment);
+            None
+        }
+        ExprKind::Set { object, value, .. } => {
+            expression(object);
+            expression(value);
+            None
+        }
+        ExprKind::Ternary {
+            condition: test,
+            then_branch,
+            else_branch,
+        } => {
+            condition(test);
+            expression(then_branch);
+            expression(else_branch);
+
+            literal(test).map(is_truthy).map(|truthy| {
+                Simplified::Expr(take(if truthy { then_branch } else { else_branch }))
+            })
+        }
+        ExprKind::Unary { operator, right } => match operator.typ() {
+            TokenType::Bang => {
+                condition(right);
+                literal(right).map(|value| Simplified::Value(Value::Boolean(!is_truthy(value))))
+            }
+            _ => {
+                expression(right);
+                match literal(right) {
+                    Some(Value::Number(n)) if *operator.typ() == TokenType::Minus => {
+                        Some(Simplified::Value(Value::Number(-n)))
+                    }
+                    _ => None,
+                }
+            }
+        },
+        ExprKind::Literal(_)
+        | ExprKind::Super { .. }
+        | ExprKind::This(_)
+        | ExprKind::Variable(_) => None,
+    };
+
+    match simplified {
+        Some(Simplified::Value(value)) => expr.kind = ExprKind::Literal(value),
+        Some(Simplified::Expr(simplified)) => *expr = simplified,
+        None => {}
+    }
+}
+
+/// The result of `left operator right`, unless it would fail or depends on
+/// more than the operands.
+fn binary(operator: TokenType, left: &Value, right: &Value) -> Option<Value> {
+    let value = match (operator, left, right) {
+        (TokenType::EqualEqual, left, right) => Value::Boolean(left == right),
+        (TokenType::BangEqual, left, right) => Value::Boolean(left != right),
+        (TokenType::Plus, Value::String(left), Value::String(right)) => {
+            Value::String(format!("{left}{right}").into())
+        }
+        (operator, Value::Number(left), Value::Number(right)) => {
+            let (left, right) = (*left, *right);
+            match operator {
+                TokenType::Greater => Value::Boolean(left > right),
+                TokenType::GreaterEqual => Value::Boolean(left >= right),
+                TokenType::Less => Value::Boolean(left < right),
+                TokenType::LessEqual => Value::Boolean(left <= right),
+                TokenType::Minus => Value::Number(left - right),
+                TokenType::Percent => Value::Number(left % right),
+                TokenType::Plus => Value::Number(left + right),
+                TokenType::Slash => Value::Number(left / right),
+                TokenType::Star => Value::Number(left * right),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+fn literal(expr: &Expr) -> Option<&Value> {
+    match &expr.kind {
+        ExprKind::Literal(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// Moves an expression out of the tree, leaving `nil` in its place.
+fn take(expr: &mut Expr) -> Expr {
+    mem::replace(expr, Expr::at(ExprKind::Literal(Value::Nil), 0))
+}
@@ -1,15 +1,63 @@
 use crate::{
-    ast::{Expr, ExprKind::*, Stmt},
-    report,
-    token::{Token, TokenType},
+    ast::{Expr, ExprKind::*, Param, Stmt, SwitchCase},
+    module,
+    token::{Span, Token, TokenType},
     value::Value,
 };
 use std::fmt;
 
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum Error {
-    #[error("parse error")]
-    ParseError,
+    /// A syntax error at `token`. It displays as the error line the
+    /// reference implementation prints.
+    #[error("[line {}] Error{}: {message}", token.line(), location(token))]
+    ParseError { token: Token, message: String },
+}
+
+fn location(token: &Token) -> String {
+    if token.typ() == &TokenType::Eof {
+        " at end".to_string()
+    } else {
+        format!(" at '{}'", token.lexeme())
+    }
+}
+
+/// Builds `target = target operator value`, or returns `None` if `target`
+/// can't be assigned to. Variables desugar to an assignment of a binary
+/// expression, but properties and elements carry the `operator` so that the
+/// object and index are only evaluated once.
+fn compound_assignment(target: Expr, operator: Token, value: Expr) -> Option<Expr> {
+    let value = Box::new(value);
+
+    match target.kind {
+        Variable(ref name) => {
+            let name = name.clone();
+            let value = Box::new(Expr::new(Binary {
+                left: Box::new(target),
+                operator,
+                right: value,
+            }));
+            Some(Expr::new(Assign { name, value }))
+        }
+        Get { object, name } => Some(Expr::new(Set {
+            object,
+            name,
+            value,
+            operator: Some(operator),
+        })),
+        Index {
+            object,
+            bracket,
+            index,
+        } => Some(Expr::new(IndexSet {
+            object,
+            bracket,
+            index,
+            value,
+            operator: Some(operator),
+        })),
+        _ => None,
+    }
 }
 
 enum FunKind {
@@ -26,34 +74,56 @@ impl fmt::Display for FunKind {
     }
 }
 
-pub struct Parser {
-    tokens: Vec<Token>,
+/// How deeply expressions and statements may nest. Parsing, resolving and
+/// running them all recurse, so without a limit deeply nested source would
+/// overflow the stack. Even so, each level can take tens of kilobytes of
+/// stack in debug builds, so hosts running untrusted source should do it on
+/// a thread with a large stack, as `lox` does.
+pub const MAX_NESTING: usize = 200;
+
+pub struct Parser<'t> {
+    tokens: &'t [Token],
     current: usize,
     errors: Vec<Error>,
+    /// How many expressions and statements are being parsed inside each
+    /// other.
+    depth: usize,
 }
 
-impl Parser {
-    pub fn new(tokens: &[Token]) -> Self {
+impl<'t> Parser<'t> {
+    pub fn new(tokens: &'t [Token]) -> Self {
         Self {
-            tokens: tokens.to_owned(),
+            tokens,
             current: 0,
             errors: vec![],
+            depth: 0,
         }
     }
 
-    fn previous(&self) -> Token {
-        self.tokens[self.current - 1].clone()
+    fn previous(&self) -> &'t Token {
+        &self.tokens[self.current - 1]
     }
 
-    fn peek(&self) -> Token {
-        self.tokens[self.current].clone()
+    fn peek(&self) -> &'t Token {
+        &self.tokens[self.current]
+    }
+
+    /// Places `expr` in the source, from `start` to the end of the last
+    /// token consumed.
+    fn spanned(&self, start: usize, expr: Expr) -> Expr {
+        let end = Span::of(self.previous()).end();
+
+        expr.with_span(Span {
+            offset: start,
+            len: end.saturating_sub(start),
+        })
     }
 
     fn is_at_end(&self) -> bool {
         self.peek().typ() == &TokenType::Eof
     }
 
-    fn advance(&mut self) -> Token {
+    fn advance(&mut self) -> &'t Token {
         if !self.is_at_end() {
             self.current += 1;
         }
@@ -69,6 +139,13 @@ impl Parser {
         }
     }
 
+    /// Looks `n` tokens past the current one without consuming anything.
+    fn check_nth(&self, n: usize, typ: TokenType) -> bool {
+        self.tokens
+            .get(self.current + n)
+            .is_some_and(|token| token.typ() == &typ)
+    }
+
     fn is_match(&mut self, types: &[TokenType]) -> bool {
         for typ in types {
             if self.check(*typ) {
@@ -80,23 +157,33 @@ impl Parser {
         false
     }
 
-    fn error(&mut self, token: Token, message: &str) {
-        if token.typ() == &TokenType::Eof {
-            report(token.line(), " at end", message);
-        } else {
-            let lexeme = token.lexeme();
-            report(token.line(), &format!(" at '{lexeme}'"), message);
-        };
+    fn error(&self, token: &Token, message: &str) -> Error {
+        Error::ParseError {
+            token: token.clone(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Parses something that may contain more of itself, failing if it's
+    /// nested too deeply.
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error(self.peek(), "Too much nesting."));
+        }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+
+        result
     }
 
-    fn consume(&mut self, typ: TokenType, message: &str) -> Result<Token, Error> {
+    fn consume(&mut self, typ: TokenType, message: &str) -> Result<&'t Token, Error> {
         if self.check(typ) {
             return Ok(self.advance());
         }
 
-        self.error(self.peek(), message);
-
-        Err(Error::ParseError)
+        Err(self.error(self.peek(), message))
     }
 
     fn synchronize(&mut self) {
@@ -105,12 +192,16 @@ impl Parser {
         while !self.is_at_end() {
             if self.previous().typ() == &TokenType::Semicolon
                 || [
+                    TokenType::Assert,
                     TokenType::Class,
+                    TokenType::Const,
                     TokenType::For,
                     TokenType::Fun,
                     TokenType::If,
+                    TokenType::Import,
                     TokenType::Print,
                     TokenType::Return,
+                    TokenType::Switch,
                     TokenType::Var,
                     TokenType::While,
                 ]
@@ -124,38 +215,80 @@ impl Parser {
     }
 
     fn primary(&mut self) -> Result<Expr, Error> {
+        let line = self.peek().line();
         if self.is_match(&[TokenType::False]) {
-            Ok(Expr::new(Literal(Value::Boolean(false))))
+            Ok(Expr::at(Literal(Value::Boolean(false)), line))
         } else if self.is_match(&[TokenType::True]) {
-            Ok(Expr::new(Literal(Value::Boolean(true))))
+            Ok(Expr::at(Literal(Value::Boolean(true)), line))
         } else if self.is_match(&[TokenType::Nil]) {
-            Ok(Expr::new(Literal(Value::Nil)))
+            Ok(Expr::at(Literal(Value::Nil), line))
         } else if self.is_match(&[TokenType::Number, TokenType::String]) {
-            Ok(Expr::new(Literal(
-                self.previous()
-                    .value()
-                    .clone()
-                    .expect("must have a literal"),
-            )))
+            Ok(Expr::at(
+                Literal(
+                    self.previous()
+                        .value()
+                        .clone()
+                        .expect("must have a literal"),
+                ),
+                line,
+            ))
+        } else if self.is_match(&[TokenType::Interpolation]) {
+            self.interpolation()
         } else if self.is_match(&[TokenType::Super]) {
-            let keyword = self.previous();
+            let keyword = self.previous().clone();
             self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
-            let method = self.consume(TokenType::Identifier, "Expect superclass method name.")?;
+            let method = self
+                .consume(TokenType::Identifier, "Expect superclass method name.")?
+                .clone();
 
             Ok(Expr::new(Super { keyword, method }))
         } else if self.is_match(&[TokenType::This]) {
-            Ok(Expr::new(This(self.previous())))
+            Ok(Expr::new(This(self.previous().clone())))
         } else if self.is_match(&[TokenType::Identifier]) {
-            Ok(Expr::new(Variable(self.previous())))
+            Ok(Expr::new(Variable(self.previous().clone())))
         } else if self.is_match(&[TokenType::LeftParen]) {
             let expr = self.expression()?;
             self.consume(TokenType::RightParen, "Expect ')' after expression")?;
 
-            Ok(Expr::new(Grouping(Box::new(expr))))
-        } else {
-            self.error(self.peek(), "Expect expression.");
+            Ok(Expr::at(Grouping(Box::new(expr)), line))
+        } else if self.check(TokenType::Fun) && self.check_nth(1, TokenType::LeftParen) {
+            self.lambda()
+        } else if self.is_match(&[TokenType::LeftBracket]) {
+            let mut elements = vec![];
+            if !self.check(TokenType::RightBracket) {
+                loop {
+                    elements.push(self.assignment()?);
+
+                    if !self.is_match(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
 
-            Err(Error::ParseError)
+            self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+
+            Ok(Expr::at(List(elements), line))
+        } else if self.is_match(&[TokenType::LeftBrace]) {
+            let brace = self.previous().clone();
+            let mut entries = vec![];
+            if !self.check(TokenType::RightBrace) {
+                loop {
+                    let key = self.assignment()?;
+                    self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+                    let value = self.assignment()?;
+                    entries.push((key, value));
+
+                    if !self.is_match(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+
+            self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+
+            Ok(Expr::new(Map { brace, entries }))
+        } else {
+            Err(self.error(self.peek(), "Expect expression."))
         }
     }
 
@@ -164,11 +297,13 @@ impl Parser {
         if !self.check(TokenType::RightParen) {
             loop {
                 if arguments.len() >= 255 {
-                    self.error(self.peek(), "Can't have more than 255 arguments.");
-                    self.errors.push(Error::ParseError);
+                    let error = self.error(self.peek(), "Can't have more than 255 arguments.");
+                    self.errors.push(error);
                 }
 
-                arguments.push(self.expression()?);
+                // Arguments are parsed above the comma operator so that the
+                // commas separate them instead.
+                arguments.push(self.assignment()?);
 
                 if !self.is_match(&[TokenType::Comma]) {
                     break;
@@ -176,7 +311,9 @@ impl Parser {
             }
         }
 
-        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+        let paren = self
+            .consume(TokenType::RightParen, "Expect ')' after arguments.")?
+            .clone();
 
         Ok(Expr::new(Call {
             callee: Box::new(callee),
@@ -185,19 +322,79 @@ impl Parser {
         }))
     }
 
+    /// Desugars an interpolated string into the concatenation of its parts,
+    /// with each embedded expression converted by calling `str`.
+    fn interpolation(&mut self) -> Result<Expr, Error> {
+        let part = |token: &Token| {
+            let value = token.value().clone().expect("must have a literal");
+            Expr::at(Literal(value), token.line())
+        };
+        let concat = |left: Expr, right: Expr, line: usize| {
+            Expr::new(Binary {
+                left: Box::new(left),
+                operator: Token::new(TokenType::Plus, "+", None, line),
+                right: Box::new(right),
+            })
+        };
+
+        let mut expr = part(self.previous());
+        loop {
+            let start = self.previous().clone();
+            let embedded = self.expression()?;
+            let str = Token::new(TokenType::Identifier, "str", None, start.line())
+                .with_offset(start.offset());
+            let converted = Expr::new(Call {
+                callee: Box::new(Expr::new(Variable(str))),
+                paren: start.clone(),
+                arguments: vec![embedded],
+            });
+            expr = concat(expr, converted, start.line());
+
+            if self.is_match(&[TokenType::Interpolation]) {
+                expr = concat(expr, part(self.previous()), start.line());
+            } else {
+                let end = self.consume(
+                    TokenType::InterpolationEnd,
+                    "Expect '}' after interpolated expression.",
+                )?;
+                return Ok(concat(expr, part(end), start.line()));
+            }
+        }
+    }
+
     fn call(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
         let mut expr = self.primary()?;
+        expr = self.spanned(start, expr);
 
         loop {
             if self.is_match(&[TokenType::LeftParen]) {
                 expr = self.finish_call(expr)?;
+                expr = self.spanned(start, expr);
             } else if self.is_match(&[TokenType::Dot]) {
-                let name =
-                    self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
-                expr = Expr::new(Get {
-                    object: Box::new(expr),
-                    name,
-                });
+                let name = self
+                    .consume(TokenType::Identifier, "Expect property name after '.'.")?
+                    .clone();
+                expr = self.spanned(
+                    start,
+                    Expr::new(Get {
+                        object: Box::new(expr),
+                        name,
+                    }),
+                );
+            } else if self.is_match(&[TokenType::LeftBracket]) {
+                let index = Box::new(self.expression()?);
+                let bracket = self
+                    .consume(TokenType::RightBracket, "Expect ']' after index.")?
+                    .clone();
+                expr = self.spanned(
+                    start,
+                    Expr::new(Index {
+                        object: Box::new(expr),
+                        bracket,
+                        index,
+                    }),
+                );
             } else {
                 break;
             }
@@ -206,54 +403,109 @@ impl Parser {
         Ok(expr)
     }
 
+    /// Turns an increment or decrement of `target` into an assignment that
+    /// adds or subtracts one, or returns `None` if `target` can't be
+    /// assigned to.
+    fn increment(&self, operator: &Token, target: Expr) -> Option<Expr> {
+        let (typ, lexeme) = if operator.typ() == &TokenType::PlusPlus {
+            (TokenType::Plus, "+")
+        } else {
+            (TokenType::Minus, "-")
+        };
+        let operator = Token::new(typ, lexeme, None, operator.line());
+        let one = Expr::new(Literal(Value::Number(1.0)));
+
+        compound_assignment(target, operator, one)
+    }
+
+    fn postfix(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
+        let expr = self.call()?;
+
+        if self.is_match(&[TokenType::MinusMinus, TokenType::PlusPlus]) {
+            let operator = self.previous();
+            let value = Box::new(expr.clone());
+
+            if let Some(assignment) = self.increment(operator, expr) {
+                let postfix = Expr::new(Postfix {
+                    value,
+                    assignment: Box::new(assignment),
+                });
+                return Ok(self.spanned(start, postfix));
+            }
+
+            return Err(self.error(operator, "Invalid increment or decrement target."));
+        }
+
+        Ok(expr)
+    }
+
     fn unary(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
         let expr = if self.is_match(&[TokenType::Bang, TokenType::Minus]) {
+            let operator = self.previous().clone();
+            let right = Box::new(self.nested(Self::unary)?);
+
+            self.spanned(start, Expr::new(Unary { operator, right }))
+        } else if self.is_match(&[TokenType::MinusMinus, TokenType::PlusPlus]) {
             let operator = self.previous();
-            let right = Box::new(self.unary()?);
+            let right = self.nested(Self::unary)?;
 
-            Expr::new(Unary { operator, right })
+            match self.increment(operator, right) {
+                Some(assignment) => self.spanned(start, assignment),
+                None => return Err(self.error(operator, "Invalid increment or decrement target.")),
+            }
         } else {
-            self.call()?
+            self.postfix()?
         };
 
         Ok(expr)
     }
 
     fn factor(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
         let mut expr = self.unary()?;
 
-        while self.is_match(&[TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous();
+        while self.is_match(&[TokenType::Percent, TokenType::Slash, TokenType::Star]) {
+            let operator = self.previous().clone();
             let right = Box::new(self.unary()?);
 
-            expr = Expr::new(Binary {
-                left: Box::new(expr),
-                operator,
-                right,
-            });
+            expr = self.spanned(
+                start,
+                Expr::new(Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right,
+                }),
+            );
         }
 
         Ok(expr)
     }
 
     fn term(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
         let mut expr = self.factor()?;
 
         while self.is_match(&[TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
             let right = Box::new(self.factor()?);
 
-            expr = Expr::new(Binary {
-                left: Box::new(expr),
-                operator,
-                right,
-            });
+            expr = self.spanned(
+                start,
+                Expr::new(Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right,
+                }),
+            );
         }
 
         Ok(expr)
     }
 
     fn comparison(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
         let mut expr = self.term()?;
 
         while self.is_match(&[
@@ -262,102 +514,271 @@ impl Parser {
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
             let right = Box::new(self.term()?);
 
-            expr = Expr::new(Binary {
-                left: Box::new(expr),
-                operator,
-                right,
-            });
+            expr = self.spanned(
+                start,
+                Expr::new(Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right,
+                }),
+            );
         }
 
         Ok(expr)
     }
 
     fn equality(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
         let mut expr = self.comparison()?;
 
         while self.is_match(&[TokenType::BangEqual, TokenType::EqualEqual]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
             let right = Box::new(self.comparison()?);
 
-            expr = Expr::new(Binary {
-                left: Box::new(expr),
-                operator,
-                right,
-            });
+            expr = self.spanned(
+                start,
+                Expr::new(Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right,
+                }),
+            );
         }
 
         Ok(expr)
     }
 
     fn and(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
         let mut expr = self.equality()?;
 
         while self.is_match(&[TokenType::And]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
             let right = Box::new(self.equality()?);
 
-            expr = Expr::new(Logical {
-                left: Box::new(expr),
-                operator,
-                right,
-            });
+            expr = self.spanned(
+                start,
+                Expr::new(Logical {
+                    left: Box::new(expr),
+                    operator,
+                    right,
+                }),
+            );
         }
 
         Ok(expr)
     }
 
     fn or(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
         let mut expr = self.and()?;
 
         while self.is_match(&[TokenType::Or]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
             let right = Box::new(self.and()?);
 
-            expr = Expr::new(Logical {
-                left: Box::new(expr),
-                operator,
-                right,
-            });
+            expr = self.spanned(
+                start,
+                Expr::new(Logical {
+                    left: Box::new(expr),
+                    operator,
+                    right,
+                }),
+            );
         }
 
         Ok(expr)
     }
 
-    fn assignment(&mut self) -> Result<Expr, Error> {
+    fn ternary(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
         let expr = self.or()?;
 
-        if self.is_match(&[TokenType::Equal]) {
+        if self.is_match(&[TokenType::Question]) {
+            let then_branch = Box::new(self.expression()?);
+            self.consume(
+                TokenType::Colon,
+                "Expect ':' after then branch of conditional expression.",
+            )?;
+            let else_branch = Box::new(self.ternary()?);
+
+            let ternary = Expr::new(Ternary {
+                condition: Box::new(expr),
+                then_branch,
+                else_branch,
+            });
+            return Ok(self.spanned(start, ternary));
+        }
+
+        Ok(expr)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
+        let expr = self.ternary()?;
+
+        if self.is_match(&[
+            TokenType::Equal,
+            TokenType::MinusEqual,
+            TokenType::PlusEqual,
+            TokenType::SlashEqual,
+            TokenType::StarEqual,
+        ]) {
             let equals = self.previous();
             let value = Box::new(self.assignment()?);
 
+            let operator = match equals.typ() {
+                TokenType::MinusEqual => Some((TokenType::Minus, "-")),
+                TokenType::PlusEqual => Some((TokenType::Plus, "+")),
+                TokenType::SlashEqual => Some((TokenType::Slash, "/")),
+                TokenType::StarEqual => Some((TokenType::Star, "*")),
+                _ => None,
+            };
+            if let Some((typ, lexeme)) = operator {
+                let operator = Token::new(typ, lexeme, None, equals.line());
+                return match compound_assignment(expr, operator, *value) {
+                    Some(assignment) => Ok(self.spanned(start, assignment)),
+                    None => Err(self.error(equals, "Invalid assignment target.")),
+                };
+            }
+
             if let Variable(name) = expr.kind {
-                return Ok(Expr::new(Assign { name, value }));
+                return Ok(self.spanned(start, Expr::new(Assign { name, value })));
             } else if let Get { object, name } = expr.kind {
-                return Ok(Expr::new(Set {
-                    object,
-                    name,
-                    value,
-                }));
+                return Ok(self.spanned(
+                    start,
+                    Expr::new(Set {
+                        object,
+                        name,
+                        value,
+                        operator: None,
+                    }),
+                ));
+            } else if let Index {
+                object,
+                bracket,
+                index,
+            } = expr.kind
+            {
+                return Ok(self.spanned(
+                    start,
+                    Expr::new(IndexSet {
+                        object,
+                        bracket,
+                        index,
+                        value,
+                        operator: None,
+                    }),
+                ));
+            } else if let List(targets) = expr.kind {
+                let is_plain = equals.typ() == &TokenType::Equal;
+                if is_plain && targets.iter().all(|t| matches!(t.kind, Variable(_))) {
+                    return Ok(self.spanned(
+                        start,
+                        Expr::new(AssignList {
+                            targets,
+                            equals: equals.clone(),
+                            value,
+                        }),
+                    ));
+                }
             }
 
-            self.error(equals, "Invalid assignment target.");
+            return Err(self.error(equals, "Invalid assignment target."));
+        }
 
-            return Err(Error::ParseError);
+        Ok(expr)
+    }
+
+    fn comma(&mut self) -> Result<Expr, Error> {
+        let start = self.peek().offset();
+        let mut expr = self.assignment()?;
+
+        while self.is_match(&[TokenType::Comma]) {
+            let operator = self.previous().clone();
+            let right = Box::new(self.assignment()?);
+
+            expr = self.spanned(
+                start,
+                Expr::new(Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right,
+                }),
+            );
         }
 
         Ok(expr)
     }
 
     fn expression(&mut self) -> Result<Expr, Error> {
-        self.assignment()
+        self.nested(Self::comma)
+    }
+
+    fn assert_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'assert'.")?;
+
+        // Like arguments, these are parsed above the comma operator.
+        let start = self.current;
+        let condition = self.assignment()?;
+        let text = self.source_since(start);
+        let mut message = None;
+        if self.is_match(&[TokenType::Comma]) {
+            message = Some(self.assignment()?);
+        }
+
+        self.consume(TokenType::RightParen, "Expect ')' after assertion.")?;
+        self.consume(TokenType::Semicolon, "Expect ';' after assertion.")?;
+
+        Ok(Stmt::Assert {
+            keyword,
+            condition,
+            text,
+            message,
+        })
+    }
+
+    /// The source of the tokens from `start` up to the current one, with
+    /// any space between them collapsed to a single space.
+    fn source_since(&self, start: usize) -> String {
+        let mut source = String::new();
+        let mut end = None;
+        for token in &self.tokens[start..self.current] {
+            if end.is_some_and(|end| end < token.offset()) {
+                source.push(' ');
+            }
+            source.push_str(token.lexeme());
+            end = Some(token.offset() + token.lexeme().len());
+        }
+
+        source
+    }
+
+    fn break_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::Semicolon, "Expect ';' after 'break'.")?;
+
+        Ok(Stmt::Break(keyword))
+    }
+
+    fn continue_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.")?;
+
+        Ok(Stmt::Continue(keyword))
     }
 
     fn for_statement(&mut self) -> Result<Stmt, Error> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
 
+        if self.check(TokenType::Var) && self.check_nth(2, TokenType::In) {
+            return self.for_in_statement();
+        }
+
         let initializer = if self.is_match(&[TokenType::Semicolon]) {
             None
         } else if self.is_match(&[TokenType::Var]) {
@@ -381,18 +802,18 @@ impl Parser {
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
         let mut body = self.statement()?;
-        if let Some(increment) = increment {
-            body = Stmt::Block(vec![body, Stmt::Expression(increment)]);
-        }
 
         let condition = match condition {
             None => Expr::new(Literal(Value::Boolean(true))),
             Some(expr) => expr,
         };
 
+        // The increment is kept separate from the body so that `continue`
+        // still runs it before the next iteration.
         body = Stmt::While {
             condition,
             body: Box::new(body),
+            increment,
         };
 
         if let Some(initializer) = initializer {
@@ -402,6 +823,24 @@ impl Parser {
         Ok(body)
     }
 
+    fn for_in_statement(&mut self) -> Result<Stmt, Error> {
+        self.consume(TokenType::Var, "Expect 'var' in for-in loop.")?;
+        let name = self
+            .consume(TokenType::Identifier, "Expect variable name.")?
+            .clone();
+        self.consume(TokenType::In, "Expect 'in' after loop variable.")?;
+        let iterable = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after for-in clause.")?;
+
+        let body = Box::new(self.statement()?);
+
+        Ok(Stmt::ForIn {
+            name,
+            iterable,
+            body,
+        })
+    }
+
     fn if_statement(&mut self) -> Result<Stmt, Error> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
@@ -421,14 +860,15 @@ impl Parser {
     }
 
     fn print_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
         let value = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
 
-        Ok(Stmt::Print(value))
+        Ok(Stmt::Print { keyword, value })
     }
 
     fn return_statement(&mut self) -> Result<Stmt, Error> {
-        let keyword = self.previous();
+        let keyword = self.previous().clone();
 
         let mut value = None;
         if !self.check(TokenType::Semicolon) {
@@ -446,7 +886,11 @@ impl Parser {
         self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
         let body = Box::new(self.statement()?);
 
-        Ok(Stmt::While { condition, body })
+        Ok(Stmt::While {
+            condition,
+            body,
+            increment: None,
+        })
     }
 
     fn block(&mut self) -> Result<Vec<Stmt>, Error> {
@@ -471,7 +915,17 @@ impl Parser {
     }
 
     fn statement(&mut self) -> Result<Stmt, Error> {
-        let stmt = if self.is_match(&[TokenType::For]) {
+        self.nested(Self::any_statement)
+    }
+
+    fn any_statement(&mut self) -> Result<Stmt, Error> {
+        let stmt = if self.is_match(&[TokenType::Assert]) {
+            self.assert_statement()?
+        } else if self.is_match(&[TokenType::Break]) {
+            self.break_statement()?
+        } else if self.is_match(&[TokenType::Continue]) {
+            self.continue_statement()?
+        } else if self.is_match(&[TokenType::For]) {
             self.for_statement()?
         } else if self.is_match(&[TokenType::If]) {
             self.if_statement()?
@@ -479,6 +933,12 @@ impl Parser {
             self.print_statement()?
         } else if self.is_match(&[TokenType::Return]) {
             self.return_statement()?
+        } else if self.is_match(&[TokenType::Switch]) {
+            self.switch_statement()?
+        } else if self.is_match(&[TokenType::Throw]) {
+            self.throw_statement()?
+        } else if self.is_match(&[TokenType::Try]) {
+            self.try_statement()?
         } else if self.is_match(&[TokenType::While]) {
             self.while_statement()?
         } else if self.is_match(&[TokenType::LeftBrace]) {
@@ -493,8 +953,102 @@ impl Parser {
         Ok(stmt)
     }
 
+    fn switch_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'switch'.")?;
+        let subject = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after switch value.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before switch body.")?;
+
+        let mut cases = vec![];
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            let keyword = self.peek().clone();
+            let value = if self.is_match(&[TokenType::Case]) {
+                Some(self.expression()?)
+            } else if self.is_match(&[TokenType::Default]) {
+                None
+            } else {
+                return Err(self.error(self.peek(), "Expect 'case' or 'default'."));
+            };
+            self.consume(TokenType::Colon, "Expect ':' after case.")?;
+
+            let mut body = vec![];
+            while ![TokenType::Case, TokenType::Default, TokenType::RightBrace]
+                .iter()
+                .any(|typ| self.check(*typ))
+                && !self.is_at_end()
+            {
+                if let Some(stmt) = self.declaration() {
+                    body.push(stmt);
+                }
+            }
+
+            cases.push(SwitchCase {
+                keyword,
+                value,
+                body,
+            });
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after switch body.")?;
+
+        Ok(Stmt::Switch {
+            keyword,
+            subject,
+            cases,
+        })
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after thrown value.")?;
+
+        Ok(Stmt::Throw { keyword, value })
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, Error> {
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+
+        let mut catch = None;
+        if self.is_match(&[TokenType::Catch]) {
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self
+                .consume(TokenType::Identifier, "Expect exception variable name.")?
+                .clone();
+            self.consume(
+                TokenType::RightParen,
+                "Expect ')' after exception variable.",
+            )?;
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body.")?;
+            catch = Some((name, self.block()?));
+        }
+
+        let mut finally = None;
+        if self.is_match(&[TokenType::Finally]) {
+            self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.")?;
+            finally = Some(self.block()?);
+        }
+
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error(self.peek(), "Expect 'catch' or 'finally' after try block."));
+        }
+
+        Ok(Stmt::Try {
+            body,
+            catch,
+            finally,
+        })
+    }
+
     fn var_declaration(&mut self) -> Result<Stmt, Error> {
-        let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+        if self.is_match(&[TokenType::LeftBracket]) {
+            return self.var_list_declaration();
+        }
+
+        let name = self
+            .consume(TokenType::Identifier, "Expect variable name.")?
+            .clone();
 
         let mut initializer = None;
         if self.is_match(&[TokenType::Equal]) {
@@ -509,20 +1063,113 @@ impl Parser {
         Ok(Stmt::Var { name, initializer })
     }
 
+    fn const_declaration(&mut self) -> Result<Stmt, Error> {
+        let name = self
+            .consume(TokenType::Identifier, "Expect constant name.")?
+            .clone();
+        self.consume(TokenType::Equal, "Expect '=' after constant name.")?;
+        let initializer = self.expression()?;
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after constant declaration.",
+        )?;
+
+        Ok(Stmt::Const { name, initializer })
+    }
+
+    /// Parses `var [a, b] = initializer;`, which unpacks a list into one
+    /// variable per element.
+    fn var_list_declaration(&mut self) -> Result<Stmt, Error> {
+        let mut names = vec![];
+        loop {
+            names.push(
+                self.consume(TokenType::Identifier, "Expect variable name.")?
+                    .clone(),
+            );
+            if !self.is_match(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBracket, "Expect ']' after variable names.")?;
+
+        let equals = self
+            .consume(TokenType::Equal, "Expect '=' after variable names.")?
+            .clone();
+        let initializer = self.expression()?;
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+
+        Ok(Stmt::VarList {
+            names,
+            equals,
+            initializer,
+        })
+    }
+
+    /// Parses `import name;` or `import "path/to/name.lox";`, either of which
+    /// binds the module to `name`.
+    fn import_declaration(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().clone();
+        if !self.is_match(&[TokenType::Identifier, TokenType::String]) {
+            return Err(self.error(self.peek(), "Expect module name or path after 'import'."));
+        }
+        let path = self.previous().clone();
+
+        let file = module::file(&path);
+        if !module::stays_inside(&file) {
+            return Err(self.error(
+                &path,
+                "Module path must be relative and can't contain '..'.",
+            ));
+        }
+        let stem = file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let is_identifier = stem.starts_with(|c: char| c == '_' || c.is_alphabetic())
+            && stem.chars().all(|c| c == '_' || c.is_alphanumeric());
+        if !is_identifier {
+            return Err(self.error(&path, "Module file name must be a valid identifier."));
+        }
+        let name =
+            Token::new(TokenType::Identifier, &stem, None, path.line()).with_offset(path.offset());
+
+        self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+
+        Ok(Stmt::Import {
+            keyword,
+            path,
+            name,
+        })
+    }
+
     fn class_declaration(&mut self) -> Result<Stmt, Error> {
-        let name = self.consume(TokenType::Identifier, "Except class name.")?;
+        let name = self
+            .consume(TokenType::Identifier, "Except class name.")?
+            .clone();
 
         let mut superclass = None;
         if self.is_match(&[TokenType::Less]) {
             self.consume(TokenType::Identifier, "Expect superclass name.")?;
-            superclass = Some(Expr::new(Variable(self.previous())));
+            superclass = Some(Expr::new(Variable(self.previous().clone())));
         }
 
         self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
 
         let mut methods = vec![];
+        let mut class_methods = vec![];
+        let mut getters = vec![];
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(self.function(FunKind::Method)?);
+            if self.is_match(&[TokenType::Class]) {
+                class_methods.push(self.function(FunKind::Method)?);
+            } else if self.check_nth(1, TokenType::LeftBrace) {
+                getters.push(self.getter()?);
+            } else {
+                methods.push(self.function(FunKind::Method)?);
+            }
         }
 
         self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
@@ -531,23 +1178,73 @@ impl Parser {
             name,
             superclass,
             methods,
+            class_methods,
+            getters,
+        })
+    }
+
+    /// Getters are methods without a parameter list, which run as soon as
+    /// they're accessed.
+    fn getter(&mut self) -> Result<Stmt, Error> {
+        let name = self
+            .consume(TokenType::Identifier, "Expect getter name.")?
+            .clone();
+        self.consume(TokenType::LeftBrace, "Expect '{' before getter body.")?;
+        let body = self.block()?;
+
+        Ok(Stmt::Function {
+            name,
+            params: vec![],
+            body,
         })
     }
 
     fn function(&mut self, kind: FunKind) -> Result<Stmt, Error> {
-        let name = self.consume(TokenType::Identifier, &format!("Expect {kind} name"))?;
+        let name = self
+            .consume(TokenType::Identifier, &format!("Expect {kind} name"))?
+            .clone();
         self.consume(
             TokenType::LeftParen,
             &format!("Expect '(' after {kind} name."),
         )?;
+        let (params, body) = self.function_body(kind)?;
+
+        Ok(Stmt::Function { name, params, body })
+    }
+
+    fn lambda(&mut self) -> Result<Expr, Error> {
+        let line = self.consume(TokenType::Fun, "Expect 'fun'.")?.line();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'fun'.")?;
+        let (params, body) = self.function_body(FunKind::Function)?;
+
+        Ok(Expr::at(Lambda { params, body }, line))
+    }
 
-        let mut params = vec![];
+    /// Parses the parameter list and body shared by named and anonymous
+    /// functions, starting just after the opening parenthesis.
+    fn function_body(&mut self, kind: FunKind) -> Result<(Vec<Param>, Vec<Stmt>), Error> {
+        let mut params: Vec<Param> = vec![];
         if !self.check(TokenType::RightParen) {
             loop {
                 if params.len() >= 255 {
-                    self.error(self.peek(), "Can't have more than 255 parameters.");
+                    let error = self.error(self.peek(), "Can't have more than 255 parameters.");
+                    self.errors.push(error);
                 }
-                params.push(self.consume(TokenType::Identifier, "Expect parameter name.")?);
+                let name = self
+                    .consume(TokenType::Identifier, "Expect parameter name.")?
+                    .clone();
+                // Defaults are parsed above the comma operator, like
+                // arguments.
+                let default = if self.is_match(&[TokenType::Equal]) {
+                    Some(self.assignment()?)
+                } else {
+                    None
+                };
+                if default.is_none() && params.iter().any(|param| param.default.is_some()) {
+                    let error = self.error(&name, "Parameters after a default must have defaults.");
+                    self.errors.push(error);
+                }
+                params.push(Param { name, default });
                 if !self.is_match(&[TokenType::Comma]) {
                     break;
                 }
@@ -559,18 +1256,23 @@ impl Parser {
             &format!("Expect '{{' before {kind} body."),
         )?;
 
-        let body = self.block()?;
+        let body = self.nested(Self::block)?;
 
-        Ok(Stmt::Function { name, params, body })
+        Ok((params, body))
     }
 
     fn declaration(&mut self) -> Option<Stmt> {
         let res = if self.is_match(&[TokenType::Class]) {
             self.class_declaration()
-        } else if self.is_match(&[TokenType::Fun]) {
+        } else if self.check(TokenType::Fun) && self.check_nth(1, TokenType::Identifier) {
+            self.advance();
             self.function(FunKind::Function)
         } else if self.is_match(&[TokenType::Var]) {
             self.var_declaration()
+        } else if self.is_match(&[TokenType::Const]) {
+            self.const_declaration()
+        } else if self.is_match(&[TokenType::Import]) {
+            self.import_declaration()
         } else {
             self.statement()
         };
@@ -586,7 +1288,10 @@ impl Parser {
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, Error> {
+    /// Parses as many statements as it can, recovering from each syntax
+    /// error at the next statement boundary so that every error is found in
+    /// one pass. The program is only valid if no errors are returned.
+    pub fn parse(&mut self) -> (Vec<Stmt>, Vec<Error>) {
         let mut statements = vec![];
         while !self.is_at_end() {
             if let Some(stmt) = self.declaration() {
@@ -594,10 +1299,26 @@ impl Parser {
             }
         }
 
-        if self.errors.is_empty() {
-            Ok(statements)
-        } else {
-            Err(self.errors[0].clone())
+        (statements, std::mem::take(&mut self.errors))
+    }
+
+    /// Parses source holding nothing but a single expression.
+    pub fn parse_expression(&mut self) -> Result<Expr, Vec<Error>> {
+        let expr = self.expression().and_then(|expr| {
+            if self.is_at_end() {
+                Ok(expr)
+            } else {
+                Err(self.error(self.peek(), "Expect end of expression."))
+            }
+        });
+
+        match expr {
+            Ok(expr) if self.errors.is_empty() => Ok(expr),
+            Ok(_) => Err(std::mem::take(&mut self.errors)),
+            Err(error) => {
+                self.errors.push(error);
+                Err(std::mem::take(&mut self.errors))
+            }
         }
     }
 }
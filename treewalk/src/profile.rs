@@ -0,0 +1,174 @@
+//! Measures where a script spends its time, for finding hot Lox code.
+//!
+//! [`Profiler`] is a [`Hook`] that times every call and counts the
+//! statements run on each line. A function's inclusive time covers its whole
+//! call, and its exclusive time leaves out the calls it makes. Recursive
+//! calls are only counted once towards inclusive time, by the outermost one.
+
+use crate::{
+    interpreter::{Error, Hook, Interpreter},
+    sync::{Lock, Shared},
+};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// How many of the hottest lines the report lists.
+const HOT_LINES: usize = 10;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    pub inclusive: Duration,
+    pub exclusive: Duration,
+}
+
+/// A call that hasn't returned yet.
+struct Call {
+    function: String,
+    start: Instant,
+    /// The time spent in the calls it made.
+    callees: Duration,
+}
+
+#[derive(Default)]
+struct Measurements {
+    functions: HashMap<String, FunctionProfile>,
+    /// The statements run on each line, by file.
+    lines: HashMap<(Option<PathBuf>, usize), u64>,
+    calls: Vec<Call>,
+}
+
+/// Measurements shared between the copy of a `Profiler` given to the
+/// interpreter and the one kept to write the report.
+#[derive(Clone, Default)]
+pub struct Profiler {
+    measurements: Shared<Lock<Measurements>>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Each function called so far, with how often and for how long, the
+    /// most time spent in it first.
+    pub fn functions(&self) -> Vec<(String, FunctionProfile)> {
+        let mut functions = self
+            .measurements
+            .borrow()
+            .functions
+            .iter()
+            .map(|(function, profile)| (function.clone(), *profile))
+            .collect::<Vec<_>>();
+        functions.sort_by(|(a_name, a), (b_name, b)| {
+            b.exclusive
+                .cmp(&a.exclusive)
+                .then_with(|| a_name.cmp(b_name))
+        });
+
+        functions
+    }
+
+    /// The lines that ran the most statements, with how many they ran, most
+    /// first.
+    pub fn hot_lines(&self) -> Vec<(Option<PathBuf>, usize, u64)> {
+        let mut lines = self
+            .measurements
+            .borrow()
+            .lines
+            .iter()
+            .map(|((file, line), count)| (file.clone(), *line, *count))
+            .collect::<Vec<_>>();
+        lines.sort_by(|(a_file, a_line, a), (b_file, b_line, b)| {
+            b.cmp(a)
+                .then_with(|| a_file.cmp(b_file))
+                .then_with(|| a_line.cmp(b_line))
+        });
+
+        lines
+    }
+
+    /// Writes a table of the functions called, the most time spent in them
+    /// first, followed by the hottest lines.
+    pub fn write_report(&self, output: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            output,
+            "{:<32} {:>10} {:>12} {:>12}",
+            "function", "calls", "inclusive", "exclusive"
+        )?;
+        for (function, profile) in self.functions() {
+            writeln!(
+                output,
+                "{function:<32} {:>10} {:>12} {:>12}",
+                profile.calls,
+                format!("{:.3?}", profile.inclusive),
+                format!("{:.3?}", profile.exclusive),
+            )?;
+        }
+
+        writeln!(output)?;
+        writeln!(output, "{:<32} {:>10}", "line", "statements")?;
+        for (file, line, count) in self.hot_lines().into_iter().take(HOT_LINES) {
+            let location = match file {
+                Some(file) => format!("{}:{line}", file.display()),
+                None => format!("line {line}"),
+            };
+            writeln!(output, "{location:<32} {count:>10}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Hook for Profiler {
+    fn before_statement(
+        &mut self,
+        interpreter: &mut Interpreter,
+        line: usize,
+    ) -> Result<(), Error> {
+        let file = interpreter.current_file().map(PathBuf::from);
+        *self
+            .measurements
+            .borrow_mut()
+            .lines
+            .entry((file, line))
+            .or_default() += 1;
+
+        Ok(())
+    }
+
+    fn enter_call(&mut self, function: &str) {
+        self.measurements.borrow_mut().calls.push(Call {
+            function: function.to_string(),
+            start: Instant::now(),
+            callees: Duration::ZERO,
+        });
+    }
+
+    fn exit_call(&mut self) {
+        let mut measurements = self.measurements.borrow_mut();
+        let Some(call) = measurements.calls.pop() else {
+            return;
+        };
+
+        let elapsed = call.start.elapsed();
+        let recursive = measurements
+            .calls
+            .iter()
+            .any(|caller| caller.function == call.function);
+        if let Some(caller) = measurements.calls.last_mut() {
+            caller.callees += elapsed;
+        }
+
+        let profile = measurements.functions.entry(call.function).or_default();
+        profile.calls += 1;
+        profile.exclusive += elapsed.saturating_sub(call.callees);
+        if !recursive {
+            profile.inclusive += elapsed;
+        }
+    }
+}
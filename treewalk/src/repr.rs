@@ -0,0 +1,158 @@
+//! Owned copies of values, for passing data between scripts and Rust.
+//!
+//! A [`Value`] shares its lists, maps and instances with the script, which
+//! can go on changing them, and can only be used on the interpreter's thread.
+//! A [`LoxValueRepr`] is made of plain data instead: lists, maps and instance
+//! fields are copied all the way down, and classes and functions are
+//! described rather than copied, so that hosts can keep, compare or
+//! serialize them however they like.
+
+use crate::{
+    class::LoxClass,
+    function::LoxFunction,
+    interpreter::{Error, Interpreter},
+    map::LoxMap,
+    native::NativeFunction,
+    value::{Value, Visiting},
+};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoxValueRepr {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    List(Vec<LoxValueRepr>),
+    /// A map's entries, in the order they were inserted.
+    Map(Vec<(LoxValueRepr, LoxValueRepr)>),
+    Instance {
+        class: String,
+        fields: BTreeMap<String, LoxValueRepr>,
+    },
+    Class {
+        name: String,
+    },
+    /// A function or native, which has no name if it's anonymous or one of
+    /// the built-in natives that don't know their own.
+    Function {
+        name: Option<String>,
+        min_arity: usize,
+        max_arity: usize,
+    },
+    /// A module or range, as it prints.
+    Other(String),
+    /// A list, map or instance inside itself, which can't be copied.
+    Cycle,
+}
+
+impl LoxValueRepr {
+    pub fn from_value(value: &Value) -> Self {
+        copy(value, &mut Visiting::default())
+    }
+
+    /// Makes the value this describes, for data. Classes, functions,
+    /// instances and the rest can only be made by scripts.
+    pub fn to_value(&self, interpreter: &Interpreter) -> Result<Value, Error> {
+        let value = match self {
+            Self::Nil => Value::Nil,
+            Self::Boolean(b) => Value::Boolean(*b),
+            Self::Number(n) => Value::Number(*n),
+            Self::String(s) => Value::String(s.as_str().into()),
+            Self::List(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| element.to_value(interpreter))
+                    .collect::<Result<_, _>>()?;
+                interpreter.list_value(elements)
+            }
+            Self::Map(entries) => {
+                let mut map = LoxMap::new();
+                for (key, value) in entries {
+                    let key = key.to_value(interpreter)?;
+                    if !LoxMap::is_hashable(&key) {
+                        return Err(Error::native(
+                            "Map keys must be booleans, nil, numbers or strings.",
+                        ));
+                    }
+                    map.insert(key, value.to_value(interpreter)?);
+                }
+                interpreter.map_value(map)
+            }
+            Self::Instance { .. }
+            | Self::Class { .. }
+            | Self::Function { .. }
+            | Self::Other(_)
+            | Self::Cycle => {
+                return Err(Error::native(
+                    "Only nil, booleans, numbers, strings, lists and maps can be made from Rust.",
+                ));
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+/// Copies `value`, marking each list, map and instance as it's copied so
+/// that one inside itself becomes a [`LoxValueRepr::Cycle`].
+fn copy(value: &Value, copying: &mut Visiting) -> LoxValueRepr {
+    copying
+        .visit(value, |copying| copy_visited(value, copying))
+        .unwrap_or(LoxValueRepr::Cycle)
+}
+
+fn copy_visited(value: &Value, copying: &mut Visiting) -> LoxValueRepr {
+    match value {
+        Value::Nil => LoxValueRepr::Nil,
+        Value::Boolean(b) => LoxValueRepr::Boolean(*b),
+        Value::Number(n) => LoxValueRepr::Number(*n),
+        Value::String(s) => LoxValueRepr::String(s.to_string()),
+        Value::List(list) => LoxValueRepr::List(
+            list.borrow()
+                .iter()
+                .map(|element| copy(element, copying))
+                .collect(),
+        ),
+        Value::Map(map) => LoxValueRepr::Map(
+            map.borrow()
+                .keys()
+                .zip(map.borrow().values())
+                .map(|(key, value)| (copy(key, copying), copy(value, copying)))
+                .collect(),
+        ),
+        Value::Instance(instance) => {
+            let instance = instance.borrow();
+            LoxValueRepr::Instance {
+                class: instance.class().name().to_string(),
+                fields: instance
+                    .fields()
+                    .map(|(name, value)| (name.to_string(), copy(value, copying)))
+                    .collect(),
+            }
+        }
+        Value::Callable(callable) => {
+            let any = callable.as_any();
+            if let Some(class) = any.downcast_ref::<LoxClass>() {
+                LoxValueRepr::Class {
+                    name: class.name().to_string(),
+                }
+            } else {
+                let name = any
+                    .downcast_ref::<LoxFunction>()
+                    .and_then(LoxFunction::name)
+                    .or_else(|| {
+                        any.downcast_ref::<NativeFunction>()
+                            .map(NativeFunction::name)
+                    });
+                let arity = callable.arity();
+                LoxValueRepr::Function {
+                    name: name.map(str::to_string),
+                    min_arity: *arity.start(),
+                    max_arity: *arity.end(),
+                }
+            }
+        }
+        Value::Module(_) | Value::Range(_) => LoxValueRepr::Other(value.to_string()),
+    }
+}
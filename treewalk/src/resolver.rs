@@ -1,10 +1,63 @@
 use crate::{
-    ast::{Expr, ExprKind, Stmt},
-    error_token,
+    ast::{Expr, ExprKind, Param, Stmt},
     interpreter::Interpreter,
     token::Token,
 };
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::RangeInclusive,
+};
+
+/// A problem that stops the program from running, such as reading a local
+/// variable in its own initializer.
+#[derive(Clone, Debug)]
+pub struct ResolveError {
+    pub token: Token,
+    pub message: String,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Error at '{}': {}",
+            self.token.line(),
+            self.token.lexeme(),
+            self.message
+        )
+    }
+}
+
+/// A problem that doesn't stop the program from running, such as a local
+/// variable that's never read.
+#[derive(Clone, Debug)]
+pub struct Warning {
+    pub token: Token,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Warning at '{}': {}",
+            self.token.line(),
+            self.token.lexeme(),
+            self.message
+        )
+    }
+}
+
+/// What to do about a local variable with the same name as one in an
+/// enclosing scope, which it hides for the rest of its block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shadowing {
+    #[default]
+    Allow,
+    Warn,
+    Deny,
+}
 
 #[derive(Clone, Copy)]
 enum FunKind {
@@ -14,6 +67,30 @@ enum FunKind {
     None,
 }
 
+#[derive(Clone, Copy)]
+enum LoopKind {
+    Loop,
+    None,
+}
+
+/// A local variable, and the slot it will occupy in its scope's environment.
+///
+/// `name` is kept for variables that should be reported if they're never
+/// read, and is `None` for parameters and other implicitly used variables.
+/// `assigned` is false for a variable declared without an initializer until
+/// an assignment to it is found.
+#[derive(Clone)]
+struct Variable {
+    defined: bool,
+    read: bool,
+    assigned: bool,
+    constant: bool,
+    slot: usize,
+    name: Option<Token>,
+    /// The name in the declaration, or `None` for `this` and `super`.
+    declaration: Option<Token>,
+}
+
 #[derive(Clone, Copy)]
 enum ClassKind {
     Class,
@@ -21,12 +98,54 @@ enum ClassKind {
     Subclass,
 }
 
+/// A global class, as far as checking the methods that override its own
+/// goes.
+struct ClassShape {
+    superclass: Option<String>,
+    /// How many arguments each method takes.
+    methods: HashMap<String, RangeInclusive<usize>>,
+}
+
+/// How many arguments a function with `params` takes.
+fn arity(params: &[Param]) -> RangeInclusive<usize> {
+    let required = params
+        .iter()
+        .take_while(|param| param.default.is_none())
+        .count();
+
+    required..=params.len()
+}
+
+fn describe_arity(arity: &RangeInclusive<usize>) -> String {
+    match (arity.start(), arity.end()) {
+        (1, 1) => "1 parameter".to_string(),
+        (start, end) if start == end => format!("{start} parameters"),
+        (start, end) => format!("{start} to {end} parameters"),
+    }
+}
+
 pub struct Resolver<'r> {
     interpreter: &'r mut Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<String, Variable>>,
     current_function: FunKind,
     current_class: ClassKind,
-    had_error: bool,
+    /// The name of the class whose methods are being resolved, which the
+    /// private properties they use through `this` belong to.
+    class_name: Option<Token>,
+    current_loop: LoopKind,
+    /// Whether a switch's case is being resolved, which 'break' can leave
+    /// even outside a loop.
+    in_switch: bool,
+    errors: Vec<ResolveError>,
+    warnings: Vec<Warning>,
+    /// The first declaration of each global variable.
+    globals: HashMap<String, Token>,
+    /// The globals whose latest declaration so far is a constant.
+    constants: HashSet<String>,
+    /// The global classes declared so far, by name.
+    classes: HashMap<String, ClassShape>,
+    /// Each mention of a variable, and its declaration if it's local.
+    mentions: Vec<(Token, Option<Token>)>,
 }
 
 impl<'r> Resolver<'r> {
@@ -38,12 +157,101 @@ impl<'r> Resolver<'r> {
             scopes,
             current_function: FunKind::None,
             current_class: ClassKind::None,
-            had_error: false,
+            class_name: None,
+            current_loop: LoopKind::None,
+            in_switch: false,
+            errors: vec![],
+            warnings: vec![],
+            globals: HashMap::new(),
+            constants: HashSet::new(),
+            classes: HashMap::new(),
+            mentions: vec![],
         }
     }
 
     pub fn had_error(&self) -> bool {
-        self.had_error
+        !self.errors.is_empty()
+    }
+
+    /// The errors found so far, in the order they were found. They aren't
+    /// reported anywhere, so it's up to the caller to show them.
+    pub fn errors(&self) -> &[ResolveError] {
+        &self.errors
+    }
+
+    /// The warnings found so far, in the order they were found.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Where each variable mentioned was declared, as pairs of the mention
+    /// and the name in the declaration. Declarations count as mentions of
+    /// themselves, and mentions of undeclared globals are left out.
+    pub fn definitions(&self) -> Vec<(Token, Token)> {
+        self.mentions
+            .iter()
+            .filter_map(|(mention, declaration)| {
+                let declaration = match declaration {
+                    Some(declaration) => declaration,
+                    None => self.globals.get(mention.lexeme())?,
+                };
+
+                Some((mention.clone(), declaration.clone()))
+            })
+            .collect()
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        self.errors.push(ResolveError {
+            token: token.clone(),
+            message: message.to_string(),
+        });
+    }
+
+    fn warn(&mut self, token: &Token, message: &str) {
+        self.warnings.push(Warning {
+            token: token.clone(),
+            message: message.to_string(),
+        });
+    }
+
+    /// Warns about methods that take a different number of arguments from
+    /// the ones they override, which code written for the superclass would
+    /// call wrongly. Initializers are left out, since subclasses are made
+    /// differently as a matter of course.
+    fn check_overrides(&mut self, superclass: &str, methods: &[(Token, RangeInclusive<usize>)]) {
+        for (name, arity) in methods {
+            if name.lexeme() == "init" {
+                continue;
+            }
+            let Some((class, overridden)) = self.overridden(superclass, name.lexeme()) else {
+                continue;
+            };
+            if overridden != *arity {
+                let message = format!(
+                    "Method takes {} but overrides one in '{class}' that takes {}.",
+                    describe_arity(arity),
+                    describe_arity(&overridden)
+                );
+                self.warn(name, &message);
+            }
+        }
+    }
+
+    /// The class the method named `method` would be inherited from, starting
+    /// at `class`, and how many arguments it takes.
+    fn overridden(&self, class: &str, method: &str) -> Option<(String, RangeInclusive<usize>)> {
+        let mut class = class;
+        // Redeclaring classes can make the chain of names loop.
+        for _ in 0..self.classes.len() {
+            let shape = self.classes.get(class)?;
+            if let Some(arity) = shape.methods.get(method) {
+                return Some((class.to_string(), arity.clone()));
+            }
+            class = shape.superclass.as_deref()?;
+        }
+
+        None
     }
 
     fn begin_scope(&mut self) {
@@ -51,32 +259,169 @@ impl<'r> Resolver<'r> {
     }
 
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        if let Some(scope) = self.scopes.pop() {
+            let mut variables = scope.into_values().collect::<Vec<_>>();
+            variables.sort_by_key(|variable| variable.slot);
+
+            for variable in variables {
+                let Some(name) = variable.name else {
+                    continue;
+                };
+                if !variable.read {
+                    self.warn(&name, "Local variable is never read.");
+                } else if !variable.assigned {
+                    self.warn(&name, "Local variable is read but never assigned.");
+                }
+            }
+        }
     }
 
+    /// Variables get slots in the order they're declared, which is the order
+    /// the interpreter defines them in.
     fn declare(&mut self, name: &Token) {
-        if let Some(scope) = self.scopes.last_mut() {
-            if scope.contains_key(name.lexeme()) {
-                error_token(name, "Already a variable with this name in this scope.");
-                self.had_error = true;
+        let Some(scope) = self.scopes.last() else {
+            self.globals
+                .entry(name.lexeme().to_string())
+                .or_insert_with(|| name.clone());
+            self.constants.remove(name.lexeme());
+            self.mentions.push((name.clone(), None));
+            return;
+        };
+
+        let slot = scope.len();
+        if scope.contains_key(name.lexeme()) {
+            self.error(name, "Already a variable with this name in this scope.");
+        } else if self.interpreter.shadowing() != Shadowing::Allow && self.shadows(name) {
+            let message = "Shadows a variable from an enclosing scope.";
+            match self.interpreter.shadowing() {
+                Shadowing::Deny => self.error(name, message),
+                _ => self.warn(name, message),
             }
-            scope.insert(name.lexeme().to_string(), false);
+        }
+        let variable = Variable {
+            defined: false,
+            read: false,
+            assigned: true,
+            constant: false,
+            slot,
+            name: Some(name.clone()),
+            declaration: Some(name.clone()),
+        };
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme().to_string(), variable);
+        }
+        self.mentions.push((name.clone(), Some(name.clone())));
+    }
+
+    /// Whether a variable called `name` is declared in a scope enclosing the
+    /// innermost one, or as a global before it.
+    fn shadows(&self, name: &Token) -> bool {
+        let enclosing = &self.scopes[..self.scopes.len().saturating_sub(1)];
+
+        enclosing
+            .iter()
+            .any(|scope| scope.contains_key(name.lexeme()))
+            || self.globals.contains_key(name.lexeme())
+    }
+
+    /// Declares and defines a variable that's bound implicitly, like a
+    /// parameter, and so isn't reported if it's never read.
+    fn declare_binding(&mut self, name: &Token) {
+        self.declare(name);
+        self.define(name);
+        if let Some(variable) = self.innermost(name) {
+            variable.name = None;
         }
     }
 
     fn define(&mut self, name: &Token) {
+        if let Some(variable) = self.innermost(name) {
+            variable.defined = true;
+        }
+    }
+
+    /// The variable `name` in the innermost scope, if it's declared there.
+    fn innermost(&mut self, name: &Token) -> Option<&mut Variable> {
+        self.scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(name.lexeme()))
+    }
+
+    /// Declares and defines one of the implicit `this` and `super` variables,
+    /// which each get a scope of their own.
+    fn define_keyword(&mut self, keyword: &str) {
+        let variable = Variable {
+            defined: true,
+            read: true,
+            assigned: true,
+            constant: false,
+            slot: 0,
+            name: None,
+            declaration: None,
+        };
         if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme().to_string(), true);
+            scope.insert(keyword.to_string(), variable);
+        }
+    }
+
+    /// Records an assignment to `name`, reporting it if `name` is a constant.
+    ///
+    /// A global is only known to be constant outside functions, since the
+    /// statements before those have all run by the time they are. A function
+    /// might run after the global has been redeclared, or in another session,
+    /// so the interpreter checks those assignments instead.
+    fn assign(&mut self, name: &Token) {
+        let variable = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name.lexeme()));
+        let constant = match variable {
+            Some(variable) => {
+                variable.assigned = true;
+                variable.constant
+            }
+            None => {
+                matches!(self.current_function, FunKind::None)
+                    && self.constants.contains(name.lexeme())
+            }
+        };
+        if constant {
+            self.error(
+                name,
+                &format!("Can't assign to constant '{}'.", name.lexeme()),
+            );
         }
     }
 
     fn resolve_local(&mut self, expr: Expr, name: &Token) {
-        for (i, scope) in self.scopes.iter().enumerate().rev() {
-            if scope.contains_key(name.lexeme()) {
-                self.interpreter.resolve(expr, self.scopes.len() - 1 - i);
+        let scopes = self.scopes.len();
+        for (i, scope) in self.scopes.iter_mut().enumerate().rev() {
+            if let Some(variable) = scope.get_mut(name.lexeme()) {
+                if matches!(expr.kind, ExprKind::Variable(_)) {
+                    variable.read = true;
+                }
+                if let Some(declaration) = &variable.declaration {
+                    self.mentions
+                        .push((name.clone(), Some(declaration.clone())));
+                }
+                self.interpreter
+                    .resolve(expr, scopes - 1 - i, variable.slot);
                 return;
             }
         }
+
+        self.mentions.push((name.clone(), None));
+    }
+
+    /// Records the class that `expr` is in if it uses a private property
+    /// through `this`, since only that class's own can be used.
+    fn resolve_private(&mut self, expr: Expr, object: &Expr, name: &Token) {
+        if let (ExprKind::This(_), Some(class)) = (&object.kind, &self.class_name) {
+            if name.lexeme().starts_with('_') {
+                self.interpreter.resolve_private(expr, class.clone());
+            }
+        }
     }
 
     fn resolve_expr(&mut self, expr: Expr) {
@@ -84,8 +429,19 @@ impl<'r> Resolver<'r> {
         match expr.kind {
             ExprKind::Assign { name, value } => {
                 self.resolve_expr(*value);
+                self.assign(&name);
                 self.resolve_local(expr_clone, &name);
             }
+            ExprKind::AssignList { targets, value, .. } => {
+                self.resolve_expr(*value);
+                for target in targets {
+                    if let ExprKind::Variable(name) = &target.kind {
+                        let name = name.clone();
+                        self.assign(&name);
+                        self.resolve_local(target, &name);
+                    }
+                }
+            }
             ExprKind::Binary { left, right, .. } => {
                 self.resolve_expr(*left);
                 self.resolve_expr(*right);
@@ -98,38 +454,83 @@ impl<'r> Resolver<'r> {
                     self.resolve_expr(expr);
                 }
             }
-            ExprKind::Get { object, .. } => {
+            ExprKind::Get { object, name } => {
+                self.resolve_private(expr_clone, &object, &name);
                 self.resolve_expr(*object);
             }
             ExprKind::Grouping(expr) => {
                 self.resolve_expr(*expr);
             }
+            ExprKind::Index { object, index, .. } => {
+                self.resolve_expr(*object);
+                self.resolve_expr(*index);
+            }
+            ExprKind::IndexSet {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.resolve_expr(*object);
+                self.resolve_expr(*index);
+                self.resolve_expr(*value);
+            }
+            ExprKind::Lambda { params, body } => {
+                self.resolve_function(params, body, FunKind::Function);
+            }
+            ExprKind::List(elements) => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
             ExprKind::Literal(_) => {}
+            ExprKind::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
             ExprKind::Logical { left, right, .. } => {
                 self.resolve_expr(*left);
                 self.resolve_expr(*right);
             }
-            ExprKind::Set { object, value, .. } => {
+            ExprKind::Postfix { value, assignment } => {
+                self.resolve_expr(*value);
+                self.resolve_expr(*assignment);
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
+                ..
+            } => {
+                self.resolve_private(expr_clone, &object, &name);
                 self.resolve_expr(*value);
                 self.resolve_expr(*object);
             }
             ExprKind::Super { keyword, .. } => match self.current_class {
                 ClassKind::None => {
-                    error_token(&keyword, "Can't use 'super' outside of a class.");
-                    self.had_error = true;
+                    self.error(&keyword, "Can't use 'super' outside of a class.");
                 }
                 ClassKind::Class => {
-                    error_token(&keyword, "Can't use 'super' in a class with no superclass.");
-                    self.had_error = true;
+                    self.error(&keyword, "Can't use 'super' in a class with no superclass.");
                 }
                 ClassKind::Subclass => {
                     self.resolve_local(expr_clone, &keyword);
                 }
             },
+            ExprKind::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expr(*condition);
+                self.resolve_expr(*then_branch);
+                self.resolve_expr(*else_branch);
+            }
             ExprKind::This(keyword) => {
                 if matches!(self.current_class, ClassKind::None) {
-                    error_token(&keyword, "Can't use 'this' outside of a class.");
-                    self.had_error = true;
+                    self.error(&keyword, "Can't use 'this' outside of a class.");
                 }
 
                 self.resolve_local(expr_clone, &keyword);
@@ -139,9 +540,11 @@ impl<'r> Resolver<'r> {
             }
             ExprKind::Variable(name) => {
                 if let Some(scope) = self.scopes.last() {
-                    if matches!(scope.get(name.lexeme()), Some(false)) {
-                        error_token(&name, "Can't read local variable in its own initializer.");
-                        self.had_error = true;
+                    if matches!(
+                        scope.get(name.lexeme()),
+                        Some(Variable { defined: false, .. })
+                    ) {
+                        self.error(&name, "Can't read local variable in its own initializer.");
                     }
                 }
 
@@ -150,16 +553,25 @@ impl<'r> Resolver<'r> {
         }
     }
 
-    fn resolve_function(&mut self, params: Vec<Token>, body: Vec<Stmt>, kind: FunKind) {
+    fn resolve_function(&mut self, params: Vec<Param>, body: Vec<Stmt>, kind: FunKind) {
         let enclosing_function = self.current_function;
+        let enclosing_loop = self.current_loop;
+        let enclosing_switch = self.in_switch;
         self.current_function = kind;
+        self.current_loop = LoopKind::None;
+        self.in_switch = false;
         self.begin_scope();
         for param in params {
-            self.declare(&param);
-            self.define(&param);
+            // Defaults see the parameters before their own.
+            if let Some(default) = param.default {
+                self.resolve_expr(default);
+            }
+            self.declare_binding(&param.name);
         }
         self.resolve_statements(body);
         self.end_scope();
+        self.current_loop = enclosing_loop;
+        self.in_switch = enclosing_switch;
         self.current_function = enclosing_function;
     }
 
@@ -170,17 +582,34 @@ impl<'r> Resolver<'r> {
                 self.resolve_statements(statements);
                 self.end_scope();
             }
+            Stmt::Break(keyword) => {
+                if matches!(self.current_loop, LoopKind::None) && !self.in_switch {
+                    self.error(&keyword, "Can't use 'break' outside of a loop.");
+                }
+            }
             Stmt::Class {
                 name,
                 superclass,
                 methods,
+                class_methods,
+                getters,
             } => {
                 let enclosing_class = self.current_class;
                 self.current_class = ClassKind::Class;
+                let enclosing_name = self.class_name.replace(name.clone());
 
                 self.declare(&name);
                 self.define(&name);
 
+                let arities = methods
+                    .iter()
+                    .filter_map(|method| match method {
+                        Stmt::Function { name, params, .. } => Some((name.clone(), arity(params))),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                let mut global_superclass = None;
+
                 let has_superclass = superclass.is_some();
                 if let Some(superclass) = superclass {
                     self.current_class = ClassKind::Subclass;
@@ -190,25 +619,35 @@ impl<'r> Resolver<'r> {
                     } = superclass
                     {
                         if name.lexeme() == superclass_name.lexeme() {
-                            error_token(superclass_name, "A class can't inherit from itself.");
-                            self.had_error = true;
+                            self.error(superclass_name, "A class can't inherit from itself.");
+                        }
+                        let superclass_name = superclass_name.lexeme().to_string();
+                        if !self
+                            .scopes
+                            .iter()
+                            .any(|scope| scope.contains_key(&superclass_name))
+                        {
+                            self.check_overrides(&superclass_name, &arities);
+                            global_superclass = Some(superclass_name);
                         }
                         self.resolve_expr(superclass);
                     }
 
                     self.begin_scope();
-                    if let Some(scope) = self.scopes.last_mut() {
-                        scope.insert("super".to_string(), true);
-                    }
+                    self.define_keyword("super");
                 }
 
                 self.begin_scope();
-                if let Some(scope) = self.scopes.last_mut() {
-                    scope.insert("this".to_string(), true);
-                }
+                self.define_keyword("this");
 
                 for method in methods {
                     if let Stmt::Function { name, params, body } = method {
+                        if name.lexeme() == "equals" && !arity(&params).contains(&1) {
+                            self.warn(
+                                &name,
+                                "'==' only uses an 'equals' method that takes one argument.",
+                            );
+                        }
                         let declaration = if name.lexeme() == "init" {
                             FunKind::Initializer
                         } else {
@@ -220,6 +659,14 @@ impl<'r> Resolver<'r> {
                     }
                 }
 
+                for method in class_methods.into_iter().chain(getters) {
+                    if let Stmt::Function { params, body, .. } = method {
+                        self.resolve_function(params, body, FunKind::Method);
+                    } else {
+                        panic!("Cannot resolve '{method:?}' as Stmt::Function");
+                    }
+                }
+
                 self.end_scope();
 
                 if has_superclass {
@@ -227,6 +674,22 @@ impl<'r> Resolver<'r> {
                 }
 
                 self.current_class = enclosing_class;
+                self.class_name = enclosing_name;
+                if self.scopes.is_empty() {
+                    let shape = ClassShape {
+                        superclass: global_superclass,
+                        methods: arities
+                            .into_iter()
+                            .map(|(name, arity)| (name.lexeme().to_string(), arity))
+                            .collect(),
+                    };
+                    self.classes.insert(name.lexeme().to_string(), shape);
+                }
+            }
+            Stmt::Continue(keyword) => {
+                if matches!(self.current_loop, LoopKind::None) {
+                    self.error(&keyword, "Can't use 'continue' outside of a loop.");
+                }
             }
             Stmt::Expression(expr) => {
                 self.resolve_expr(expr);
@@ -247,40 +710,172 @@ impl<'r> Resolver<'r> {
                     self.resolve_stmt(*else_branch);
                 }
             }
-            Stmt::Print(expr) => {
+            Stmt::Assert {
+                condition, message, ..
+            } => {
+                self.resolve_expr(condition);
+                if let Some(message) = message {
+                    self.resolve_expr(message);
+                }
+            }
+            Stmt::Print { value: expr, .. } => {
                 self.resolve_expr(expr);
             }
             Stmt::Return { value, keyword } => {
                 if matches!(self.current_function, FunKind::None) {
-                    error_token(&keyword, "Can't return from top-level code.");
-                    self.had_error = true;
+                    self.error(&keyword, "Can't return from top-level code.");
                 }
 
                 if let Some(value) = value {
                     if matches!(self.current_function, FunKind::Initializer) {
-                        error_token(&keyword, "Can't return a value from an initializer.");
-                        self.had_error = true;
+                        self.error(&keyword, "Can't return a value from an initializer.");
                     }
 
                     self.resolve_expr(value);
                 }
             }
+            Stmt::Switch { subject, cases, .. } => {
+                self.resolve_expr(subject);
+
+                let mut has_default = false;
+                for case in cases {
+                    match case.value {
+                        Some(value) => self.resolve_expr(value),
+                        None if has_default => self.error(
+                            &case.keyword,
+                            "Can't have more than one default case in a switch.",
+                        ),
+                        None => has_default = true,
+                    }
+
+                    let enclosing_switch = std::mem::replace(&mut self.in_switch, true);
+                    self.begin_scope();
+                    self.resolve_statements(case.body);
+                    self.end_scope();
+                    self.in_switch = enclosing_switch;
+                }
+            }
+            Stmt::Throw { value, .. } => {
+                self.resolve_expr(value);
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.begin_scope();
+                self.resolve_statements(body);
+                self.end_scope();
+
+                if let Some((name, handler)) = catch {
+                    self.begin_scope();
+                    self.declare_binding(&name);
+                    self.resolve_statements(handler);
+                    self.end_scope();
+                }
+
+                if let Some(finally) = finally {
+                    self.begin_scope();
+                    self.resolve_statements(finally);
+                    self.end_scope();
+                }
+            }
+            Stmt::Import { name, .. } => {
+                self.declare(&name);
+                self.define(&name);
+            }
             Stmt::Var { name, initializer } => {
                 self.declare(&name);
+                let assigned = initializer.is_some();
                 if let Some(initializer) = initializer {
                     self.resolve_expr(initializer);
                 }
                 self.define(&name);
+                if let Some(variable) = self.innermost(&name) {
+                    variable.assigned = assigned;
+                }
+            }
+            Stmt::Const { name, initializer } => {
+                self.declare(&name);
+                self.resolve_expr(initializer);
+                self.define(&name);
+                if self.scopes.is_empty() {
+                    self.constants.insert(name.lexeme().to_string());
+                } else if let Some(variable) = self.innermost(&name) {
+                    variable.constant = true;
+                }
             }
-            Stmt::While { condition, body } => {
+            Stmt::VarList {
+                names, initializer, ..
+            } => {
+                for name in &names {
+                    self.declare(name);
+                }
+                self.resolve_expr(initializer);
+                for name in &names {
+                    self.define(name);
+                }
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+            } => {
+                self.resolve_expr(iterable);
+
+                let enclosing_loop = self.current_loop;
+                self.current_loop = LoopKind::Loop;
+
+                self.begin_scope();
+                self.declare_binding(&name);
+                self.resolve_stmt(*body);
+                self.end_scope();
+
+                self.current_loop = enclosing_loop;
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                let enclosing_loop = self.current_loop;
+                self.current_loop = LoopKind::Loop;
+
                 self.resolve_expr(condition);
                 self.resolve_stmt(*body);
+                if let Some(increment) = increment {
+                    self.resolve_expr(increment);
+                }
+
+                self.current_loop = enclosing_loop;
             }
         }
     }
 
     pub fn resolve_statements(&mut self, statements: Vec<Stmt>) {
+        // The keyword of the first statement that always leaves the block,
+        // and whether the code after it has been reported yet.
+        let mut exit: Option<(Token, bool)> = None;
         for stmt in statements {
+            // The warning points at the first unreachable statement, or at
+            // the exit if that statement keeps no token of its own.
+            if let Some((keyword, reported @ false)) = &mut exit {
+                let message = format!("Code after '{}' is unreachable.", keyword.lexeme());
+                self.warnings.push(Warning {
+                    token: stmt.first_token().unwrap_or(keyword).clone(),
+                    message,
+                });
+                *reported = true;
+            }
+
+            if let Stmt::Return { keyword, .. }
+            | Stmt::Throw { keyword, .. }
+            | Stmt::Break(keyword)
+            | Stmt::Continue(keyword) = &stmt
+            {
+                exit.get_or_insert_with(|| (keyword.clone(), false));
+            }
+
             self.resolve_stmt(stmt);
         }
     }
@@ -1,35 +1,64 @@
 use crate::{
-    error_line,
-    token::{Token, TokenType},
+    intern::intern,
+    token::{Comment, Token, TokenType},
     value::Value,
 };
 use itertools::{Itertools, MultiPeek};
 use once_cell::sync::Lazy;
-use std::{collections::HashMap, str::Chars};
+use std::{collections::HashMap, fmt, str::Chars};
 
 static KEYWORDS: Lazy<HashMap<&str, TokenType>> = Lazy::new(|| {
     let mut m = HashMap::new();
 
     m.insert("and", TokenType::And);
+    m.insert("assert", TokenType::Assert);
+    m.insert("break", TokenType::Break);
+    m.insert("case", TokenType::Case);
+    m.insert("catch", TokenType::Catch);
     m.insert("class", TokenType::Class);
+    m.insert("const", TokenType::Const);
+    m.insert("continue", TokenType::Continue);
+    m.insert("default", TokenType::Default);
     m.insert("else", TokenType::Else);
     m.insert("false", TokenType::False);
+    m.insert("finally", TokenType::Finally);
     m.insert("for", TokenType::For);
     m.insert("fun", TokenType::Fun);
     m.insert("if", TokenType::If);
+    m.insert("import", TokenType::Import);
+    m.insert("in", TokenType::In);
     m.insert("nil", TokenType::Nil);
     m.insert("or", TokenType::Or);
     m.insert("print", TokenType::Print);
     m.insert("return", TokenType::Return);
     m.insert("super", TokenType::Super);
+    m.insert("switch", TokenType::Switch);
     m.insert("this", TokenType::This);
+    m.insert("throw", TokenType::Throw);
     m.insert("true", TokenType::True);
+    m.insert("try", TokenType::Try);
     m.insert("var", TokenType::Var);
     m.insert("while", TokenType::While);
 
     m
 });
 
+/// Source the scanner couldn't make a token from, such as an unexpected
+/// character or an unterminated string.
+#[derive(Clone, Debug)]
+pub struct ScanError {
+    pub line: usize,
+    /// Where the token being scanned starts, in bytes.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
+
 pub struct Scanner<'a> {
     source: &'a str,
     chars: MultiPeek<Chars<'a>>,
@@ -37,6 +66,13 @@ pub struct Scanner<'a> {
     start: usize,
     current: usize,
     line: usize,
+    errors: Vec<ScanError>,
+    keep_comments: bool,
+    /// Comments waiting to be attached to the next token.
+    leading: Vec<Comment>,
+    /// The brace depth inside each `${` being scanned, so that the `}` that
+    /// closes it can be told from those inside the expression.
+    interpolations: Vec<usize>,
 }
 
 impl<'a> Scanner<'a> {
@@ -50,9 +86,32 @@ impl<'a> Scanner<'a> {
             start: 0,
             current: 0,
             line: 1,
+            errors: vec![],
+            keep_comments: false,
+            leading: vec![],
+            interpolations: vec![],
         }
     }
 
+    /// Keeps `//` comments as [`Trivia`](crate::token::Trivia) on the tokens
+    /// around them, for tools that need to reproduce the source. The parser
+    /// ignores them.
+    pub fn with_trivia(mut self) -> Self {
+        self.keep_comments = true;
+
+        self
+    }
+
+    pub fn had_error(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    /// The errors found so far, in the order they were found. They aren't
+    /// reported anywhere, so it's up to the caller to show them.
+    pub fn errors(&self) -> &[ScanError] {
+        &self.errors
+    }
+
     fn is_at_end(&mut self) -> bool {
         self.chars.peek().is_none()
     }
@@ -65,75 +124,247 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_match(&mut self, expected: char) -> bool {
-        if let Some(c) = self.chars.peek() {
-            if *c == expected {
-                self.advance();
+        let is_match = matches!(self.chars.peek(), Some(c) if *c == expected);
 
-                true
-            } else {
-                false
-            }
+        if is_match {
+            self.advance();
         } else {
-            false
+            // Leave the peek cursor where it was so the next check looks at
+            // the same character.
+            self.chars.reset_peek();
         }
+
+        is_match
     }
 
     fn add_token(&mut self, typ: TokenType, value: Option<Value>) {
         let lexeme = &self.source[self.start..self.current];
-        let token = Token::new(typ, lexeme, value, self.line);
+        let token = Token::new(typ, lexeme, value, self.line).with_offset(self.start);
+        self.push(token);
+    }
+
+    fn push(&mut self, mut token: Token) {
+        if !self.leading.is_empty() {
+            token.trivia_mut().leading = std::mem::take(&mut self.leading);
+        }
         self.tokens.push(token);
     }
 
-    fn string(&mut self) {
-        while let Some(c) = self.chars.peek() {
-            if *c == '"' {
+    /// Attaches a comment starting on `line` to the token before it if they
+    /// share a line, and to the token after it otherwise.
+    fn comment(&mut self, line: usize) {
+        let comment = Comment {
+            text: self.source[self.start..self.current].trim_end().into(),
+            line,
+            offset: self.start,
+        };
+
+        match self.tokens.last_mut() {
+            Some(token)
+                if self.leading.is_empty()
+                    && token.line() == comment.line
+                    && token
+                        .trivia()
+                        .is_none_or(|trivia| trivia.trailing.is_none()) =>
+            {
+                token.trivia_mut().trailing = Some(comment);
+            }
+            _ => self.leading.push(comment),
+        }
+    }
+
+    /// Skips a `/* ... */` comment, which may contain others.
+    fn block_comment(&mut self) {
+        let line = self.line;
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                self.error("Unterminated block comment.");
+                return;
+            }
+
+            match self.advance() {
+                '\n' => self.line += 1,
+                '/' if self.is_match('*') => depth += 1,
+                '*' if self.is_match('/') => depth -= 1,
+                _ => {}
+            }
+        }
+
+        if self.keep_comments {
+            self.comment(line);
+        }
+    }
+
+    /// Scans the rest of a string literal, or the part of one up to an
+    /// embedded `${`. `typ` is the type to give it if it's a whole string.
+    fn string(&mut self, typ: TokenType) {
+        let mut value = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c == '"' {
                 break;
-            } else if *c == '\n' {
-                self.line += 1;
+            }
+            if c == '$' && self.chars.peek() == Some(&'{') {
+                self.advance();
+                self.advance();
+                self.interpolations.push(0);
+                self.add_token(
+                    TokenType::Interpolation,
+                    Some(Value::String(intern(&value))),
+                );
+                return;
             }
             self.advance();
+
+            match c {
+                '\\' => value.extend(self.escape()),
+                '\n' => {
+                    self.line += 1;
+                    value.push(c);
+                }
+                c => value.push(c),
+            }
         }
+        self.chars.reset_peek();
 
         if self.is_at_end() {
-            error_line(self.line, "Unterminated string.");
+            self.error("Unterminated string.");
             return;
         }
 
         self.advance(); // The closing ".
 
-        // Trim the surrounding quotes.
-        let s = self.source[self.start + 1..self.current - 1].to_string();
-        self.add_token(TokenType::String, Some(Value::String(s)));
+        // Invalid escapes have been reported, but still produce a string so
+        // the parser doesn't report errors of its own.
+        self.add_token(typ, Some(Value::String(intern(&value))));
     }
 
-    fn number(&mut self) {
-        while let Some(c) = self.chars.peek() {
-            if !c.is_digit(10) {
-                self.chars.reset_peek();
+    /// Decodes the escape sequence after a backslash, reporting an error if
+    /// it isn't one.
+    fn escape(&mut self) -> Option<char> {
+        let c = *self.chars.peek()?;
+        self.advance();
+
+        match c {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            'u' => self.unicode_escape(),
+            c => {
+                if c == '\n' {
+                    self.line += 1;
+                }
+                self.error("Invalid escape sequence.");
+                None
+            }
+        }
+    }
+
+    /// Decodes the `{XXXX}` after `\u`: one to six hex digits naming a
+    /// Unicode scalar value.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let mut digits = String::new();
+        if self.is_match('{') {
+            while let Some(&c) = self.chars.peek() {
+                if !c.is_ascii_hexdigit() {
+                    break;
+                }
+                self.advance();
+                digits.push(c);
+            }
+            self.chars.reset_peek();
+        }
+
+        let c = Some(&digits)
+            .filter(|digits| (1..=6).contains(&digits.len()) && self.is_match('}'))
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .and_then(char::from_u32);
+        if c.is_none() {
+            self.error("Invalid unicode escape sequence.");
+        }
+
+        c
+    }
+
+    /// The next character, without moving the peek cursor.
+    fn peek(&mut self) -> Option<char> {
+        let c = self.chars.peek().copied();
+        self.chars.reset_peek();
+
+        c
+    }
+
+    /// The character after the next one, without moving the peek cursor.
+    fn peek_next(&mut self) -> Option<char> {
+        self.chars.peek();
+        let c = self.chars.peek().copied();
+        self.chars.reset_peek();
+
+        c
+    }
+
+    /// Consumes a run of digits in `radix`, which may be separated by single
+    /// underscores. `previous` is the digit before the run, if it has been
+    /// consumed already. Returns whether the run was well formed.
+    fn digits(&mut self, radix: u32, mut previous: Option<char>) -> bool {
+        let mut valid = true;
+        while let Some(c) = self.peek() {
+            if c == '_' {
+                valid &= previous.is_some_and(|previous| previous != '_');
+            } else if !c.is_digit(radix) {
                 break;
             }
+            previous = Some(c);
             self.advance();
         }
 
-        if let Some('.') = self.chars.peek() {
-            match self.chars.peek() {
-                Some(c) if c.is_digit(10) => {
-                    self.advance();
+        valid && previous.is_some_and(|previous| previous != '_')
+    }
 
-                    while let Some(c) = self.chars.peek() {
-                        if !c.is_digit(10) {
-                            break;
-                        }
-                        self.advance();
-                    }
-                }
-                _ => {}
+    fn number(&mut self) {
+        let first = self.source[self.start..].chars().next();
+        if first == Some('0') && matches!(self.peek(), Some('x' | 'X')) {
+            self.advance();
+            if !self.digits(16, None) {
+                self.error("Expect hex digits after '0x'.");
             }
+
+            let value = self.source[self.start + 2..self.current]
+                .chars()
+                .filter_map(|c| c.to_digit(16))
+                .fold(0.0, |n, digit| n * 16.0 + f64::from(digit));
+            self.add_token(TokenType::Number, Some(Value::Number(value)));
+            return;
         }
 
-        let lexeme = &self.source[self.start..self.current];
-        let value = lexeme.parse().expect("must have a valid double");
+        let mut valid = self.digits(10, first);
+
+        if self.peek() == Some('.') && self.peek_next().is_some_and(|c| c.is_ascii_digit()) {
+            self.advance();
+            valid &= self.digits(10, None);
+        }
+
+        if matches!(self.peek(), Some('e' | 'E')) {
+            self.advance();
+            if matches!(self.peek(), Some('+' | '-')) {
+                self.advance();
+            }
+            if !self.digits(10, None) {
+                self.error("Expect digits in exponent.");
+            }
+        }
+
+        if !valid {
+            self.error("Underscores in numbers must separate digits.");
+        }
 
+        // Malformed literals have been reported, but still produce a number so
+        // the parser doesn't report errors of its own.
+        let value = self.source[self.start..self.current]
+            .replace('_', "")
+            .parse()
+            .unwrap_or(0.0);
         self.add_token(TokenType::Number, Some(Value::Number(value)));
     }
 
@@ -157,14 +388,60 @@ impl<'a> Scanner<'a> {
         match c {
             '(' => self.add_token(TokenType::LeftParen, None),
             ')' => self.add_token(TokenType::RightParen, None),
-            '{' => self.add_token(TokenType::LeftBrace, None),
-            '}' => self.add_token(TokenType::RightBrace, None),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(TokenType::LeftBrace, None);
+            }
+            '}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string(TokenType::InterpolationEnd);
+                }
+                depth => {
+                    if let Some(depth) = depth {
+                        *depth -= 1;
+                    }
+                    self.add_token(TokenType::RightBrace, None);
+                }
+            },
+            '[' => self.add_token(TokenType::LeftBracket, None),
+            ']' => self.add_token(TokenType::RightBracket, None),
+            ':' => self.add_token(TokenType::Colon, None),
             ',' => self.add_token(TokenType::Comma, None),
             '.' => self.add_token(TokenType::Dot, None),
-            '-' => self.add_token(TokenType::Minus, None),
-            '+' => self.add_token(TokenType::Plus, None),
+            '-' => {
+                let typ = if self.is_match('-') {
+                    TokenType::MinusMinus
+                } else if self.is_match('=') {
+                    TokenType::MinusEqual
+                } else {
+                    TokenType::Minus
+                };
+                self.add_token(typ, None);
+            }
+            '+' => {
+                let typ = if self.is_match('+') {
+                    TokenType::PlusPlus
+                } else if self.is_match('=') {
+                    TokenType::PlusEqual
+                } else {
+                    TokenType::Plus
+                };
+                self.add_token(typ, None);
+            }
+            '%' => self.add_token(TokenType::Percent, None),
+            '?' => self.add_token(TokenType::Question, None),
             ';' => self.add_token(TokenType::Semicolon, None),
-            '*' => self.add_token(TokenType::Star, None),
+            '*' => {
+                let typ = if self.is_match('=') {
+                    TokenType::StarEqual
+                } else {
+                    TokenType::Star
+                };
+                self.add_token(typ, None);
+            }
             '!' => {
                 let typ = if self.is_match('=') {
                     TokenType::BangEqual
@@ -205,27 +482,42 @@ impl<'a> Scanner<'a> {
                         }
                         self.advance();
                     }
+                    if self.keep_comments {
+                        self.comment(self.line);
+                    }
+                } else if self.is_match('*') {
+                    self.block_comment();
+                } else if self.is_match('=') {
+                    self.add_token(TokenType::SlashEqual, None);
                 } else {
                     self.add_token(TokenType::Slash, None);
                 }
             }
             ' ' | '\r' | '\t' => {} // Ignore whitespace.
             '\n' => self.line += 1,
-            '"' => self.string(),
-            c if c.is_digit(10) => self.number(),
+            '"' => self.string(TokenType::String),
+            c if c.is_ascii_digit() => self.number(),
             c if c == '_' || c.is_alphabetic() => self.identifier(),
-            _ => error_line(self.line, "Unexpected character."),
+            _ => self.error("Unexpected character."),
         }
     }
 
-    pub fn scan(&'a mut self) -> &'a [Token] {
+    fn error(&mut self, message: &str) {
+        self.errors.push(ScanError {
+            line: self.line,
+            offset: self.start,
+            message: message.to_string(),
+        });
+    }
+
+    pub fn scan(&mut self) -> &[Token] {
         while !self.is_at_end() {
             self.start = self.current;
             self.scan_token();
         }
 
-        self.tokens
-            .push(Token::new(TokenType::Eof, "", None, self.line));
+        let eof = Token::new(TokenType::Eof, "", None, self.line).with_offset(self.current);
+        self.push(eof);
 
         &self.tokens
     }
@@ -0,0 +1,312 @@
+//! Math, string, collection, JSON and reflection natives that go beyond the
+//! core language.
+//!
+//! They are loaded into the globals of every [`Interpreter`] by default; use
+//! [`Interpreter::without_stdlib`] for a pure Lox environment.
+
+use crate::{
+    class::{LoxClass, LoxInstance},
+    interpreter::{Error, Interpreter},
+    iterator::Range,
+    json::Json,
+    map::LoxMap,
+    native::NativeFunction,
+    sync::{Lock, Shared},
+    value::Value,
+};
+
+fn number(value: &Value) -> Result<f64, Error> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        _ => Err(Error::native("Argument must be a number.")),
+    }
+}
+
+fn string(value: &Value) -> Result<&str, Error> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(Error::native("Argument must be a string.")),
+    }
+}
+
+fn list(value: &Value) -> Result<Shared<Lock<Vec<Value>>>, Error> {
+    match value {
+        Value::List(list) => Ok(list.clone()),
+        _ => Err(Error::native("Argument must be a list.")),
+    }
+}
+
+fn map(value: &Value) -> Result<Shared<Lock<LoxMap>>, Error> {
+    match value {
+        Value::Map(map) => Ok(map.clone()),
+        _ => Err(Error::native("Argument must be a map.")),
+    }
+}
+
+fn instance(value: &Value) -> Result<Shared<Lock<LoxInstance>>, Error> {
+    match value {
+        Value::Instance(instance) => Ok(instance.clone()),
+        _ => Err(Error::native("Argument must be an instance.")),
+    }
+}
+
+/// The name of a field the reflection natives may use. Private fields, whose
+/// names start with `_`, can only be used through `this` in the methods of
+/// their class, which natives aren't.
+fn field_name(value: &Value) -> Result<&str, Error> {
+    let name = string(value)?;
+    if name.starts_with('_') {
+        return Err(Error::native(format!(
+            "Private property '{name}' can only be accessed through 'this'."
+        )));
+    }
+
+    Ok(name)
+}
+
+fn class(value: &Value) -> Result<&LoxClass, Error> {
+    match value {
+        Value::Callable(callable) => callable.as_any().downcast_ref::<LoxClass>(),
+        _ => None,
+    }
+    .ok_or_else(|| Error::native("Argument must be a class."))
+}
+
+fn list_value(interpreter: &Interpreter, values: impl Iterator<Item = Value>) -> Value {
+    interpreter.list_value(values.collect())
+}
+
+fn index(value: &Value) -> Result<usize, Error> {
+    match value {
+        Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+        _ => Err(Error::native("Index must be a non-negative integer.")),
+    }
+}
+
+/// A `math` member applying `function` to its one argument.
+fn unary(name: &'static str, function: fn(f64) -> f64) -> (&'static str, Value) {
+    let native = NativeFunction::new(name, 1, move |_, arguments| {
+        Ok(Value::Number(function(number(&arguments[0])?)))
+    });
+
+    (name, native.value())
+}
+
+/// A `math` member applying `function` to its two arguments.
+fn binary(name: &'static str, function: fn(f64, f64) -> f64) -> (&'static str, Value) {
+    let native = NativeFunction::new(name, 2, move |_, arguments| {
+        let (a, b) = (number(&arguments[0])?, number(&arguments[1])?);
+        Ok(Value::Number(function(a, b)))
+    });
+
+    (name, native.value())
+}
+
+pub fn load(interpreter: &mut Interpreter) {
+    interpreter.define_native("abs", 1, |_, arguments| {
+        Ok(Value::Number(number(&arguments[0])?.abs()))
+    });
+    interpreter.define_native("floor", 1, |_, arguments| {
+        Ok(Value::Number(number(&arguments[0])?.floor()))
+    });
+    interpreter.define_native("ceil", 1, |_, arguments| {
+        Ok(Value::Number(number(&arguments[0])?.ceil()))
+    });
+    interpreter.define_native("sqrt", 1, |_, arguments| {
+        Ok(Value::Number(number(&arguments[0])?.sqrt()))
+    });
+    interpreter.define_native("min", 2, |_, arguments| {
+        let (a, b) = (number(&arguments[0])?, number(&arguments[1])?);
+        Ok(Value::Number(a.min(b)))
+    });
+    interpreter.define_native("max", 2, |_, arguments| {
+        let (a, b) = (number(&arguments[0])?, number(&arguments[1])?);
+        Ok(Value::Number(a.max(b)))
+    });
+    interpreter.define_native("random", 0, |interpreter, _| {
+        Ok(Value::Number(interpreter.random()))
+    });
+    // The rest of the numeric functions are read from a module, as in
+    // `math.sin(x)`, rather than crowding the globals.
+    let seed = NativeFunction::new("seed", 1, |interpreter, arguments| {
+        match number(&arguments[0])? {
+            n if n >= 0.0 && n.fract() == 0.0 => interpreter.set_seed(n as u64),
+            _ => return Err(Error::native("Seed must be a non-negative integer.")),
+        }
+        Ok(Value::Nil)
+    });
+    interpreter.define_module(
+        "math",
+        vec![
+            ("pi", Value::Number(std::f64::consts::PI)),
+            ("e", Value::Number(std::f64::consts::E)),
+            unary("sin", f64::sin),
+            unary("cos", f64::cos),
+            unary("tan", f64::tan),
+            unary("atan", f64::atan),
+            binary("atan2", f64::atan2),
+            unary("exp", f64::exp),
+            unary("log", f64::ln),
+            binary("pow", f64::powf),
+            ("seed", seed.value()),
+        ],
+    );
+
+    interpreter.define_native("len", 1, |_, arguments| match &arguments[0] {
+        Value::List(list) => Ok(Value::Number(list.borrow().len() as f64)),
+        Value::Map(map) => Ok(Value::Number(map.borrow().len() as f64)),
+        Value::String(s) => Ok(Value::Number(s.chars().count() as f64)),
+        _ => Err(Error::native("Argument must be a string, list or map.")),
+    });
+    // Indices count characters rather than bytes, and a substring that runs
+    // past the end of the string is cut short.
+    interpreter.define_native("substr", 3, |_, arguments| {
+        let s = string(&arguments[0])?;
+        let (start, length) = (index(&arguments[1])?, index(&arguments[2])?);
+        Ok(Value::String(
+            s.chars()
+                .skip(start)
+                .take(length)
+                .collect::<String>()
+                .into(),
+        ))
+    });
+    interpreter.define_native("push", 2, |_, arguments| {
+        list(&arguments[0])?.borrow_mut().push(arguments[1].clone());
+        Ok(Value::Nil)
+    });
+    interpreter.define_native("pop", 1, |_, arguments| {
+        list(&arguments[0])?
+            .borrow_mut()
+            .pop()
+            .ok_or_else(|| Error::native("Can't pop from an empty list."))
+    });
+
+    interpreter.define_native("jsonParse", 1, |interpreter, arguments| {
+        let json = Json::parse(string(&arguments[0])?).map_err(|e| Error::native(e.to_string()))?;
+        Ok(Value::from_json(&json, interpreter))
+    });
+    interpreter.define_native("jsonStringify", 1, |_, arguments| {
+        Ok(Value::String(arguments[0].to_json()?.to_string().into()))
+    });
+
+    interpreter.define_native("range", 3, |_, arguments| {
+        let (start, stop, step) = (
+            number(&arguments[0])?,
+            number(&arguments[1])?,
+            number(&arguments[2])?,
+        );
+        Range::new(start, stop, step)
+            .map(Range::value)
+            .ok_or_else(|| Error::native("Range step can't be zero."))
+    });
+
+    interpreter.define_native("keys", 1, |interpreter, arguments| {
+        Ok(list_value(
+            interpreter,
+            map(&arguments[0])?.borrow().keys().cloned(),
+        ))
+    });
+    interpreter.define_native("values", 1, |interpreter, arguments| {
+        Ok(list_value(
+            interpreter,
+            map(&arguments[0])?.borrow().values().cloned(),
+        ))
+    });
+    interpreter.define_native("has", 2, |_, arguments| {
+        let map = map(&arguments[0])?;
+        let has = map.borrow().contains_key(&arguments[1]);
+        Ok(Value::Boolean(has))
+    });
+
+    interpreter.define_native("upper", 1, |_, arguments| {
+        Ok(Value::String(string(&arguments[0])?.to_uppercase().into()))
+    });
+    interpreter.define_native("lower", 1, |_, arguments| {
+        Ok(Value::String(string(&arguments[0])?.to_lowercase().into()))
+    });
+
+    interpreter.define_native("hasField", 2, |_, arguments| {
+        let instance = instance(&arguments[0])?;
+        let has = instance
+            .borrow()
+            .field(field_name(&arguments[1])?)
+            .is_some();
+        Ok(Value::Boolean(has))
+    });
+    interpreter.define_native("getField", 2, |_, arguments| {
+        let name = field_name(&arguments[1])?;
+        let field = instance(&arguments[0])?.borrow().field(name);
+        field.ok_or_else(|| Error::native(format!("Undefined field '{name}'.")))
+    });
+    interpreter.define_native("setField", 3, |_, arguments| {
+        let name = field_name(&arguments[1])?;
+        instance(&arguments[0])?
+            .borrow_mut()
+            .set_field(name, arguments[2].clone());
+        Ok(arguments[2].clone())
+    });
+    interpreter.define_native("delField", 2, |_, arguments| {
+        let name = field_name(&arguments[1])?;
+        instance(&arguments[0])?.borrow_mut().remove_field(name);
+        Ok(Value::Nil)
+    });
+
+    // Whether an instance's class, or one it inherits from, has a method or
+    // getter with each of the names, for code that accepts anything that
+    // behaves the right way.
+    interpreter.define_variadic_native("implements", 2, |_, arguments| {
+        let names = arguments[1..]
+            .iter()
+            .map(string)
+            .collect::<Result<Vec<_>, _>>()?;
+        let Value::Instance(instance) = &arguments[0] else {
+            return Ok(Value::Boolean(false));
+        };
+        let instance = instance.borrow();
+        let class = instance.class();
+        let implements = names
+            .iter()
+            .all(|name| class.find_method(name).is_some() || class.find_getter(name).is_some());
+        Ok(Value::Boolean(implements))
+    });
+    interpreter.define_native("classOf", 1, |_, arguments| {
+        Ok(instance(&arguments[0])?.borrow().class().clone().value())
+    });
+    interpreter.define_native("superclassOf", 1, |_, arguments| {
+        let superclass = class(&arguments[0])?.superclass().cloned();
+        Ok(superclass.map_or(Value::Nil, LoxClass::value))
+    });
+    // The order methods are looked up in: the class, then each class it
+    // inherits from.
+    interpreter.define_native("mro", 1, |interpreter, arguments| {
+        let classes =
+            std::iter::successors(Some(class(&arguments[0])?), |class| class.superclass());
+        Ok(list_value(
+            interpreter,
+            classes.map(|class| class.clone().value()),
+        ))
+    });
+
+    interpreter.define_native("exit", 1, |_, arguments| match &arguments[0] {
+        Value::Number(n) if n.fract() == 0.0 && (0.0..=255.0).contains(n) => {
+            Err(Error::Exit { code: *n as i32 })
+        }
+        _ => Err(Error::native(
+            "Exit code must be an integer between 0 and 255.",
+        )),
+    });
+    interpreter.define_native("panic", 1, |interpreter, arguments| {
+        // The call site fills in where it happened.
+        Err(Error::Panic {
+            message: interpreter.stringify(&arguments[0])?,
+            line: 0,
+            trace: vec![],
+        })
+    });
+    // Cycles are collected on their own as the heap grows; this is for
+    // scripts that want to know how many there were.
+    interpreter.define_native("gc", 0, |interpreter, _| {
+        Ok(Value::Number(interpreter.collect_garbage() as f64))
+    });
+}
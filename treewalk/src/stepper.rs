@@ -0,0 +1,115 @@
+//! Runs scripts a slice at a time, for hosts with async runtimes.
+//!
+//! A tree-walking interpreter keeps its place in a script on the Rust stack,
+//! so it can't return partway through and pick up where it left off later.
+//! Instead, a [`Stepper`] runs the script on a thread of its own and takes
+//! turns with it: each [`Stepper::step`] lets the script run for a slice of
+//! statements and waits for it to pause again, so the host's thread is never
+//! blocked for longer than that. The script only runs while it's being
+//! stepped, and dropping the stepper stops it with [`Error::Interrupted`].
+//!
+//! The interpreter has to move to the script's thread, so this needs the
+//! `send` feature.
+
+use crate::interpreter::{Error, Interpreter, Pause, STACK_SIZE};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::mpsc::{self, Receiver, Sender},
+    task::{Context, Poll},
+    thread,
+};
+
+/// What the script's thread reports after each slice.
+enum Progress {
+    Paused,
+    Done(Box<Interpreter>, Result<(), Error>),
+}
+
+/// A script being run a slice at a time, made by
+/// [`Interpreter::run_async`].
+///
+/// As a [`Future`], it runs one slice per poll and asks to be polled again
+/// straight away, so that other tasks get a turn in between. It resolves to
+/// the interpreter, for running more scripts with the same globals, and how
+/// the script went.
+pub struct Stepper {
+    resume: Sender<()>,
+    progress: Receiver<Progress>,
+    finished: bool,
+}
+
+impl Stepper {
+    pub(crate) fn new(mut interpreter: Interpreter, source: &str, steps: u64) -> Self {
+        let (resume, resumed) = mpsc::channel();
+        let (report, progress) = mpsc::channel();
+        let source = source.to_string();
+
+        thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                // Nothing runs until the first step.
+                if resumed.recv().is_err() {
+                    return;
+                }
+
+                let paused = report.clone();
+                let pause: Pause = Box::new(move || {
+                    // Either channel hanging up means the stepper was dropped.
+                    paused
+                        .send(Progress::Paused)
+                        .map_err(|_| Error::Interrupted)?;
+                    resumed.recv().map_err(|_| Error::Interrupted)
+                });
+                interpreter.set_pause(Some((steps.max(1), pause)));
+                let result = interpreter.run(&source);
+                interpreter.set_pause(None);
+
+                let _ = report.send(Progress::Done(Box::new(interpreter), result));
+            })
+            .expect("script thread must spawn");
+
+        Self {
+            resume,
+            progress,
+            finished: false,
+        }
+    }
+
+    /// Runs the next slice of the script, returning the interpreter and how
+    /// the script went once it's finished.
+    ///
+    /// # Panics
+    ///
+    /// If the script has already finished.
+    pub fn step(&mut self) -> Poll<(Interpreter, Result<(), Error>)> {
+        assert!(!self.finished, "script has already finished");
+
+        let progress = self
+            .resume
+            .send(())
+            .ok()
+            .and_then(|()| self.progress.recv().ok())
+            .expect("script thread must not panic");
+        match progress {
+            Progress::Paused => Poll::Pending,
+            Progress::Done(interpreter, result) => {
+                self.finished = true;
+                Poll::Ready((*interpreter, result))
+            }
+        }
+    }
+}
+
+impl Future for Stepper {
+    type Output = (Interpreter, Result<(), Error>);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let progress = self.step();
+        if progress.is_pending() {
+            cx.waker().wake_by_ref();
+        }
+
+        progress
+    }
+}
@@ -0,0 +1,99 @@
+//! The pointers and cells that values share state through.
+//!
+//! By default these are [`Rc`](std::rc::Rc) and
+//! [`RefCell`](std::cell::RefCell), which are the cheapest, but keep an
+//! interpreter on the thread that made it. With the `send` feature they're
+//! [`Arc`](std::sync::Arc) and a lock instead, and everything an interpreter
+//! holds on to must be [`Threadsafe`], so that interpreters and their values
+//! can be sent to other threads. Running the benchmarks with and without the
+//! feature shows what that costs.
+//!
+//! Either way, a [`Lock`] is only ever meant to be used from one thread at a
+//! time. Borrowing one that's already mutably borrowed panics rather than
+//! waiting, just as it does for a `RefCell`.
+
+use std::io::Write;
+
+#[cfg(feature = "send")]
+pub use std::sync::{Arc as Shared, Weak};
+#[cfg(not(feature = "send"))]
+pub use std::{
+    cell::RefCell as Lock,
+    rc::{Rc as Shared, Weak},
+};
+
+/// What the interpreter needs of the callables, hooks and writers it holds:
+/// nothing by default, or to be `Send` and `Sync` with the `send` feature.
+#[cfg(not(feature = "send"))]
+pub trait Threadsafe {}
+
+#[cfg(not(feature = "send"))]
+impl<T: ?Sized> Threadsafe for T {}
+
+/// What the interpreter needs of the callables, hooks and writers it holds:
+/// nothing by default, or to be `Send` and `Sync` with the `send` feature.
+#[cfg(feature = "send")]
+pub trait Threadsafe: Send + Sync {}
+
+#[cfg(feature = "send")]
+impl<T: Send + Sync + ?Sized> Threadsafe for T {}
+
+pub use lox_bytecode::buffer::SharedBuffer;
+
+/// A writer the interpreter can send output to.
+pub trait Writer: Write + Threadsafe {}
+
+impl<T: Write + Threadsafe + ?Sized> Writer for T {}
+
+#[cfg(feature = "send")]
+pub use lock::Lock;
+
+#[cfg(feature = "send")]
+mod lock {
+    use std::{
+        fmt,
+        sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError, TryLockResult},
+    };
+
+    /// A [`RwLock`] with the interface of a `RefCell`.
+    #[derive(Default)]
+    pub struct Lock<T: ?Sized>(RwLock<T>);
+
+    impl<T> Lock<T> {
+        pub fn new(value: T) -> Self {
+            Self(RwLock::new(value))
+        }
+    }
+
+    impl<T: ?Sized> Lock<T> {
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            match self.0.try_read() {
+                Ok(guard) => guard,
+                Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+                Err(TryLockError::WouldBlock) => panic!("already mutably borrowed"),
+            }
+        }
+
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            match self.0.try_write() {
+                Ok(guard) => guard,
+                Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+                Err(TryLockError::WouldBlock) => panic!("already borrowed"),
+            }
+        }
+
+        pub fn try_borrow(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+            self.0.try_read()
+        }
+
+        pub fn try_borrow_mut(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+            self.0.try_write()
+        }
+    }
+
+    impl<T: fmt::Debug + ?Sized> fmt::Debug for Lock<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_tuple("Lock").field(&&*self.borrow()).finish()
+        }
+    }
+}
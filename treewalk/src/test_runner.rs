@@ -0,0 +1,156 @@
+//! Finds and runs the tests in Lox test files, for `lox test`.
+//!
+//! A test file is a script whose name ends in `_test.lox`, and each of its
+//! top-level functions whose name starts with `test_` is a test. A test
+//! passes if calling it doesn't fail, usually by way of `assert`. Every test
+//! gets an interpreter of its own, which runs the whole file before calling
+//! the test, so tests can share helpers but not state.
+//!
+//! Interpreters can't move between threads, so [`run_tests`] gives each of
+//! its threads a way to make them instead, and hands back the results in the
+//! order the tests were given whichever thread finishes first.
+
+use crate::{
+    ast::Stmt,
+    interpreter::{Interpreter, STACK_SIZE},
+    parser::Parser,
+    scanner::Scanner,
+    sync::SharedBuffer,
+};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A test found in a test file.
+#[derive(Clone, Debug)]
+pub struct Test {
+    pub path: PathBuf,
+    /// The contents of the file, shared by all of its tests.
+    pub source: Arc<str>,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    /// Why the test failed, or `None` if it passed.
+    pub failure: Option<String>,
+    /// What the test printed.
+    pub output: String,
+    pub duration: Duration,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Whether `path` is named like a test file.
+pub fn is_test_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with("_test.lox"))
+}
+
+/// The names of the tests in `source`, in the order they're declared. Fails
+/// with the syntax errors if it doesn't parse.
+pub fn tests(source: &str) -> Result<Vec<String>, Vec<String>> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan();
+    let (statements, errors) = Parser::new(tokens).parse();
+    let errors = scanner
+        .errors()
+        .iter()
+        .map(ToString::to_string)
+        .chain(errors.iter().map(ToString::to_string))
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(statements
+        .iter()
+        .filter_map(|statement| match statement {
+            Stmt::Function { name, .. } if name.lexeme().starts_with("test_") => {
+                Some(name.lexeme().to_string())
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+/// Runs the file at `path`, whose contents are `source`, in `interpreter`
+/// and then calls the test `name`. The interpreter's output is captured, and
+/// its errors are only reported as the test's failure.
+pub fn run_test(interpreter: Interpreter, path: &Path, source: &str, name: &str) -> TestResult {
+    let output = SharedBuffer::default();
+    let mut interpreter = interpreter.with_writers(output.clone(), io::sink());
+    interpreter.set_script_path(path);
+
+    let start = Instant::now();
+    let result = interpreter
+        .run(source)
+        .and_then(|()| interpreter.eval_expr(&format!("{name}()")).map(drop));
+    let duration = start.elapsed();
+
+    TestResult {
+        name: name.to_string(),
+        failure: result.err().map(|error| error.to_string()),
+        output: output.contents(),
+        duration,
+    }
+}
+
+/// Runs `tests` on up to `jobs` threads, each calling `interpreter` for a
+/// fresh interpreter per test, and passes each result to `report` in the
+/// order of `tests`.
+pub fn run_tests(
+    tests: &[Test],
+    jobs: usize,
+    interpreter: impl Fn() -> Interpreter + Sync,
+    mut report: impl FnMut(&Test, TestResult),
+) {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, tests.len().max(1)) {
+            let sender = sender.clone();
+            let (next, interpreter) = (&next, &interpreter);
+            thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn_scoped(scope, move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(test) = tests.get(index) else {
+                        break;
+                    };
+                    let result = run_test(interpreter(), &test.path, &test.source, &test.name);
+                    if sender.send((index, result)).is_err() {
+                        break;
+                    }
+                })
+                .expect("test thread must spawn");
+        }
+        drop(sender);
+
+        // Results that arrive early wait for the ones before them.
+        let mut pending = BTreeMap::new();
+        let mut reported = 0;
+        for (index, result) in receiver {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&reported) {
+                report(&tests[reported], result);
+                reported += 1;
+            }
+        }
+    });
+}
@@ -1,4 +1,5 @@
-use crate::value::Value;
+use crate::sync::Shared;
+use crate::{intern::intern, value::Value};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TokenType {
@@ -7,10 +8,15 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
+    Percent,
     Plus,
+    Question,
     Semicolon,
     Slash,
     Star,
@@ -24,51 +30,228 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    MinusEqual,
+    MinusMinus,
+    PlusEqual,
+    PlusPlus,
+    SlashEqual,
+    StarEqual,
 
     // Literals.
     Identifier,
     String,
     Number,
+    /// The text of an interpolated string up to an embedded `${`.
+    Interpolation,
+    /// The text of an interpolated string after its last embedded `}`.
+    InterpolationEnd,
 
     // Keywords.
     And,
+    Assert,
+    Break,
+    Case,
+    Catch,
     Class,
+    Const,
+    Continue,
+    Default,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Import,
+    In,
     Nil,
     Or,
     Print,
     Return,
     Super,
+    Switch,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
     Eof,
 }
 
+impl TokenType {
+    /// How tokens of this type are always spelled, or `None` for
+    /// identifiers, literals and the end of the source, which vary.
+    pub fn lexeme(&self) -> Option<&'static str> {
+        use TokenType::*;
+
+        let lexeme = match self {
+            LeftParen => "(",
+            RightParen => ")",
+            LeftBrace => "{",
+            RightBrace => "}",
+            LeftBracket => "[",
+            RightBracket => "]",
+            Colon => ":",
+            Comma => ",",
+            Dot => ".",
+            Minus => "-",
+            Percent => "%",
+            Plus => "+",
+            Question => "?",
+            Semicolon => ";",
+            Slash => "/",
+            Star => "*",
+            Bang => "!",
+            BangEqual => "!=",
+            Equal => "=",
+            EqualEqual => "==",
+            Greater => ">",
+            GreaterEqual => ">=",
+            Less => "<",
+            LessEqual => "<=",
+            MinusEqual => "-=",
+            MinusMinus => "--",
+            PlusEqual => "+=",
+            PlusPlus => "++",
+            SlashEqual => "/=",
+            StarEqual => "*=",
+            And => "and",
+            Assert => "assert",
+            Break => "break",
+            Case => "case",
+            Catch => "catch",
+            Class => "class",
+            Const => "const",
+            Continue => "continue",
+            Default => "default",
+            Else => "else",
+            False => "false",
+            Finally => "finally",
+            Fun => "fun",
+            For => "for",
+            If => "if",
+            Import => "import",
+            In => "in",
+            Nil => "nil",
+            Or => "or",
+            Print => "print",
+            Return => "return",
+            Super => "super",
+            Switch => "switch",
+            This => "this",
+            Throw => "throw",
+            True => "true",
+            Try => "try",
+            Var => "var",
+            While => "while",
+            Identifier | String | Number | Interpolation | InterpolationEnd | Eof => return None,
+        };
+
+        Some(lexeme)
+    }
+}
+
+/// A `//` or `/* */` comment, kept for tools that need to reproduce the
+/// source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub text: Shared<str>,
+    /// The line the comment starts on.
+    pub line: usize,
+    /// Where the comment starts in the source, in bytes.
+    pub offset: usize,
+}
+
+impl Comment {
+    pub fn is_block(&self) -> bool {
+        self.text.starts_with("/*")
+    }
+
+    /// The line the comment ends on, which is later than the one it starts on
+    /// for block comments spanning several.
+    pub fn last_line(&self) -> usize {
+        self.line + self.text.matches('\n').count()
+    }
+}
+
+/// The comments around a token: those on the lines before it, and one after
+/// it on the same line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trivia {
+    pub leading: Vec<Comment>,
+    pub trailing: Option<Comment>,
+}
+
+/// A range of the source, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl Span {
+    pub fn of(token: &Token) -> Self {
+        Self {
+            offset: token.offset(),
+            len: token.lexeme().len(),
+        }
+    }
+
+    /// Where the span ends, just past its last byte.
+    pub fn end(&self) -> usize {
+        self.offset + self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Token {
     typ: TokenType,
-    lexeme: String,
+    lexeme: Shared<str>,
     value: Option<Value>,
     line: usize,
+    /// Where the lexeme starts in the source, in bytes.
+    offset: usize,
+    trivia: Option<Shared<Trivia>>,
 }
 
 impl Token {
     pub fn new(typ: TokenType, lexeme: &str, value: Option<Value>, line: usize) -> Self {
         Self {
             typ,
-            lexeme: lexeme.to_string(),
+            lexeme: intern(lexeme),
             value,
             line,
+            offset: 0,
+            trivia: None,
         }
     }
 
+    /// A token of a type that's always spelled the same way, such as an
+    /// operator or keyword, for building syntax trees by hand. Other types
+    /// get an empty lexeme.
+    pub fn symbol(typ: TokenType, line: usize) -> Self {
+        Self::new(typ, typ.lexeme().unwrap_or_default(), None, line)
+    }
+
+    /// An identifier token, for building syntax trees by hand.
+    pub fn identifier(name: &str, line: usize) -> Self {
+        Self::new(TokenType::Identifier, name, None, line)
+    }
+
+    /// Places the token at `offset` bytes into its source, so that tools
+    /// can point at it.
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+
+        self
+    }
+
     pub fn typ(&self) -> &TokenType {
         &self.typ
     }
@@ -77,6 +260,11 @@ impl Token {
         &self.lexeme
     }
 
+    /// The interned lexeme, for use as a variable or field name.
+    pub fn name(&self) -> Shared<str> {
+        self.lexeme.clone()
+    }
+
     pub fn value(&self) -> &Option<Value> {
         &self.value
     }
@@ -84,4 +272,19 @@ impl Token {
     pub fn line(&self) -> usize {
         self.line
     }
+
+    /// Where the token starts in the source, in bytes. Tokens the parser
+    /// makes up start at 0.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The comments around this token, if the scanner was asked to keep them.
+    pub fn trivia(&self) -> Option<&Trivia> {
+        self.trivia.as_deref()
+    }
+
+    pub(crate) fn trivia_mut(&mut self) -> &mut Trivia {
+        Shared::make_mut(self.trivia.get_or_insert_with(Default::default))
+    }
 }
@@ -1,28 +1,73 @@
-use crate::{callable::Callable, class::LoxInstance};
-use std::{
-    cell::RefCell,
-    fmt::{self, Debug},
-    rc::Rc,
+use crate::{
+    callable::Callable,
+    class::LoxInstance,
+    iterator::Range,
+    map::LoxMap,
+    module::Module,
+    parser::MAX_NESTING,
+    sync::{Lock, Shared},
 };
+use std::fmt::{self, Debug};
 
 #[derive(Clone, Debug)]
 pub enum Value {
     Boolean(bool),
     Callable(Box<dyn Callable>),
-    Instance(Rc<RefCell<LoxInstance>>),
+    Instance(Shared<Lock<LoxInstance>>),
+    List(Shared<Lock<Vec<Value>>>),
+    Map(Shared<Lock<LoxMap>>),
+    Module(Shared<Module>),
     Nil,
     Number(f64),
-    String(String),
+    Range(Range),
+    /// Literals and identifiers are interned, while strings built at runtime
+    /// get their own allocation.
+    String(Shared<str>),
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &mut Visiting::default())
+    }
+}
+
+impl Value {
+    /// Writes this as [`fmt::Display`] does. A list or map inside itself, or
+    /// nested more than [`MAX_NESTING`] deep, is written as `[...]` or
+    /// `{...}`.
+    pub(crate) fn write(&self, f: &mut fmt::Formatter<'_>, visiting: &mut Visiting) -> fmt::Result {
         match self {
+            Self::List(_) | Self::Map(_) => {
+                let written = match visiting.depth() < MAX_NESTING {
+                    true => visiting.visit(self, |visiting| match self {
+                        Self::List(l) => {
+                            write!(f, "[")?;
+                            for (i, element) in l.borrow().iter().enumerate() {
+                                if i > 0 {
+                                    write!(f, ", ")?;
+                                }
+                                element.write(f, visiting)?;
+                            }
+                            write!(f, "]")
+                        }
+                        Self::Map(m) => m.borrow().write(f, visiting),
+                        _ => unreachable!("only lists and maps are visited here"),
+                    }),
+                    false => None,
+                };
+
+                written.unwrap_or_else(|| match self {
+                    Self::List(_) => write!(f, "[...]"),
+                    _ => write!(f, "{{...}}"),
+                })
+            }
             Self::Boolean(b) => write!(f, "{b}"),
             Self::Callable(c) => write!(f, "{c}"),
             Self::Instance(i) => write!(f, "{}", i.borrow()),
+            Self::Module(m) => write!(f, "{m}"),
             Self::Nil => write!(f, "nil"),
             Self::Number(n) => write!(f, "{n}"),
+            Self::Range(r) => write!(f, "{r}"),
             Self::String(s) => write!(f, "{s}"),
         }
     }
@@ -32,10 +77,62 @@ impl PartialEq for Value {
     fn eq(&self, other: &Value) -> bool {
         match (self, other) {
             (Value::Boolean(s), Value::Boolean(o)) => s == o,
+            (Value::Callable(s), Value::Callable(o)) => s.same_as(o.as_ref()),
+            (Value::Instance(s), Value::Instance(o)) => Shared::ptr_eq(s, o),
+            (Value::List(s), Value::List(o)) => Shared::ptr_eq(s, o),
+            (Value::Map(s), Value::Map(o)) => Shared::ptr_eq(s, o),
+            (Value::Module(s), Value::Module(o)) => Shared::ptr_eq(s, o),
             (Value::Nil, Value::Nil) => true,
             (Value::Number(s), Value::Number(o)) => s == o,
-            (Value::String(s), Value::String(o)) => s == o,
+            (Value::Range(s), Value::Range(o)) => s == o,
+            (Value::String(s), Value::String(o)) => Shared::ptr_eq(s, o) || s == o,
             _ => false,
         }
     }
 }
+
+impl Value {
+    /// Where the list, map or instance this refers to lives, which tells it
+    /// apart from others with the same contents. Other values have none.
+    pub(crate) fn address(&self) -> Option<usize> {
+        match self {
+            Self::Instance(instance) => Some(Shared::as_ptr(instance) as usize),
+            Self::List(list) => Some(Shared::as_ptr(list) as usize),
+            Self::Map(map) => Some(Shared::as_ptr(map) as usize),
+            _ => None,
+        }
+    }
+}
+
+/// The lists, maps and instances being visited around a value, so that
+/// visiting values that contain themselves can stop.
+#[derive(Default)]
+pub(crate) struct Visiting(Vec<usize>);
+
+impl Visiting {
+    /// Calls `visit` with `value` marked as being visited, or returns `None`
+    /// if it already is.
+    pub(crate) fn visit<T>(
+        &mut self,
+        value: &Value,
+        visit: impl FnOnce(&mut Self) -> T,
+    ) -> Option<T> {
+        let Some(address) = value.address() else {
+            return Some(visit(self));
+        };
+        if self.0.contains(&address) {
+            return None;
+        }
+
+        self.0.push(address);
+        let result = visit(self);
+        self.0.pop();
+
+        Some(result)
+    }
+
+    /// How many values are being visited inside each other.
+    pub(crate) fn depth(&self) -> usize {
+        self.0.len()
+    }
+}
@@ -0,0 +1,63 @@
+use lox_treewalk::{ast::Stmt, ast_printer, json, parser::Parser, scanner::Scanner};
+
+fn parse(source: &str) -> Vec<Stmt> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan();
+
+    let (statements, errors) = Parser::new(tokens).parse();
+    assert!(errors.is_empty(), "source must parse: {errors:?}");
+
+    statements
+}
+
+#[test]
+fn expressions_are_parenthesized() {
+    let statements = parse("print -123 * (45.67);\nvar s = \"hi\";\n");
+
+    assert_eq!(
+        ast_printer::to_string(&statements),
+        "(print (* (- 123) (group 45.67)))\n(var s \"hi\")\n"
+    );
+}
+
+#[test]
+fn declarations_are_parenthesized() {
+    let statements =
+        parse("fun add(a, b) { return a + b; }\nclass A < B { get() { return this.x; } }\n");
+
+    assert_eq!(
+        ast_printer::to_string(&statements),
+        "(fun add (params a b) (return (+ a b)))\n\
+         (class A (< B) (fun get (params) (return (. this x))))\n"
+    );
+}
+
+#[test]
+fn parameter_defaults_are_printed() {
+    let statements = parse("fun greet(name, greeting = \"Hello\") {}\n");
+
+    assert_eq!(
+        ast_printer::to_string(&statements),
+        "(fun greet (params name (= greeting \"Hello\")))\n"
+    );
+}
+
+#[test]
+fn trees_are_indented() {
+    let statements = parse("if (a) print b.c = 1;\n");
+
+    assert_eq!(
+        ast_printer::to_tree(&statements),
+        "if\n  a\n  print\n    =\n      .\n        b\n        c\n      1\n"
+    );
+}
+
+#[test]
+fn json_names_every_node_and_field() {
+    let statements = parse("print -\"a\nb\";\n");
+
+    assert_eq!(
+        json::statements(&statements).to_string(),
+        r#"[{"type":"Print","keyword":{"type":"Print","lexeme":"print","line":1},"expression":{"type":"Unary","operator":{"type":"Minus","lexeme":"-","line":1},"right":{"type":"Literal","value":"a\nb"}}}]"#
+    );
+}
@@ -0,0 +1,179 @@
+mod framing;
+
+use framing::framed_messages;
+use lox_treewalk::{dap, interpreter::Interpreter, json::Json, sync::SharedBuffer};
+use std::{env, fs, io::Cursor};
+
+/// Frames a Debug Adapter Protocol request.
+fn dap_request(seq: usize, command: &str, arguments: Json) -> String {
+    let body = Json::fields(vec![
+        ("seq", seq.into()),
+        ("type", "request".into()),
+        ("command", command.into()),
+        ("arguments", arguments),
+    ])
+    .to_string();
+
+    format!("Content-Length: {}\r\n\r\n{body}", body.len())
+}
+
+#[test]
+fn debug_adapters_stop_at_breakpoints_and_show_variables() {
+    let program = env::temp_dir().join(format!("dap-{}.lox", std::process::id()));
+    fs::write(
+        &program,
+        "fun add(a, b) {\n  var sum = a + b;\n  return sum;\n}\nprint add(1, 2);\n",
+    )
+    .expect("must be able to write the script");
+    let program = program.canonicalize().expect("script must exist");
+    let path = program.display().to_string();
+
+    let requests = [
+        dap_request(1, "initialize", Json::fields(vec![])),
+        dap_request(
+            2,
+            "launch",
+            Json::fields(vec![("program", path.as_str().into())]),
+        ),
+        dap_request(
+            3,
+            "setBreakpoints",
+            Json::fields(vec![
+                ("source", Json::fields(vec![("path", path.as_str().into())])),
+                (
+                    "breakpoints",
+                    vec![Json::fields(vec![("line", 2.0.into())])].into(),
+                ),
+            ]),
+        ),
+        dap_request(4, "configurationDone", Json::Null),
+        dap_request(
+            5,
+            "stackTrace",
+            Json::fields(vec![("threadId", 1.0.into())]),
+        ),
+        dap_request(6, "scopes", Json::fields(vec![("frameId", 2.0.into())])),
+        dap_request(
+            7,
+            "variables",
+            Json::fields(vec![("variablesReference", 1.0.into())]),
+        ),
+        dap_request(
+            8,
+            "evaluate",
+            Json::fields(vec![("expression", "b".into())]),
+        ),
+        dap_request(9, "continue", Json::fields(vec![("threadId", 1.0.into())])),
+    ]
+    .concat();
+
+    let output = SharedBuffer::default();
+    dap::serve(Cursor::new(requests), output.clone(), Interpreter::new);
+    fs::remove_file(&program).expect("must be able to remove the script");
+
+    let messages = framed_messages(&output.contents());
+    let kinds = messages
+        .iter()
+        .map(|message| {
+            let kind = message.get("command").or_else(|| message.get("event"));
+            kind.and_then(Json::as_str).unwrap_or("").to_string()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            "initialize",
+            "initialized",
+            "launch",
+            "setBreakpoints",
+            "configurationDone",
+            "stopped",
+            "stackTrace",
+            "scopes",
+            "variables",
+            "evaluate",
+            "continue",
+            "output",
+            "exited",
+            "terminated",
+        ]
+    );
+
+    let body = |kind: &str| {
+        let index = kinds
+            .iter()
+            .position(|k| k == kind)
+            .expect("message must be sent");
+        messages[index].get("body").cloned().unwrap_or(Json::Null)
+    };
+    assert_eq!(
+        body("stopped").get("reason").and_then(Json::as_str),
+        Some("breakpoint")
+    );
+    let frames = body("stackTrace");
+    let frames = frames.get("stackFrames").and_then(Json::as_array).unwrap();
+    let frames = frames
+        .iter()
+        .map(|frame| {
+            let name = frame
+                .get("name")
+                .and_then(Json::as_str)
+                .unwrap()
+                .to_string();
+            let line = frame.get("line").and_then(Json::as_f64).unwrap();
+            (name, line)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        frames,
+        [("<fn add>".to_string(), 2.0), ("script".to_string(), 5.0)]
+    );
+    let variables = body("variables");
+    let variables = variables.get("variables").and_then(Json::as_array).unwrap();
+    let variables = variables
+        .iter()
+        .map(|variable| {
+            let name = variable.get("name").and_then(Json::as_str).unwrap();
+            let value = variable.get("value").and_then(Json::as_str).unwrap();
+            format!("{name} = {value}")
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(variables, ["a = 1", "b = 2"]);
+    assert_eq!(
+        body("evaluate").get("result").and_then(Json::as_str),
+        Some("2")
+    );
+    assert_eq!(
+        body("output").get("output").and_then(Json::as_str),
+        Some("3\n")
+    );
+    assert_eq!(
+        body("exited").get("exitCode").and_then(Json::as_f64),
+        Some(0.0)
+    );
+}
+
+#[test]
+fn oversized_messages_end_the_session() {
+    let output = SharedBuffer::default();
+    dap::serve(
+        Cursor::new(format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX)),
+        output.clone(),
+        Interpreter::new,
+    );
+
+    assert_eq!(output.contents(), "");
+}
+
+#[test]
+fn deeply_nested_messages_end_the_session() {
+    let body = "[".repeat(100_000);
+    let output = SharedBuffer::default();
+    dap::serve(
+        Cursor::new(format!("Content-Length: {}\r\n\r\n{body}", body.len())),
+        output.clone(),
+        Interpreter::new,
+    );
+
+    assert_eq!(output.contents(), "");
+}
@@ -0,0 +1,788 @@
+use lox_treewalk::{
+    ast::{Expr, ExprKind, Stmt},
+    ast_printer,
+    clock::VirtualClock,
+    coverage::Coverage,
+    debugger::Debugger,
+    diagnostic::Diagnostic,
+    intern::intern,
+    interpreter::{Error, Interpreter},
+    parser::Parser,
+    profile::Profiler,
+    repr::LoxValueRepr,
+    resolver::Resolver,
+    scanner::Scanner,
+    sync::{Lock, Shared, SharedBuffer},
+    test_runner,
+    token::{Span, Token, TokenType},
+    value::Value,
+};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, Cursor},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+fn run(interpreter: &mut Interpreter, source: &str) {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan();
+    let (statements, errors) = Parser::new(tokens).parse();
+    assert!(errors.is_empty(), "source must parse: {errors:?}");
+
+    let mut resolver = Resolver::new(interpreter);
+    resolver.resolve_statements(statements.clone());
+    assert!(!resolver.had_error());
+
+    // Runtime errors are reported to the interpreter's error writer.
+    let _ = interpreter.interpret(statements);
+}
+
+#[test]
+fn native_functions_can_be_called_from_lox() {
+    let seen = Shared::new(Lock::new(vec![]));
+    let mut interpreter = Interpreter::new();
+
+    let sink = seen.clone();
+    interpreter.define_native("record", 1, move |_, arguments| {
+        sink.borrow_mut().push(arguments[0].to_string());
+        Ok(Value::Nil)
+    });
+    interpreter.define_native("double", 1, |_, arguments| match arguments[0] {
+        Value::Number(n) => Ok(Value::Number(n * 2.0)),
+        _ => Err(Error::Native {
+            message: "Argument must be a number.".to_string(),
+        }),
+    });
+
+    run(
+        &mut interpreter,
+        "record(double(21)); record(double); fun f() { record(\"local\"); } f();",
+    );
+
+    assert_eq!(*seen.borrow(), vec!["42", "<native fn>", "local"]);
+}
+
+#[test]
+fn native_functions_check_arity() {
+    let calls = Shared::new(Lock::new(0));
+    let mut interpreter = Interpreter::new();
+
+    let counter = calls.clone();
+    interpreter.define_native("count", 0, move |_, _| {
+        *counter.borrow_mut() += 1;
+        Ok(Value::Nil)
+    });
+
+    run(&mut interpreter, "count(); count(1);");
+
+    assert_eq!(*calls.borrow(), 1);
+}
+
+#[test]
+fn io_natives_are_opt_in() {
+    let seen = Shared::new(Lock::new(vec![]));
+    let mut interpreter = Interpreter::new();
+
+    let sink = seen.clone();
+    interpreter.define_native("record", 1, move |_, arguments| {
+        sink.borrow_mut().push(arguments[0].to_string());
+        Ok(Value::Nil)
+    });
+
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+    let source = format!("record(substr(readFile(\"{path}\"), 0, 9));");
+
+    run(&mut interpreter, &source);
+    assert!(seen.borrow().is_empty());
+
+    interpreter.enable_io();
+    run(&mut interpreter, &source);
+    assert_eq!(*seen.borrow(), vec!["[package]"]);
+}
+
+#[test]
+fn output_and_errors_go_to_their_writers() {
+    let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), err.clone());
+
+    run(&mut interpreter, "print \"hello\";\nprint nil + 1;\n");
+
+    assert_eq!(out.contents(), "hello\n");
+    assert_eq!(
+        err.contents(),
+        "Operands must be two numbers or two strings.\n[line 2]\n"
+    );
+}
+
+#[test]
+fn runtime_errors_can_show_the_failing_code() {
+    let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), err.clone());
+    interpreter.set_show_source(true);
+
+    let source = "fun half(n) {\n  return n / 2;\n}\nprint half(4) + half(\"six\");\n";
+    assert!(interpreter.run(source).is_err());
+
+    // The innermost code is underlined, where the error's line is.
+    assert_eq!(
+        err.contents(),
+        "Operands must be numbers.\n[line 2]\n[line 2] in <fn half>\n[line 4] in script\n  \
+         |\n2 |   return n / 2;\n  |          ^^^^^\n"
+    );
+}
+
+#[test]
+fn diagnostics_render_with_notes_help_and_color() {
+    let source = "var a = 1;\n\tprint a +\n  2;\n";
+    let diagnostic = Diagnostic::error("[line 2] Error: Something.")
+        .with_span(Span { offset: 18, len: 9 })
+        .with_note("A note.")
+        .with_help("Some help.");
+
+    // Spans running onto later lines are underlined to the end of the first.
+    assert_eq!(
+        diagnostic.render(Some(source), false),
+        "[line 2] Error: Something.\n  |\n2 | \tprint a +\n  | \t      ^^^\n  \
+         = note: A note.\n  = help: Some help."
+    );
+    assert_eq!(
+        diagnostic.render(None, false),
+        "[line 2] Error: Something.\n  = note: A note.\n  = help: Some help."
+    );
+    assert_eq!(
+        Diagnostic::warning("Careful.").render(None, true),
+        "\x1b[1;33mCareful.\x1b[0m"
+    );
+}
+
+#[test]
+fn output_can_be_captured_on_its_own() {
+    let out = SharedBuffer::default();
+    let mut interpreter = Interpreter::new().with_output(out.clone());
+
+    run(&mut interpreter, "print 1 + 2;\nprint \"done\";\n");
+
+    assert_eq!(out.contents(), "3\ndone\n");
+}
+
+#[test]
+fn strings_compare_only_with_extensions() {
+    let source =
+        "print \"apple\" < \"banana\";\nprint \"b\" <= \"a\";\nprint \"Zebra\" > \"apple\";\n";
+
+    let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), err.clone());
+    run(&mut interpreter, source);
+    assert_eq!(out.contents(), "");
+    assert_eq!(err.contents(), "Operands must be numbers.\n[line 1]\n");
+
+    let out = SharedBuffer::default();
+    let mut interpreter = Interpreter::new().with_output(out.clone());
+    interpreter.set_extensions(true);
+    run(&mut interpreter, source);
+    // Uppercase letters sort before lowercase ones.
+    assert_eq!(out.contents(), "true\nfalse\nfalse\n");
+}
+
+#[test]
+fn call_depth_is_limited() {
+    let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), err.clone());
+    interpreter.set_max_call_depth(8);
+
+    run(
+        &mut interpreter,
+        "fun depth(n) { if (n == 0) return 0; return depth(n - 1) + 1; }\n\
+         print depth(7);\n\
+         print depth(8);\n",
+    );
+
+    assert_eq!(out.contents(), "7\n");
+    assert!(err.contents().starts_with("Stack overflow.\n[line 1]\n"));
+}
+
+#[test]
+fn step_limits_stop_runaway_scripts() {
+    let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), err.clone());
+    interpreter.set_step_limit(1000);
+
+    run(
+        &mut interpreter,
+        "try { while (true) {} } catch (e) { print \"caught\"; } finally { print \"finally\"; }",
+    );
+    assert_eq!(out.contents(), "");
+    assert_eq!(err.contents(), "Execution budget exceeded.\n");
+
+    // Each run gets a fresh budget.
+    run(&mut interpreter, "print \"again\";");
+    assert_eq!(out.contents(), "again\n");
+}
+
+#[test]
+fn timeouts_stop_runaway_scripts() {
+    let err = SharedBuffer::default();
+    let mut interpreter = Interpreter::new().with_writers(io::sink(), err.clone());
+    interpreter.set_timeout(Duration::from_millis(10));
+
+    run(&mut interpreter, "while (true) {}");
+
+    assert_eq!(err.contents(), "Execution budget exceeded.\n");
+}
+
+#[test]
+fn hosts_can_interrupt_scripts() {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let err = SharedBuffer::default();
+    let mut interpreter = Interpreter::new()
+        .with_writers(io::sink(), err.clone())
+        .with_cancel_flag(cancel.clone());
+
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        cancel.store(true, Ordering::Relaxed);
+    });
+    run(&mut interpreter, "while (true) {}");
+    canceller.join().expect("canceller must not panic");
+
+    assert_eq!(err.contents(), "Interrupted.\n");
+}
+
+#[test]
+fn modules_are_found_on_module_paths() {
+    let out = SharedBuffer::default();
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), io::sink());
+    interpreter.add_module_path(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../resources/test/import/modules"
+    ));
+
+    run(&mut interpreter, "import shapes;\nprint shapes.hello;\n");
+
+    assert_eq!(out.contents(), "loading greeter\nhello shapes\n");
+}
+
+#[test]
+fn imports_stay_inside_the_sandbox() {
+    let err = SharedBuffer::default();
+    let mut interpreter = Interpreter::new().with_writers(io::sink(), err.clone());
+
+    // Without I/O, a script with no path has nowhere to import from.
+    run(&mut interpreter, "import Cargo;\n");
+    assert_eq!(err.contents(), "Can't find module 'Cargo.lox'.\n[line 1]\n");
+
+    let err = SharedBuffer::default();
+    let mut interpreter = Interpreter::new().with_writers(io::sink(), err.clone());
+    interpreter.set_show_source(true);
+    interpreter.add_module_path(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../resources/test/import/modules"
+    ));
+
+    run(&mut interpreter, "import not_lox;\n");
+    assert!(err.contents().contains("Error at ':'"));
+    assert!(!err.contents().contains("hunter2"));
+}
+
+#[test]
+fn interned_strings_are_dropped_once_nothing_uses_them() {
+    let used = intern("used");
+    let unused = Shared::downgrade(&intern("unused"));
+    for i in 0..10_000 {
+        intern(&format!("string {i}"));
+    }
+
+    assert!(unused.upgrade().is_none());
+    assert!(Shared::ptr_eq(&used, &intern("used")));
+}
+
+#[test]
+fn exits_are_left_to_the_host() {
+    let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), err.clone());
+
+    let mut scanner = Scanner::new("print \"bye\";\nexit(3);\nprint \"unreachable\";\n");
+    let tokens = scanner.scan();
+    let (statements, _) = Parser::new(tokens).parse();
+    Resolver::new(&mut interpreter).resolve_statements(statements.clone());
+
+    assert!(matches!(
+        interpreter.interpret(statements),
+        Err(Error::Exit { code: 3 })
+    ));
+    assert_eq!(out.contents(), "bye\n");
+    assert_eq!(err.contents(), "");
+}
+
+#[test]
+fn expressions_can_be_evaluated_on_their_own() {
+    let mut interpreter = Interpreter::new().with_writers(io::sink(), io::sink());
+    run(
+        &mut interpreter,
+        "var rate = 0.5;\nfun double(n) { return n * 2; }\n",
+    );
+
+    assert_eq!(
+        interpreter.eval_expr("double(10) * rate").ok(),
+        Some(Value::Number(10.0))
+    );
+    assert_eq!(
+        interpreter.eval_expr("\"a\" + \"b\"").ok(),
+        Some(Value::String("ab".into()))
+    );
+
+    let error = interpreter.eval_expr("1 +").expect_err("must not parse");
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Error at end: Expect expression."
+    );
+    let error = interpreter.eval_expr("1; 2").expect_err("must not parse");
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Error at ';': Expect end of expression."
+    );
+    let error = interpreter.eval_expr("@1").expect_err("must not scan");
+    assert_eq!(error.to_string(), "[line 1] Error: Unexpected character.");
+    assert!(matches!(
+        interpreter.eval_expr("missing"),
+        Err(Error::Runtime { .. })
+    ));
+}
+
+#[test]
+fn debuggers_stop_at_breakpoints_and_show_variables() {
+    let commands = "b 2\nc\nlocals\nbt\nfinish\np x\nc\n";
+    let shown = SharedBuffer::default();
+    let out = SharedBuffer::default();
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), io::sink());
+    interpreter.set_hook(Debugger::new(Cursor::new(commands), shown.clone()));
+    run(
+        &mut interpreter,
+        "fun add(a, b) {\n  var sum = a + b;\n  return sum;\n}\nvar x = add(1, 2);\nprint x;\n",
+    );
+
+    assert_eq!(
+        shown.contents(),
+        "line 1\n\
+         (debug) Breakpoint at line 2.\n\
+         (debug) line 2\n\
+         (debug) a = 1\nb = 2\n\
+         (debug) in <fn add>\nin script\n\
+         (debug) line 6\n\
+         (debug) x = 3\n\
+         (debug) "
+    );
+    assert_eq!(out.contents(), "3\n");
+}
+
+#[test]
+fn coverage_reports_lines_that_never_ran() {
+    let script = env::temp_dir().join(format!("coverage-{}.lox", std::process::id()));
+    let source = "fun check(x) {\n  if (x) {\n    print \"yes\";\n  } else {\n    print \"no\";\n  }\n}\ncheck(true);\ncheck(true);\n";
+    fs::write(&script, source).expect("must be able to write the script");
+
+    let coverage = Coverage::new();
+    let mut interpreter = Interpreter::new().with_writers(io::sink(), io::sink());
+    interpreter.set_script_path(&script);
+    interpreter.set_hook(coverage.clone());
+    run(&mut interpreter, source);
+
+    let mut report = vec![];
+    coverage
+        .write_lcov(&mut report)
+        .expect("must be able to write the report");
+    fs::remove_file(&script).expect("must be able to remove the script");
+
+    assert_eq!(
+        String::from_utf8(report).expect("report must be utf-8"),
+        format!(
+            "TN:\nSF:{}\nDA:1,1\nDA:2,2\nDA:3,2\nDA:5,0\nDA:8,1\nDA:9,1\nLF:6\nLH:5\nend_of_record\n",
+            script.display()
+        )
+    );
+}
+
+#[test]
+fn profilers_count_calls_and_statements() {
+    let profiler = Profiler::new();
+    let mut interpreter = Interpreter::new().with_writers(io::sink(), io::sink());
+    interpreter.set_hook(profiler.clone());
+    run(
+        &mut interpreter,
+        "fun fib(n) {\n  if (n < 2) return n;\n  return fib(n - 1) + fib(n - 2);\n}\nfib(5);\n",
+    );
+
+    let functions = profiler.functions();
+    assert_eq!(functions.len(), 1);
+    let (name, fib) = &functions[0];
+    assert_eq!(name, "<fn fib>");
+    assert_eq!(fib.calls, 15);
+    assert!(fib.exclusive <= fib.inclusive);
+
+    let lines = profiler
+        .hot_lines()
+        .into_iter()
+        .map(|(_, line, count)| (line, count))
+        .collect::<Vec<_>>();
+    // Line 2 runs both the `if` and, for the 8 base cases, its `return`.
+    assert_eq!(lines, [(2, 23), (3, 7), (1, 1), (5, 1)]);
+}
+
+#[test]
+fn seeded_runs_are_reproducible() {
+    let source = "print random();\nprint random();\nvar start = clock();\nprint clock() - start;\n";
+    let run_seeded = |seed| {
+        let out = SharedBuffer::default();
+        let mut interpreter = Interpreter::new().with_writers(out.clone(), io::sink());
+        interpreter.set_seed(seed);
+        interpreter.set_clock(VirtualClock::ticking(0.5));
+        run(&mut interpreter, source);
+
+        out.contents()
+    };
+
+    let first = run_seeded(7);
+    assert_eq!(first, run_seeded(7));
+    assert_ne!(first, run_seeded(8));
+    assert!(first.ends_with("\n0.5\n"), "{first}");
+}
+
+#[test]
+fn hosts_can_move_virtual_clocks() {
+    let out = SharedBuffer::default();
+    let clock = VirtualClock::new();
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), io::sink());
+    interpreter.set_clock(clock.clone());
+
+    run(&mut interpreter, "print clock();\n");
+    clock.advance(2.5);
+    run(&mut interpreter, "print clock();\n");
+
+    assert_eq!(out.contents(), "0\n2.5\n");
+}
+
+#[test]
+fn malformed_syntax_trees_are_errors() {
+    let err = SharedBuffer::default();
+    let mut interpreter = Interpreter::new().with_writers(io::sink(), err.clone());
+    let plus = Token::new(TokenType::Plus, "+", None, 1);
+    let statement = Stmt::print(Expr::new(ExprKind::Unary {
+        operator: plus,
+        right: Box::new(Expr::new(ExprKind::Literal(Value::Number(1.0)))),
+    }));
+
+    assert!(matches!(
+        interpreter.interpret(vec![statement]),
+        Err(Error::Internal { .. })
+    ));
+    assert_eq!(
+        err.contents(),
+        "Internal error: Plus is not a valid unary operator.\n"
+    );
+}
+
+#[test]
+fn built_syntax_trees_are_resolved_and_run() {
+    let out = SharedBuffer::default();
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), io::sink());
+    let square = Stmt::function(
+        "square",
+        &["n"],
+        vec![Stmt::return_value(
+            Some(Expr::binary(
+                Expr::variable("n", 1),
+                TokenType::Star,
+                Expr::variable("n", 1),
+            )),
+            1,
+        )],
+        1,
+    );
+    let call = Stmt::print(Expr::call(
+        Expr::variable("square", 2),
+        vec![Expr::literal(Value::Number(3.0), 2)],
+    ));
+
+    interpreter
+        .run_statements(vec![square, call])
+        .expect("built tree must run");
+    assert_eq!(out.contents(), "9\n");
+}
+
+#[test]
+fn optimizing_folds_constants_and_drops_dead_branches() {
+    let mut interpreter = Interpreter::new().with_writers(io::sink(), io::sink());
+    interpreter.set_opt_level(1);
+    let statements = interpreter
+        .compile(
+            "var x = 1;\n\
+             print 1 + 2 * -3 < 0;\n\
+             if (!!x) print \"a\" + \"b\"; else print x;\n\
+             if (false) print x;\n\
+             while (nil or false) print x;\n\
+             print x + (1 + 1);\n\
+             print 1 / \"a\";\n",
+        )
+        .expect("script must compile");
+
+    assert_eq!(
+        ast_printer::to_string(&statements),
+        "(var x 1)\n\
+         (print true)\n\
+         (if x (print \"ab\") (print x))\n\
+         (block)\n\
+         (block)\n\
+         (print (+ x 2))\n\
+         (print (/ 1 \"a\"))\n"
+    );
+}
+
+#[test]
+fn test_files_run_each_test_in_a_fresh_interpreter() {
+    let path = std::path::Path::new("counter_test.lox");
+    let source = "var count = 0;\nfun bump() { count = count + 1; return count; }\nfun test_first() { assert(bump() == 1); }\nfun test_second() { print \"bumping\"; assert(bump() == 2, \"not shared\"); }\nfun helper() {}\n";
+
+    assert!(test_runner::is_test_file(path));
+    let names = test_runner::tests(source).expect("source must parse");
+    assert_eq!(names, ["test_first", "test_second"]);
+
+    let results = names
+        .iter()
+        .map(|name| test_runner::run_test(Interpreter::new(), path, source, name))
+        .collect::<Vec<_>>();
+    assert!(results[0].passed());
+    assert_eq!(results[0].output, "");
+    assert_eq!(
+        results[1].failure.as_deref(),
+        Some("Assertion failed: bump() == 2 (not shared).\n[line 4]")
+    );
+    assert_eq!(results[1].output, "bumping\n");
+}
+
+#[test]
+fn tests_run_in_parallel_report_in_order() {
+    let source: std::sync::Arc<str> = (0..20)
+        .map(|n| format!("fun test_{n}() {{ assert({n} % 7 != 3); }}\n"))
+        .collect::<String>()
+        .into();
+    let tests = test_runner::tests(&source)
+        .expect("source must parse")
+        .into_iter()
+        .map(|name| test_runner::Test {
+            path: "parallel_test.lox".into(),
+            source: source.clone(),
+            name,
+        })
+        .collect::<Vec<_>>();
+
+    let mut reported = vec![];
+    test_runner::run_tests(&tests, 4, Interpreter::new, |test, result| {
+        reported.push((test.name.clone(), result.passed()))
+    });
+
+    let expected = (0..20)
+        .map(|n| (format!("test_{n}"), n % 7 != 3))
+        .collect::<Vec<_>>();
+    assert_eq!(reported, expected);
+}
+
+#[test]
+fn cycles_are_collected_as_the_heap_grows() {
+    let mut interpreter = Interpreter::new();
+    let before = interpreter.live_objects();
+    run(
+        &mut interpreter,
+        "for (var i = 0; i < 10000; i = i + 1) { var list = []; push(list, list); }\n",
+    );
+
+    let live = interpreter.live_objects();
+    assert!(live <= 2048, "{live} objects survived");
+    assert!(interpreter.collect_garbage() > 0);
+    assert_eq!(interpreter.live_objects(), before);
+}
+
+#[test]
+fn dropping_an_interpreter_frees_what_scripts_made() {
+    let mut interpreter = Interpreter::new();
+    run(
+        &mut interpreter,
+        "var list = [];\npush(list, list);\nfun get() { return list; }\n",
+    );
+    let list = match interpreter.eval_expr("get()") {
+        Ok(Value::List(list)) => Shared::downgrade(&list),
+        other => panic!("get must return the list, not {other:?}"),
+    };
+
+    drop(interpreter);
+    assert!(list.upgrade().is_none());
+}
+
+#[cfg(feature = "send")]
+#[test]
+fn interpreters_can_move_between_threads() {
+    let out = SharedBuffer::default();
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), io::sink());
+    run(
+        &mut interpreter,
+        "class Counter { init() { this.count = 0; } bump() { this.count = this.count + 1; return this.count; } }\nvar counter = Counter();\nprint counter.bump();\n",
+    );
+
+    let mut interpreter = thread::spawn(move || {
+        run(&mut interpreter, "print counter.bump();");
+        interpreter
+    })
+    .join()
+    .expect("interpreter must not panic");
+    run(&mut interpreter, "print counter.bump();");
+
+    assert_eq!(out.contents(), "1\n2\n3\n");
+}
+
+#[test]
+fn globals_can_be_copied_out_as_data() {
+    let mut interpreter = Interpreter::new();
+    run(
+        &mut interpreter,
+        "class Point { init(x) { this.x = x; } }
+var point = Point(1);
+var list = [true, nil, \"a\"];
+push(list, list);
+var counts = {1: 2};
+fun add(a, b) { return a + b; }
+var empty;
+",
+    );
+
+    let globals = interpreter.globals_snapshot();
+    assert_eq!(globals.len(), 6);
+    assert_eq!(
+        globals["point"],
+        LoxValueRepr::Instance {
+            class: "Point".to_string(),
+            fields: BTreeMap::from([("x".to_string(), LoxValueRepr::Number(1.0))]),
+        }
+    );
+    assert_eq!(
+        globals["list"],
+        LoxValueRepr::List(vec![
+            LoxValueRepr::Boolean(true),
+            LoxValueRepr::Nil,
+            LoxValueRepr::String("a".to_string()),
+            LoxValueRepr::Cycle,
+        ])
+    );
+    assert_eq!(
+        globals["counts"],
+        LoxValueRepr::Map(vec![(LoxValueRepr::Number(1.0), LoxValueRepr::Number(2.0))])
+    );
+    assert_eq!(
+        globals["Point"],
+        LoxValueRepr::Class {
+            name: "Point".to_string()
+        }
+    );
+    assert_eq!(
+        globals["add"],
+        LoxValueRepr::Function {
+            name: Some("add".to_string()),
+            min_arity: 2,
+            max_arity: 2,
+        }
+    );
+    assert_eq!(globals["empty"], LoxValueRepr::Nil);
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn values_convert_to_and_from_serde_json() {
+    let interpreter = Interpreter::new();
+    let document = serde_json::json!({"a": [1.5, true, null], "b": "c"});
+
+    let value = Value::from_json(&lox_treewalk::json::Json::from(&document), &interpreter);
+    assert_eq!(value.to_string(), "{a: [1.5, true, nil], b: c}");
+    let json = value.to_json().expect("value must encode");
+    assert_eq!(serde_json::Value::from(&json), document);
+}
+
+#[test]
+fn globals_can_be_set_from_data() {
+    let out = SharedBuffer::default();
+    let mut interpreter = Interpreter::new().with_writers(out.clone(), io::sink());
+    interpreter
+        .set_global(
+            "config",
+            &LoxValueRepr::Map(vec![(
+                LoxValueRepr::String("sizes".to_string()),
+                LoxValueRepr::List(vec![LoxValueRepr::Number(1.0), LoxValueRepr::Number(2.0)]),
+            )]),
+        )
+        .unwrap();
+    run(&mut interpreter, "print len(config[\"sizes\"]);");
+    assert_eq!(out.contents(), "2\n");
+
+    let class = LoxValueRepr::Class {
+        name: "Point".to_string(),
+    };
+    assert!(matches!(
+        interpreter.set_global("Point", &class),
+        Err(Error::Native { .. })
+    ));
+}
+
+#[cfg(feature = "send")]
+#[test]
+fn async_runs_take_turns_with_the_host() {
+    use std::task::Poll;
+
+    let out = SharedBuffer::default();
+    let interpreter = Interpreter::new().with_writers(out.clone(), io::sink());
+    let mut stepper = interpreter.run_async(
+        "var total = 0;\nfor (var i = 0; i < 100; i = i + 1) total = total + i;\nprint total;\n",
+        10,
+    );
+
+    let mut slices = 1;
+    let (mut interpreter, result) = loop {
+        match stepper.step() {
+            Poll::Ready(finished) => break finished,
+            Poll::Pending => slices += 1,
+        }
+    };
+    assert!(result.is_ok());
+    assert!(slices > 10, "ran in {slices} slices");
+
+    // The interpreter comes back with the script's globals.
+    run(&mut interpreter, "print total + 1;");
+    assert_eq!(out.contents(), "4950\n4951\n");
+}
+
+#[cfg(feature = "send")]
+#[test]
+fn async_runs_can_be_awaited() {
+    use std::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    let out = SharedBuffer::default();
+    let interpreter = Interpreter::new().with_writers(out.clone(), io::sink());
+    let mut run = pin!(interpreter.run_async("for (var i = 0; i < 3; i = i + 1) print i;\n", 1));
+
+    // The simplest executor there is: poll until it's ready.
+    let mut cx = Context::from_waker(Waker::noop());
+    let result = loop {
+        if let Poll::Ready((_, result)) = run.as_mut().poll(&mut cx) {
+            break result;
+        }
+    };
+
+    assert!(result.is_ok());
+    assert_eq!(out.contents(), "0\n1\n2\n");
+}
@@ -0,0 +1,115 @@
+use lox_treewalk::{ast_printer, formatter, parser::Parser, scanner::Scanner};
+use std::{fs, path::Path};
+
+fn tree(source: &str) -> String {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan();
+    let (statements, errors) = Parser::new(tokens).parse();
+    assert!(errors.is_empty(), "source must parse: {errors:?}");
+
+    ast_printer::to_string(&statements)
+}
+
+fn comments(source: &str) -> Vec<String> {
+    Scanner::new(source)
+        .with_trivia()
+        .scan()
+        .iter()
+        .filter_map(|token| token.trivia())
+        .flat_map(|trivia| trivia.leading.iter().chain(&trivia.trailing))
+        .map(|comment| comment.text.to_string())
+        .collect()
+}
+
+fn lox_files(dir: &Path, files: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).expect("directory must be readable") {
+        let path = entry.expect("entry must be readable").path();
+        if path.is_dir() {
+            lox_files(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+}
+
+#[test]
+fn layout_is_canonical() {
+    let source = "fun  add(a,b){return a+b;}\nvar x=-1;if(x<0)print \"neg\";else{x+=1;}\n";
+
+    assert_eq!(
+        formatter::format(source).expect("source must format"),
+        "fun add(a, b) {\n  return a + b;\n}\nvar x = -1;\nif (x < 0) print \"neg\";\nelse {\n  x += 1;\n}\n"
+    );
+}
+
+#[test]
+fn comments_before_separators_stay_with_the_value() {
+    let source = "f(1 /* c */ , 3);\nf(1 // one\n  , 2);\nf(a /* a */ );\nprint 1 // c\n;\n";
+
+    assert_eq!(
+        formatter::format(source).expect("source must format"),
+        "f(1 /* c */, 3);\nf(1, // one\n  2);\nf(a /* a */);\nprint 1; // c\n"
+    );
+}
+
+#[test]
+fn comments_and_sugar_are_kept() {
+    let source =
+        "// Counts.\n\n\nfor (var i = 0; i < 3; i++) { // Loop.\n  print i;\n\n  // Done.\n}\n";
+
+    assert_eq!(
+        formatter::format(source).expect("source must format"),
+        "// Counts.\n\nfor (var i = 0; i < 3; i++) { // Loop.\n  print i;\n\n  // Done.\n}\n"
+    );
+}
+
+#[test]
+fn formatting_preserves_meaning_and_is_stable() {
+    let mut files = vec![];
+    lox_files(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("../resources/test"),
+        &mut files,
+    );
+
+    for file in files {
+        let source = fs::read_to_string(&file).expect("file must be readable");
+        let Ok(formatted) = formatter::format(&source) else {
+            continue;
+        };
+
+        assert_eq!(tree(&formatted), tree(&source), "{file} changed meaning");
+        assert_eq!(
+            comments(&formatted),
+            comments(&source),
+            "{file} lost comments"
+        );
+        assert_eq!(
+            formatter::format(&formatted).expect("formatted source must format"),
+            formatted,
+            "{file} isn't stable"
+        );
+    }
+}
+
+#[test]
+fn comments_are_attached_as_trivia() {
+    let mut scanner = Scanner::new("// a\nprint 1; // b\n// c\n").with_trivia();
+    let tokens = scanner.scan();
+    let text = |comments: &[lox_treewalk::token::Comment]| {
+        comments
+            .iter()
+            .map(|comment| comment.text.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let print = tokens[0].trivia().expect("print must have trivia");
+    assert_eq!(text(&print.leading), ["// a"]);
+    let semicolon = tokens[2].trivia().expect("; must have trivia");
+    assert_eq!(text(semicolon.trailing.as_slice()), ["// b"]);
+    let eof = tokens[3].trivia().expect("Eof must have trivia");
+    assert_eq!(text(&eof.leading), ["// c"]);
+
+    let (statements, errors) = Parser::new(tokens).parse();
+    assert!(errors.is_empty());
+    assert_eq!(statements.len(), 1);
+}
@@ -0,0 +1,18 @@
+//! Helpers shared by the tests of the protocols that frame their messages
+//! with a `Content-Length` header.
+
+use lox_treewalk::json::Json;
+
+/// Splits messages framed by a `Content-Length` header apart.
+pub fn framed_messages(mut output: &str) -> Vec<Json> {
+    let mut messages = vec![];
+    while let Some(rest) = output.strip_prefix("Content-Length: ") {
+        let (length, rest) = rest.split_once("\r\n\r\n").expect("header must end");
+        let length = length.parse::<usize>().expect("length must be a number");
+        let (body, rest) = rest.split_at(length);
+        messages.push(Json::parse(body).expect("body must be JSON"));
+        output = rest;
+    }
+
+    messages
+}
@@ -0,0 +1,217 @@
+//! Feeds arbitrary and randomly generated programs to both front ends and
+//! interpreters, checking that bad input is reported rather than crashing.
+//!
+//! Each case is generated from its own seed, which is printed when it fails
+//! so that it can be replayed. `LOX_FUZZ_CASES` sets how many cases each
+//! test runs.
+
+use lox_bytecode::vm::Vm;
+use lox_treewalk::{
+    interpreter::{Interpreter, STACK_SIZE},
+    parser::{Parser, MAX_NESTING},
+    scanner::Scanner,
+    value::Value,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{env, io, panic, thread};
+
+const DEFAULT_CASES: u64 = 300;
+
+/// Lox's tokens and the pieces of them, so that random text runs into the
+/// scanner's and parser's edge cases more often than arbitrary bytes do.
+const FRAGMENTS: &[&str] = &[
+    "(", ")", "{", "}", "[", "]", ",", ".", "-", "+", ";", "/", "*", "%", "!", "!=", "=", "==",
+    ">", ">=", "<", "<=", "?", ":", "=>", "++", "--", "+=", "\"", "\"${", "}\"", "/*", "*/", "//",
+    "\n", " ", "0", "1.5", "0x", "1e", "1_0", ".5", "and", "class", "else", "false", "for", "fun",
+    "if", "nil", "or", "print", "return", "super", "this", "true", "var", "while", "const",
+    "break", "continue", "try", "catch", "throw", "import", "in", "switch", "case", "default",
+    "assert", "a", "b", "é", "\u{0}", "\t",
+];
+
+fn cases() -> u64 {
+    env::var("LOX_FUZZ_CASES")
+        .ok()
+        .and_then(|cases| cases.parse().ok())
+        .unwrap_or(DEFAULT_CASES)
+}
+
+/// Runs `source` through everything that accepts it, with limits that keep
+/// runaway programs short.
+fn run(source: &str) {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan();
+    let _ = Parser::new(tokens).parse();
+
+    let mut interpreter = Interpreter::new().with_writers(io::sink(), io::sink());
+    interpreter.set_step_limit(10_000);
+    interpreter.set_max_call_depth(32);
+    let _ = interpreter.run(source);
+
+    let mut vm = Vm::new().with_writers(io::sink(), io::sink());
+    vm.set_instruction_budget(10_000);
+    let _ = vm.interpret(source);
+}
+
+/// Runs the program `generate` makes from each seed, reporting the first
+/// one that panics.
+fn fuzz(generate: impl Fn(&mut StdRng) -> String) {
+    for seed in 0..cases() {
+        let source = generate(&mut StdRng::seed_from_u64(seed));
+        if panic::catch_unwind(|| run(&source)).is_err() {
+            panic!("seed {seed} panicked on {source:?}");
+        }
+    }
+}
+
+#[test]
+fn arbitrary_bytes_are_rejected_without_panicking() {
+    fuzz(|rng| {
+        let len = rng.gen_range(0..64);
+        let bytes = (0..len).map(|_| rng.gen()).collect::<Vec<u8>>();
+
+        String::from_utf8_lossy(&bytes).into_owned()
+    });
+}
+
+#[test]
+fn token_soup_is_rejected_without_panicking() {
+    fuzz(|rng| {
+        let len = rng.gen_range(0..48);
+
+        (0..len)
+            .map(|_| *FRAGMENTS.choose(rng).expect("fragments must not be empty"))
+            .collect()
+    });
+}
+
+// Nesting as deep as the parsers allow needs more stack than a test thread
+// has, at least in debug builds, so these run on a thread with the stack
+// `lox` gives scripts.
+#[test]
+fn deep_nesting_is_rejected_without_overflowing_the_stack() {
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(deep_nesting)
+        .expect("thread must spawn")
+        .join()
+        .expect("deep nesting must not panic");
+}
+
+fn deep_nesting() {
+    for source in [
+        format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000)),
+        format!("{}1", "-".repeat(100_000)),
+        "{".repeat(100_000),
+        "if (true) ".repeat(100_000),
+        "fun f() {".repeat(100_000),
+    ] {
+        run(&source);
+    }
+
+    let mut interpreter = Interpreter::new().with_writers(io::sink(), io::sink());
+    let nested = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+    assert_eq!(
+        interpreter.eval_expr(&nested).ok(),
+        Some(Value::Number(1.0))
+    );
+    let mut vm = Vm::new().with_writers(io::sink(), io::sink());
+    assert!(vm.interpret(&nested).is_ok());
+
+    let error = interpreter
+        .eval_expr(&format!("{}true", "!".repeat(MAX_NESTING)))
+        .expect_err("must be nested too deeply");
+    assert_eq!(
+        error.to_string(),
+        "[line 1] Error at 'true': Too much nesting."
+    );
+}
+
+#[test]
+fn generated_programs_run_without_panicking() {
+    fuzz(|rng| {
+        let mut source = String::new();
+        for _ in 0..rng.gen_range(1..6) {
+            source.push_str(&statement(rng, 3));
+            source.push('\n');
+        }
+
+        source
+    });
+}
+
+/// A statement that parses, though it may well fail when it runs.
+fn statement(rng: &mut StdRng, depth: usize) -> String {
+    let block = |rng: &mut StdRng| {
+        let body = (0..rng.gen_range(0..3))
+            .map(|_| statement(rng, depth - 1))
+            .collect::<Vec<_>>();
+        format!("{{ {} }}", body.join(" "))
+    };
+
+    match rng.gen_range(0..if depth == 0 { 4 } else { 12 }) {
+        0 => format!("print {};", expression(rng, depth)),
+        1 => format!("var {} = {};", name(rng), expression(rng, depth)),
+        2 => format!("{};", expression(rng, depth)),
+        3 => format!("{} = {};", name(rng), expression(rng, depth)),
+        4 => format!("if ({}) {}", expression(rng, depth), block(rng)),
+        5 => format!("while ({}) {}", expression(rng, depth), block(rng)),
+        6 => format!(
+            "for (var {} = 0; {}; {}) {}",
+            name(rng),
+            expression(rng, depth),
+            expression(rng, depth),
+            block(rng)
+        ),
+        7 => format!("fun {}({}) {}", name(rng), name(rng), block(rng)),
+        8 => format!("return {};", expression(rng, depth)),
+        9 => format!("class {} {{ {}() {} }}", name(rng), name(rng), block(rng)),
+        10 => format!("try {} catch ({}) {}", block(rng), name(rng), block(rng)),
+        _ => block(rng),
+    }
+}
+
+/// An expression that parses on its own.
+fn expression(rng: &mut StdRng, depth: usize) -> String {
+    if depth == 0 {
+        return match rng.gen_range(0..6) {
+            0 => rng.gen_range(-3..1000).to_string(),
+            1 => format!("{:?}", rng.gen::<f64>() * 10.0),
+            2 => format!("\"{}\"", name(rng)),
+            3 => ["nil", "true", "false", "this"]
+                .choose(rng)
+                .expect("literals must not be empty")
+                .to_string(),
+            _ => name(rng),
+        };
+    }
+
+    let operand = |rng: &mut StdRng| expression(rng, depth - 1);
+    match rng.gen_range(0..10) {
+        0 => {
+            let operator = ["-", "!"].choose(rng).expect("operators must not be empty");
+            format!("{operator}{}", operand(rng))
+        }
+        1..=3 => {
+            let operator = [
+                "+", "-", "*", "/", "%", "==", "!=", "<", "<=", ">", ">=", "and", "or",
+            ]
+            .choose(rng)
+            .expect("operators must not be empty");
+            format!("({} {operator} {})", operand(rng), operand(rng))
+        }
+        4 => format!("{} ? {} : {}", operand(rng), operand(rng), operand(rng)),
+        5 => format!("{}({})", name(rng), operand(rng)),
+        6 => format!("{}.{}", operand(rng), name(rng)),
+        7 => format!("[{}, {}]", operand(rng), operand(rng)),
+        8 => format!("{}[{}]", operand(rng), operand(rng)),
+        _ => format!("\"${{{}}}\"", operand(rng)),
+    }
+}
+
+/// One of a few names, so that programs reuse the variables they declare.
+fn name(rng: &mut StdRng) -> String {
+    ["a", "b", "f", "len", "str", "clock", "init"]
+        .choose(rng)
+        .expect("names must not be empty")
+        .to_string()
+}
@@ -0,0 +1,75 @@
+use lox_treewalk::highlight::{semantic_tokens, TokenCategory};
+
+/// Each token's text and category.
+fn classify(source: &str) -> Vec<(&str, TokenCategory)> {
+    semantic_tokens(source)
+        .into_iter()
+        .map(|(span, category)| (&source[span.offset..span.offset + span.len], category))
+        .collect()
+}
+
+#[test]
+fn identifiers_are_classified_by_their_declarations() {
+    use TokenCategory::*;
+
+    let source = "class A {\n  get(key) { return this.items[key]; }\n}\nconst limit = 2;\nfun twice(f) { return f(limit); } // call it\n";
+
+    assert_eq!(
+        classify(source),
+        [
+            ("class", Keyword),
+            ("A", Class),
+            ("{", Punctuation),
+            ("get", Method),
+            ("(", Punctuation),
+            ("key", Parameter),
+            (")", Punctuation),
+            ("{", Punctuation),
+            ("return", Keyword),
+            ("this", Keyword),
+            (".", Punctuation),
+            ("items", Property),
+            ("[", Punctuation),
+            ("key", Parameter),
+            ("]", Punctuation),
+            (";", Punctuation),
+            ("}", Punctuation),
+            ("}", Punctuation),
+            ("const", Keyword),
+            ("limit", Constant),
+            ("=", Operator),
+            ("2", Number),
+            (";", Punctuation),
+            ("fun", Keyword),
+            ("twice", Function),
+            ("(", Punctuation),
+            ("f", Parameter),
+            (")", Punctuation),
+            ("{", Punctuation),
+            ("return", Keyword),
+            ("f", Parameter),
+            ("(", Punctuation),
+            ("limit", Constant),
+            (")", Punctuation),
+            (";", Punctuation),
+            ("}", Punctuation),
+            ("// call it", Comment),
+        ]
+    );
+}
+
+#[test]
+fn unparsable_source_is_still_classified() {
+    use TokenCategory::*;
+
+    assert_eq!(
+        classify("/* note */ print \"hi\" +;"),
+        [
+            ("/* note */", Comment),
+            ("print", Keyword),
+            ("\"hi\"", String),
+            ("+", Operator),
+            (";", Punctuation),
+        ]
+    );
+}
@@ -1,5 +1,18 @@
+use lox_treewalk::{
+    interpreter::{Interpreter, STACK_SIZE},
+    json::Json,
+    optimizer::MAX_OPT_LEVEL,
+    resolver::Shadowing,
+    sync::SharedBuffer,
+};
 use simple_test_case::dir_cases;
-use std::{env, process::Command};
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    process::{Command, Stdio},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
 
 macro_rules! regex {
     ($re:literal $(,)?) => {{
@@ -8,12 +21,22 @@ macro_rules! regex {
     }};
 }
 
-fn extract_expected_data(line_num: usize, line: &str) -> Option<String> {
+/// A line a test expects the script to print, or to report as an error.
+enum Expected {
+    Stdout(String),
+    Stderr(String),
+}
+
+fn extract_expected_data(line_num: usize, line: &str) -> Option<Expected> {
     if let Some(cap) = regex!(r"// expect: ?(.*)").captures_iter(line).next() {
         let capture = &cap[1];
-        return Some(capture.to_string());
+        return Some(Expected::Stdout(capture.to_string()));
     }
 
+    extract_expected_error(line_num, line).map(Expected::Stderr)
+}
+
+fn extract_expected_error(line_num: usize, line: &str) -> Option<String> {
     if let Some(cap) = regex!(r"// (Error.*)").captures_iter(line).next() {
         let capture = &cap[1];
         return Some(format!("[line {line_num}] {capture}"));
@@ -56,68 +79,464 @@ fn extract_expected_data(line_num: usize, line: &str) -> Option<String> {
     None
 }
 
-fn run_test(bin_path: &str, source_file: &str, source: &str) -> anyhow::Result<()> {
-    println!("{bin_path:?}");
-    let mut expected = String::new();
+/// What a script printed, and the code `lox` would have exited with.
+struct Output {
+    stdout: String,
+    stderr: String,
+    code: i32,
+}
+
+/// Runs the script at `path`, relative to the workspace root, the way
+/// `lox <path>` would but without leaving the test process.
+fn run_script(path: &str, configure: impl FnOnce(&mut Interpreter) + Send + 'static) -> Output {
+    spawn_script(path, configure)
+        .join()
+        .expect("interpreter must not panic")
+}
+
+/// Starts running the script at `path` on a thread of its own, with an
+/// interpreter made there, since interpreters can't move between threads.
+fn spawn_script(
+    path: &str,
+    configure: impl FnOnce(&mut Interpreter) + Send + 'static,
+) -> JoinHandle<Output> {
+    let path = format!("{}/../{path}", env!("CARGO_MANIFEST_DIR"));
+
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let source = fs::read_to_string(&path).expect("script must be readable");
+            let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+            let mut interpreter = Interpreter::new().with_writers(out.clone(), err.clone());
+            interpreter.set_script_path(&path);
+            interpreter.set_args([]);
+            configure(&mut interpreter);
+
+            let code = match interpreter.run(&source) {
+                Ok(()) => 0,
+                Err(error) => error.exit_code(),
+            };
+
+            Output {
+                stdout: out.contents(),
+                stderr: err.contents(),
+                code,
+            }
+        })
+        .expect("thread must spawn")
+}
+
+/// Runs the script at every opt level at once, checking each against the
+/// expectations in its comments in turn.
+fn run_test(source_file: &str, source: &str) {
+    let (mut stdout, mut stderr) = (String::new(), String::new());
     for (line_idx, line) in source.lines().enumerate() {
         let line_num = line_idx + 1;
-        if let Some(line) = extract_expected_data(line_num, line) {
-            expected.push_str(&format!("{line}\n"));
+        match extract_expected_data(line_num, line) {
+            Some(Expected::Stdout(line)) => stdout.push_str(&format!("{line}\n")),
+            Some(Expected::Stderr(line)) => stderr.push_str(&format!("{line}\n")),
+            None => {}
         }
     }
 
-    let output = Command::new(bin_path)
-        .arg(&format!("../{source_file}"))
-        .output()?;
+    let expected_code = if regex!(r"(?m)^\[line \d+\] Error").is_match(&stderr) {
+        65
+    } else if source.contains("// expect runtime error:") {
+        70
+    } else {
+        0
+    };
 
-    let output = String::from_utf8(output.stdout)?;
-    assert_eq!(output, expected);
+    let runs = (0..=MAX_OPT_LEVEL)
+        .map(|opt_level| {
+            let run = spawn_script(source_file, move |interpreter| {
+                interpreter.set_opt_level(opt_level)
+            });
+            (opt_level, run)
+        })
+        .collect::<Vec<_>>();
 
-    Ok(())
+    for (opt_level, run) in runs {
+        let output = run.join().expect("interpreter must not panic");
+        check_output(&output, &stdout, &stderr, expected_code, opt_level);
+    }
+}
+
+fn check_output(output: &Output, stdout: &str, stderr: &str, expected_code: i32, opt_level: u8) {
+    // Stack traces and warnings aren't part of the expectations.
+    let errors: String = output
+        .stderr
+        .lines()
+        .filter(|line| !regex!(r"^\[line \d+\] (in |Warning)").is_match(line))
+        .map(|line| format!("{line}\n"))
+        .collect();
+    assert_eq!(output.stdout, stdout, "stdout at opt level {opt_level}");
+    assert_eq!(errors, stderr, "stderr at opt level {opt_level}");
+
+    assert_eq!(output.code, expected_code, "at opt level {opt_level}");
 }
 
 #[dir_cases(
     "resources/test",
+    "resources/test/assert",
     "resources/test/assignment",
     "resources/test/block",
     "resources/test/bool",
+    "resources/test/break",
     "resources/test/call",
     "resources/test/class",
+    "resources/test/class_method",
     "resources/test/closure",
+    "resources/test/comma",
+    "resources/test/compound_assignment",
     "resources/test/comments",
+    "resources/test/const",
     "resources/test/constructor",
+    "resources/test/continue",
+    "resources/test/destructuring",
+    "resources/test/exception",
     "resources/test/field",
     "resources/test/for",
+    "resources/test/for_in",
     "resources/test/function",
+    "resources/test/getter",
     "resources/test/if",
+    "resources/test/import",
+    "resources/test/increment",
+    "resources/test/interpolation",
     "resources/test/inheritance",
+    "resources/test/io",
+    "resources/test/lambda",
+    "resources/test/list",
     "resources/test/logical_operator",
+    "resources/test/map",
     "resources/test/method",
+    "resources/test/native",
     "resources/test/nil",
     "resources/test/number",
     "resources/test/operator",
     "resources/test/print",
+    "resources/test/process",
     "resources/test/regression",
     "resources/test/return",
+    "resources/test/stdlib",
     "resources/test/string",
     "resources/test/super",
+    "resources/test/switch",
+    "resources/test/ternary",
     "resources/test/this",
+    "resources/test/to_string",
     "resources/test/variable",
     "resources/test/while"
 )]
 #[test]
 fn crafting_interpreters_test_suite(path: &str, contents: &str) -> anyhow::Result<()> {
-    // FIXME: The following tests should pass, but don't, so are skipped.
-    if path.ends_with("decimal_point_at_eof.lox")
-        || path.ends_with("equals_class.lox")
-        || path.ends_with("equals_method.lox")
-    {
+    // FIXME: This test should pass, but doesn't, so is skipped.
+    if path.ends_with("decimal_point_at_eof.lox") {
+        return Ok(());
+    }
+    // `{}` is an empty map now, so these are no longer syntax errors. The
+    // block_* tests next to them check clauses that still are.
+    if regex!(r"for/statement_(condition|increment|initializer)\.lox$").is_match(path) {
+        return Ok(());
+    }
+    // `--` decrements now, so it's an error before anything that can't be
+    // assigned to. negate_twice.lox checks what's left of this test.
+    if path.ends_with("operator/negate.lox") {
         return Ok(());
     }
 
-    let name = env::var("CARGO_PKG_NAME")?;
-    let manifest_dir = env::var("CARGO_MANIFEST_DIR")?;
-    let bin_path = format!("{manifest_dir}/../target/debug/{name}");
+    // Optimizing must never change what a script does.
+    run_test(path, contents);
+
+    Ok(())
+}
 
-    run_test(&bin_path, path, contents)
+#[test]
+fn runtime_errors_print_a_stack_trace() {
+    let output = run_script("resources/test/function/stack_trace.lox", |_| {});
+
+    assert_eq!(
+        output.stderr,
+        "Operands must be two numbers or two strings.\n[line 2]\n\
+         [line 2] in <fn inner>\n[line 6] in <fn outer>\n[line 9] in script\n"
+    );
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn warnings_can_be_denied() {
+    let path = "resources/test/variable/unused_local.lox";
+    let warnings = "[line 4] Warning at 'print': Code after 'return' is unreachable.\n\
+                    [line 2] Warning at 'unused': Local variable is never read.\n";
+
+    let output = run_script(path, |_| {});
+    assert_eq!(output.stdout, "result\n");
+    assert_eq!(output.stderr, warnings);
+    assert_eq!(output.code, 0);
+
+    let output = run_script(path, |interpreter| interpreter.set_deny_warnings(true));
+    assert_eq!(output.stdout, "");
+    assert_eq!(output.stderr, warnings);
+    assert_eq!(output.code, 65);
+}
+
+#[test]
+fn overrides_with_a_different_arity_are_reported() {
+    let output = run_script("resources/test/inheritance/override_arity.lox", |_| {});
+
+    assert_eq!(
+        output.stderr,
+        "[line 12] Warning at 'scale': Method takes 2 parameters but overrides one in 'Shape' that takes 1 parameter.\n\
+         [line 22] Warning at 'describe': Method takes 0 parameters but overrides one in 'Circle' that takes 1 to 2 parameters.\n"
+    );
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn equals_methods_that_cant_be_used_are_reported() {
+    let output = run_script("resources/test/operator/equals_hook_arity.lox", |_| {});
+
+    assert_eq!(output.stdout, "true\nfalse\n");
+    assert_eq!(
+        output.stderr,
+        "[line 2] Warning at 'equals': '==' only uses an 'equals' method that takes one argument.\n"
+    );
+}
+
+#[test]
+fn shadowing_can_be_reported() {
+    let path = "resources/test/variable/shadow_local.lox";
+    let message = "at 'a': Shadows a variable from an enclosing scope.\n";
+
+    let output = run_script(path, |_| {});
+    assert_eq!(output.stderr, "");
+
+    let output = run_script(path, |interpreter| {
+        interpreter.set_shadowing(Shadowing::Warn)
+    });
+    assert_eq!(output.stdout, "shadow\nlocal\n");
+    assert_eq!(output.stderr, format!("[line 4] Warning {message}"));
+    assert_eq!(output.code, 0);
+
+    let output = run_script(path, |interpreter| {
+        interpreter.set_shadowing(Shadowing::Deny)
+    });
+    assert_eq!(output.stdout, "");
+    assert_eq!(output.stderr, format!("[line 4] Error {message}"));
+    assert_eq!(output.code, 65);
+
+    // Globals declared earlier count too.
+    let output = run_script("resources/test/variable/shadow_global.lox", |interpreter| {
+        interpreter.set_shadowing(Shadowing::Warn)
+    });
+    assert_eq!(output.stderr, format!("[line 3] Warning {message}"));
+}
+
+#[test]
+fn scripts_can_choose_their_exit_code() {
+    let output = run_script("resources/test/process/status/exit_code.lox", |_| {});
+
+    assert_eq!(output.stdout, "");
+    assert_eq!(output.stderr, "");
+    assert_eq!(output.code, 42);
+}
+
+// The tests below cover the command line itself, so they run the binary.
+
+#[test]
+fn check_reports_without_running() -> anyhow::Result<()> {
+    let output = Command::new(env!("CARGO_BIN_EXE_lox-treewalk"))
+        .arg("check")
+        .arg("../resources/test/return")
+        .output()?;
+
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("../resources/test/return/after_if.lox: ok\n"));
+    assert!(stdout.contains("../resources/test/return/at_top_level.lox: failed\n"));
+    // Only the verdicts are printed, since nothing is run.
+    assert!(stdout
+        .lines()
+        .all(|line| line.ends_with(": ok") || line.ends_with(": failed")));
+    assert!(String::from_utf8(output.stderr)?
+        .contains("[line 1] Error at 'return': Can't return from top-level code.\n"));
+    assert_eq!(output.status.code(), Some(65));
+
+    Ok(())
+}
+
+#[test]
+fn check_shows_the_offending_code() -> anyhow::Result<()> {
+    let output = Command::new(env!("CARGO_BIN_EXE_lox-treewalk"))
+        .args([
+            "--color=never",
+            "check",
+            "../resources/test/return/at_top_level.lox",
+        ])
+        .output()?;
+
+    assert_eq!(
+        String::from_utf8(output.stderr)?,
+        "[line 1] Error at 'return': Can't return from top-level code.\n  \
+         |\n1 | return \"wat\"; // Error at 'return': Can't return from top-level code.\n  \
+         | ^^^^^^\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn diagnostics_can_be_json_lines() -> anyhow::Result<()> {
+    let output = Command::new(env!("CARGO_BIN_EXE_lox-treewalk"))
+        .args([
+            "--error-format=json",
+            "../resources/test/function/stack_trace.lox",
+        ])
+        .output()?;
+
+    let stderr = String::from_utf8(output.stderr)?;
+    let lines = stderr.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1);
+    let diagnostic = Json::parse(lines[0])?;
+    let field = |key| diagnostic.get(key).cloned();
+    assert!(field("file")
+        .and_then(|file| file.as_str().map(str::to_string))
+        .is_some_and(|file| file.ends_with("stack_trace.lox")));
+    assert_eq!(field("line"), Some(Json::from(2)));
+    assert_eq!(field("severity"), Some(Json::from("error")));
+    assert_eq!(field("code"), Some(Json::from("runtime-error")));
+    assert_eq!(
+        field("message"),
+        Some(Json::from("Operands must be two numbers or two strings."))
+    );
+    assert_eq!(output.status.code(), Some(70));
+
+    Ok(())
+}
+
+#[test]
+fn script_arguments_are_exposed() -> anyhow::Result<()> {
+    // Flags after the script are its own.
+    let output = Command::new(env!("CARGO_BIN_EXE_lox-treewalk"))
+        .args(["../resources/test/native/args.lox", "one", "--two"])
+        .output()?;
+
+    let stdout = String::from_utf8(output.stdout)?;
+    assert_eq!(stdout, "[one, --two]\n2\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    Ok(())
+}
+
+#[test]
+fn repl_commands_inspect_and_reset_the_session() -> anyhow::Result<()> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox-treewalk"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("stdin must be piped")
+        .write_all(b"var a = 1;\nvar b;\n:env\n:ast\n:reset\n:env\n:nope\n")?;
+    let output = child.wait_with_output()?;
+
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "> > > a = 1\nb\n> var\n  b\n> > > > "
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr)?,
+        "Unknown command ':nope'. Try ':help'.\n"
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    Ok(())
+}
+
+#[test]
+fn watch_reruns_the_script_keeping_its_data() -> anyhow::Result<()> {
+    let script = env::temp_dir().join(format!("watch-{}.lox", std::process::id()));
+    let counting = |greeting: &str| {
+        format!("var runs = 0;\nruns = runs + 1;\nfun greet() {{ return \"{greeting}\"; }}\nprint greet() + \" \" + str(runs);\n")
+    };
+    fs::write(&script, counting("hello"))?;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox-treewalk"))
+        .args(["--keep-globals", "watch"])
+        .arg(&script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout must be piped")).lines();
+
+    let first = stdout.next().transpose()?;
+    fs::write(&script, counting("goodbye"))?;
+    let second = stdout.next().transpose()?;
+    child.kill()?;
+    child.wait()?;
+    fs::remove_file(&script)?;
+
+    // The function is redefined, but the count carries on.
+    assert_eq!(first.as_deref(), Some("hello 1"));
+    assert_eq!(second.as_deref(), Some("goodbye 2"));
+
+    Ok(())
+}
+
+#[test]
+fn watch_reports_an_unreadable_script_once() -> anyhow::Result<()> {
+    let script = env::temp_dir().join(format!("watch-unreadable-{}.lox", std::process::id()));
+    fs::write(&script, b"print \"\xff\";\n")?;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox-treewalk"))
+        .arg("watch")
+        .arg(&script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout must be piped")).lines();
+    let mut stderr = BufReader::new(child.stderr.take().expect("stderr must be piped"));
+
+    let mut error = String::new();
+    stderr.read_line(&mut error)?;
+    thread::sleep(Duration::from_millis(500));
+    fs::write(&script, "print \"fixed\";\n")?;
+    let output = stdout.next().transpose()?;
+    child.kill()?;
+    child.wait()?;
+    let mut rest = String::new();
+    stderr.read_to_string(&mut rest)?;
+    fs::remove_file(&script)?;
+
+    assert!(error.starts_with("Can't read "), "{error}");
+    assert!(!rest.contains("Can't read "), "{rest}");
+    assert_eq!(output.as_deref(), Some("fixed"));
+
+    Ok(())
+}
+
+#[test]
+fn vm_repl_keeps_globals_between_lines() -> anyhow::Result<()> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox-treewalk"))
+        .arg("--backend=vm")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("stdin must be piped")
+        .write_all(b"var a = 1;\na + 1\nb\na = a * 10;\nprint a;\n")?;
+    let output = child.wait_with_output()?;
+
+    // An error on one line doesn't lose what earlier lines defined.
+    assert_eq!(String::from_utf8(output.stdout)?, "> > 2\n> > > 10\n> ");
+    assert_eq!(
+        String::from_utf8(output.stderr)?,
+        "Undefined variable 'b'.\n[line 1] in script\n"
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    Ok(())
 }
@@ -0,0 +1,144 @@
+mod framing;
+
+use framing::framed_messages;
+use lox_treewalk::{json::Json, lsp, sync::SharedBuffer};
+use std::io::Cursor;
+
+/// Frames a Language Server Protocol request, or a notification if it has
+/// no `id`.
+fn lsp_message(id: Option<usize>, method: &str, params: Json) -> String {
+    let mut fields = vec![("jsonrpc", "2.0".into())];
+    if let Some(id) = id {
+        fields.push(("id", id.into()));
+    }
+    fields.push(("method", method.into()));
+    fields.push(("params", params));
+    let body = Json::fields(fields).to_string();
+
+    format!("Content-Length: {}\r\n\r\n{body}", body.len())
+}
+
+fn lsp_position(uri: &str, line: usize, character: usize) -> Vec<(&str, Json)> {
+    vec![
+        ("textDocument", Json::fields(vec![("uri", uri.into())])),
+        (
+            "position",
+            Json::fields(vec![("line", line.into()), ("character", character.into())]),
+        ),
+    ]
+}
+
+#[test]
+fn language_servers_check_documents_and_find_definitions() {
+    let uri = "file:///greet.lox";
+    let text =
+        "fun greet(name) {\n  print \"hi \" + name;\n}\n{\n  var x = 1;\n}\ngreet(\"you\");\n";
+    let mut rename = lsp_position(uri, 1, 16);
+    rename.push(("newName", "who".into()));
+
+    let messages = [
+        lsp_message(Some(1), "initialize", Json::fields(vec![])),
+        lsp_message(
+            None,
+            "textDocument/didOpen",
+            Json::fields(vec![(
+                "textDocument",
+                Json::fields(vec![("uri", uri.into()), ("text", text.into())]),
+            )]),
+        ),
+        lsp_message(
+            Some(2),
+            "textDocument/definition",
+            Json::fields(lsp_position(uri, 6, 0)),
+        ),
+        lsp_message(Some(3), "textDocument/rename", Json::fields(rename)),
+        lsp_message(
+            Some(4),
+            "textDocument/documentSymbol",
+            Json::fields(vec![(
+                "textDocument",
+                Json::fields(vec![("uri", uri.into())]),
+            )]),
+        ),
+        lsp_message(
+            None,
+            "textDocument/didChange",
+            Json::fields(vec![
+                ("textDocument", Json::fields(vec![("uri", uri.into())])),
+                (
+                    "contentChanges",
+                    vec![Json::fields(vec![("text", "print ;".into())])].into(),
+                ),
+            ]),
+        ),
+        lsp_message(Some(5), "shutdown", Json::Null),
+        lsp_message(None, "exit", Json::Null),
+    ]
+    .concat();
+
+    let output = SharedBuffer::default();
+    lsp::serve(Cursor::new(messages), output.clone());
+
+    let messages = framed_messages(&output.contents());
+    let shown = messages
+        .iter()
+        .map(|message| match message.get("method") {
+            Some(method) => {
+                let params = message.get("params").unwrap();
+                format!("{method} {}", params.get("diagnostics").unwrap())
+            }
+            None => format!(
+                "{} {}",
+                message.get("id").unwrap(),
+                message.get("result").unwrap()
+            ),
+        })
+        .collect::<Vec<_>>();
+    let range = |line: usize, start: usize, end: usize| {
+        format!(
+            r#"{{"start":{{"line":{line},"character":{start}}},"end":{{"line":{line},"character":{end}}}}}"#
+        )
+    };
+
+    assert!(shown[0].starts_with(r#"1 {"capabilities":"#));
+    assert_eq!(
+        shown[1],
+        format!(
+            r#""textDocument/publishDiagnostics" [{{"range":{},"severity":2,"source":"loxide","message":"Local variable is never read."}}]"#,
+            range(4, 6, 7)
+        )
+    );
+    assert_eq!(
+        shown[2],
+        format!(r#"2 {{"uri":"{uri}","range":{}}}"#, range(0, 4, 9))
+    );
+    assert_eq!(
+        shown[3],
+        format!(
+            r#"3 {{"changes":{{"{uri}":[{{"range":{},"newText":"who"}},{{"range":{},"newText":"who"}}]}}}}"#,
+            range(0, 10, 14),
+            range(1, 16, 20)
+        )
+    );
+    assert!(shown[4].starts_with(r#"4 [{"name":"greet","kind":12,"#));
+    assert_eq!(
+        shown[5],
+        format!(
+            r#""textDocument/publishDiagnostics" [{{"range":{},"severity":1,"source":"loxide","message":"Expect expression."}}]"#,
+            range(0, 6, 7)
+        )
+    );
+    assert_eq!(shown[6], "5 null");
+    assert_eq!(shown.len(), 7);
+}
+
+#[test]
+fn oversized_messages_end_the_session() {
+    let output = SharedBuffer::default();
+    lsp::serve(
+        Cursor::new(format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX)),
+        output.clone(),
+    );
+
+    assert_eq!(output.contents(), "");
+}
@@ -0,0 +1,18 @@
+use lox_treewalk::{parser::Parser, scanner::Scanner};
+
+#[test]
+fn every_syntax_error_is_reported() {
+    let mut scanner = Scanner::new("var = 1;\nprint (2;\nprint 3;\n1 +;\n");
+    let tokens = scanner.scan();
+    let (statements, errors) = Parser::new(tokens).parse();
+
+    assert_eq!(statements.len(), 1);
+    assert_eq!(
+        errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        vec![
+            "[line 1] Error at '=': Expect variable name.",
+            "[line 2] Error at ';': Expect ')' after expression",
+            "[line 4] Error at ';': Expect expression.",
+        ]
+    );
+}
@@ -0,0 +1,184 @@
+//! JavaScript bindings for the treewalk interpreter, so that it can power a
+//! playground in the browser.
+//!
+//! Build with `wasm-pack build wasm --target web`, then from JavaScript:
+//!
+//! ```js
+//! const lox = new LoxideWasm();
+//! lox.onPrint((line) => console.log(line));
+//! const { output, diagnostics } = lox.run('print "hi";');
+//! ```
+//!
+//! There's no stdin, file system or process in the browser, so scripts only
+//! get the core natives and the standard library, and `print` output and
+//! errors go to callbacks rather than stdout and stderr.
+
+use js_sys::Function;
+use lox_treewalk::{
+    interpreter::Interpreter,
+    sync::{Lock, Shared, Threadsafe},
+};
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+};
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    /// The `onPrint` and `onError` callbacks of the script that's running.
+    /// JavaScript functions can't be sent to other threads, so they're kept
+    /// here rather than in the interpreter's writers, which lets this crate
+    /// build with lox-treewalk's `send` feature.
+    static CALLBACKS: RefCell<[Option<Function>; 2]> = const { RefCell::new([None, None]) };
+}
+
+const ON_PRINT: usize = 0;
+const ON_ERROR: usize = 1;
+
+/// What a script printed, and the errors and warnings it was reported with.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunResult {
+    pub output: String,
+    /// Each line of compile errors, warnings and runtime errors, in the order
+    /// they were reported.
+    pub diagnostics: Vec<String>,
+}
+
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct LoxideWasm {
+    on_print: Option<Function>,
+    on_error: Option<Function>,
+}
+
+#[wasm_bindgen]
+impl LoxideWasm {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `callback` with each line scripts print, as they print it.
+    #[wasm_bindgen(js_name = onPrint)]
+    pub fn on_print(&mut self, callback: Function) {
+        self.on_print = Some(callback);
+    }
+
+    /// Calls `callback` with each line of diagnostics, as they're reported.
+    #[wasm_bindgen(js_name = onError)]
+    pub fn on_error(&mut self, callback: Function) {
+        self.on_error = Some(callback);
+    }
+
+    /// Runs `source` in a fresh interpreter.
+    pub fn run(&self, source: &str) -> RunResult {
+        let callbacks = [self.on_print.clone(), self.on_error.clone()];
+        // A callback may run another script, whose callbacks replace these
+        // until it's done.
+        let previous = CALLBACKS.replace(callbacks);
+        let result = run(source, callback(ON_PRINT), callback(ON_ERROR));
+        CALLBACKS.set(previous);
+
+        result
+    }
+}
+
+/// Calls the JavaScript function in slot `index` of [`CALLBACKS`], if there
+/// is one, ignoring anything it throws.
+fn callback(index: usize) -> impl FnMut(&str) + Threadsafe {
+    move |line| {
+        let function = CALLBACKS.with_borrow(|callbacks| callbacks[index].clone());
+        if let Some(function) = function {
+            let _ = function.call1(&JsValue::NULL, &JsValue::from_str(line));
+        }
+    }
+}
+
+/// Runs `source` in a fresh interpreter, passing each line it prints to
+/// `on_print` and each line of diagnostics to `on_error` as they happen.
+pub fn run(
+    source: &str,
+    on_print: impl FnMut(&str) + Threadsafe + 'static,
+    on_error: impl FnMut(&str) + Threadsafe + 'static,
+) -> RunResult {
+    let output = Lines::new(on_print);
+    let diagnostics = Lines::new(on_error);
+    let (printed, reported) = (output.lines.clone(), diagnostics.lines.clone());
+
+    let mut interpreter = Interpreter::new().with_writers(output, diagnostics);
+    // The system clock panics in the browser, so `clock()` asks JavaScript.
+    #[cfg(target_arch = "wasm32")]
+    interpreter.define_native("clock", 0, |_, _| {
+        Ok(lox_treewalk::value::Value::Number(
+            js_sys::Date::now() / 1000.0,
+        ))
+    });
+    // Errors have already been reported by the time they're returned, and
+    // `exit()` just stops the script.
+    let _ = interpreter.run(source);
+    // Dropping the interpreter drops its writers, which passes on any line
+    // left without a newline.
+    drop(interpreter);
+
+    let output = printed
+        .borrow()
+        .iter()
+        .map(|line| format!("{line}\n"))
+        .collect();
+    let diagnostics = reported.borrow().clone();
+
+    RunResult {
+        output,
+        diagnostics,
+    }
+}
+
+/// A writer that splits what it's given into lines, passing each to a
+/// callback and keeping them for the [`RunResult`].
+struct Lines<F: FnMut(&str)> {
+    partial: Vec<u8>,
+    lines: Shared<Lock<Vec<String>>>,
+    callback: F,
+}
+
+impl<F: FnMut(&str)> Lines<F> {
+    fn new(callback: F) -> Self {
+        Self {
+            partial: vec![],
+            lines: Shared::default(),
+            callback,
+        }
+    }
+
+    fn line(&mut self, bytes: &[u8]) {
+        let line = String::from_utf8_lossy(bytes).into_owned();
+        (self.callback)(&line);
+        self.lines.borrow_mut().push(line);
+    }
+}
+
+impl<F: FnMut(&str)> Write for Lines<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        while let Some(end) = self.partial.iter().position(|&byte| byte == b'\n') {
+            let line = self.partial.drain(..=end).collect::<Vec<_>>();
+            self.line(&line[..end]);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&str)> Drop for Lines<F> {
+    fn drop(&mut self) {
+        if !self.partial.is_empty() {
+            let partial = std::mem::take(&mut self.partial);
+            self.line(&partial);
+        }
+    }
+}
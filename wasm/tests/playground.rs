@@ -0,0 +1,39 @@
+use lox_wasm::run;
+use std::sync::{Arc, Mutex};
+
+// The callbacks must be `Send` and `Sync` when lox-treewalk's `send`
+// feature is on.
+fn recorder() -> (Arc<Mutex<Vec<String>>>, impl FnMut(&str) + Send + Sync) {
+    let lines = Arc::new(Mutex::new(vec![]));
+    let recorded = lines.clone();
+    (lines, move |line: &str| {
+        recorded.lock().unwrap().push(line.to_string())
+    })
+}
+
+#[test]
+fn output_goes_to_the_result_and_the_callback() {
+    let (printed, on_print) = recorder();
+    let result = run("print 1 + 2;\nprint \"three\";\n", on_print, |_| {});
+
+    assert_eq!(result.output, "3\nthree\n");
+    assert!(result.diagnostics.is_empty());
+    assert_eq!(*printed.lock().unwrap(), ["3", "three"]);
+}
+
+#[test]
+fn errors_are_diagnostics() {
+    let (reported, on_error) = recorder();
+    let result = run("print 1;\nprint nil + 1;\nprint 2;\n", |_| {}, on_error);
+
+    assert_eq!(result.output, "1\n");
+    assert_eq!(
+        result.diagnostics,
+        ["Operands must be two numbers or two strings.", "[line 2]"]
+    );
+    assert_eq!(*reported.lock().unwrap(), result.diagnostics);
+
+    let result = run("print (;\n", |_| {}, |_| {});
+    assert_eq!(result.output, "");
+    assert_eq!(result.diagnostics.len(), 1);
+}